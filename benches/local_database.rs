@@ -0,0 +1,129 @@
+//! Benchmarks of reading and querying the local database, on fixtures written to a temporary
+//! directory in the same layout as `/var/lib/pacman/local`.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use pacman_rs::database::local::LocalDatabase;
+use tempfile::TempDir;
+
+/// Adds an entry for `name` that owns `files`, which are relative to root.
+fn add_entry(dir: &Path, name: &str, extra_desc: &str, files: &[String]) {
+    let dir = dir.join(format!("{}-1.0-1", name));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("desc"),
+        format!("%NAME%\n{}\n\n%VERSION%\n1.0-1\n\n{}", name, extra_desc),
+    )
+    .unwrap();
+    let mut mtree = String::from("#mtree\n/set type=file uid=0 gid=0 mode=644\n");
+    for file in files {
+        mtree.push_str(&format!("./{} time=1615000000.0 size=0 type=file\n", file));
+    }
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(mtree.as_bytes()).unwrap();
+    std::fs::write(dir.join("mtree"), encoder.finish().unwrap()).unwrap();
+}
+
+/// `pacman -Qo` on 500 paths, against 50 packages of 200 files each: with the file index built
+/// by `owners_of`, and by asking each package in turn.
+fn owners_of(c: &mut Criterion) {
+    let fixture = TempDir::new().unwrap();
+    let mut paths = Vec::new();
+    for pkg in 0..50 {
+        let files: Vec<String> = (0..200)
+            .map(|file| format!("usr/share/pkg{}/file{}", pkg, file))
+            .collect();
+        add_entry(fixture.path(), &format!("pkg{}", pkg), "", &files);
+        paths.push(PathBuf::from(format!("/usr/share/pkg{}/file{}", pkg, pkg)));
+    }
+    for file in 0..450 {
+        paths.push(PathBuf::from(format!("/usr/share/unowned/file{}", file)));
+    }
+
+    let mut group = c.benchmark_group("owners_of");
+    group.bench_function("indexed", |b| {
+        b.iter_batched(
+            || LocalDatabase::with_path(fixture.path()),
+            |mut db| db.owners_of(&paths).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("naive", |b| {
+        b.iter_batched(
+            || LocalDatabase::with_path(fixture.path()),
+            |mut db| {
+                db.populate_full_database().unwrap();
+                paths
+                    .iter()
+                    .map(|file| {
+                        let owner = db
+                            .db
+                            .iter()
+                            .find(|(_, entry)| entry.owns(file).unwrap())
+                            .map(|(name, _)| name.clone());
+                        (file.clone(), owner)
+                    })
+                    .collect::<HashMap<_, _>>()
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+/// `pacman -Qe` over 100 packages of 2000 files each, reading the mtrees lazily as populate does
+/// by default, and reading them along with each package.
+fn explicit_packages(c: &mut Criterion) {
+    let fixture = TempDir::new().unwrap();
+    let files: Vec<String> = (0..2000)
+        .map(|file| format!("usr/share/file{}", file))
+        .collect();
+    for pkg in 0..100 {
+        let reason = if pkg % 2 == 0 { "%REASON%\n1\n\n" } else { "" };
+        add_entry(fixture.path(), &format!("pkg{}", pkg), reason, &files);
+    }
+
+    let mut group = c.benchmark_group("explicit_packages");
+    for (name, load_files) in [("lazy", false), ("load_files", true)].iter() {
+        group.bench_function(*name, |b| {
+            b.iter(|| {
+                let mut db = LocalDatabase::with_path(fixture.path());
+                db.set_load_files(*load_files);
+                db.explicit_packages().unwrap().len()
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Reading 500 packages of 300 files each with their mtrees, on one thread and with
+/// `populate_ordered`.
+fn populate(c: &mut Criterion) {
+    let fixture = TempDir::new().unwrap();
+    let files: Vec<String> = (0..300)
+        .map(|file| format!("usr/share/file{}", file))
+        .collect();
+    for pkg in 0..500 {
+        add_entry(fixture.path(), &format!("pkg{}", pkg), "", &files);
+    }
+    let db = || {
+        let mut db = LocalDatabase::with_path(fixture.path());
+        db.set_load_files(true);
+        db
+    };
+
+    let mut group = c.benchmark_group("populate");
+    group.bench_function("serial", |b| {
+        b.iter(|| db().populate_full_database().unwrap())
+    });
+    group.bench_function("ordered", |b| b.iter(|| db().populate_ordered().unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, owners_of, explicit_packages, populate);
+criterion_main!(benches);
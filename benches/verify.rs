@@ -0,0 +1,55 @@
+//! Benchmarks of checking the files of a package against the disk.
+
+use std::io::Write;
+use std::os::unix::fs::MetadataExt;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use pacman_rs::database::local::LocalDatabase;
+use pacman_rs::sha256;
+use pacman_rs::sysroot::PathResolver;
+use tempfile::TempDir;
+
+/// `pacman -Qkk` on a package of 2000 files of 4 KiB each, one file at a time and with
+/// `verify_parallel`.
+fn verify(c: &mut Criterion) {
+    let fixture = TempDir::new().unwrap();
+    let sysroot = TempDir::new().unwrap();
+    let metadata = sysroot.path().metadata().unwrap();
+    let mut mtree = format!(
+        "#mtree\n/set type=file uid={} gid={} mode=644\n",
+        metadata.uid(),
+        metadata.gid()
+    );
+    std::fs::create_dir(sysroot.path().join("data")).unwrap();
+    for i in 0..2000 {
+        let contents = format!("{:08}", i).repeat(512);
+        let path = sysroot.path().join(format!("data/{:04}", i));
+        std::fs::write(&path, &contents).unwrap();
+        mtree.push_str(&format!(
+            "./data/{:04} size={} sha256digest={}\n",
+            i,
+            contents.len(),
+            sha256::hex_digest(contents.as_bytes()).unwrap()
+        ));
+    }
+    let dir = fixture.path().join("big-1.0-1");
+    std::fs::create_dir(&dir).unwrap();
+    std::fs::write(dir.join("desc"), "%NAME%\nbig\n\n%VERSION%\n1.0-1\n\n").unwrap();
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(mtree.as_bytes()).unwrap();
+    std::fs::write(dir.join("mtree"), encoder.finish().unwrap()).unwrap();
+
+    let mut db = LocalDatabase::with_resolver(fixture.path(), PathResolver::new(sysroot.path()));
+    let entry = db.get("big").unwrap();
+    entry.mtree().unwrap();
+
+    let mut group = c.benchmark_group("verify");
+    group.bench_function("serial", |b| b.iter(|| entry.verify().unwrap()));
+    group.bench_function("parallel", |b| b.iter(|| entry.verify_parallel().unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, verify);
+criterion_main!(benches);
@@ -0,0 +1,143 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use crate::database::local::LocalDatabase;
+use crate::database::sync::SyncDatabases;
+use crate::Result;
+
+/// Splits a package file name (`<name>-<pkgver>-<pkgrel>-<arch>.pkg.tar.<ext>`, optionally with a
+/// `.sig` suffix) into the package name and its `<pkgver>-<pkgrel>` version. Returns `None` for
+/// files that aren't packages.
+fn parse_package_filename(filename: &str) -> Option<(&str, String)> {
+    let stem = &filename[..filename.find(".pkg.tar")?];
+    let mut parts = stem.rsplitn(4, '-');
+    let _arch = parts.next()?;
+    let pkgrel = parts.next()?;
+    let pkgver = parts.next()?;
+    let name = parts.next()?;
+    Some((name, format!("{}-{}", pkgver, pkgrel)))
+}
+
+/// Removes the package files in `cache_dir` that are no longer useful, like `pacman -Sc`. A file
+/// is kept if it is the current version of a package in one of the `sync` databases or, with
+/// `keep_installed`, if it is the installed version of a package. `local` must already be
+/// populated. Signature files are treated the same as the package they belong to, and anything
+/// that isn't a package is left alone.
+///
+/// Returns the paths of the files that were removed, sorted. With `dry_run`, nothing is removed
+/// and the files that would have been are returned instead.
+pub fn clean_cache(
+    cache_dir: &Path,
+    local: &LocalDatabase,
+    sync: &SyncDatabases,
+    keep_installed: bool,
+    dry_run: bool,
+) -> Result<Vec<PathBuf>> {
+    let current: HashSet<&str> = sync
+        .iter()
+        .flat_map(|db| db.packages.values())
+        .filter_map(|desc| desc.filename.as_deref())
+        .collect();
+
+    let mut removed = Vec::new();
+    for file in cache_dir.read_dir()? {
+        let file = file?;
+        let filename = file.file_name();
+        let filename = match filename.to_str() {
+            Some(x) => x,
+            None => continue,
+        };
+        let package_filename = filename.strip_suffix(".sig").unwrap_or(filename);
+        let (name, version) = match parse_package_filename(package_filename) {
+            Some(x) => x,
+            None => continue,
+        };
+        if current.contains(package_filename) {
+            continue;
+        }
+        if keep_installed
+            && local
+                .db
+                .get(name)
+                .is_some_and(|entry| entry.desc.version == version)
+        {
+            continue;
+        }
+        removed.push(file.path());
+    }
+    removed.sort();
+
+    if !dry_run {
+        for path in removed.iter() {
+            std::fs::remove_file(path)?;
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::sync::SyncDatabase;
+    use crate::test_util::{gzip, Fixture, TarBuilder};
+
+    #[test]
+    fn test_parse_package_filename() {
+        assert_eq!(
+            parse_package_filename("foo-bar-2:1.0-3-x86_64.pkg.tar.zst"),
+            Some(("foo-bar", "2:1.0-3".to_owned()))
+        );
+        assert_eq!(parse_package_filename("foo.db"), None);
+        assert_eq!(parse_package_filename("foo-any.pkg.tar.xz"), None);
+    }
+
+    #[test]
+    fn test_clean_cache() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("foo", "1.0-1", "", &[]);
+        let mut local = LocalDatabase::with_path(fixture.path());
+        local.populate_full_database()?;
+        let archive = TarBuilder::new()
+            .file(
+                "foo-2.0-1/desc",
+                0o644,
+                b"%FILENAME%\nfoo-2.0-1-x86_64.pkg.tar.zst\n\n%NAME%\nfoo\n\n%VERSION%\n2.0-1\n\n",
+            )
+            .finish();
+        let sync = SyncDatabases::new(vec![SyncDatabase::from_archive("core", &gzip(&archive))?]);
+
+        let cache = tempfile::tempdir()?;
+        for name in [
+            "foo-0.9-1-x86_64.pkg.tar.zst",
+            "foo-0.9-1-x86_64.pkg.tar.zst.sig",
+            "foo-1.0-1-x86_64.pkg.tar.zst",
+            "foo-2.0-1-x86_64.pkg.tar.zst",
+            "notes.txt",
+        ] {
+            std::fs::write(cache.path().join(name), "")?;
+        }
+        let stale = vec![
+            cache.path().join("foo-0.9-1-x86_64.pkg.tar.zst"),
+            cache.path().join("foo-0.9-1-x86_64.pkg.tar.zst.sig"),
+        ];
+
+        let would_remove = clean_cache(cache.path(), &local, &sync, true, true)?;
+        assert_eq!(would_remove, stale);
+        assert!(stale.iter().all(|x| x.exists()));
+
+        assert_eq!(
+            clean_cache(cache.path(), &local, &sync, true, false)?,
+            stale
+        );
+        assert!(stale.iter().all(|x| !x.exists()));
+
+        // The installed version is no longer in a sync database, so it goes without keep_installed
+        assert_eq!(
+            clean_cache(cache.path(), &local, &sync, false, false)?,
+            [cache.path().join("foo-1.0-1-x86_64.pkg.tar.zst")]
+        );
+        assert!(cache.path().join("foo-2.0-1-x86_64.pkg.tar.zst").exists());
+        assert!(cache.path().join("notes.txt").exists());
+        Ok(())
+    }
+}
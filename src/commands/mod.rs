@@ -0,0 +1,108 @@
+//! The handlers for each mode of operation, chosen by `run` from the mode flag given on the
+//! command line. Each mode lives in its own function so that it can be run and tested without
+//! going through `main`.
+
+mod query;
+mod sync;
+
+use crate::config::Config;
+use crate::dependency::Dependency;
+use crate::interface::{self, Args, Mode};
+use crate::resolver::Resolver;
+use crate::transaction::DependencyChecks;
+use crate::Result;
+
+pub use query::run_query;
+pub use sync::run_sync;
+
+/// Runs `mode` with the options in `args`.
+pub fn run(mode: Mode, args: &Args, config: &Config) -> Result<()> {
+    match mode {
+        Mode::Database => not_implemented("-D"),
+        Mode::Files => not_implemented("-F"),
+        Mode::Query => {
+            let mut local_database = config.local_database()?;
+            if args.show_repo {
+                local_database.attribute_repos(&config.sync_databases()?);
+            }
+            run_query(
+                args,
+                &mut local_database,
+                &mut std::io::stdout(),
+                &mut std::io::stderr(),
+            )
+        }
+        Mode::Remove => run_remove(args, config),
+        Mode::Sync => run_sync(args, config),
+        Mode::Deptest => run_deptest(args, config),
+        Mode::Upgrade => not_implemented("-U"),
+        Mode::SelfTest => run_selftest(config),
+    }
+}
+
+/// The error for an operation that pacman-rs can't do yet.
+fn not_implemented(operation: &str) -> Result<()> {
+    Err(format!("{} is not implemented yet", operation).into())
+}
+
+/// Runs `-R`, removing the targets from the database after asking for confirmation. Only
+/// `--dbonly` is supported, so the files of the packages are left in place.
+pub fn run_remove(args: &Args, config: &Config) -> Result<()> {
+    if !args.dbonly {
+        return not_implemented("-R without --dbonly");
+    }
+    let mut local_database = config.local_database()?;
+    let targets: Vec<&str> = args.targets.iter().map(|x| x.as_str()).collect();
+    let checks = DependencyChecks::from_nodeps(args.nodeps);
+    let mut transaction = local_database.remove(&targets, checks)?;
+    transaction.flag_held(&config.hold_packages);
+    print!(
+        "{}\n{}",
+        transaction,
+        transaction.size_summary(&local_database)
+    );
+    // Held packages are never removed without asking, even with --noconfirm
+    if transaction.needs_hold_confirmation()
+        && !interface::confirm_default_no(":: Do you want to remove them anyway?")
+    {
+        return Ok(());
+    }
+    if !args.noconfirm && !interface::confirm(":: Do you want to remove these packages?") {
+        return Ok(());
+    }
+    for target in transaction.remove.iter() {
+        local_database.remove_db_entry(target)?;
+    }
+    Ok(())
+}
+
+/// Runs `-T`, printing each of the targets that isn't satisfied by the installed packages and
+/// exiting with 127 if there are any, like `pacman -T`.
+pub fn run_deptest(args: &Args, config: &Config) -> Result<()> {
+    let deps = args
+        .targets
+        .iter()
+        .map(|x| x.parse())
+        .collect::<std::result::Result<Vec<Dependency>, _>>()?;
+    let resolver = Resolver::new(args.assume_installed.clone());
+    let mut local_database = config.local_database()?;
+    let missing = resolver.deptest(&mut local_database, &deps)?;
+    for dep in missing.iter() {
+        println!("{}", dep);
+    }
+    if !missing.is_empty() {
+        std::process::exit(127);
+    }
+    Ok(())
+}
+
+/// Runs `--selftest`, printing a report of the checks on the local database and exiting with 1 if
+/// any of them failed.
+pub fn run_selftest(config: &Config) -> Result<()> {
+    let report = config.local_database()?.self_test()?;
+    print!("{}", report);
+    if !report.passed() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
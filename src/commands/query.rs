@@ -0,0 +1,925 @@
+use std::collections::BTreeMap;
+use std::io::{IsTerminal, Write};
+
+use crate::database::local::sort::{self, SortKey};
+use crate::database::local::verify::VerifyLevel;
+use crate::database::local::{LocalDatabase, LocalDatabaseEntry};
+use crate::format;
+use crate::glob::Glob;
+use crate::interface::Args;
+use crate::output::OutputFormat;
+use crate::Result;
+
+use regex::RegexBuilder;
+
+/// Runs `-Q` against `db`, which the caller has already set up for `args`. Listings are written to
+/// `out`, and anything that shouldn't end up in piped output, such as the summary after a full
+/// listing, to `err`.
+pub fn run_query(
+    args: &Args,
+    db: &mut LocalDatabase,
+    out: &mut impl Write,
+    err: &mut impl Write,
+) -> Result<()> {
+    if args.owns {
+        let all_owned = print_owners(db, &args.targets, args.quiet, out, err)?;
+        if !all_owned {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if args.owns_regex {
+        let any_owned = print_owners_matching(db, &args.targets, args.quiet, out, err)?;
+        if !any_owned {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if args.search {
+        let found = print_search(db, &args.targets, args.quiet, out)?;
+        if !found {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if args.groups {
+        let width = format::terminal_width();
+        return print_groups(db, &args.targets, width, out);
+    }
+    if args.tree {
+        for target in args.targets.iter() {
+            let tree = db.dependency_tree(target, args.depth, args.reverse)?;
+            write!(out, "{}", tree)?;
+        }
+        return Ok(());
+    }
+    if args.check > 0 {
+        let level = if args.check > 1 {
+            VerifyLevel::Full
+        } else {
+            VerifyLevel::Quick
+        };
+        let all_ok = print_check(db, &args.targets, level, out)?;
+        if !all_ok {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if args.changed_config {
+        return print_changed_configs(db, out);
+    }
+    if args.unrequired {
+        return print_unrequired(
+            db,
+            args.deps_only(),
+            args.quiet,
+            &args.exclude,
+            args.sort,
+            args.reverse,
+            out,
+        );
+    }
+    if args.dump_manifest {
+        write!(out, "{}", db.dump_manifest()?)?;
+        return Ok(());
+    }
+    if args.explicit {
+        return print_explicit(db, args.quiet, &args.exclude, args.sort, args.reverse, out);
+    }
+    if let Some(n) = args.recent {
+        return print_recent(db, n, out);
+    }
+    let no_color = std::env::var_os(format::NO_COLOR_ENV);
+    let color = args
+        .color
+        .enabled(std::io::stdout().is_terminal(), no_color.as_deref());
+    let format = args.output_format(color);
+    if args.newer_than.is_some() || args.older_than.is_some() {
+        return print_installed_between(db, args.newer_than, args.older_than, &format, out);
+    }
+    if !args.targets.is_empty() {
+        return print_targets(db, &args.targets, &format, out);
+    }
+    list_packages(
+        db,
+        &format,
+        &args.exclude,
+        args.sort,
+        args.reverse,
+        out,
+        err,
+    )
+}
+
+/// Looks up each of `names`, which must already be in the database, and sorts them for a listing
+/// as given by `--sort` and `--reverse`.
+fn sorted_entries<'a>(
+    db: &'a LocalDatabase,
+    names: impl IntoIterator<Item = &'a str>,
+    sort: SortKey,
+    reverse: bool,
+) -> Vec<&'a LocalDatabaseEntry> {
+    let mut entries: Vec<_> = names.into_iter().map(|x| &db.db[x]).collect();
+    sort::sort_entries(&mut entries, sort, reverse);
+    entries
+}
+
+/// Whether a package is left out of query listings by one of the `--exclude` patterns.
+fn is_excluded(name: &str, exclude: &[Glob]) -> bool {
+    exclude.iter().any(|x| x.is_match(name))
+}
+
+/// Prints every installed package to `out` in `format`. In the default text format, this is
+/// followed by a summary of the database to `err` so that it doesn't end up in piped output.
+/// Packages matching `exclude` are left out, but still counted in the summary.
+fn list_packages(
+    db: &mut LocalDatabase,
+    format: &OutputFormat,
+    exclude: &[Glob],
+    sort: SortKey,
+    reverse: bool,
+    out: &mut impl Write,
+    err: &mut impl Write,
+) -> Result<()> {
+    let summary = db.summary()?;
+    let packages = sorted_entries(
+        db,
+        db.package_names().filter(|x| !is_excluded(x, exclude)),
+        sort,
+        reverse,
+    );
+    let mut formatter = format.formatter(out);
+    for pkg in packages {
+        formatter.package(pkg, db.attributed_repo(&pkg.desc.name))?;
+    }
+    formatter.finish()?;
+    if let OutputFormat::Text { quiet: false, .. } = format {
+        writeln!(err, "{}", summary)?;
+    }
+    Ok(())
+}
+
+/// Checks the files of each of `targets`, or of every installed package if there are no targets,
+/// and prints any that differ from the database, followed by a summary line for each package.
+/// Returns whether every file matched.
+fn print_check(
+    db: &mut LocalDatabase,
+    targets: &[String],
+    level: VerifyLevel,
+    out: &mut impl Write,
+) -> Result<bool> {
+    let names = if targets.is_empty() {
+        db.populate_full_database()?;
+        let mut names: Vec<String> = db.db.keys().cloned().collect();
+        names.sort_unstable();
+        names
+    } else {
+        targets.to_vec()
+    };
+    let mut all_ok = true;
+    for name in names.iter() {
+        let entry = db.get(name)?;
+        let issues = match level {
+            VerifyLevel::Quick => entry.verify_quick()?,
+            VerifyLevel::Full => entry.verify()?,
+        };
+        for issue in issues.iter() {
+            writeln!(out, "warning: {}: {}", name, issue)?;
+        }
+        let total = entry
+            .mtree()?
+            .iter()
+            .filter(|x| !x.is_package_metadata())
+            .count();
+        writeln!(
+            out,
+            "{}: {} total files, {} altered files",
+            name,
+            total,
+            issues.len()
+        )?;
+        all_ok &= issues.is_empty();
+    }
+    Ok(all_ok)
+}
+
+/// Prints the packages that nothing depends on, like `pacman -Qt`. With `deps_only`, only the ones
+/// installed as dependencies are printed, like `pacman -Qdt`.
+fn print_unrequired(
+    db: &mut LocalDatabase,
+    deps_only: bool,
+    quiet: bool,
+    exclude: &[Glob],
+    sort: SortKey,
+    reverse: bool,
+    out: &mut impl Write,
+) -> Result<()> {
+    let names = if deps_only {
+        db.orphans()?
+    } else {
+        db.leaves()?
+    };
+    let names: Vec<String> = names
+        .into_iter()
+        .filter(|x| !is_excluded(x, exclude))
+        .map(|x| x.to_owned())
+        .collect();
+    for entry in sorted_entries(db, names.iter().map(|x| x.as_str()), sort, reverse) {
+        if quiet {
+            writeln!(out, "{}", entry.desc.name)?;
+        } else {
+            writeln!(out, "{} {}", entry.desc.name, entry.desc.version)?;
+        }
+    }
+    Ok(())
+}
+
+/// Prints the changed configuration files of every installed package, one `<package> <file>` pair
+/// per line.
+fn print_changed_configs(db: &mut LocalDatabase, out: &mut impl Write) -> Result<()> {
+    for (name, path) in db.modified_configs()? {
+        writeln!(out, "{} {}", name, path.display())?;
+    }
+    Ok(())
+}
+
+/// Prints the explicitly installed packages, like `pacman -Qe`. With `quiet`, only the names are
+/// printed, which can be passed to `pacman -S` to reinstall them.
+fn print_explicit(
+    db: &mut LocalDatabase,
+    quiet: bool,
+    exclude: &[Glob],
+    sort: SortKey,
+    reverse: bool,
+    out: &mut impl Write,
+) -> Result<()> {
+    let names: Vec<String> = db
+        .explicit_packages()?
+        .into_iter()
+        .filter(|x| !is_excluded(x, exclude))
+        .map(|x| x.to_owned())
+        .collect();
+    for entry in sorted_entries(db, names.iter().map(|x| x.as_str()), sort, reverse) {
+        if quiet {
+            writeln!(out, "{}", entry.desc.name)?;
+        } else {
+            writeln!(out, "{} {}", entry.desc.name, entry.desc.version)?;
+        }
+    }
+    Ok(())
+}
+
+/// Prints the `n` most recently installed packages with their install dates, newest first.
+fn print_recent(db: &mut LocalDatabase, n: usize, out: &mut impl Write) -> Result<()> {
+    let entries = db.recently_installed(n)?;
+    let width = format::column_width(entries.iter().map(|x| x.desc.name.as_str()));
+    for entry in entries {
+        let date = match entry.desc.install_date {
+            Some(date) => format::format_timestamp(date),
+            None => "unknown".to_owned(),
+        };
+        writeln!(
+            out,
+            "{:19} {:width$} {}",
+            date,
+            entry.desc.name,
+            entry.desc.version,
+            width = width
+        )?;
+    }
+    Ok(())
+}
+
+/// Prints the packages installed between `start` and `end` in `format`, oldest first, as given to
+/// `--newer-than` and `--older-than`.
+fn print_installed_between(
+    db: &mut LocalDatabase,
+    start: Option<u64>,
+    end: Option<u64>,
+    format: &OutputFormat,
+    out: &mut impl Write,
+) -> Result<()> {
+    let names: Vec<String> = db
+        .installed_between(start, end)?
+        .iter()
+        .map(|x| x.desc.name.clone())
+        .collect();
+    print_targets(db, &names, format, out)
+}
+
+/// Prints each of `targets` in `format`, in the order they were given.
+fn print_targets(
+    db: &mut LocalDatabase,
+    targets: &[String],
+    format: &OutputFormat,
+    out: &mut impl Write,
+) -> Result<()> {
+    let targets: Vec<&str> = targets.iter().map(|x| x.as_str()).collect();
+    let repos: Vec<Option<String>> = targets
+        .iter()
+        .map(|x| db.attributed_repo(x).map(|x| x.to_owned()))
+        .collect();
+    let mut formatter = format.formatter(out);
+    for (entry, repo) in db.get_many(&targets)?.into_iter().zip(repos) {
+        formatter.package(entry, repo.as_deref())?;
+    }
+    formatter.finish()
+}
+
+/// Prints each package group followed by its installed members, wrapped to `width` columns. If
+/// `groups` is not empty, only those groups are printed.
+fn print_groups(
+    db: &mut LocalDatabase,
+    groups: &[String],
+    width: usize,
+    out: &mut impl Write,
+) -> Result<()> {
+    db.populate_full_database()?;
+    let mut members: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for (name, entry) in db.db.iter() {
+        for group in entry.desc.groups.iter() {
+            if groups.is_empty() || groups.contains(group) {
+                members.entry(group).or_default().push(name);
+            }
+        }
+    }
+    let label_width = format::column_width(members.keys().copied());
+    for (group, members) in members.iter_mut() {
+        members.sort_unstable();
+        let members = format::wrap_list(members, label_width + 1, width);
+        writeln!(out, "{:width$} {}", group, members, width = label_width)?;
+    }
+    Ok(())
+}
+
+/// Prints the packages that own each of `files`, in the same format as `pacman -Qo`, or only the
+/// names of the packages if `quiet`. Returns whether every file was owned by at least one package.
+fn print_owners(
+    db: &mut LocalDatabase,
+    files: &[String],
+    quiet: bool,
+    out: &mut impl Write,
+    err: &mut impl Write,
+) -> Result<bool> {
+    let mut all_owned = true;
+    for file in files {
+        let path = std::env::current_dir()?.join(file);
+        let owners = db.file_index()?.get(&path).cloned().unwrap_or_default();
+        if owners.is_empty() {
+            writeln!(err, "error: No package owns {}", file)?;
+            all_owned = false;
+        }
+        for owner in owners {
+            if quiet {
+                writeln!(out, "{}", owner)?;
+                continue;
+            }
+            let version = &db.db[&owner].desc.version;
+            writeln!(out, "{} is owned by {} {}", file, owner, version)?;
+        }
+    }
+    Ok(all_owned)
+}
+
+/// Prints the owners of every file matching each of `patterns`, in the same format as `-Qo`, or
+/// only the names of the packages if `quiet`. Returns whether every pattern matched at least one
+/// owned file.
+fn print_owners_matching(
+    db: &mut LocalDatabase,
+    patterns: &[String],
+    quiet: bool,
+    out: &mut impl Write,
+    err: &mut impl Write,
+) -> Result<bool> {
+    let mut all_matched = true;
+    for pattern in patterns {
+        let owners = db.owners_matching(pattern)?;
+        if owners.is_empty() {
+            writeln!(err, "error: No package owns a file matching {}", pattern)?;
+            all_matched = false;
+        }
+        for (file, owner) in owners {
+            if quiet {
+                writeln!(out, "{}", owner)?;
+                continue;
+            }
+            let version = &db.db[&owner].desc.version;
+            writeln!(out, "{} is owned by {} {}", file.display(), owner, version)?;
+        }
+    }
+    Ok(all_matched)
+}
+
+/// Prints the installed packages whose name or description matches every one of `patterns`,
+/// ignoring case, in the same format as `pacman -Qs`, or only their names if `quiet`. Returns
+/// whether any package matched.
+fn print_search(
+    db: &mut LocalDatabase,
+    patterns: &[String],
+    quiet: bool,
+    out: &mut impl Write,
+) -> Result<bool> {
+    let patterns = patterns
+        .iter()
+        .map(|x| RegexBuilder::new(x).case_insensitive(true).build())
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    let matches = db.search(&patterns)?;
+    for entry in matches.iter() {
+        let desc = &entry.desc;
+        if quiet {
+            writeln!(out, "{}", desc.name)?;
+        } else {
+            writeln!(out, "local/{} {}", desc.name, desc.version)?;
+            writeln!(out, "    {}", desc.description.as_deref().unwrap_or(""))?;
+        }
+    }
+    Ok(!matches.is_empty())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sysroot;
+    use crate::test_util::{capture_logs, Fixture};
+
+    use ansi_term::Style;
+    use structopt::StructOpt;
+
+    #[test]
+    fn test_run_query() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("bash", "5.1.004-1", "%SIZE%\n2048\n\n%REASON%\n0\n\n", &[]);
+        fixture.add("readline", "8.1-1", "%REASON%\n1\n\n", &[]);
+        let mut db = LocalDatabase::with_path(fixture.path());
+        let mut query = |args: &[&str]| -> Result<(String, String)> {
+            let args = Args::from_iter_safe(args)?;
+            let (mut out, mut err) = (Vec::new(), Vec::new());
+            run_query(&args, &mut db, &mut out, &mut err)?;
+            Ok((String::from_utf8(out)?, String::from_utf8(err)?))
+        };
+
+        assert_eq!(
+            query(&["pacman-rs", "-Q", "--color", "never"])?,
+            (
+                "bash     5.1.004-1\nreadline 8.1-1\n".to_owned(),
+                "2 packages installed, total size 2.00 KiB\n".to_owned()
+            )
+        );
+        assert_eq!(
+            query(&["pacman-rs", "-Qq", "readline"])?,
+            ("readline\n".to_owned(), String::new())
+        );
+        assert_eq!(query(&["pacman-rs", "-Qqe"])?.0, "bash\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_packages() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("foo", "1.0-1", "%SIZE%\n2048\n\n", &[]);
+        fixture.add("barbaz", "2.0-1", "", &[]);
+
+        let mut db = LocalDatabase::with_path(fixture.path());
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        list_packages(
+            &mut db,
+            &OutputFormat::Text {
+                quiet: false,
+                color: true,
+            },
+            &[],
+            SortKey::Name,
+            false,
+            &mut out,
+            &mut err,
+        )?;
+        let out = String::from_utf8(out)?;
+        let err = String::from_utf8(err)?;
+        assert_eq!(out.lines().count(), 2);
+        // The versions are aligned after the longest name
+        assert!(out.contains(&Style::new().bold().paint("barbaz").to_string()));
+        assert!(out.contains(&Style::new().bold().paint("foo   ").to_string()));
+        assert!(!out.contains("installed"));
+        assert_eq!(err, "2 packages installed, total size 2.00 KiB\n");
+
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        list_packages(
+            &mut db,
+            &OutputFormat::Text {
+                quiet: true,
+                color: true,
+            },
+            &[],
+            SortKey::Name,
+            false,
+            &mut out,
+            &mut err,
+        )?;
+        assert_eq!(String::from_utf8(out)?, "barbaz\nfoo\n");
+        assert!(err.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_packages_exclude() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("linux", "5.11.6-1", "", &[]);
+        fixture.add("linux-firmware", "20210315-1", "", &[]);
+        fixture.add("util-linux", "2.36.2-1", "", &[]);
+        fixture.add("bash", "5.1.004-1", "", &[]);
+
+        let mut db = LocalDatabase::with_path(fixture.path());
+        let args = Args::from_iter(&["pacman-rs", "-Qq", "--exclude", "linux*"]);
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        list_packages(
+            &mut db,
+            &OutputFormat::Text {
+                quiet: true,
+                color: true,
+            },
+            &args.exclude,
+            SortKey::Name,
+            false,
+            &mut out,
+            &mut err,
+        )?;
+        assert_eq!(String::from_utf8(out)?, "bash\nutil-linux\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_packages_sorted() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("glibc", "2.33-4", "%SIZE%\n4096\n\n", &[]);
+        fixture.add("bash", "5.1.004-1", "%SIZE%\n8192\n\n", &[]);
+        fixture.add("acl", "2.2.53-3", "", &[]);
+
+        let mut db = LocalDatabase::with_path(fixture.path());
+        let args = Args::from_iter(&["pacman-rs", "-Qq", "--sort", "size", "--reverse"]);
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        list_packages(
+            &mut db,
+            &OutputFormat::Text {
+                quiet: true,
+                color: true,
+            },
+            &[],
+            args.sort,
+            args.reverse,
+            &mut out,
+            &mut err,
+        )?;
+        assert_eq!(String::from_utf8(out)?, "bash\nglibc\nacl\n");
+
+        let mut out = Vec::new();
+        print_explicit(&mut db, true, &[], SortKey::Name, true, &mut out)?;
+        assert_eq!(String::from_utf8(out)?, "glibc\nbash\nacl\n");
+        assert!(Args::from_iter_safe(&["pacman-rs", "-Q", "--sort", "version"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_print_owners() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add(
+            "bash",
+            "5.1.004-1",
+            "",
+            &["usr/", "usr/bin/", "usr/bin/bash"],
+        );
+        fixture.add("filesystem", "2021.01.19-1", "", &["usr/", "usr/bin/"]);
+
+        let mut db = LocalDatabase::with_path(fixture.path());
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        let files = ["/usr/bin/bash".to_owned(), "/usr/bin/".to_owned()];
+        assert!(print_owners(&mut db, &files, false, &mut out, &mut err)?);
+        assert_eq!(
+            String::from_utf8(out)?,
+            "/usr/bin/bash is owned by bash 5.1.004-1\n\
+             /usr/bin/ is owned by bash 5.1.004-1\n\
+             /usr/bin/ is owned by filesystem 2021.01.19-1\n"
+        );
+        assert!(err.is_empty());
+
+        let mut out = Vec::new();
+        let args = Args::from_iter(&["pacman-rs", "-Qoq", "/usr/bin/bash", "/usr/bin/"]);
+        run_query(&args, &mut db, &mut out, &mut Vec::new())?;
+        assert_eq!(String::from_utf8(out)?, "bash\nbash\nfilesystem\n");
+
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        let files = ["/usr/bin/zsh".to_owned()];
+        assert!(!print_owners(&mut db, &files, false, &mut out, &mut err)?);
+        assert!(out.is_empty());
+        assert_eq!(
+            String::from_utf8(err)?,
+            "error: No package owns /usr/bin/zsh\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_print_owners_skips_bad_mtree() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add(
+            "bash",
+            "5.1.004-1",
+            "",
+            &["usr/", "usr/bin/", "usr/bin/bash"],
+        );
+        fixture.add("broken", "1.0-1", "", &["usr/bin/broken"]);
+        std::fs::write(
+            fixture.path().join("broken-1.0-1/mtree"),
+            b"\x1f\x8bnot gzipped",
+        )?;
+
+        let mut db = LocalDatabase::with_path(fixture.path());
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        let files = ["/usr/bin/bash".to_owned()];
+        let (owned, logs) =
+            capture_logs(|| print_owners(&mut db, &files, true, &mut out, &mut err));
+        assert!(owned?);
+        assert_eq!(String::from_utf8(out)?, "bash\n");
+        assert!(err.is_empty());
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].1.contains("broken-1.0-1"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_print_formatted() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("foo", "1.0-1", "%DESC%\nA foo utility\n\n", &[]);
+        fixture.add("bar", "2.0-1", "", &[]);
+
+        let mut db = LocalDatabase::with_path(fixture.path());
+        let format = OutputFormat::Template("%n %v".parse()?);
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        list_packages(
+            &mut db,
+            &format,
+            &[],
+            SortKey::Name,
+            false,
+            &mut out,
+            &mut err,
+        )?;
+        assert_eq!(String::from_utf8(out)?, "bar 2.0-1\nfoo 1.0-1\n");
+        assert!(err.is_empty());
+
+        let format = OutputFormat::Template("%r/%n: %d".parse()?);
+        let mut out = Vec::new();
+        print_targets(&mut db, &["foo".to_owned()], &format, &mut out)?;
+        assert_eq!(String::from_utf8(out)?, "local/foo: A foo utility\n");
+
+        let mut out = Vec::new();
+        let targets = ["foo".to_owned(), "bar".to_owned()];
+        print_targets(&mut db, &targets, &OutputFormat::Null, &mut out)?;
+        assert_eq!(String::from_utf8(out)?, "foo\0bar\0");
+        assert!(print_targets(&mut db, &["baz".to_owned()], &format, &mut Vec::new()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_print_check() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("foo", "1.0-1", "", &[]);
+        fixture.add("bar", "1.0-1", "", &["nonexistent/bar"]);
+
+        let mut db = LocalDatabase::with_path(fixture.path());
+        let mut out = Vec::new();
+        assert!(!print_check(&mut db, &[], VerifyLevel::Quick, &mut out)?);
+        assert_eq!(
+            String::from_utf8(out)?,
+            "warning: bar: /nonexistent/bar (No such file or directory)\n\
+             bar: 1 total files, 1 altered files\n\
+             foo: 0 total files, 0 altered files\n"
+        );
+        let mut out = Vec::new();
+        assert!(print_check(
+            &mut db,
+            &["foo".to_owned()],
+            VerifyLevel::Full,
+            &mut out
+        )?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_print_unrequired() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("glibc", "2.33-4", "%REASON%\n1\n\n", &[]);
+        fixture.add("bash", "5.1.004-1", "%DEPENDS%\nglibc\n\n", &[]);
+        fixture.add("libfoo", "1.0-1", "%REASON%\n1\n\n", &[]);
+
+        let mut db = LocalDatabase::with_path(fixture.path());
+        let mut out = Vec::new();
+        print_unrequired(&mut db, false, false, &[], SortKey::Name, false, &mut out)?;
+        assert_eq!(String::from_utf8(out)?, "bash 5.1.004-1\nlibfoo 1.0-1\n");
+        let mut out = Vec::new();
+        print_unrequired(&mut db, true, true, &[], SortKey::Name, false, &mut out)?;
+        assert_eq!(String::from_utf8(out)?, "libfoo\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_print_changed_configs() -> Result<()> {
+        let fixture = Fixture::new();
+        let sysroot = tempfile::tempdir()?;
+        // The sha256 of "hello\n"
+        fixture.add_raw(
+            "foo-1.0-1",
+            "%NAME%\nfoo\n\n%VERSION%\n1.0-1\n\n",
+            "#mtree\n./etc/foo.conf type=file size=6 sha256digest=5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03\n",
+        );
+        std::fs::write(
+            fixture.path().join("foo-1.0-1/files"),
+            "%BACKUP%\netc/foo.conf\tmd5\n",
+        )?;
+        std::fs::create_dir(sysroot.path().join("etc"))?;
+        std::fs::write(sysroot.path().join("etc/foo.conf"), "hello\n")?;
+
+        let mut db = LocalDatabase::with_resolver(
+            fixture.path(),
+            sysroot::PathResolver::new(sysroot.path()),
+        );
+        let mut out = Vec::new();
+        print_changed_configs(&mut db, &mut out)?;
+        assert!(out.is_empty());
+
+        std::fs::write(sysroot.path().join("etc/foo.conf"), "edited\n")?;
+        let mut out = Vec::new();
+        print_changed_configs(&mut db, &mut out)?;
+        assert_eq!(String::from_utf8(out)?, "foo /etc/foo.conf\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_print_owners_matching() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add(
+            "systemd",
+            "247-1",
+            "",
+            &["etc/systemd/system.conf", "usr/bin/systemctl"],
+        );
+        fixture.add("bash", "5.1.004-1", "", &["etc/bash.bashrc"]);
+
+        let mut db = LocalDatabase::with_path(fixture.path());
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        let patterns = ["^/etc/".to_owned(), "^/opt/".to_owned()];
+        assert!(!print_owners_matching(
+            &mut db, &patterns, false, &mut out, &mut err
+        )?);
+        assert_eq!(
+            String::from_utf8(out)?,
+            "/etc/bash.bashrc is owned by bash 5.1.004-1\n\
+             /etc/systemd/system.conf is owned by systemd 247-1\n"
+        );
+        assert_eq!(
+            String::from_utf8(err)?,
+            "error: No package owns a file matching ^/opt/\n"
+        );
+
+        let mut out = Vec::new();
+        let patterns = ["^/etc/".to_owned()];
+        assert!(print_owners_matching(
+            &mut db,
+            &patterns,
+            true,
+            &mut out,
+            &mut Vec::new()
+        )?);
+        assert_eq!(String::from_utf8(out)?, "bash\nsystemd\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_print_search() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add(
+            "bash",
+            "5.1.004-1",
+            "%DESC%\nThe GNU Bourne Again shell\n\n",
+            &[],
+        );
+        fixture.add("zsh", "5.8-1", "%DESC%\nA very advanced shell\n\n", &[]);
+        fixture.add("vim", "8.2-1", "%DESC%\nVi Improved\n\n", &[]);
+        let mut db = LocalDatabase::with_path(fixture.path());
+
+        let mut out = Vec::new();
+        assert!(print_search(
+            &mut db,
+            &["SHELL".to_owned()],
+            false,
+            &mut out
+        )?);
+        assert_eq!(
+            String::from_utf8(out)?,
+            "local/bash 5.1.004-1\n    The GNU Bourne Again shell\n\
+             local/zsh 5.8-1\n    A very advanced shell\n"
+        );
+        let mut out = Vec::new();
+        let args = Args::from_iter(&["pacman-rs", "-Qsq", "shell", "gnu"]);
+        run_query(&args, &mut db, &mut out, &mut Vec::new())?;
+        assert_eq!(String::from_utf8(out)?, "bash\n");
+        assert!(!print_search(
+            &mut db,
+            &["emacs".to_owned()],
+            true,
+            &mut Vec::new()
+        )?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_print_explicit() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("glibc", "2.33-4", "%REASON%\n1\n\n", &[]);
+        fixture.add("bash", "5.1.004-1", "", &[]);
+
+        let mut db = LocalDatabase::with_path(fixture.path());
+        let mut out = Vec::new();
+        print_explicit(&mut db, false, &[], SortKey::Name, false, &mut out)?;
+        assert_eq!(String::from_utf8(out)?, "bash 5.1.004-1\n");
+        let mut out = Vec::new();
+        print_explicit(&mut db, true, &[], SortKey::Name, false, &mut out)?;
+        assert_eq!(String::from_utf8(out)?, "bash\n");
+        let mut out = Vec::new();
+        print_explicit(
+            &mut db,
+            true,
+            &["b*".parse()?],
+            SortKey::Name,
+            false,
+            &mut out,
+        )?;
+        assert!(out.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_print_installed_between() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("old", "1.0-1", "%INSTALLDATE%\n1600000000\n\n", &[]);
+        fixture.add("undated", "1.0-1", "", &[]);
+        fixture.add("new", "2.0-1", "%INSTALLDATE%\n1620000000\n\n", &[]);
+        fixture.add("middle", "1.5-1", "%INSTALLDATE%\n1610000000\n\n", &[]);
+        let mut db = LocalDatabase::with_path(fixture.path());
+        let format = OutputFormat::Text {
+            quiet: false,
+            color: false,
+        };
+
+        let mut out = Vec::new();
+        let start = format::parse_date("2021-01-01")?;
+        print_installed_between(&mut db, Some(start), None, &format, &mut out)?;
+        assert_eq!(String::from_utf8(out)?, "middle 1.5-1\nnew    2.0-1\n");
+        let mut out = Vec::new();
+        print_installed_between(&mut db, None, Some(start), &format, &mut out)?;
+        assert_eq!(String::from_utf8(out)?, "old 1.0-1\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_print_recent() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("vim", "8.2-1", "%INSTALLDATE%\n1615000000\n\n", &[]);
+        fixture.add("bash", "5.1.004-1", "%INSTALLDATE%\n1600000000\n\n", &[]);
+        fixture.add("linux", "5.11.6-1", "", &[]);
+
+        let mut db = LocalDatabase::with_path(fixture.path());
+        let mut out = Vec::new();
+        print_recent(&mut db, 3, &mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "2021-03-06 03:06:40 vim   8.2-1\n\
+             2020-09-13 12:26:40 bash  5.1.004-1\n\
+             unknown             linux 5.11.6-1\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_print_groups() -> Result<()> {
+        let fixture = Fixture::new();
+        for name in ["autoconf", "automake", "binutils", "bison", "fakeroot"].iter() {
+            fixture.add(name, "1.0-1", "%GROUPS%\nbase-devel\n\n", &[]);
+        }
+        fixture.add("xorg-server", "1.20.10-3", "%GROUPS%\nxorg\n\n", &[]);
+        fixture.add("bash", "5.1.004-1", "", &[]);
+
+        let mut db = LocalDatabase::with_path(fixture.path());
+        let mut out = Vec::new();
+        print_groups(&mut db, &[], 40, &mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "base-devel autoconf  automake  binutils\n           bison  fakeroot\n\
+             xorg       xorg-server\n"
+        );
+
+        let mut out = Vec::new();
+        print_groups(&mut db, &["xorg".to_owned()], 40, &mut out)?;
+        assert_eq!(String::from_utf8(out)?, "xorg xorg-server\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,327 @@
+use std::io::Write;
+
+use crate::cache;
+use crate::config::{Config, Repo};
+use crate::database::sync::{self, SyncDatabases};
+use crate::download::RetryPolicy;
+use crate::format::PrintFormat;
+use crate::interface::{self, Args};
+use crate::manifest;
+use crate::resolver::Resolver;
+use crate::transaction::{DependencyChecks, Transaction};
+use crate::Result;
+
+use log::warn;
+use regex::RegexBuilder;
+
+/// Runs `-S`: refreshing the sync databases, cleaning the package cache, applying a manifest,
+/// printing or downloading the packages that would be installed, or searching the repositories.
+pub fn run_sync(args: &Args, config: &Config) -> Result<()> {
+    if args.refresh > 0 {
+        println!(":: Synchronizing package databases...");
+        let refreshed = sync::refresh_databases(
+            &config.repos,
+            &config.architecture,
+            &config.sync_db_path(),
+            &config.gpg_dir(),
+            args.refresh > 1,
+            &RetryPolicy::default(),
+        )?;
+        for (repo, downloaded) in refreshed {
+            if downloaded {
+                println!(" {} downloaded", repo);
+            } else {
+                println!(" {} is up to date", repo);
+            }
+        }
+        if args.targets.is_empty() && args.clean == 0 {
+            return Ok(());
+        }
+    }
+    if args.clean > 0 {
+        let mut local_database = config.local_database()?;
+        local_database.populate_full_database()?;
+        let sync_databases = config.sync_databases()?;
+        for cache_dir in config.cache_dirs.iter() {
+            let removed = cache::clean_cache(
+                &config.sysroot.resolve(cache_dir),
+                &local_database,
+                &sync_databases,
+                args.clean == 1,
+                args.dry_run,
+            )?;
+            for path in removed.iter() {
+                println!("{}", path.display());
+            }
+        }
+        return Ok(());
+    }
+    if let Some(path) = &args.apply_manifest {
+        let manifest = manifest::parse_manifest(&std::fs::read_to_string(path)?)?;
+        let mut local_database = config.local_database()?;
+        let transaction =
+            manifest::plan_manifest(&mut local_database, &config.sync_databases()?, &manifest)?;
+        print!(
+            "{}\n{}",
+            transaction,
+            transaction.size_summary(&local_database)
+        );
+        return Ok(());
+    }
+    if args.print {
+        let transaction = plan_targets(args, config)?;
+        return print_download_uris(
+            &transaction,
+            &config.repos,
+            &config.architecture,
+            args.print_format().as_ref(),
+            &mut std::io::stdout(),
+        );
+    }
+    if args.download_only {
+        let transaction = plan_targets(args, config)?;
+        print!("{}", transaction);
+        if !args.noconfirm && !interface::confirm(":: Proceed with download?") {
+            return Ok(());
+        }
+        let cache_dir = config
+            .cache_dirs
+            .first()
+            .ok_or("No cache directory is configured")?;
+        transaction.download_only(
+            &config.repos,
+            &config.architecture,
+            &config.sysroot.resolve(cache_dir),
+            &config.gpg_dir(),
+            &RetryPolicy::default(),
+        )?;
+        return Ok(());
+    }
+    if args.search {
+        let found = print_search(
+            &config.sync_databases()?,
+            &args.targets,
+            args.repo.as_deref(),
+            args.quiet,
+            &mut std::io::stdout(),
+        )?;
+        if !found {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    super::not_implemented("-S without --print, --downloadonly or --search")
+}
+
+/// Plans the installation of the targets for `-Sp` and `-Sw`, with their optional dependencies if
+/// `--install-optdeps` is given. Packages that are already up to date are dropped with a warning
+/// for `--needed`.
+fn plan_targets(args: &Args, config: &Config) -> Result<Transaction> {
+    let sync_databases = config.sync_databases()?;
+    let targets: Vec<&str> = args.targets.iter().map(|x| x.as_str()).collect();
+    let checks = DependencyChecks::from_nodeps(args.nodeps);
+    let mut local_database = config.local_database()?;
+    let installed = local_database.names()?;
+    let mut transaction = sync::plan_install(
+        &sync_databases,
+        &targets,
+        &config.architecture,
+        checks,
+        args.provider_policy().chooser(&installed),
+    )?;
+    if args.install_optdeps {
+        let resolver = Resolver::new(args.assume_installed.clone());
+        let optdeps = resolver.resolve_optional(
+            &mut local_database,
+            &sync_databases,
+            &transaction.install,
+            args.provider_policy(),
+        )?;
+        transaction.add_dependencies(optdeps);
+    }
+    transaction.flag_upgrades(&mut local_database)?;
+    if args.needed {
+        for package in transaction.skip_reinstalls() {
+            let desc = &package.desc;
+            eprintln!(
+                "warning: {}-{} is up to date -- skipping",
+                desc.name, desc.version
+            );
+        }
+    }
+    Ok(transaction)
+}
+
+/// Prints the URL that each package to be installed would be downloaded from, like `pacman -Sp`,
+/// or fills in `format` for each package if it is given. Packages without a download URL are
+/// skipped with a warning.
+fn print_download_uris(
+    transaction: &Transaction,
+    repos: &[Repo],
+    arch: &str,
+    format: Option<&PrintFormat>,
+    out: &mut impl Write,
+) -> Result<()> {
+    for package in transaction.install.iter() {
+        let uri = match package.download_uri(repos, arch) {
+            Ok(uri) => uri,
+            Err(e) => {
+                warn!("{}", e);
+                continue;
+            }
+        };
+        match format {
+            Some(format) => writeln!(
+                out,
+                "{}",
+                format.render_with_link(&package.desc, &package.repo, &uri)
+            )?,
+            None => writeln!(out, "{}", uri)?,
+        }
+    }
+    Ok(())
+}
+
+/// Prints the packages in the sync databases whose name or description matches every one of
+/// `patterns`, ignoring case, in the same format as `pacman -Ss`. Only the repository `repo` is
+/// searched if it is given. Returns whether any package matched.
+fn print_search(
+    databases: &SyncDatabases,
+    patterns: &[String],
+    repo: Option<&str>,
+    quiet: bool,
+    out: &mut impl Write,
+) -> Result<bool> {
+    let patterns = patterns
+        .iter()
+        .map(|x| RegexBuilder::new(x).case_insensitive(true).build())
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    let matches = databases.search(&patterns, repo)?;
+    for (repo, desc) in matches.iter() {
+        if quiet {
+            writeln!(out, "{}", desc.name)?;
+        } else {
+            writeln!(out, "{}/{} {}", repo, desc.name, desc.version)?;
+            writeln!(out, "    {}", desc.description.as_deref().unwrap_or(""))?;
+        }
+    }
+    Ok(!matches.is_empty())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::SigLevel;
+    use crate::database::sync::SyncDatabase;
+    use crate::test_util::TarBuilder;
+
+    #[test]
+    fn test_print_search() -> Result<()> {
+        let db = |name: &str, package: &str, description: &str| {
+            let desc = format!(
+                "%NAME%\n{}\n\n%VERSION%\n1.0-1\n\n%DESC%\n{}\n\n",
+                package, description
+            );
+            let archive = TarBuilder::new()
+                .file(&format!("{}-1.0-1/desc", package), 0o644, desc.as_bytes())
+                .finish();
+            SyncDatabase::from_archive(name, &archive)
+        };
+        let dbs = SyncDatabases::new(vec![
+            db("core", "bash", "The GNU Bourne Again shell")?,
+            db("extra", "fish", "Smart and user friendly Shell")?,
+        ]);
+
+        let mut out = Vec::new();
+        assert!(print_search(
+            &dbs,
+            &["SHELL".to_owned()],
+            None,
+            false,
+            &mut out
+        )?);
+        assert_eq!(
+            String::from_utf8(out)?,
+            "core/bash 1.0-1\n    The GNU Bourne Again shell\n\
+             extra/fish 1.0-1\n    Smart and user friendly Shell\n"
+        );
+
+        let mut out = Vec::new();
+        assert!(print_search(
+            &dbs,
+            &["shell".to_owned()],
+            Some("core"),
+            false,
+            &mut out
+        )?);
+        assert_eq!(
+            String::from_utf8(out)?,
+            "core/bash 1.0-1\n    The GNU Bourne Again shell\n"
+        );
+
+        let mut out = Vec::new();
+        assert!(!print_search(
+            &dbs,
+            &["fish".to_owned()],
+            Some("core"),
+            true,
+            &mut out
+        )?);
+        assert!(out.is_empty());
+        assert!(print_search(
+            &dbs,
+            &["fish".to_owned()],
+            None,
+            true,
+            &mut out
+        )?);
+        assert_eq!(String::from_utf8(out)?, "fish\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_print_download_uris() -> Result<()> {
+        let archive = TarBuilder::new()
+            .file(
+                "bash-5.1.004-1/desc",
+                0o644,
+                b"%FILENAME%\nbash-5.1.004-1-x86_64.pkg.tar.zst\n\n\
+                  %NAME%\nbash\n\n%VERSION%\n5.1.004-1\n\n",
+            )
+            .file(
+                "nofile-1.0-1/desc",
+                0o644,
+                b"%NAME%\nnofile\n\n%VERSION%\n1.0-1\n\n",
+            )
+            .finish();
+        let dbs = SyncDatabases::new(vec![SyncDatabase::from_archive("core", &archive)?]);
+        let transaction = sync::plan_install(
+            &dbs,
+            &["bash", "nofile"],
+            "x86_64",
+            DependencyChecks::Full,
+            |_, _| 0,
+        )?;
+        let repos = [Repo {
+            name: "core".into(),
+            servers: vec!["https://mirror.example/$repo/os/$arch".into()],
+            sig_level: SigLevel::default(),
+        }];
+
+        let mut out = Vec::new();
+        let format: PrintFormat = "%n %l".parse()?;
+        print_download_uris(&transaction, &repos, "x86_64", Some(&format), &mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "bash https://mirror.example/core/os/x86_64/bash-5.1.004-1-x86_64.pkg.tar.zst\n"
+        );
+
+        let mut out = Vec::new();
+        print_download_uris(&transaction, &repos, "x86_64", None, &mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "https://mirror.example/core/os/x86_64/bash-5.1.004-1-x86_64.pkg.tar.zst\n"
+        );
+        Ok(())
+    }
+}
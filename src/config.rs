@@ -0,0 +1,593 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::database::local::LocalDatabase;
+use crate::database::sync::{self, SyncDatabases};
+use crate::hooks::{self, Hook};
+use crate::interface::Args;
+use crate::sysroot::PathResolver;
+use crate::Result;
+
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/pacman.conf";
+
+/// An environment variable that overrides the database path from the config file, for testing and
+/// scripting. `--dbpath` still takes precedence over it.
+pub const DBPATH_ENV: &str = "PACMAN_RS_DBPATH";
+
+/// The settings from `pacman.conf` that pacman-rs uses.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Config {
+    /// The root directory that packages are installed into.
+    pub root_dir: PathBuf,
+    /// The `DBPath`, if one was given. Otherwise the database is under the root directory, see
+    /// `db_path()`.
+    pub db_path: Option<PathBuf>,
+    pub cache_dirs: Vec<PathBuf>,
+    /// Directories that hooks are read from, after the system hook directory.
+    pub hook_dirs: Vec<PathBuf>,
+    /// The directory containing the keyring that signatures are checked against.
+    pub gpg_dir: PathBuf,
+    /// Packages that should only be removed after an extra confirmation.
+    pub hold_packages: Vec<String>,
+    /// The architecture that packages are installed for, substituted for `$arch` in mirror URLs.
+    pub architecture: String,
+    /// The signature checks for packages and databases, unless a repository overrides them.
+    pub sig_level: SigLevel,
+    /// The sync repositories, in the order they appear in the config file.
+    pub repos: Vec<Repo>,
+    /// Maps the paths above, and the paths of installed files, onto the `--sysroot`.
+    pub sysroot: PathResolver,
+}
+
+/// A sync repository section (e.g. `[core]`) in `pacman.conf`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Repo {
+    pub name: String,
+    /// The mirror URLs of the repository, which may contain the `$repo` and `$arch` variables.
+    pub servers: Vec<String>,
+    /// The signature checks for the repository: the global `SigLevel` with any set in the
+    /// repository's section applied on top.
+    pub sig_level: SigLevel,
+}
+
+/// How strictly a signature is checked, from a `SigLevel` option. Signatures are checked with
+/// `gpgv` against the keyring in the `GPGDir`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SigCheck {
+    /// Signatures aren't downloaded or checked.
+    Never,
+    /// A file without a signature is allowed, but one with an invalid signature isn't.
+    Optional,
+    /// Every file must have a valid signature.
+    Required,
+}
+
+impl FromStr for SigCheck {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "Never" => Ok(Self::Never),
+            "Optional" => Ok(Self::Optional),
+            "Required" => Ok(Self::Required),
+            _ => Err(format!("Invalid SigLevel '{}'", s)),
+        }
+    }
+}
+
+/// The signature checks for packages and sync databases, from the `SigLevel` option in the
+/// `[options]` section or a repository section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SigLevel {
+    pub package: SigCheck,
+    pub database: SigCheck,
+}
+
+/// `Required DatabaseOptional`, as in the `pacman.conf` that pacman ships.
+impl Default for SigLevel {
+    fn default() -> Self {
+        Self {
+            package: SigCheck::Required,
+            database: SigCheck::Optional,
+        }
+    }
+}
+
+impl SigLevel {
+    /// Applies the value of a `SigLevel` option, such as `Required DatabaseOptional`. A bare level
+    /// applies to both packages and databases, and one prefixed with `Package` or `Database` to
+    /// just that kind of file. The trust levels (`TrustedOnly` and `TrustAll`) are accepted, but
+    /// have no effect: any key in the keyring is trusted.
+    pub fn apply(&mut self, value: &str) -> std::result::Result<(), String> {
+        for token in value.split_whitespace() {
+            if let Some(level) = token.strip_prefix("Package") {
+                if !is_trust_level(level) {
+                    self.package = level.parse()?;
+                }
+            } else if let Some(level) = token.strip_prefix("Database") {
+                if !is_trust_level(level) {
+                    self.database = level.parse()?;
+                }
+            } else if !is_trust_level(token) {
+                let level = token.parse()?;
+                self.package = level;
+                self.database = level;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_trust_level(s: &str) -> bool {
+    s == "TrustedOnly" || s == "TrustAll"
+}
+
+impl Repo {
+    /// The mirror URLs of the repository, with `$repo` and `$arch` substituted.
+    pub fn mirrors(&self, arch: &str) -> Vec<String> {
+        self.servers
+            .iter()
+            .map(|x| x.replace("$repo", &self.name).replace("$arch", arch))
+            .collect()
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            root_dir: PathBuf::from("/"),
+            db_path: None,
+            cache_dirs: vec![PathBuf::from("/var/cache/pacman/pkg/")],
+            hook_dirs: vec![PathBuf::from("/etc/pacman.d/hooks/")],
+            gpg_dir: PathBuf::from("/etc/pacman.d/gnupg/"),
+            hold_packages: vec!["pacman".into(), "glibc".into()],
+            architecture: std::env::consts::ARCH.to_owned(),
+            sig_level: SigLevel::default(),
+            repos: Vec::new(),
+            sysroot: PathResolver::default(),
+        }
+    }
+}
+
+impl Config {
+    /// The directory containing the databases: the `DBPath` if one was given, and otherwise
+    /// `var/lib/pacman/` under the root directory, like pacman.
+    pub fn db_path(&self) -> PathBuf {
+        match &self.db_path {
+            Some(db_path) => db_path.clone(),
+            None => self.root_dir.join("var/lib/pacman/"),
+        }
+    }
+
+    /// The directory containing the local database, on disk.
+    pub fn local_db_path(&self) -> PathBuf {
+        self.sysroot.resolve(self.db_path().join("local"))
+    }
+
+    /// Maps the paths of installed files onto the root directory, which is itself under the
+    /// sysroot if there is one.
+    pub fn root_resolver(&self) -> PathResolver {
+        if self.root_dir == Path::new("/") {
+            self.sysroot.clone()
+        } else {
+            PathResolver::new(self.sysroot.resolve(&self.root_dir))
+        }
+    }
+
+    /// Opens the local database, looking up installed files under the root directory. Warns if
+    /// the database is in a format other than the supported one.
+    pub fn local_database(&self) -> Result<LocalDatabase> {
+        let db = LocalDatabase::with_resolver(self.local_db_path(), self.root_resolver());
+        db.check_db_version()?;
+        Ok(db)
+    }
+
+    /// The directory containing the sync databases, on disk.
+    pub fn sync_db_path(&self) -> PathBuf {
+        self.sysroot.resolve(self.db_path().join("sync"))
+    }
+
+    /// The `GPGDir` containing the keyring, on disk.
+    pub fn gpg_dir(&self) -> PathBuf {
+        self.sysroot.resolve(&self.gpg_dir)
+    }
+
+    /// Reads the sync databases of the configured repositories that have been downloaded.
+    pub fn sync_databases(&self) -> Result<SyncDatabases> {
+        sync::read_sync_databases(&self.sync_db_path(), &self.repos)
+    }
+
+    /// Finds and parses the hooks in the system hook directory and the configured `HookDir`s.
+    pub fn hooks(&self) -> Result<Vec<Hook>> {
+        let dirs: Vec<PathBuf> = std::iter::once(Path::new(hooks::SYSTEM_HOOK_DIR))
+            .chain(self.hook_dirs.iter().map(|x| x.as_path()))
+            .map(|x| self.sysroot.resolve(x))
+            .collect();
+        hooks::discover_hooks(&dirs)
+    }
+}
+
+/// Builds the configuration for a run of the program. The config file given with `--config` (or
+/// `/etc/pacman.conf` if it exists) overrides the built-in defaults, and `--root` and `--dbpath`
+/// override the config file. Without a database path, the database is found under the root. The database path can also be overridden by the `PACMAN_RS_DBPATH`
+/// environment variable, which comes between `--dbpath` and the config file. With `--sysroot`, the
+/// config file and any files it includes are read from under the sysroot.
+pub fn load(args: &Args) -> Result<Config> {
+    let dbpath_env = std::env::var_os(DBPATH_ENV)
+        .filter(|x| !x.is_empty())
+        .map(PathBuf::from);
+    load_with_dbpath_env(args, dbpath_env)
+}
+
+/// Like `load`, with the value of the `PACMAN_RS_DBPATH` environment variable given explicitly.
+fn load_with_dbpath_env(args: &Args, dbpath_env: Option<PathBuf>) -> Result<Config> {
+    let sysroot = match &args.sysroot {
+        Some(sysroot) => PathResolver::new(sysroot),
+        None => PathResolver::default(),
+    };
+    let default_config_path = sysroot.resolve(DEFAULT_CONFIG_PATH);
+    let mut config = match &args.config {
+        Some(path) => read_config_from_file(sysroot.resolve(path), &sysroot)?,
+        None if default_config_path.is_file() => {
+            read_config_from_file(default_config_path, &sysroot)?
+        }
+        None => Config::default(),
+    };
+    config.sysroot = sysroot;
+    if let Some(root) = &args.root {
+        config.root_dir = root.clone();
+    }
+    if let Some(dbpath) = args.dbpath.clone().or(dbpath_env) {
+        config.db_path = Some(dbpath);
+    }
+    if let Some(arch) = &args.machine_arch {
+        config.architecture = arch.clone();
+    }
+    Ok(config)
+}
+
+pub fn read_config_from_file<P: AsRef<Path>>(
+    filepath: P,
+    sysroot: &PathResolver,
+) -> Result<Config> {
+    let config = std::fs::read_to_string(filepath)?;
+    parse_config(config.as_str(), sysroot)
+}
+
+fn parse_config(config: &str, sysroot: &PathResolver) -> Result<Config> {
+    let mut ret = Config::default();
+    let mut cache_dirs = Vec::new();
+    let mut hook_dirs = Vec::new();
+    let mut hold_packages = Vec::new();
+    // The SigLevel options of each repository, applied once the global one is known
+    let mut repo_sig_levels = Vec::new();
+    let mut section = None;
+    for line in config.lines() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|x| x.strip_suffix(']')) {
+            if name != "options" {
+                ret.repos.push(Repo {
+                    name: name.to_owned(),
+                    servers: Vec::new(),
+                    sig_level: SigLevel::default(),
+                });
+                repo_sig_levels.push(Vec::new());
+            }
+            section = Some(name.to_owned());
+            continue;
+        }
+        let (key, value) = match line.find('=') {
+            Some(i) => (line[..i].trim(), line[i + 1..].trim()),
+            None => (line, ""),
+        };
+        match section.as_deref() {
+            None => {
+                return Err(format!("Option '{}' found outside of a section", key).into());
+            }
+            Some("options") => match key {
+                "RootDir" => ret.root_dir = PathBuf::from(value),
+                "DBPath" => ret.db_path = Some(PathBuf::from(value)),
+                "CacheDir" => cache_dirs.extend(value.split_whitespace().map(PathBuf::from)),
+                "HookDir" => hook_dirs.extend(value.split_whitespace().map(PathBuf::from)),
+                "GPGDir" => ret.gpg_dir = PathBuf::from(value),
+                "HoldPkg" => hold_packages.extend(value.split_whitespace().map(String::from)),
+                // Only the first architecture is used; "auto" means the machine's architecture
+                "Architecture" => match value.split_whitespace().next() {
+                    Some("auto") | None => {}
+                    Some(arch) => ret.architecture = arch.to_owned(),
+                },
+                "SigLevel" => ret.sig_level.apply(value)?,
+                _ => {}
+            },
+            Some(_) => {
+                let repo = ret.repos.last_mut().unwrap();
+                match key {
+                    "Server" => repo.servers.push(value.to_owned()),
+                    "Include" => repo
+                        .servers
+                        .extend(read_mirrorlist(&sysroot.resolve(value))?),
+                    "SigLevel" => repo_sig_levels.last_mut().unwrap().push(value),
+                    _ => {}
+                }
+            }
+        }
+    }
+    if !cache_dirs.is_empty() {
+        ret.cache_dirs = cache_dirs;
+    }
+    if !hook_dirs.is_empty() {
+        ret.hook_dirs = hook_dirs;
+    }
+    if !hold_packages.is_empty() {
+        ret.hold_packages = hold_packages;
+    }
+    for (repo, sig_levels) in ret.repos.iter_mut().zip(repo_sig_levels) {
+        repo.sig_level = ret.sig_level;
+        for value in sig_levels {
+            repo.sig_level.apply(value)?;
+        }
+    }
+    Ok(ret)
+}
+
+/// Reads the `Server` lines from a file included from a repo section, such as
+/// `/etc/pacman.d/mirrorlist`.
+fn read_mirrorlist(filepath: &Path) -> Result<Vec<String>> {
+    let mirrorlist = std::fs::read_to_string(filepath).map_err(|e| {
+        format!(
+            "Could not read included file '{}': {}",
+            filepath.display(),
+            e
+        )
+    })?;
+    Ok(mirrorlist
+        .lines()
+        .map(|line| line.split('#').next().unwrap().trim())
+        .filter_map(|line| {
+            let i = line.find('=')?;
+            if line[..i].trim() == "Server" {
+                Some(line[i + 1..].trim().to_owned())
+            } else {
+                None
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Result;
+    use structopt::StructOpt;
+
+    #[test]
+    fn test_config_flag() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mirrorlist = dir.path().join("mirrorlist");
+        std::fs::write(
+            &mirrorlist,
+            "## Worldwide\n#Server = https://commented.out/$repo/os/$arch\nServer = https://geo.mirror.pkgbuild.com/$repo/os/$arch\n",
+        )?;
+        let config_path = dir.path().join("pacman.conf");
+        std::fs::write(
+            &config_path,
+            format!(
+                "# General options\n[options]\nRootDir = /mnt\nDBPath = /mnt/var/lib/pacman/\nCacheDir = /mnt/cache/ /srv/cache/\nHookDir = /mnt/hooks/\nGPGDir = /mnt/gnupg/\nArchitecture = x86_64\nHoldPkg = linux pacman\nHoldPkg = systemd\nColor\nSigLevel = Required DatabaseOptional\n\n[core]\nInclude = {}\n\n[custom]\nServer = file:///home/custompkgs\n",
+                mirrorlist.display()
+            ),
+        )?;
+        let config_arg = config_path.to_str().unwrap();
+
+        let args = Args::from_iter(&["pacman-rs", "-Q", "--config", config_arg]);
+        let config = load(&args)?;
+        let sig_level = SigLevel {
+            package: SigCheck::Required,
+            database: SigCheck::Optional,
+        };
+        assert_eq!(
+            config,
+            Config {
+                root_dir: PathBuf::from("/mnt"),
+                db_path: Some(PathBuf::from("/mnt/var/lib/pacman/")),
+                cache_dirs: vec![PathBuf::from("/mnt/cache/"), PathBuf::from("/srv/cache/")],
+                hook_dirs: vec![PathBuf::from("/mnt/hooks/")],
+                gpg_dir: PathBuf::from("/mnt/gnupg/"),
+                hold_packages: vec!["linux".into(), "pacman".into(), "systemd".into()],
+                architecture: "x86_64".into(),
+                sig_level,
+                repos: vec![
+                    Repo {
+                        name: "core".into(),
+                        servers: vec!["https://geo.mirror.pkgbuild.com/$repo/os/$arch".into()],
+                        sig_level,
+                    },
+                    Repo {
+                        name: "custom".into(),
+                        servers: vec!["file:///home/custompkgs".into()],
+                        sig_level,
+                    },
+                ],
+                sysroot: PathResolver::default(),
+            }
+        );
+        assert_eq!(
+            config.local_db_path(),
+            Path::new("/mnt/var/lib/pacman/local")
+        );
+
+        let args = Args::from_iter(&[
+            "pacman-rs",
+            "-Q",
+            "--config",
+            config_arg,
+            "--dbpath",
+            "/tmp/db",
+            "--root",
+            "/tmp/root",
+            "--machine-arch",
+            "aarch64",
+        ]);
+        let config = load(&args)?;
+        assert_eq!(config.db_path(), Path::new("/tmp/db"));
+        assert_eq!(config.root_dir, Path::new("/tmp/root"));
+        assert_eq!(config.architecture, "aarch64");
+        assert_eq!(config.cache_dirs.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_root() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config_path = dir.path().join("pacman.conf");
+        std::fs::write(&config_path, "[options]\n")?;
+        let config_arg = config_path.to_str().unwrap();
+
+        let args = Args::from_iter(&["pacman-rs", "-Q", "--config", config_arg]);
+        let config = load_with_dbpath_env(&args, None)?;
+        assert_eq!(config.db_path(), Path::new("/var/lib/pacman/"));
+        assert_eq!(config.root_resolver(), PathResolver::default());
+
+        let args = Args::from_iter(&["pacman-rs", "-Q", "--config", config_arg, "--root", "/mnt"]);
+        let config = load_with_dbpath_env(&args, None)?;
+        assert_eq!(config.db_path(), Path::new("/mnt/var/lib/pacman"));
+        assert_eq!(
+            config.local_db_path(),
+            Path::new("/mnt/var/lib/pacman/local")
+        );
+        assert_eq!(
+            config.root_resolver().resolve("/usr/bin/bash"),
+            Path::new("/mnt/usr/bin/bash")
+        );
+
+        // A DBPath from the config file isn't moved under the root
+        std::fs::write(&config_path, "[options]\nRootDir = /srv\nDBPath = /db/\n")?;
+        let config = load_with_dbpath_env(&args, None)?;
+        assert_eq!(config.root_dir, Path::new("/mnt"));
+        assert_eq!(config.db_path(), Path::new("/db/"));
+
+        // With --sysroot, the root is under the sysroot too
+        let config = Config {
+            root_dir: PathBuf::from("/mnt"),
+            sysroot: PathResolver::new("/sysroot"),
+            ..Config::default()
+        };
+        assert_eq!(
+            config.local_db_path(),
+            Path::new("/sysroot/mnt/var/lib/pacman/local")
+        );
+        assert_eq!(
+            config.root_resolver().resolve("/usr/bin/bash"),
+            Path::new("/sysroot/mnt/usr/bin/bash")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_sysroot() -> Result<()> {
+        let sysroot = tempfile::tempdir()?;
+        std::fs::create_dir_all(sysroot.path().join("etc/pacman.d"))?;
+        std::fs::write(
+            sysroot.path().join("etc/pacman.d/mirrorlist"),
+            "Server = https://mirror.example/$repo/os/$arch\n",
+        )?;
+        std::fs::write(
+            sysroot.path().join("etc/pacman.conf"),
+            "[options]\nDBPath = /srv/pacman/\n[core]\nInclude = /etc/pacman.d/mirrorlist\n",
+        )?;
+        let sysroot_arg = sysroot.path().to_str().unwrap();
+
+        let args = Args::from_iter(&["pacman-rs", "-Q", "--sysroot", sysroot_arg]);
+        let config = load(&args)?;
+        assert_eq!(config.db_path(), Path::new("/srv/pacman/"));
+        assert_eq!(
+            config.local_db_path(),
+            sysroot.path().join("srv/pacman/local")
+        );
+        assert_eq!(
+            config.repos[0].servers,
+            ["https://mirror.example/$repo/os/$arch"]
+        );
+        assert_eq!(
+            config.repos[0].mirrors("aarch64"),
+            ["https://mirror.example/core/os/aarch64"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_dbpath_env() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config_path = dir.path().join("pacman.conf");
+        std::fs::write(&config_path, "[options]\nDBPath = /srv/pacman/\n")?;
+        let config_arg = config_path.to_str().unwrap();
+        let env = Some(PathBuf::from("/tmp/env-db"));
+
+        // The environment variable overrides the config file
+        let args = Args::from_iter(&["pacman-rs", "-Q", "--config", config_arg]);
+        let config = load_with_dbpath_env(&args, env.clone())?;
+        assert_eq!(config.db_path(), Path::new("/tmp/env-db"));
+        assert_eq!(config.local_db_path(), Path::new("/tmp/env-db/local"));
+        assert_eq!(
+            load_with_dbpath_env(&args, None)?.db_path(),
+            Path::new("/srv/pacman/")
+        );
+
+        // ...but not --dbpath
+        let args = Args::from_iter(&[
+            "pacman-rs",
+            "-Q",
+            "--config",
+            config_arg,
+            "--dbpath",
+            "/tmp/cli-db",
+        ]);
+        let config = load_with_dbpath_env(&args, env)?;
+        assert_eq!(config.db_path(), Path::new("/tmp/cli-db"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_defaults() -> Result<()> {
+        let config = parse_config(
+            "[options]\nHoldPkg = pacman glibc\nArchitecture = auto\n",
+            &PathResolver::default(),
+        )?;
+        assert_eq!(config, Config::default());
+        assert!(parse_config("DBPath = /foo\n", &PathResolver::default()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sig_level() -> Result<()> {
+        let mut level = SigLevel::default();
+        level.apply("Required TrustedOnly DatabaseNever PackageTrustAll")?;
+        assert_eq!(level.package, SigCheck::Required);
+        assert_eq!(level.database, SigCheck::Never);
+        assert!(level.apply("Sometimes").is_err());
+        assert!(level.apply("PackageTrusted").is_err());
+
+        // Repository sections override the global level, wherever it appears
+        let config = parse_config(
+            "[core]\nSigLevel = PackageNever\n[extra]\n[options]\nSigLevel = Required\n",
+            &PathResolver::default(),
+        )?;
+        assert_eq!(
+            config.repos[0].sig_level,
+            SigLevel {
+                package: SigCheck::Never,
+                database: SigCheck::Required,
+            }
+        );
+        assert_eq!(
+            config.repos[1].sig_level,
+            SigLevel {
+                package: SigCheck::Required,
+                database: SigCheck::Required,
+            }
+        );
+        assert!(parse_config("[core]\nSigLevel = Always\n", &PathResolver::default()).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,57 @@
+use std::io::prelude::*;
+use std::path::Path;
+
+use crate::Result;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Reads a text file from disk that may be gzip or zstd compressed. The compression is detected
+/// from the first few bytes of the file, and files without a known magic number are read as plain
+/// text. The text is normalised with `normalize_text`, ready for parsing.
+pub fn read_to_string<P: AsRef<Path>>(filepath: P) -> Result<String> {
+    let bytes = std::fs::read(filepath)?;
+    Ok(normalize_text(decompress_to_string(&bytes)?))
+}
+
+/// Removes a leading UTF-8 byte order mark from `text`, and converts CRLF line endings to LF, as
+/// found in files written on other platforms.
+pub fn normalize_text(text: String) -> String {
+    let text = match text.strip_prefix('\u{feff}') {
+        Some(rest) => rest.to_owned(),
+        None => text,
+    };
+    if text.contains("\r\n") {
+        text.replace("\r\n", "\n")
+    } else {
+        text
+    }
+}
+
+/// Decompresses `bytes` if they start with a gzip or zstd magic number, and returns them as a
+/// string.
+pub fn decompress_to_string(bytes: &[u8]) -> Result<String> {
+    Ok(String::from_utf8(decompress(bytes)?)?)
+}
+
+/// Decompresses `bytes` if they start with a gzip or zstd magic number. Anything else is returned
+/// unchanged.
+pub fn decompress(bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut ret = Vec::new();
+    decoder(bytes)?.read_to_end(&mut ret)?;
+    Ok(ret)
+}
+
+/// Wraps `reader` in a gzip or zstd decoder if it starts with the matching magic number, so that
+/// archives can be decompressed as they are read rather than all at once. Anything else is read
+/// unchanged.
+pub fn decoder<'a, R: BufRead + 'a>(mut reader: R) -> Result<Box<dyn Read + 'a>> {
+    let magic = reader.fill_buf()?;
+    if magic.starts_with(GZIP_MAGIC) {
+        Ok(Box::new(flate2::bufread::GzDecoder::new(reader)))
+    } else if magic.starts_with(ZSTD_MAGIC) {
+        Ok(Box::new(zstd::stream::read::Decoder::with_buffer(reader)?))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
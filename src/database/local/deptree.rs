@@ -0,0 +1,300 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::database::local::LocalDatabase;
+use crate::dependency::Dependency;
+use crate::Result;
+
+/// A node in a tree of the dependencies of an installed package, as built by
+/// `LocalDatabase::dependency_tree`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct DepNode {
+    /// The name of the installed package, or of the dependency if it is not installed.
+    pub name: String,
+    /// The dependency this package was included for, if it is satisfied through a provision rather
+    /// than by name.
+    pub provides: Option<String>,
+    pub status: NodeStatus,
+    pub children: Vec<DepNode>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum NodeStatus {
+    Installed,
+    /// Nothing installed satisfies the dependency.
+    Missing,
+    /// The package already appears above this node, so its dependencies are not repeated.
+    Cycle,
+}
+
+impl LocalDatabase {
+    /// Builds the tree of dependencies of an installed package, going at most `max_depth` levels
+    /// below the root. If `reverse` is true, the tree instead contains the packages that depend on
+    /// the root. A package that depends on one of its own ancestors is marked as a cycle rather than
+    /// being followed again.
+    pub fn dependency_tree(
+        &mut self,
+        root: &str,
+        max_depth: Option<usize>,
+        reverse: bool,
+    ) -> Result<DepNode> {
+        self.get(root)?;
+        self.populate_full_database()?;
+        if reverse {
+            self.reverse_dependencies()?;
+        }
+        let mut ancestors = Vec::new();
+        self.build_node(root, None, max_depth, reverse, &mut ancestors)
+    }
+
+    /// Returns the names of every installed package that `roots` depend on, directly or through
+    /// other dependencies, sorted. Dependencies are resolved like `find_satisfier`, so they can be
+    /// satisfied through provisions, and those that nothing installed satisfies are skipped. The
+    /// roots themselves are never included, even if they are part of a cycle.
+    pub fn dependency_closure(&mut self, roots: &[&str]) -> Result<Vec<String>> {
+        for root in roots {
+            self.get(root)?;
+        }
+        self.populate_full_database()?;
+        let mut seen: HashSet<&str> = roots.iter().copied().collect();
+        let mut stack: Vec<&str> = roots.to_vec();
+        let mut ret = Vec::new();
+        while let Some(name) = stack.pop() {
+            for dep in self.db[name].desc.dependencies.iter() {
+                if let Some(satisfier) = self.find_satisfier(&dep.parse()?) {
+                    let satisfier = satisfier.desc.name.as_str();
+                    if seen.insert(satisfier) {
+                        stack.push(satisfier);
+                        ret.push(satisfier.to_owned());
+                    }
+                }
+            }
+        }
+        ret.sort_unstable();
+        Ok(ret)
+    }
+
+    fn build_node(
+        &self,
+        name: &str,
+        provides: Option<String>,
+        depth_left: Option<usize>,
+        reverse: bool,
+        ancestors: &mut Vec<String>,
+    ) -> Result<DepNode> {
+        let mut node = DepNode {
+            name: name.to_owned(),
+            provides,
+            status: NodeStatus::Installed,
+            children: Vec::new(),
+        };
+        if ancestors.iter().any(|x| x == name) {
+            node.status = NodeStatus::Cycle;
+            return Ok(node);
+        }
+        if depth_left == Some(0) {
+            return Ok(node);
+        }
+        let depth_left = depth_left.map(|x| x - 1);
+
+        ancestors.push(name.to_owned());
+        if reverse {
+            let required_by = &self.reverse_dependencies.as_ref().unwrap()[name];
+            for dependent in required_by {
+                let child = self.build_node(dependent, None, depth_left, reverse, ancestors)?;
+                node.children.push(child);
+            }
+        } else {
+            for dep in self.db[name].desc.dependencies.iter() {
+                let dep: Dependency = dep.parse()?;
+                let child = match self.find_satisfier(&dep) {
+                    Some(satisfier) => {
+                        let satisfier = satisfier.desc.name.as_str();
+                        let provides =
+                            Some(dep.display().to_owned()).filter(|_| satisfier != dep.name);
+                        self.build_node(satisfier, provides, depth_left, reverse, ancestors)?
+                    }
+                    None => DepNode {
+                        name: dep.display().to_owned(),
+                        provides: None,
+                        status: NodeStatus::Missing,
+                        children: Vec::new(),
+                    },
+                };
+                node.children.push(child);
+            }
+        }
+        ancestors.pop();
+        Ok(node)
+    }
+}
+
+impl DepNode {
+    fn fmt_children(&self, f: &mut fmt::Formatter<'_>, prefix: &str) -> fmt::Result {
+        for (i, child) in self.children.iter().enumerate() {
+            let last = i == self.children.len() - 1;
+            write!(f, "{}{}", prefix, if last { "└─" } else { "├─" })?;
+            child.fmt_label(f)?;
+            child.fmt_children(f, &format!("{}{}", prefix, if last { "  " } else { "│ " }))?;
+        }
+        Ok(())
+    }
+
+    fn fmt_label(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(provides) = &self.provides {
+            write!(f, " provides {}", provides)?;
+        }
+        match self.status {
+            NodeStatus::Installed => writeln!(f),
+            NodeStatus::Missing => writeln!(f, " (not installed)"),
+            NodeStatus::Cycle => writeln!(f, " (cycle)"),
+        }
+    }
+}
+
+/// Renders the tree in the same style as `pactree`.
+impl fmt::Display for DepNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_label(f)?;
+        self.fmt_children(f, "")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::Fixture;
+    use crate::Result;
+
+    fn fixture() -> Fixture {
+        let fixture = Fixture::new();
+        fixture.add("glibc", "2.33-4", "", &[]);
+        fixture.add("bash", "5.1.004-1", "%DEPENDS%\nreadline\nglibc\n\n", &[]);
+        fixture.add(
+            "readline",
+            "8.1.0-2",
+            "%DEPENDS%\nglibc\nncurses\n\n%PROVIDES%\nlibreadline.so=8-64\n\n",
+            &[],
+        );
+        fixture.add("ncurses", "6.2-1", "%DEPENDS%\nbash\n\n", &[]);
+        fixture.add(
+            "python",
+            "3.9.2-1",
+            "%DEPENDS%\nlibreadline.so=8-64\nmissing\n\n",
+            &[],
+        );
+        fixture
+    }
+
+    #[test]
+    fn test_dependency_closure() -> Result<()> {
+        let fixture = fixture();
+        let mut db = LocalDatabase::with_path(fixture.path());
+
+        // readline is found through the library it provides, and missing is skipped
+        assert_eq!(
+            db.dependency_closure(&["python"])?,
+            ["bash", "glibc", "ncurses", "readline"]
+        );
+        // ncurses depends on bash again, but the cycle stops there
+        assert_eq!(
+            db.dependency_closure(&["bash"])?,
+            ["glibc", "ncurses", "readline"]
+        );
+        assert_eq!(
+            db.dependency_closure(&["ncurses", "glibc"])?,
+            ["bash", "readline"]
+        );
+        assert!(db.dependency_closure(&["glibc"])?.is_empty());
+        assert!(db.dependency_closure(&[])?.is_empty());
+        assert!(db.dependency_closure(&["bash", "nonexistent"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_dependency_tree() -> Result<()> {
+        let fixture = fixture();
+        let mut db = LocalDatabase::with_path(fixture.path());
+
+        let tree = db.dependency_tree("bash", None, false)?;
+        assert_eq!(
+            tree.children[0].children[1].children[0].status,
+            NodeStatus::Cycle
+        );
+        assert_eq!(
+            tree.to_string(),
+            "bash\n\
+             ├─readline\n\
+             │ ├─glibc\n\
+             │ └─ncurses\n\
+             │   └─bash (cycle)\n\
+             └─glibc\n"
+        );
+
+        let tree = db.dependency_tree("python", None, false)?;
+        assert_eq!(
+            tree.to_string(),
+            "python\n\
+             ├─readline provides libreadline.so=8-64\n\
+             │ ├─glibc\n\
+             │ └─ncurses\n\
+             │   └─bash\n\
+             │     ├─readline (cycle)\n\
+             │     └─glibc\n\
+             └─missing (not installed)\n"
+        );
+
+        let tree = db.dependency_tree("python", Some(1), false)?;
+        assert_eq!(
+            tree.to_string(),
+            "python\n\
+             ├─readline provides libreadline.so=8-64\n\
+             └─missing (not installed)\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_reverse_dependency_tree() -> Result<()> {
+        let fixture = fixture();
+        let mut db = LocalDatabase::with_path(fixture.path());
+
+        let tree = db.dependency_tree("glibc", Some(2), true)?;
+        assert_eq!(
+            tree.to_string(),
+            "glibc\n\
+             ├─bash\n\
+             │ └─ncurses\n\
+             └─readline\n  \
+               ├─bash\n  \
+               └─python\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_dependency_tree_sonames() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add(
+            "systemd-libs",
+            "248-5",
+            "%PROVIDES%\nlibsystemd.so=0-64\n\n",
+            &[],
+        );
+        fixture.add(
+            "dbus",
+            "1.12.20-1",
+            "%DEPENDS%\nlibsystemd.so=0-64\nlibexpat.so=1-64\n\n",
+            &[],
+        );
+        let mut db = LocalDatabase::with_path(fixture.path());
+        assert_eq!(
+            db.dependency_tree("dbus", None, false)?.to_string(),
+            "dbus\n\
+             ├─systemd-libs provides libsystemd.so=0-64\n\
+             └─libexpat.so=1-64 (not installed)\n"
+        );
+        Ok(())
+    }
+}
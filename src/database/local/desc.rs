@@ -1,5 +1,6 @@
 use std::path::Path;
 
+use crate::database::compression;
 use crate::Result;
 
 use lazy_static::lazy_static;
@@ -15,7 +16,7 @@ lazy_static! {
 
 /// Represents the data from the `desc` file of a local database entry. This contains information
 /// about the package itself, not the files it owns.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct PackageDescription {
     pub name: String,
     pub version: String,
@@ -36,14 +37,53 @@ pub struct PackageDescription {
     pub provides: Vec<String>,
     pub groups: Vec<String>,
     pub conflicts: Vec<String>,
+    /// The file name of the package archive. This is only recorded in sync databases.
+    pub filename: Option<String>,
+    /// The size of the package archive, in bytes. This is only recorded in sync databases.
+    pub download_size: Option<u64>,
+    /// The sha256 checksum of the package archive. This is only recorded in sync databases.
+    pub sha256sum: Option<String>,
 }
 
+impl PackageDescription {
+    /// Whether the package was installed explicitly, rather than as a dependency of another
+    /// package (`%REASON%` 1).
+    pub fn is_explicit(&self) -> bool {
+        self.reason != Some(1)
+    }
+
+    /// Whether the package can be installed on a machine with the architecture `machine`, as
+    /// given by `std::env::consts::ARCH`. Packages for `any` architecture, and packages that
+    /// don't record one, are compatible with every machine.
+    pub fn is_compatible_with(&self, machine: &str) -> bool {
+        match &self.arch {
+            None | Some(Arch::Any) => true,
+            Some(arch) => arch.as_str() == machine,
+        }
+    }
+
+    /// Whether the name or description of the package matches every one of `patterns`, as
+    /// searched for by `-Ss` and `-Qs`.
+    pub fn matches_all(&self, patterns: &[Regex]) -> bool {
+        patterns.iter().all(|pattern| {
+            pattern.is_match(&self.name)
+                || self
+                    .description
+                    .as_deref()
+                    .is_some_and(|x| pattern.is_match(x))
+        })
+    }
+}
+
+/// Reads a `desc` file from disk and parses it. The file may be gzip or zstd compressed, as
+/// happens with some tooling, in which case it is decompressed first.
 pub fn read_desc_from_file<P: AsRef<Path>>(filepath: P) -> Result<PackageDescription> {
-    let desc = std::fs::read_to_string(filepath)?;
+    let desc = compression::read_to_string(filepath)?;
     parse_desc(desc.as_str())
 }
 
-fn parse_desc(desc: &str) -> Result<PackageDescription> {
+/// Parses the contents of a `desc` file, from either the local database or a sync database.
+pub fn parse_desc(desc: &str) -> Result<PackageDescription> {
     let mut name = None;
     let mut version = None;
     let mut pkgbase = None;
@@ -63,6 +103,9 @@ fn parse_desc(desc: &str) -> Result<PackageDescription> {
     let mut provides = None;
     let mut groups = None;
     let mut conflicts = None;
+    let mut filename = None;
+    let mut download_size = None;
+    let mut sha256sum = None;
     for captures in SPLITTING_REGEX.captures_iter(desc) {
         match &captures[1] {
             "NAME" => {
@@ -81,16 +124,10 @@ fn parse_desc(desc: &str) -> Result<PackageDescription> {
                 url = captures.get(2).map(|x| x.as_str().trim().to_owned());
             }
             "ARCH" => {
-                let tmp = captures.get(2).map(|x| match x.as_str().trim() {
-                    "any" => Ok(Arch::Any),
-                    "x86_64" => Ok(Arch::x86_64),
-                    x => Err(format!("Unexpected architecture: '{}'", x)),
-                });
-                if let Some(Err(e)) = tmp {
-                    return Err(e.into());
-                } else {
-                    arch = tmp.map(|x| x.unwrap());
-                }
+                arch = captures
+                    .get(2)
+                    .map(|x| x.as_str().trim().parse())
+                    .transpose()?;
             }
             "BUILDDATE" => {
                 build_date = captures.get(2).and_then(|x| x.as_str().trim().parse().ok());
@@ -99,19 +136,23 @@ fn parse_desc(desc: &str) -> Result<PackageDescription> {
                 install_date = captures.get(2).and_then(|x| x.as_str().trim().parse().ok());
             }
             "PACKAGER" => {
-                packager = captures.get(2).and_then(|x| {
-                    let x = x.as_str().trim();
-                    if x == "Unknown packager" {
-                        return None;
-                    }
-                    let name = x[..x.find('<').map(|x| x - 1).unwrap_or(x.len())]
-                        .trim()
-                        .to_owned();
-                    let email = EMAIL_REGEX.find(x).map(|x| x.as_str().to_owned());
-                    Some(Packager { name, email })
-                });
+                packager = captures
+                    .get(2)
+                    .and_then(|x| Packager::parse(x.as_str().trim()));
+            }
+            "FILENAME" => {
+                filename = captures.get(2).map(|x| x.as_str().trim().to_owned());
+            }
+            "CSIZE" => {
+                download_size = captures.get(2).and_then(|x| x.as_str().trim().parse().ok());
             }
-            "SIZE" => {
+            "SHA256SUM" => {
+                sha256sum = captures.get(2).map(|x| x.as_str().trim().to_owned());
+            }
+            // Only found in sync databases, and not used: md5 is superseded by sha256, and
+            // signatures and build dependencies aren't checked
+            "MD5SUM" | "PGPSIG" | "MAKEDEPENDS" | "CHECKDEPENDS" => {}
+            "SIZE" | "ISIZE" => {
                 size = captures.get(2).and_then(|x| x.as_str().trim().parse().ok());
             }
             "REASON" => {
@@ -162,13 +203,7 @@ fn parse_desc(desc: &str) -> Result<PackageDescription> {
                     x.as_str()
                         .trim()
                         .split('\n')
-                        .map(|line| {
-                            let mut it = line.split(':');
-                            OptionalDependency {
-                                package: it.next().map(|x| x.trim().to_owned()).unwrap(),
-                                reason: it.next().map(|x| x.trim().to_owned()),
-                            }
-                        })
+                        .map(OptionalDependency::parse)
                         .collect()
                 });
             }
@@ -230,34 +265,88 @@ fn parse_desc(desc: &str) -> Result<PackageDescription> {
         provides: provides.unwrap_or_else(Vec::new),
         groups: groups.unwrap_or_else(Vec::new),
         conflicts: conflicts.unwrap_or_else(Vec::new),
+        filename,
+        download_size,
+        sha256sum,
     })
 }
 
 #[allow(non_camel_case_types)]
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Arch {
     Any,
     x86_64,
+    /// Any other architecture, such as `aarch64` from Arch Linux ARM.
+    Other(String),
+}
+
+impl Arch {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Arch::Any => "any",
+            Arch::x86_64 => "x86_64",
+            Arch::Other(x) => x,
+        }
+    }
+}
+
+impl std::str::FromStr for Arch {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "any" => Ok(Arch::Any),
+            "x86_64" => Ok(Arch::x86_64),
+            "" => Err("Empty architecture".to_owned()),
+            x => Ok(Arch::Other(x.to_owned())),
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Validation {
     None,
     Pgp,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Packager {
     pub name: String,
     pub email: Option<String>,
 }
 
-#[derive(Debug)]
+impl Packager {
+    /// Parses a packager of the form `Name <email>`. Packages built without a packager set use
+    /// `Unknown packager`, which is treated as no packager.
+    pub fn parse(s: &str) -> Option<Self> {
+        if s == "Unknown packager" {
+            return None;
+        }
+        let name = s[..s.find('<').map(|x| x - 1).unwrap_or(s.len())]
+            .trim()
+            .to_owned();
+        let email = EMAIL_REGEX.find(s).map(|x| x.as_str().to_owned());
+        Some(Packager { name, email })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct OptionalDependency {
     pub package: String,
     pub reason: Option<String>,
 }
 
+impl OptionalDependency {
+    /// Parses an optional dependency of the form `package: reason`, where the reason is optional.
+    pub fn parse(s: &str) -> Self {
+        let mut it = s.split(':');
+        OptionalDependency {
+            package: it.next().map(|x| x.trim().to_owned()).unwrap(),
+            reason: it.next().map(|x| x.trim().to_owned()),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::Result;
@@ -268,4 +357,68 @@ mod test {
         println!("{:#?}", v);
         Ok(())
     }
+
+    #[test]
+    fn test_read_compressed_desc() -> Result<()> {
+        let desc = "%NAME%\nbash\n\n%VERSION%\n5.1.004-1\n\n%DEPENDS%\nreadline\nglibc\n\n";
+        let dir = tempfile::tempdir()?;
+        let plain = dir.path().join("plain");
+        let gzipped = dir.path().join("gzipped");
+        let zstd = dir.path().join("zstd");
+        std::fs::write(&plain, desc)?;
+        std::fs::write(&gzipped, crate::test_util::gzip(desc.as_bytes()))?;
+        std::fs::write(&zstd, zstd::encode_all(desc.as_bytes(), 0)?)?;
+
+        let expected = super::read_desc_from_file(&plain)?;
+        assert_eq!(expected.dependencies, ["readline", "glibc"]);
+        assert_eq!(super::read_desc_from_file(&gzipped)?, expected);
+        assert_eq!(super::read_desc_from_file(&zstd)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_desc_bom_and_crlf() -> Result<()> {
+        let desc = "%NAME%\nbash\n\n%VERSION%\n5.1.004-1\n\n%DEPENDS%\nreadline\nglibc\n\n";
+        let dir = tempfile::tempdir()?;
+        let crlf = dir.path().join("crlf");
+        let bom = dir.path().join("bom");
+        std::fs::write(&crlf, desc.replace('\n', "\r\n"))?;
+        std::fs::write(&bom, format!("\u{feff}{}", desc.replace('\n', "\r\n")))?;
+
+        let expected = super::parse_desc(desc)?;
+        assert_eq!(super::read_desc_from_file(&crlf)?, expected);
+        assert_eq!(super::read_desc_from_file(&bom)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_desc_equality() -> Result<()> {
+        let desc = "%NAME%\nvim\n\n%VERSION%\n8.2-1\n\n%ARCH%\nx86_64\n\n\
+                    %PACKAGER%\nJohn Doe <jd@example.org>\n\n\
+                    %OPTDEPENDS%\npython: for python plugins\n\n";
+        let a = super::parse_desc(desc)?;
+        let mut b = a.clone();
+        assert_eq!(a, b);
+        b.install_date = Some(1615000000);
+        assert_ne!(a, b);
+        let unique: std::collections::HashSet<_> = vec![a.clone(), a, b].into_iter().collect();
+        assert_eq!(unique.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_compatible_with() -> Result<()> {
+        let desc = |arch: &str| {
+            super::parse_desc(&format!(
+                "%NAME%\nfoo\n\n%VERSION%\n1.0-1\n\n%ARCH%\n{}\n\n",
+                arch
+            ))
+        };
+        assert!(desc("x86_64")?.is_compatible_with("x86_64"));
+        assert!(!desc("x86_64")?.is_compatible_with("aarch64"));
+        assert!(desc("aarch64")?.is_compatible_with("aarch64"));
+        assert!(desc("any")?.is_compatible_with("aarch64"));
+        assert!(desc("any")?.is_compatible_with("x86_64"));
+        Ok(())
+    }
 }
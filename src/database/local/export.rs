@@ -0,0 +1,154 @@
+use std::convert::TryFrom;
+use std::path::Path;
+
+use rusqlite::{params, Connection};
+
+use crate::database::local::LocalDatabase;
+use crate::dependency::Dependency;
+use crate::Result;
+
+/// The tables written by `LocalDatabase::export_sqlite`. Every row of `files` and `deps` refers to
+/// a row of `packages` by name.
+const SCHEMA: &str = "\
+CREATE TABLE packages (
+    name TEXT PRIMARY KEY,
+    version TEXT NOT NULL,
+    description TEXT,
+    size INTEGER,
+    install_date INTEGER,
+    build_date INTEGER,
+    explicit INTEGER NOT NULL
+);
+CREATE TABLE files (
+    package TEXT NOT NULL REFERENCES packages (name),
+    path TEXT NOT NULL,
+    type TEXT NOT NULL
+);
+CREATE TABLE deps (
+    package TEXT NOT NULL REFERENCES packages (name),
+    name TEXT NOT NULL,
+    version TEXT
+);
+CREATE INDEX files_path ON files (path);
+";
+
+impl LocalDatabase {
+    /// Exports every installed package into a new SQLite database at `path`, with the tables
+    /// `packages`, `files` and `deps`. A file's type is `file`, `dir` or `link`, and the `version`
+    /// of a dependency is its constraint, like `>=2.33`, if it has one.
+    pub fn export_sqlite(&mut self, path: &Path) -> Result<()> {
+        if path.exists() {
+            return Err(format!("'{}' already exists", path.display()).into());
+        }
+        self.populate_full_database()?;
+        let mut connection = Connection::open(path)?;
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(SCHEMA)?;
+        {
+            let mut insert_package =
+                transaction.prepare("INSERT INTO packages VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")?;
+            let mut insert_file = transaction.prepare("INSERT INTO files VALUES (?1, ?2, ?3)")?;
+            let mut insert_dep = transaction.prepare("INSERT INTO deps VALUES (?1, ?2, ?3)")?;
+            for entry in self.db.values() {
+                let desc = &entry.desc;
+                insert_package.execute(params![
+                    desc.name,
+                    desc.version,
+                    desc.description,
+                    integer(desc.size)?,
+                    integer(desc.install_date)?,
+                    integer(desc.build_date)?,
+                    desc.is_explicit(),
+                ])?;
+                for file in entry.entries()?.filter(|x| !x.is_package_metadata()) {
+                    let filetype = if file.is_dir() {
+                        "dir"
+                    } else if file.is_symlink() {
+                        "link"
+                    } else {
+                        "file"
+                    };
+                    let path = file.path().to_string_lossy();
+                    insert_file.execute(params![desc.name, path, filetype])?;
+                }
+                for dep in desc.dependencies.iter() {
+                    let dep: Dependency = dep.parse()?;
+                    let version = dep
+                        .constraint
+                        .map(|(comparison, version)| format!("{}{}", comparison.as_str(), version));
+                    insert_dep.execute(params![desc.name, dep.name, version])?;
+                }
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+/// Converts `n` to the signed integers that SQLite stores.
+fn integer(n: Option<u64>) -> Result<Option<i64>> {
+    Ok(n.map(i64::try_from).transpose()?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::Fixture;
+
+    #[test]
+    fn test_export_sqlite() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add(
+            "bash",
+            "5.1.004-1",
+            "%DESC%\nThe GNU Bourne Again shell\n\n%SIZE%\n8192\n\n\
+             %DEPENDS%\nreadline>=7.0\nglibc\n\n",
+            &[".PKGINFO", "usr/", "usr/bin/", "usr/bin/bash"],
+        );
+        fixture.add(
+            "readline",
+            "8.1-1",
+            "%DESC%\nGNU's readline library\n\n%REASON%\n1\n\n",
+            &["usr/", "usr/lib/", "usr/lib/libreadline.so"],
+        );
+        let mut db = LocalDatabase::with_path(fixture.path());
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("packages.db");
+        db.export_sqlite(&path)?;
+        assert!(db.export_sqlite(&path).is_err());
+
+        let connection = Connection::open(&path)?;
+        let count = |table: &str| -> Result<i64> {
+            let sql = format!("SELECT count(*) FROM {}", table);
+            Ok(connection.query_row(&sql, [], |row| row.get(0))?)
+        };
+        assert_eq!(count("packages")?, 2);
+        assert_eq!(count("files")?, 6);
+        assert_eq!(count("deps")?, 2);
+
+        let owner: (String, String) = connection.query_row(
+            "SELECT packages.name, packages.version FROM files \
+             JOIN packages ON files.package = packages.name \
+             WHERE files.path = ?1",
+            ["/usr/lib/libreadline.so"],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        assert_eq!(owner, ("readline".to_owned(), "8.1-1".to_owned()));
+        let bash: (Option<String>, i64, bool) = connection.query_row(
+            "SELECT description, size, explicit FROM packages WHERE name = 'bash'",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?;
+        assert_eq!(
+            bash,
+            (Some("The GNU Bourne Again shell".to_owned()), 8192, true)
+        );
+        let version: Option<String> = connection.query_row(
+            "SELECT version FROM deps WHERE package = 'bash' AND name = 'readline'",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(version.as_deref(), Some(">=7.0"));
+        Ok(())
+    }
+}
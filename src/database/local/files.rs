@@ -1,28 +1,100 @@
 use std::path::{Path, PathBuf};
 
+use crate::database::local::LocalDatabaseEntry;
+use crate::sysroot::PathResolver;
 use crate::Result;
 
 /// Reads a `files` file on disk, and returns a Vec of PathBufs to the files owned by the package.
-/// This only works on packages that have been installed. This may change depending on how this
-/// ends up being used.
 pub fn read_files_from_file<P: AsRef<Path>>(filepath: P) -> Result<Vec<PathBuf>> {
-    let file = std::fs::read_to_string(filepath)?;
-    read_files(file.as_str().trim())
+    read_files(std::fs::read_to_string(filepath)?.trim())
 }
 
-fn read_files(files: &str) -> Result<Vec<PathBuf>> {
-    let mut ret = Vec::new();
-    for line in files.trim().split('\n').skip(1) {
-        let path = PathBuf::from("/").join(line);
-        if path.is_file() {
-            ret.push(path)
+/// Parses the `%FILES%` section of a `files` file. The paths are stored relative to root, and are
+/// returned as absolute paths, whether or not they currently exist. Any other sections, such as
+/// `%BACKUP%`, are ignored.
+pub fn read_files(files: &str) -> Result<Vec<PathBuf>> {
+    Ok(section_lines(files, "%FILES%")
+        .map(|x| PathBuf::from("/").join(x))
+        .collect())
+}
+
+/// Reads the `%BACKUP%` section of a `files` file on disk, and returns the absolute paths of the
+/// package's backup files.
+pub fn read_backup_from_file<P: AsRef<Path>>(filepath: P) -> Result<Vec<PathBuf>> {
+    read_backup(&std::fs::read_to_string(filepath)?)
+}
+
+/// Parses the `%BACKUP%` section of a `files` file. Each line is a path relative to root, followed
+/// by a tab and the md5sum the file had when it was installed.
+pub fn read_backup(files: &str) -> Result<Vec<PathBuf>> {
+    section_lines(files, "%BACKUP%")
+        .map(|x| match x.split_once('\t') {
+            Some((path, _)) => Ok(PathBuf::from("/").join(path)),
+            None => Err(format!("Expected a tab separated backup entry, found '{}'", x).into()),
+        })
+        .collect()
+}
+
+/// Returns the non-empty lines in the section of `files` that starts with the line `header`.
+fn section_lines<'a>(files: &'a str, header: &'a str) -> impl Iterator<Item = &'a str> {
+    let mut in_section = false;
+    files.split('\n').map(|x| x.trim()).filter(move |line| {
+        if line.starts_with('%') && line.ends_with('%') {
+            in_section = *line == header;
+            false
+        } else {
+            in_section && !line.is_empty()
         }
+    })
+}
+
+/// Returns the paths in `files` that currently exist on the filesystem, looked up under the
+/// sysroot of `resolver`. Symlinks are checked themselves, rather than what they point to, so a
+/// dangling symlink still counts as existing.
+pub fn existing_files<'a>(files: &'a [PathBuf], resolver: &PathResolver) -> Vec<&'a Path> {
+    files
+        .iter()
+        .map(|x| x.as_path())
+        .filter(|x| resolver.resolve(x).symlink_metadata().is_ok())
+        .collect()
+}
+
+/// Generates the contents of the `files` file for a package, from the paths in its `mtree`. Like
+/// pacman, paths are written relative to root, directories have a trailing `/`, and the package
+/// metadata files at the root of the archive (`.PKGINFO`, `.BUILDINFO`, ...) are left out.
+pub fn write_files(entry: &LocalDatabaseEntry) -> Result<String> {
+    let mut paths: Vec<_> = entry
+        .mtree()?
+        .iter()
+        .filter_map(|x| {
+            let path = x.filepath.strip_prefix('/').unwrap_or(&x.filepath);
+            if path.is_empty() || x.is_package_metadata() {
+                None
+            } else if x.is_dir() {
+                Some(format!("{}/", path))
+            } else {
+                Some(path.to_owned())
+            }
+        })
+        .collect();
+    paths.sort();
+
+    let mut ret = String::from("%FILES%\n");
+    for path in paths {
+        ret.push_str(&path);
+        ret.push('\n');
     }
+    ret.push('\n');
     Ok(ret)
 }
 
 #[cfg(test)]
 mod test {
+    use std::path::{Path, PathBuf};
+
+    use crate::database::local::LocalDatabaseEntry;
+    use crate::sysroot::PathResolver;
+    use crate::test_util::Fixture;
     use crate::Result;
 
     #[test]
@@ -31,4 +103,97 @@ mod test {
         println!("{:#?}", v);
         Ok(())
     }
+
+    #[test]
+    fn test_files_round_trip() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add(
+            "foo",
+            "1.0-1",
+            "",
+            &[
+                ".PKGINFO",
+                "usr/",
+                "usr/bin/",
+                "usr/bin/foo",
+                "usr/share/foo/.keep",
+            ],
+        );
+        let entry = LocalDatabaseEntry::new_from_directory(fixture.path().join("foo-1.0-1"))?;
+        let written = super::write_files(&entry)?;
+        assert_eq!(
+            written,
+            "%FILES%\nusr/\nusr/bin/\nusr/bin/foo\nusr/share/foo/.keep\n\n"
+        );
+
+        let read = super::read_files(&written)?;
+        let expected: Vec<_> = entry
+            .files()?
+            .filter(|x| *x != Path::new("/.PKGINFO"))
+            .collect();
+        assert_eq!(read, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_files_sections() -> Result<()> {
+        let v = super::read_files(
+            "%FILES%\n\
+             etc/\n\
+             etc/foo.conf\n\
+             usr/\n\
+             usr/bin/\n\
+             usr/bin/foo\n\
+             usr/lib/libfoo.so\n\
+             \n\
+             %BACKUP%\n\
+             etc/foo.conf\td41d8cd98f00b204e9800998ecf8427e\n",
+        )?;
+        let expected: Vec<PathBuf> = [
+            "/etc",
+            "/etc/foo.conf",
+            "/usr",
+            "/usr/bin",
+            "/usr/bin/foo",
+            "/usr/lib/libfoo.so",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+        assert_eq!(v, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_existing_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir(dir.path().join("usr"))?;
+        std::fs::write(dir.path().join("usr/foo"), "")?;
+        let files = vec![
+            PathBuf::from("/usr"),
+            PathBuf::from("/usr/foo"),
+            PathBuf::from("/usr/missing"),
+        ];
+        let resolver = PathResolver::new(dir.path());
+        assert_eq!(
+            super::existing_files(&files, &resolver),
+            [&files[0], &files[1]]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_backup() -> Result<()> {
+        let v = super::read_backup(
+            "%FILES%\n\
+             etc/\n\
+             etc/foo.conf\n\
+             \n\
+             %BACKUP%\n\
+             etc/foo.conf\td41d8cd98f00b204e9800998ecf8427e\n",
+        )?;
+        assert_eq!(v, [PathBuf::from("/etc/foo.conf")]);
+        assert!(super::read_backup("%BACKUP%\netc/foo.conf\n").is_err());
+        Ok(())
+    }
 }
@@ -1,18 +1,50 @@
-use std::{collections::HashMap, path::Path};
+use std::{
+    cell::OnceCell,
+    collections::{BTreeMap, HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
 
-use crate::database::local::{desc::PackageDescription, mtree::MTreeEntry};
+use crate::database::local::desc::PackageDescription;
+use crate::database::local::mtree::MTreeEntry;
+use crate::database::local::sort::SortKey;
+use crate::database::local::source::{DatabaseSource, FilesystemSource};
+use crate::database::local::verify::{FileIssue, VerifyLevel};
+use crate::database::sync::SyncDatabases;
+use crate::dependency::Dependency;
+use crate::format;
+use crate::sha256;
+use crate::sysroot::PathResolver;
+use crate::transaction::{DependencyChecks, Transaction};
+use crate::version::vercmp;
 use crate::Result;
 
+use log::warn;
+use regex::{Regex, RegexBuilder};
+
+pub mod deptree;
 pub mod desc;
+#[cfg(feature = "sqlite")]
+pub mod export;
 pub mod files;
 pub mod mtree;
+pub mod selftest;
+pub mod sort;
+pub mod source;
+pub mod verify;
 
 /// Represents an entry in the pacman local database (found in `/var/lib/pacman/local`). This
 /// contains information about a specific installed package, and the files it owns.
 #[derive(Debug)]
 pub struct LocalDatabaseEntry {
     pub desc: PackageDescription,
-    pub mtree: Vec<MTreeEntry>,
+    /// The source the entry was read from, and the entry's name in it. Entries built from strings
+    /// have neither.
+    source: Option<(Arc<dyn DatabaseSource>, String)>,
+    mtree: OnceCell<Vec<MTreeEntry>>,
+    resolver: PathResolver,
 }
 
 impl LocalDatabase {}
@@ -23,67 +55,548 @@ impl LocalDatabaseEntry {
     /// also uses a file called `files`, but the data in there is a also contained in `mtree`, so
     /// it is not required
     pub fn new_from_directory<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let entry = Self::new_desc_only(dir)?;
+        entry.mtree()?;
+
+        Ok(entry)
+    }
+
+    /// Reads an entry in the database from a directory on disk, like `new_from_directory`, but
+    /// only parses the `desc` file. The `mtree` is read the first time it is needed, which saves
+    /// decompressing it for queries that only look at package metadata.
+    pub fn new_desc_only<P: AsRef<Path>>(dir: P) -> Result<Self> {
         let dir = dir.as_ref();
         assert!(dir.is_dir());
-        let desc = desc::read_desc_from_file(dir.join("desc"))?;
-        let mtree = mtree::read_mtree_from_file(dir.join("mtree"))?;
+        let (parent, name) = match (dir.parent(), dir.file_name().and_then(|x| x.to_str())) {
+            (Some(parent), Some(name)) => (parent, name),
+            _ => return Err(format!("'{}' is not a database entry", dir.display()).into()),
+        };
+        Self::from_source(Arc::new(FilesystemSource::new(parent)), name)
+    }
+
+    /// Reads the entry called `name` from `source`. Like `new_desc_only`, only the `desc` is
+    /// parsed straight away.
+    pub fn from_source(source: Arc<dyn DatabaseSource>, name: &str) -> Result<Self> {
+        let desc = source
+            .read_member(name, "desc")?
+            .ok_or_else(|| missing_member(source.as_ref(), name, "desc"))?;
+        let mut entry = Self::from_strings(&desc, None)?;
+        entry.source = Some((source, name.to_owned()));
+        Ok(entry)
+    }
+
+    /// Builds an entry from the (decompressed) contents of its `desc` and `mtree` files, such as
+    /// the members of a database archive that has been read into memory. An entry made without an
+    /// `mtree` has no files, and doesn't correspond to a directory on disk.
+    pub fn from_strings(desc: &str, mtree: Option<&str>) -> Result<Self> {
+        let entry = Self {
+            desc: desc::parse_desc(desc)?,
+            source: None,
+            mtree: OnceCell::new(),
+            resolver: PathResolver::default(),
+        };
+        if let Some(mtree) = mtree {
+            let _ = entry.mtree.set(mtree::read_mtree(mtree)?);
+        }
+        Ok(entry)
+    }
+
+    /// Reads one of the members of the entry from its source. Entries without a source have no
+    /// members.
+    fn read_member(&self, member: &str) -> Result<Option<String>> {
+        match &self.source {
+            Some((source, name)) => source.read_member(name, member),
+            None => Ok(None),
+        }
+    }
 
-        Ok(Self { desc, mtree })
+    /// Returns the parsed `mtree` of the package, reading it from its source if it hasn't been
+    /// already.
+    pub fn mtree(&self) -> Result<&[MTreeEntry]> {
+        if let Some(mtree) = self.mtree.get() {
+            return Ok(mtree);
+        }
+        let mtree = match (&self.source, self.read_member("mtree")?) {
+            (_, Some(mtree)) => mtree::read_mtree(&mtree)?,
+            (Some((source, name)), None) => {
+                return Err(missing_member(source.as_ref(), name, "mtree").into())
+            }
+            (None, None) => Vec::new(),
+        };
+        Ok(self.mtree.get_or_init(|| mtree))
     }
 
     /// Returns an iterator over std::path::Path objects of every file owned by the package.
-    pub fn files(&self) -> impl Iterator<Item = &Path> {
-        self.mtree
-            .iter()
-            .map(|x| std::path::Path::new(x.filepath.as_str()))
+    pub fn files(&self) -> Result<impl Iterator<Item = &Path>> {
+        Ok(self.entries()?.map(|x| x.path()))
+    }
+
+    /// Returns an iterator over the `mtree` entries of every file owned by the package, for when
+    /// more than the path is needed, such as the size, mode or checksums.
+    pub fn entries(&self) -> Result<impl Iterator<Item = &MTreeEntry>> {
+        Ok(self.mtree()?.iter())
+    }
+
+    /// Returns the backup files of the package, as recorded in the `%BACKUP%` section of its
+    /// `files` member. These aren't recorded in the `mtree`. Entries without a `files` member have
+    /// no backup files.
+    pub fn backup_files(&self) -> Result<Vec<PathBuf>> {
+        match self.read_member("files")? {
+            Some(files) => files::read_backup(&files),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Compares the files owned by the package against the disk, only checking that each exists
+    /// and has the type, size and permissions recorded in the `mtree`. This is cheap enough to run
+    /// over large packages, but misses changes to contents that keep the same size.
+    pub fn verify_quick(&self) -> Result<Vec<FileIssue>> {
+        self.verify_with(VerifyLevel::Quick)
+    }
+
+    /// Compares the files owned by the package against the disk like `verify_quick`, and also
+    /// compares the checksums of their contents.
+    pub fn verify(&self) -> Result<Vec<FileIssue>> {
+        self.verify_with(VerifyLevel::Full)
+    }
+
+    /// Like `verify`, but checks files on several threads at once, which is much faster for
+    /// packages with many files. The issues are sorted by path. An error checking any file, or a
+    /// thread panicking, is returned as an error.
+    #[cfg(feature = "parallel")]
+    pub fn verify_parallel(&self) -> Result<Vec<FileIssue>> {
+        use rayon::prelude::*;
+
+        let entries = self.mtree()?;
+        let resolver = &self.resolver;
+        // Errors are converted to strings, so that they can be sent back from the threads
+        let results = std::panic::catch_unwind(|| {
+            entries
+                .par_iter()
+                .map(|entry| {
+                    verify::verify_entry(entry, resolver, VerifyLevel::Full)
+                        .map_err(|e| e.to_string())
+                })
+                .collect::<std::result::Result<Vec<_>, String>>()
+        })
+        .map_err(|_| format!("A thread verifying '{}' panicked", self.desc.name))?;
+        let mut issues: Vec<FileIssue> = results?.into_iter().flatten().collect();
+        issues.sort_by(|a, b| a.path().cmp(b.path()));
+        Ok(issues)
+    }
+
+    fn verify_with(&self, level: VerifyLevel) -> Result<Vec<FileIssue>> {
+        let mut issues = Vec::new();
+        for entry in self.entries()? {
+            if let Some(issue) = verify::verify_entry(entry, &self.resolver, level)? {
+                issues.push(issue);
+            }
+        }
+        Ok(issues)
+    }
+
+    /// Sums the sizes of the package's regular files as they are on disk, to compare against the
+    /// recorded `size`, which can drift from reality as files are edited or replaced. Directories
+    /// and symlinks aren't counted, and files that are missing are skipped with a warning.
+    pub fn installed_size_on_disk(&self) -> Result<u64> {
+        let mut total = 0;
+        for entry in self.entries()? {
+            if entry.is_package_metadata() {
+                continue;
+            }
+            match self.resolver.resolve(entry.path()).symlink_metadata() {
+                Ok(metadata) if metadata.is_file() => total += metadata.len(),
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    warn!("{}: {} is missing", self.desc.name, entry.filepath);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(total)
+    }
+
+    /// Check if the package owns a given file, comparing paths literally.
+    pub fn owns<P: AsRef<Path>>(&self, file: P) -> Result<bool> {
+        let file = file.as_ref();
+        Ok(self.files()?.any(|x| x == file))
+    }
+
+    /// Like `owns`, but symlinks in the directories leading up to both `file` and the package's
+    /// files are resolved first, under the sysroot if there is one. So `/bin/sh` is owned by the
+    /// package that owns `/usr/bin/sh` when `/bin` links to `usr/bin`, and vice versa. This reads
+    /// the filesystem for every file in the package, so it is much slower.
+    pub fn owns_resolved<P: AsRef<Path>>(&self, file: P) -> Result<bool> {
+        let file = file.as_ref();
+        let real_path = self.resolver.real_path(file);
+        Ok(self
+            .files()?
+            .any(|x| x == file || x == real_path || self.resolver.real_path(x) == real_path))
+    }
+}
+
+/// The error for an entry that is missing one of its members. This is an IO error, so that it is
+/// reported as `EntryErrorKind::NotFound`.
+fn missing_member(source: &dyn DatabaseSource, name: &str, member: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!("'{}' has no {}", source.entry_path(name).display(), member),
+    )
+}
+
+/// Totals over every package in a LocalDatabase.
+#[derive(Debug, PartialEq, Eq)]
+pub struct DbSummary {
+    pub packages: usize,
+    /// The sum of the installed sizes of the packages, in bytes.
+    pub total_size: u64,
+}
+
+impl std::fmt::Display for DbSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} packages installed, total size {}",
+            self.packages,
+            format::human_size(self.total_size)
+        )
+    }
+}
+
+/// The differences between the packages in two LocalDatabases, from `LocalDatabase::diff`. Every
+/// list is sorted by name.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DbDiff {
+    /// Packages that are only in the database `diff` was called on.
+    pub only_in_self: Vec<String>,
+    /// Packages that are only in the other database.
+    pub only_in_other: Vec<String>,
+    /// Packages in both databases, but at different versions.
+    pub changed: Vec<VersionChange>,
+}
+
+/// A package that is at different versions in two databases.
+#[derive(Debug, PartialEq, Eq)]
+pub struct VersionChange {
+    pub name: String,
+    pub self_version: String,
+    pub other_version: String,
+}
+
+impl DbDiff {
+    /// Whether the two databases have the same packages at the same versions.
+    pub fn is_empty(&self) -> bool {
+        self.only_in_self.is_empty() && self.only_in_other.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Selects which packages are read by `LocalDatabase::populate`, by matching against the package
+/// name.
+#[derive(Debug)]
+pub enum PopulateFilter {
+    /// The name is exactly this string.
+    Exact(String),
+    /// The name starts with this string.
+    Prefix(String),
+    /// The name contains this string.
+    Substring(String),
+    /// The name matches this regular expression.
+    Regex(String),
+}
+
+impl PopulateFilter {
+    fn to_regex(&self, case_sensitive: bool) -> Result<Regex> {
+        let pattern = match self {
+            PopulateFilter::Exact(x) => format!("^{}$", regex::escape(x)),
+            PopulateFilter::Prefix(x) => format!("^{}", regex::escape(x)),
+            PopulateFilter::Substring(x) => regex::escape(x),
+            PopulateFilter::Regex(x) => x.clone(),
+        };
+        Ok(RegexBuilder::new(pattern.as_str())
+            .case_insensitive(!case_sensitive)
+            .build()?)
+    }
+}
+
+/// An error that stops the whole database from being read, rather than a single entry.
+#[derive(Debug, PartialEq, Eq)]
+pub enum DatabaseError {
+    /// The database directory doesn't exist, which usually means that this isn't an Arch system
+    /// or that `--dbpath` is wrong.
+    DatabaseNotFound(PathBuf),
+}
+
+impl std::fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DatabaseError::DatabaseNotFound(path) => write!(
+                f,
+                "No package database found at '{}'. Is this an Arch Linux system, or does \
+                 --dbpath need to be set?",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DatabaseError {}
+
+/// A database entry that could not be read while populating a LocalDatabase.
+#[derive(Debug)]
+pub struct EntryError {
+    pub path: PathBuf,
+    pub kind: EntryErrorKind,
+    pub error: Box<dyn std::error::Error>,
+}
+
+/// Why a database entry could not be read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryErrorKind {
+    /// A member of the entry is missing, or the entry disappeared while it was being read.
+    NotFound,
+    /// The entry isn't readable by the current user, which is common when not running as root.
+    PermissionDenied,
+    /// Any other IO error, or the entry couldn't be parsed.
+    Other,
+}
+
+impl EntryErrorKind {
+    pub fn of(error: &(dyn std::error::Error + 'static)) -> Self {
+        match error.downcast_ref::<std::io::Error>().map(|x| x.kind()) {
+            Some(std::io::ErrorKind::NotFound) => EntryErrorKind::NotFound,
+            Some(std::io::ErrorKind::PermissionDenied) => EntryErrorKind::PermissionDenied,
+            _ => EntryErrorKind::Other,
+        }
+    }
+}
+
+/// Reads the entry called `name` if its package name matches `matcher`. Only the desc is needed to
+/// check the name, so this avoids decompressing the mtree of packages that aren't going to be kept.
+/// The mtree of packages that are kept is only read straight away if `load_files`.
+fn read_entry_if_matches(
+    source: &Arc<dyn DatabaseSource>,
+    name: &str,
+    matcher: &Regex,
+    resolver: &PathResolver,
+    load_files: bool,
+) -> Result<Option<LocalDatabaseEntry>> {
+    let mut entry = LocalDatabaseEntry::from_source(source.clone(), name)?;
+    if !matcher.is_match(entry.desc.name.as_str()) {
+        return Ok(None);
+    }
+    entry.resolver = resolver.clone();
+    if load_files {
+        entry.mtree()?;
     }
+    Ok(Some(entry))
+}
+
+/// An entry read on another thread, or why it couldn't be.
+type SharedEntryResult = std::result::Result<LocalDatabaseEntry, (EntryErrorKind, String)>;
 
-    /// Check if the package owns a given file.
-    pub fn owns<P: AsRef<Path>>(&self, file: P) -> bool {
-        self.files().any(|x| x == file.as_ref())
+/// Reads the entries in `names`, one at a time in the order given by `next`, which is shared with
+/// the other threads doing the same. Each result is returned with the index of its entry, and
+/// errors are returned as strings, so that they can be sent back from the thread.
+fn read_entries_shared(
+    source: &Arc<dyn DatabaseSource>,
+    names: &[String],
+    next: &AtomicUsize,
+    matcher: &Regex,
+    resolver: &PathResolver,
+    load_files: bool,
+) -> Vec<(usize, SharedEntryResult)> {
+    let mut ret = Vec::new();
+    loop {
+        let i = next.fetch_add(1, Ordering::Relaxed);
+        let name = match names.get(i) {
+            Some(name) => name,
+            None => return ret,
+        };
+        match read_entry_if_matches(source, name, matcher, resolver, load_files) {
+            Ok(Some(entry)) => ret.push((i, Ok(entry))),
+            Ok(None) => {}
+            Err(e) => ret.push((i, Err((EntryErrorKind::of(e.as_ref()), e.to_string())))),
+        }
     }
 }
 
-fn is_valid_local_entry_dir<P: AsRef<Path>>(path: P) -> bool {
-    let path = path.as_ref();
-    path.is_dir() && path.join("desc").is_file() && path.join("mtree").is_file()
+/// Splits the name of an entry directory, `<name>-<pkgver>-<pkgrel>`, into the package name and
+/// its version. Neither pkgver (which may include an epoch, like `2:1.0`) nor pkgrel can contain a
+/// hyphen, so the directory name is split from the right and anything left over is the name.
+fn split_entry_dirname(dir: &str) -> Option<(&str, &str)> {
+    let mut parts = dir.rsplitn(3, '-');
+    let pkgrel = parts.next()?;
+    let pkgver = parts.next()?;
+    let name = parts.next()?;
+    if name.is_empty() || pkgver.is_empty() || pkgrel.is_empty() {
+        return None;
+    }
+    Some((name, &dir[name.len() + 1..]))
 }
 
-/// A lazy representation of the local database. It reads packages from the filesystem when they
-/// are requested.
+/// The newest version of the local database format that this understands, as recorded in its
+/// `ALPM_DB_VERSION` file.
+pub const SUPPORTED_DB_VERSION: u32 = 9;
+
+/// A lazy representation of the local database. It reads packages from its source (usually the
+/// filesystem) when they are requested.
 pub struct LocalDatabase {
-    pub db: HashMap<String, LocalDatabaseEntry>,
-    path: &'static Path, // Path::new("/var/lib/pacman/local")
+    /// The packages that have been read, by name. Iterating over them is always in name order.
+    pub db: BTreeMap<String, LocalDatabaseEntry>,
+    source: Arc<dyn DatabaseSource>,
+    file_index: Option<HashMap<PathBuf, Vec<String>>>,
+    reverse_dependencies: Option<HashMap<String, Vec<String>>>,
+    resolver: PathResolver,
+    /// Whether the mtree of each package is read as soon as the package is, rather than the first
+    /// time it's needed. See `set_load_files`.
+    load_files: bool,
+    /// The sync repository that each package name is taken from, set by `attribute_repos`.
+    repos: HashMap<String, String>,
+    /// The last listing of the source's entries, and when the source had last been modified when
+    /// it was taken. See `entry_names`.
+    listing: Mutex<Option<(SystemTime, Arc<[String]>)>>,
+    /// Whether every entry of the source has been read, so that `populate_full_database` has
+    /// nothing left to do.
+    fully_populated: bool,
+}
+
+impl Default for LocalDatabase {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl LocalDatabase {
     pub fn new() -> Self {
+        Self::with_path("/var/lib/pacman/local")
+    }
+
+    /// Creates a LocalDatabase reading from a directory other than `/var/lib/pacman/local`.
+    pub fn with_path<P: Into<PathBuf>>(path: P) -> Self {
+        Self::with_resolver(path, PathResolver::default())
+    }
+
+    /// Creates a LocalDatabase reading from `path`, whose entries look up the files they own with
+    /// `resolver`. `path` is used as is, and isn't passed through the resolver.
+    pub fn with_resolver<P: Into<PathBuf>>(path: P, resolver: PathResolver) -> Self {
+        Self::with_source(FilesystemSource::new(path), resolver)
+    }
+
+    /// Creates a LocalDatabase reading its entries from `source`, such as a `MemorySource` in
+    /// tests.
+    pub fn with_source<S: DatabaseSource + 'static>(source: S, resolver: PathResolver) -> Self {
         Self {
-            db: HashMap::new(),
-            path: Path::new("/var/lib/pacman/local"),
+            db: BTreeMap::new(),
+            source: Arc::new(source),
+            file_index: None,
+            reverse_dependencies: None,
+            resolver,
+            load_files: false,
+            repos: HashMap::new(),
+            listing: Mutex::new(None),
+            fully_populated: false,
         }
     }
 
+    /// Makes packages read from now on have their `mtree` read along with their `desc`, rather than
+    /// the first time their files are needed. Reading lazily saves decompressing every mtree for
+    /// queries that only look at package metadata, which is most of the time spent reading a
+    /// large database, but a damaged mtree is then only reported when the files are asked for.
+    pub fn set_load_files(&mut self, load_files: bool) {
+        self.load_files = load_files;
+    }
+
+    /// Returns the format version of the database, from its `ALPM_DB_VERSION` file. A database
+    /// without one is assumed to be in the supported format.
+    pub fn db_version(&self) -> Result<u32> {
+        match self.source.read_db_version()? {
+            Some(version) => version
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid local database version '{}'", version.trim()).into()),
+            None => Ok(SUPPORTED_DB_VERSION),
+        }
+    }
+
+    /// Checks that the database is in a format that can be read, warning if it isn't. Entries in
+    /// a newer format may be misread, and older ones need to be upgraded with `pacman-db-upgrade`.
+    pub fn check_db_version(&self) -> Result<()> {
+        let version = self.db_version()?;
+        if version > SUPPORTED_DB_VERSION {
+            warn!(
+                "Local database version {} is newer than the supported version {}, packages may not be read correctly",
+                version, SUPPORTED_DB_VERSION
+            );
+        } else if version < SUPPORTED_DB_VERSION {
+            warn!(
+                "Local database version {} is older than the supported version {}, run pacman-db-upgrade",
+                version, SUPPORTED_DB_VERSION
+            );
+        }
+        Ok(())
+    }
+
+    /// Clears everything computed from the set of packages in the database, after it changes.
+    fn invalidate_caches(&mut self) {
+        self.file_index = None;
+        self.reverse_dependencies = None;
+    }
+
+    /// Returns the `mtree` of every package that has been read, by name, for the queries that look
+    /// at the files of every package. With lazy loading, this is when a damaged `mtree` is first
+    /// read, so as in `populate`, a package whose `mtree` can't be read is skipped with a warning
+    /// and returned as an `EntryError`, rather than failing the whole query.
+    fn package_files(&self) -> (Vec<(&String, &[MTreeEntry])>, Vec<EntryError>) {
+        let mut files = Vec::new();
+        let mut errors = Vec::new();
+        for (name, entry) in self.db.iter() {
+            match entry.mtree() {
+                Ok(mtree) => files.push((name, mtree)),
+                Err(error) => {
+                    let path = match &entry.source {
+                        Some((source, entry_name)) => source.entry_path(entry_name),
+                        None => PathBuf::from(name),
+                    };
+                    let kind = EntryErrorKind::of(error.as_ref());
+                    warn!("Could not read '{}': {}", path.display(), error);
+                    errors.push(EntryError { path, kind, error });
+                }
+            }
+        }
+        (files, errors)
+    }
+
     pub fn package_names(&self) -> impl Iterator<Item = &str> {
-        self.db.iter().map(|(name, _)| name.as_str())
+        self.db.keys().map(|name| name.as_str())
     }
 
+    /// Returns the names of every installed package. Entries that can't be read are skipped with
+    /// a warning.
     pub fn names(&self) -> Result<Vec<String>> {
-        self.path
-            .read_dir()?
-            .filter_map(|x| -> Option<Result<_>> {
-                match x {
-                    Ok(x) => {
-                        if is_valid_local_entry_dir(x.path()) {
-                            Some(desc::read_desc_from_file(x.path().join("desc")).map(|x| x.name))
-                        } else {
-                            None
-                        }
-                    }
-                    Err(e) => Some(Err(e.into())),
-                }
-            })
-            .collect()
+        let mut ret = Vec::new();
+        for name in self.source.entry_names()? {
+            match LocalDatabaseEntry::from_source(self.source.clone(), &name) {
+                Ok(entry) => ret.push(entry.desc.name),
+                Err(e) => warn!(
+                    "Could not read '{}': {}",
+                    self.source.entry_path(&name).display(),
+                    e
+                ),
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Checks whether a package with the given name is installed, without reading its entry. The
+    /// name is taken from the `<name>-<pkgver>-<pkgrel>` entry names, so no files are parsed.
+    pub fn contains(&self, package_name: &str) -> Result<bool> {
+        if self.db.contains_key(package_name) {
+            return Ok(true);
+        }
+        Ok(self
+            .entry_names()?
+            .iter()
+            .any(|x| split_entry_dirname(x).is_some_and(|(name, _)| name == package_name)))
     }
 
     /// Retrieves a LocalDatabaseEntry for the package with a specified name. If this package is
@@ -103,53 +616,642 @@ impl LocalDatabase {
 
     /// Read the contents of a package, by name
     pub fn read_package(&mut self, package_name: &str) -> Result<&LocalDatabaseEntry> {
-        for subdir in self.path.read_dir()? {
-            let subdir = subdir?;
-            if let Some(true) = subdir
-                .file_name()
-                .to_str()
-                .map(|x| x.starts_with(package_name))
-            {
-                // Package found in filesystem
-                let entry = LocalDatabaseEntry::new_from_directory(subdir.path())?;
+        let entry_names = self.entry_names()?;
+        self.read_package_from(&entry_names, package_name)?;
+        Ok(&self.db[package_name])
+    }
+
+    /// Like calling `get` for each of `names`, but the source is only listed once, however many of
+    /// the packages have to be read. Fails if any of them isn't installed.
+    pub fn get_many(&mut self, names: &[&str]) -> Result<Vec<&LocalDatabaseEntry>> {
+        if names.iter().any(|x| !self.db.contains_key(*x)) {
+            let entry_names = self.entry_names()?;
+            for name in names {
+                if !self.db.contains_key(*name) {
+                    self.read_package_from(&entry_names, name)?;
+                }
+            }
+        }
+        let db = &self.db;
+        Ok(names.iter().map(|x| &db[*x]).collect())
+    }
+
+    /// Lists the entries of the source for looking up single packages, reusing the last listing if
+    /// the source hasn't been modified since. A listing taken within a second of the modification
+    /// isn't reused, as filesystems with coarse timestamps could give a later change the same
+    /// mtime.
+    fn entry_names(&self) -> Result<Arc<[String]>> {
+        let modified = self.source.modified();
+        let mut listing = self.listing.lock().unwrap();
+        if let (Some(modified), Some((cached, names))) = (modified, listing.as_ref()) {
+            if modified == *cached {
+                return Ok(names.clone());
+            }
+        }
+        let scanned_at = SystemTime::now();
+        let names: Arc<[String]> = self.source.entry_names()?.into();
+        *listing = match modified {
+            Some(modified) if modified + Duration::from_secs(1) < scanned_at => {
+                Some((modified, names.clone()))
+            }
+            _ => None,
+        };
+        Ok(names)
+    }
+
+    /// Reads the package called `package_name` into the database, finding its entry in
+    /// `entry_names`.
+    fn read_package_from(&mut self, entry_names: &[String], package_name: &str) -> Result<()> {
+        for entry_name in entry_names {
+            if split_entry_dirname(entry_name).is_some_and(|(name, _)| name == package_name) {
+                // Package found in the source
+                let mut entry = LocalDatabaseEntry::from_source(self.source.clone(), entry_name)?;
                 if entry.desc.name.as_str() != package_name {
                     continue;
                 }
+                entry.resolver = self.resolver.clone();
+                if self.load_files {
+                    entry.mtree()?;
+                }
                 self.db.insert(package_name.to_owned(), entry);
+                self.invalidate_caches();
+                return Ok(());
+            }
+        }
+        Err(format!(
+            "Could not find package '{}' in filesystem. Is it installed?",
+            package_name
+        )
+        .into())
+    }
 
-                return self.db.get(package_name).ok_or_else(|| unreachable!());
+    /// Reads every package whose name matches `filter` into the database. Entries that can't be
+    /// read are skipped, and returned so that the caller can report them.
+    pub fn populate(
+        &mut self,
+        filter: &PopulateFilter,
+        case_sensitive: bool,
+    ) -> Result<Vec<EntryError>> {
+        let matcher = filter.to_regex(case_sensitive)?;
+        self.invalidate_caches();
+        let mut errors = Vec::new();
+        for name in self.source.entry_names()? {
+            match read_entry_if_matches(
+                &self.source,
+                &name,
+                &matcher,
+                &self.resolver,
+                self.load_files,
+            ) {
+                Ok(Some(entry)) => {
+                    self.db.insert(entry.desc.name.clone(), entry);
+                }
+                Ok(None) => {}
+                Err(error) => {
+                    let path = self.source.entry_path(&name);
+                    let kind = EntryErrorKind::of(error.as_ref());
+                    warn!("Could not read '{}': {}", path.display(), error);
+                    errors.push(EntryError { path, kind, error });
+                }
             }
         }
-        Err("Could not find package '{}' in filesystem. Is it installed?".into())
+        Ok(errors)
     }
 
-    pub fn populate(&mut self, query: &str) -> Result<()> {
-        self.db.extend(self.path.read_dir()?.filter_map(|x| {
-            if let Ok(x) = x {
-                if is_valid_local_entry_dir(x.path()) {
-                    if let Some(true) = x.file_name().to_str().map(|x| x.contains(query)) {
-                        let x = match LocalDatabaseEntry::new_from_directory(x.path()) {
-                            Ok(x) => x,
-                            Err(_) => return None,
-                        };
-                        return Some((x.desc.name.clone(), x));
-                    }
+    /// Reads every package into the database. This is done once: the many queries that need the
+    /// whole database call this before looking at it, so once it has been read, later calls return
+    /// straight away, keeping the packages and the caches built from them. Entries that couldn't
+    /// be read are only returned by the call that read them. Use `populate` or `populate_ordered`
+    /// to read the source again.
+    pub fn populate_full_database(&mut self) -> Result<Vec<EntryError>> {
+        if self.fully_populated {
+            return Ok(Vec::new());
+        }
+        let errors = self.populate(&PopulateFilter::Substring(String::new()), true)?;
+        self.fully_populated = true;
+        Ok(errors)
+    }
+
+    /// Reads every package into the database like `populate_full_database`, but reads and parses
+    /// the entries on several threads at once. They are still inserted in order of their sorted
+    /// entry names, so which entry is kept when two have the same package name, and the order of
+    /// the returned errors, don't depend on how the threads happened to be scheduled.
+    pub fn populate_ordered(&mut self) -> Result<Vec<EntryError>> {
+        let mut names = self.source.entry_names()?;
+        names.sort_unstable();
+        let matcher = PopulateFilter::Substring(String::new()).to_regex(true)?;
+        self.invalidate_caches();
+
+        let threads = std::thread::available_parallelism().map_or(1, |x| x.get());
+        let next = AtomicUsize::new(0);
+        let (source, resolver, load_files) = (&self.source, &self.resolver, self.load_files);
+        let mut results: Vec<_> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..threads.min(names.len()))
+                .map(|_| {
+                    scope.spawn(|| {
+                        read_entries_shared(source, &names, &next, &matcher, resolver, load_files)
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|x| x.join().unwrap())
+                .collect()
+        });
+        results.sort_unstable_by_key(|(i, _)| *i);
+
+        let mut errors = Vec::new();
+        for (i, result) in results {
+            match result {
+                Ok(entry) => {
+                    self.db.insert(entry.desc.name.clone(), entry);
+                }
+                Err((kind, error)) => {
+                    let path = self.source.entry_path(&names[i]);
+                    warn!("Could not read '{}': {}", path.display(), error);
+                    errors.push(EntryError {
+                        path,
+                        kind,
+                        error: error.into(),
+                    });
                 }
             }
-            None
-        }));
+        }
+        self.fully_populated = true;
+        Ok(errors)
+    }
+
+    /// Deletes the database entry of an installed package, without touching any of the files it
+    /// owns, like `pacman -R --dbonly`.
+    pub fn remove_db_entry(&mut self, name: &str) -> Result<()> {
+        match &self.get(name)?.source {
+            Some((source, entry_name)) => source.remove_entry(entry_name)?,
+            None => return Err(format!("'{}' has no database entry", name).into()),
+        }
+        self.db.remove(name);
+        self.invalidate_caches();
         Ok(())
     }
 
-    pub fn populate_full_database(&mut self) -> Result<()> {
-        self.populate("")
+    /// Plans the removal of the given packages. Unless dependency checks are skipped, this fails if
+    /// any package that would remain installed depends on one of the packages being removed, and
+    /// nothing else would satisfy that dependency.
+    pub fn remove(&mut self, targets: &[&str], checks: DependencyChecks) -> Result<Transaction> {
+        let mut files = HashMap::new();
+        for (target, entry) in targets.iter().zip(self.get_many(targets)?) {
+            files.insert(
+                (*target).to_owned(),
+                entry.files()?.map(PathBuf::from).collect(),
+            );
+        }
+        let transaction = Transaction {
+            remove: targets.iter().map(|x| (*x).to_owned()).collect(),
+            files,
+            dependency_checks: checks,
+            ..Default::default()
+        };
+        if checks == DependencyChecks::Skip {
+            return Ok(transaction);
+        }
+
+        self.populate_full_database()?;
+        let (removed, remaining): (Vec<_>, Vec<_>) = self
+            .db
+            .values()
+            .partition(|entry| targets.contains(&entry.desc.name.as_str()));
+        let mut broken = Vec::new();
+        for entry in remaining.iter() {
+            for dep in entry.desc.dependencies.iter() {
+                let mut dep: Dependency = dep.parse()?;
+                if checks == DependencyChecks::IgnoreVersions {
+                    dep.constraint = None;
+                }
+                let satisfied_by = |x: &LocalDatabaseEntry| dep.is_satisfied_by_package(&x.desc);
+                if let Some(removed) = removed.iter().find(|x| satisfied_by(x)) {
+                    if !remaining.iter().any(|x| satisfied_by(x)) {
+                        broken.push(format!(
+                            ":: removing {} breaks dependency '{}' required by {}",
+                            removed.desc.name,
+                            dep.display(),
+                            entry.desc.name
+                        ));
+                    }
+                }
+            }
+        }
+        if !broken.is_empty() {
+            broken.sort_unstable();
+            return Err(format!(
+                "failed to prepare transaction (could not satisfy dependencies)\n{}",
+                broken.join("\n")
+            )
+            .into());
+        }
+        Ok(transaction)
+    }
+
+    /// Reads the full database, and returns the number of installed packages and their total size.
+    pub fn summary(&mut self) -> Result<DbSummary> {
+        self.populate_full_database()?;
+        Ok(DbSummary {
+            packages: self.db.len(),
+            total_size: self.db.values().filter_map(|x| x.desc.size).sum(),
+        })
+    }
+
+    /// Finds the installed packages whose name or description matches every one of `patterns`,
+    /// like `pacman -Qs`, sorted by name.
+    pub fn search(&mut self, patterns: &[Regex]) -> Result<Vec<&LocalDatabaseEntry>> {
+        self.populate_full_database()?;
+        let mut entries: Vec<&LocalDatabaseEntry> = self
+            .db
+            .values()
+            .filter(|x| x.desc.matches_all(patterns))
+            .collect();
+        entries.sort_unstable_by(|a, b| a.desc.name.cmp(&b.desc.name));
+        Ok(entries)
+    }
+
+    /// Returns the `n` most recently installed packages, newest first. Packages without an install
+    /// date come after all of the others.
+    pub fn recently_installed(&mut self, n: usize) -> Result<Vec<&LocalDatabaseEntry>> {
+        self.populate_full_database()?;
+        let mut entries: Vec<&LocalDatabaseEntry> = self.db.values().collect();
+        sort::sort_entries(&mut entries, SortKey::InstallDate, true);
+        entries.truncate(n);
+        Ok(entries)
+    }
+
+    /// Returns the packages installed at or after `start` and before `end`, both unix timestamps,
+    /// oldest first. Either bound can be left out. Packages without an install date are never
+    /// included.
+    pub fn installed_between(
+        &mut self,
+        start: Option<u64>,
+        end: Option<u64>,
+    ) -> Result<Vec<&LocalDatabaseEntry>> {
+        self.populate_full_database()?;
+        let mut entries: Vec<&LocalDatabaseEntry> = self
+            .db
+            .values()
+            .filter(|x| {
+                x.desc.install_date.is_some_and(|date| {
+                    start.is_none_or(|start| date >= start) && end.is_none_or(|end| date < end)
+                })
+            })
+            .collect();
+        sort::sort_entries(&mut entries, SortKey::InstallDate, false);
+        Ok(entries)
+    }
+
+    /// Returns the names of all installed packages built from the same `pkgbase` as the given
+    /// package (including the package itself), sorted by name. If the package has no `pkgbase`, it
+    /// is considered to be the only package built from its PKGBUILD.
+    pub fn siblings(&mut self, package_name: &str) -> Result<Vec<&str>> {
+        let pkgbase = self.get(package_name)?.desc.pkgbase.clone();
+        if pkgbase.is_some() {
+            self.populate_full_database()?;
+        }
+        let mut siblings: Vec<&str> = self
+            .db
+            .iter()
+            .filter(|(name, entry)| match &pkgbase {
+                Some(_) => entry.desc.pkgbase == pkgbase,
+                None => name.as_str() == package_name,
+            })
+            .map(|(name, _)| name.as_str())
+            .collect();
+        siblings.sort_unstable();
+        Ok(siblings)
+    }
+
+    /// Returns an index from every file owned by an installed package to the names of the packages
+    /// that own it, sorted by name. Most files have exactly one owner, but directories are usually
+    /// shared between packages. The full database is read and the index is built on the first
+    /// call, and then cached until the database is next populated. Packages whose files can't be
+    /// read are left out, with a warning.
+    pub fn file_index(&mut self) -> Result<&HashMap<PathBuf, Vec<String>>> {
+        if self.file_index.is_none() {
+            self.populate_full_database()?;
+            let mut index: HashMap<PathBuf, Vec<String>> = HashMap::new();
+            for (name, mtree) in self.package_files().0 {
+                for file in mtree.iter() {
+                    index
+                        .entry(file.path().into())
+                        .or_default()
+                        .push(name.clone());
+                }
+            }
+            for owners in index.values_mut() {
+                owners.sort_unstable();
+            }
+            self.file_index = Some(index);
+        }
+        Ok(self.file_index.as_ref().unwrap())
+    }
+
+    /// Finds the sync repository that the installed package `name` comes from: the first one in
+    /// `sync` that has a package of that name, whatever its version. Returns `None` if the package
+    /// isn't installed, or is foreign (not in any of the repositories).
+    pub fn repo_of<'a>(&mut self, name: &str, sync: &'a SyncDatabases) -> Result<Option<&'a str>> {
+        if !self.contains(name)? {
+            return Ok(None);
+        }
+        Ok(sync.get(name).map(|(repo, _)| repo))
+    }
+
+    /// Records the repository that each package comes from, as in `repo_of`, so that listings can
+    /// show installed packages as `repo/name`.
+    pub fn attribute_repos(&mut self, sync: &SyncDatabases) {
+        self.repos.clear();
+        // Later repositories are overwritten by earlier ones, which take precedence
+        for db in sync.iter().collect::<Vec<_>>().into_iter().rev() {
+            for name in db.packages.keys() {
+                self.repos.insert(name.clone(), db.name.clone());
+            }
+        }
+    }
+
+    /// The repository recorded for the package `name` by `attribute_repos`, if any.
+    pub fn attributed_repo(&self, name: &str) -> Option<&str> {
+        self.repos.get(name).map(|x| x.as_str())
+    }
+
+    /// Finds the package in the database that satisfies a dependency, preferring a package with
+    /// the same name as the dependency over one that provides it.
+    pub fn find_satisfier(&self, dep: &Dependency) -> Option<&LocalDatabaseEntry> {
+        self.db
+            .get(&dep.name)
+            .filter(|entry| dep.is_satisfied_by_package(&entry.desc))
+            .or_else(|| {
+                self.db
+                    .values()
+                    .find(|entry| dep.is_satisfied_by_package(&entry.desc))
+            })
     }
-}
 
+    /// Returns a map from the name of every installed package to the names of the installed
+    /// packages that depend on it, sorted by name. Like the file index, this reads the full
+    /// database, and is cached until the database is next populated.
+    pub fn reverse_dependencies(&mut self) -> Result<&HashMap<String, Vec<String>>> {
+        if self.reverse_dependencies.is_none() {
+            self.populate_full_database()?;
+            let mut map: HashMap<String, Vec<String>> = self
+                .db
+                .keys()
+                .map(|name| (name.clone(), Vec::new()))
+                .collect();
+            for (name, entry) in self.db.iter() {
+                for dep in entry.desc.dependencies.iter() {
+                    if let Some(satisfier) = self.find_satisfier(&dep.parse()?) {
+                        map.get_mut(&satisfier.desc.name)
+                            .unwrap()
+                            .push(name.clone());
+                    }
+                }
+            }
+            for required_by in map.values_mut() {
+                required_by.sort_unstable();
+                required_by.dedup();
+            }
+            self.reverse_dependencies = Some(map);
+        }
+        Ok(self.reverse_dependencies.as_ref().unwrap())
+    }
+
+    /// Returns the names of the packages that no other installed package depends on, sorted by
+    /// name, like `pacman -Qt`.
+    pub fn leaves(&mut self) -> Result<Vec<&str>> {
+        self.unrequired(|_| true)
+    }
+
+    /// Returns the leaves that were installed as a dependency of another package, like
+    /// `pacman -Qdt`. These are usually left over from packages that have since been removed.
+    pub fn orphans(&mut self) -> Result<Vec<&str>> {
+        self.unrequired(|entry| !entry.desc.is_explicit())
+    }
+
+    /// Returns the names of the explicitly installed packages, sorted, like `pacman -Qqe`.
+    /// Reinstalling just these brings back everything else as their dependencies.
+    pub fn explicit_packages(&mut self) -> Result<Vec<&str>> {
+        self.populate_full_database()?;
+        let mut names: Vec<&str> = self
+            .db
+            .values()
+            .filter(|entry| entry.desc.is_explicit())
+            .map(|entry| entry.desc.name.as_str())
+            .collect();
+        names.sort_unstable();
+        Ok(names)
+    }
+
+    /// Returns the names of the leaves for which `filter` returns true, sorted by name.
+    fn unrequired(&mut self, filter: impl Fn(&LocalDatabaseEntry) -> bool) -> Result<Vec<&str>> {
+        self.reverse_dependencies()?;
+        let map = self.reverse_dependencies.as_ref().unwrap();
+        let mut leaves: Vec<&str> = map
+            .iter()
+            .filter(|(name, required_by)| required_by.is_empty() && filter(&self.db[*name]))
+            .map(|(name, _)| name.as_str())
+            .collect();
+        leaves.sort_unstable();
+        Ok(leaves)
+    }
+
+    /// Compares the packages in this database with those in `other`, such as a snapshot or the
+    /// database of another root. Only packages that have already been read are compared, so both
+    /// databases should be populated first. Versions are compared with `vercmp`, so versions that
+    /// are written differently but are equivalent, like `1.0-1` and `0:1.0-1`, don't count as a
+    /// change.
+    pub fn diff(&self, other: &LocalDatabase) -> DbDiff {
+        let mut diff = DbDiff::default();
+        for (name, entry) in self.db.iter() {
+            match other.db.get(name) {
+                None => diff.only_in_self.push(name.clone()),
+                Some(theirs) => {
+                    if vercmp(&entry.desc.version, &theirs.desc.version)
+                        != std::cmp::Ordering::Equal
+                    {
+                        diff.changed.push(VersionChange {
+                            name: name.clone(),
+                            self_version: entry.desc.version.clone(),
+                            other_version: theirs.desc.version.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        diff.only_in_other = other
+            .package_names()
+            .filter(|x| !self.db.contains_key(*x))
+            .map(|x| x.to_owned())
+            .collect();
+        diff.only_in_self.sort_unstable();
+        diff.only_in_other.sort_unstable();
+        diff.changed.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        diff
+    }
+
+    /// Returns the names of the installed metapackages, like `base`, which own no files and are
+    /// only installed to pull in their dependencies. Directories don't count as files, nor do the
+    /// metadata files at the root of the package archive. Sorted by name. Packages whose files
+    /// can't be read are skipped, with a warning.
+    pub fn metapackages(&mut self) -> Result<Vec<&str>> {
+        self.populate_full_database()?;
+        let mut ret = Vec::new();
+        for (name, mtree) in self.package_files().0 {
+            if mtree.iter().all(|x| x.is_dir() || x.is_package_metadata()) {
+                ret.push(name.as_str());
+            }
+        }
+        ret.sort_unstable();
+        Ok(ret)
+    }
+
+    /// Lists every installed package as a `<name> <version> <reason>` line, where the reason is
+    /// `explicit` or `dependency`, sorted by name. The output only depends on the installed
+    /// packages, so the manifests of two systems can be compared with `diff`.
+    pub fn dump_manifest(&mut self) -> Result<String> {
+        self.populate_full_database()?;
+        let mut entries: Vec<&LocalDatabaseEntry> = self.db.values().collect();
+        entries.sort_unstable_by(|a, b| a.desc.name.cmp(&b.desc.name));
+        let mut ret = String::new();
+        for entry in entries {
+            let reason = if entry.desc.is_explicit() {
+                "explicit"
+            } else {
+                "dependency"
+            };
+            ret.push_str(&format!(
+                "{} {} {}\n",
+                entry.desc.name, entry.desc.version, reason
+            ));
+        }
+        Ok(ret)
+    }
+
+    /// Finds the backup (configuration) files of every installed package whose contents differ
+    /// from the package's original, by comparing the file on disk against the sha256 recorded in
+    /// the `mtree`. Returns `(package, file)` pairs, sorted. Files that are missing, or that have
+    /// no sha256 recorded, are skipped, as are packages whose files can't be read.
+    pub fn modified_configs(&mut self) -> Result<Vec<(String, PathBuf)>> {
+        self.populate_full_database()?;
+        let mut ret = Vec::new();
+        for (name, mtree) in self.package_files().0 {
+            for backup in self.db[name].backup_files()? {
+                let expected = mtree
+                    .iter()
+                    .find(|x| x.path() == backup)
+                    .and_then(|x| x.hashes.sha256());
+                let expected = match expected {
+                    Some(expected) => expected,
+                    None => continue,
+                };
+                let file = match std::fs::File::open(self.resolver.resolve(&backup)) {
+                    Ok(file) => file,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                    Err(e) => return Err(e.into()),
+                };
+                if sha256::hex_digest(file)? != expected {
+                    ret.push((name.clone(), backup));
+                }
+            }
+        }
+        ret.sort_unstable();
+        Ok(ret)
+    }
+
+    /// Finds the installed packages that are missing some of their files, such as after they were
+    /// partly deleted by hand. Only whether each file exists is checked, which is much faster than
+    /// `verify`. The metadata files at the root of a package archive are never installed, so they
+    /// aren't counted as missing. Returns each broken package with its missing files, sorted.
+    /// Packages whose files can't be read are skipped, with a warning.
+    pub fn find_broken(&mut self) -> Result<Vec<(String, Vec<PathBuf>)>> {
+        self.populate_full_database()?;
+        let mut ret = Vec::new();
+        for (name, mtree) in self.package_files().0 {
+            let mut missing = Vec::new();
+            for file in mtree.iter().filter(|x| !x.is_package_metadata()) {
+                match self.resolver.resolve(file.path()).symlink_metadata() {
+                    Ok(_) => {}
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                        missing.push(file.path().to_owned());
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            if !missing.is_empty() {
+                missing.sort_unstable();
+                ret.push((name.clone(), missing));
+            }
+        }
+        ret.sort_unstable();
+        Ok(ret)
+    }
+
+    /// Finds every path that is owned by more than one installed package, other than directories,
+    /// which packages are expected to share. Returns each path with its owners, sorted by path.
+    /// Packages whose files can't be read are skipped, with a warning.
+    pub fn audit_overlaps(&mut self) -> Result<Vec<(PathBuf, Vec<String>)>> {
+        self.populate_full_database()?;
+        let mut directories = HashSet::new();
+        let mut owners: HashMap<&Path, Vec<String>> = HashMap::new();
+        for (name, mtree) in self.package_files().0 {
+            for file in mtree.iter() {
+                if file.is_dir() {
+                    directories.insert(file.path());
+                }
+                owners.entry(file.path()).or_default().push(name.clone());
+            }
+        }
+        let mut overlaps: Vec<(PathBuf, Vec<String>)> = owners
+            .into_iter()
+            .filter(|(path, owners)| owners.len() > 1 && !directories.contains(path))
+            .map(|(path, mut owners)| {
+                owners.sort_unstable();
+                (path.to_owned(), owners)
+            })
+            .collect();
+        overlaps.sort_unstable();
+        Ok(overlaps)
+    }
+
+    /// Finds the owners of every file whose path matches the regular expression `pattern`, such as
+    /// `^/etc/systemd/`, using the file index. Returns a `(file, owner)` pair for each owner of each
+    /// matching file, sorted by path and then by owner.
+    pub fn owners_matching(&mut self, pattern: &str) -> Result<Vec<(PathBuf, String)>> {
+        let regex = Regex::new(pattern)?;
+        let mut ret: Vec<(PathBuf, String)> = self
+            .file_index()?
+            .iter()
+            .filter(|(path, _)| path.to_str().is_some_and(|x| regex.is_match(x)))
+            .flat_map(|(path, owners)| owners.iter().map(move |x| (path.clone(), x.clone())))
+            .collect();
+        ret.sort_unstable();
+        Ok(ret)
+    }
+
+    /// Finds the owning package of each of `files`, using the file index rather than scanning
+    /// every package's files once per query. Files not owned by any package map to `None`, and
+    /// files with more than one owner map to the first by name.
+    pub fn owners_of(&mut self, files: &[PathBuf]) -> Result<HashMap<PathBuf, Option<String>>> {
+        let index = self.file_index()?;
+        Ok(files
+            .iter()
+            .map(|file| {
+                let owner = index.get(file).and_then(|owners| owners.first()).cloned();
+                (file.clone(), owner)
+            })
+            .collect())
+    }
+}
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::database::local::source::MemorySource;
+    use crate::database::sync::SyncDatabase;
+    use crate::test_util::{capture_logs, memory_fixture, Fixture, TarBuilder, TestSource};
     use crate::Result;
 
     #[test]
@@ -167,29 +1269,1347 @@ mod test {
         let entry =
             LocalDatabaseEntry::new_from_directory("/var/lib/pacman/local/linux-5.11.6.arch1-1")?;
         let owns = entry
-            .owns("/usr/lib/modules/5.11.6-arch1-1/kernel/arch/x86/crypto/aegis128-aesni.ko.xz");
+            .owns("/usr/lib/modules/5.11.6-arch1-1/kernel/arch/x86/crypto/aegis128-aesni.ko.xz")?;
         assert!(owns);
         Ok(())
     }
 
     #[test]
-    fn check_database_get() -> Result<()> {
-        let mut lazy_db = LocalDatabase::new();
-        let v = lazy_db.get("linux")?;
-        println!("{:?}", v.desc);
-        let x = lazy_db.get("vim")?;
-        println!("{:?}", x.desc);
+    fn check_backup_files() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("foo", "1.0-1", "", &["etc/", "etc/foo.conf", "usr/bin/foo"]);
+        fixture.add("bar", "1.0-1", "", &["usr/bin/bar"]);
+        std::fs::write(
+            fixture.path().join("foo-1.0-1/files"),
+            "%FILES%\netc/\netc/foo.conf\nusr/bin/foo\n\n\
+             %BACKUP%\netc/foo.conf\td41d8cd98f00b204e9800998ecf8427e\n",
+        )?;
+
+        let foo = LocalDatabaseEntry::new_from_directory(fixture.path().join("foo-1.0-1"))?;
+        assert_eq!(foo.backup_files()?, [PathBuf::from("/etc/foo.conf")]);
+        let bar = LocalDatabaseEntry::new_from_directory(fixture.path().join("bar-1.0-1"))?;
+        assert!(bar.backup_files()?.is_empty());
         Ok(())
     }
 
     #[test]
-    fn check_database_query() -> Result<()> {
-        let mut lazy_db = LocalDatabase::new();
-        lazy_db.populate("linux")?;
-        //assert_ne!(lazy_db.db, HashMap::new());
+    fn check_owns_with_sysroot() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("x", "1.0-1", "", &["usr/", "usr/bin/", "usr/bin/x"]);
+        let sysroot = tempfile::tempdir()?;
+        std::fs::create_dir_all(sysroot.path().join("usr/bin"))?;
+        std::os::unix::fs::symlink("usr/bin", sysroot.path().join("bin"))?;
+
+        let mut db =
+            LocalDatabase::with_resolver(fixture.path(), PathResolver::new(sysroot.path()));
+        let entry = db.get("x")?;
+        assert!(entry.owns("/usr/bin/x")?);
+        assert!(!entry.owns("/bin/x")?);
+        // `/bin` is only a symlink inside the sysroot, so it must be looked up there
+        assert!(entry.owns_resolved("/bin/x")?);
+        assert!(!entry.owns_resolved("/sbin/x")?);
+
+        let mut db = LocalDatabase::with_path(fixture.path());
+        let entry = db.get("x")?;
+        assert!(!entry.owns_resolved("/nonexistent/bin/x")?);
+        Ok(())
+    }
+
+    #[test]
+    fn check_owns_resolved_stored_symlink() -> Result<()> {
+        // The package records the path through the symlink, and is queried by the real path
+        let fixture = Fixture::new();
+        fixture.add("sh", "1.0-1", "", &["bin/sh"]);
+        let sysroot = tempfile::tempdir()?;
+        std::fs::create_dir_all(sysroot.path().join("usr/bin"))?;
+        std::os::unix::fs::symlink("usr/bin", sysroot.path().join("bin"))?;
+
+        let mut db =
+            LocalDatabase::with_resolver(fixture.path(), PathResolver::new(sysroot.path()));
+        let entry = db.get("sh")?;
+        assert!(!entry.owns("/usr/bin/sh")?);
+        assert!(entry.owns_resolved("/usr/bin/sh")?);
+        assert!(entry.owns_resolved("/bin/sh")?);
+        assert!(!entry.owns_resolved("/usr/bin/bash")?);
+        Ok(())
+    }
+
+    #[test]
+    fn check_contains() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("foo", "1.0-1", "", &["usr/bin/foo"]);
+        fixture.add("foo-utils", "2:3.1-2", "", &[]);
+
+        let db = LocalDatabase::with_path(fixture.path());
+        assert!(db.contains("foo")?);
+        assert!(db.contains("foo-utils")?);
+        assert!(!db.contains("fo")?);
+        assert!(!db.contains("bogus")?);
+        assert!(db.db.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn check_split_entry_dirname() {
+        assert_eq!(
+            split_entry_dirname("python-2.0-1"),
+            Some(("python", "2.0-1"))
+        );
+        assert_eq!(split_entry_dirname("foo-2:1.0-1"), Some(("foo", "2:1.0-1")));
+        assert_eq!(
+            split_entry_dirname("my-pkg-name-1.0-1"),
+            Some(("my-pkg-name", "1.0-1"))
+        );
+        assert_eq!(split_entry_dirname("foo-1.0"), None);
+        assert_eq!(split_entry_dirname("-1.0-1"), None);
+    }
+
+    #[test]
+    fn check_read_package_with_epoch() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("foo-utils", "1:3.1-2", "", &[]);
+        fixture.add("foo", "2:1.0-1", "", &[]);
+
+        let mut db = LocalDatabase::with_path(fixture.path());
+        assert_eq!(db.read_package("foo")?.desc.version, "2:1.0-1");
+        assert_eq!(db.read_package("foo-utils")?.desc.version, "1:3.1-2");
+        assert!(db.read_package("fo").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn check_recently_installed() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("old", "1.0-1", "%INSTALLDATE%\n1600000000\n\n", &[]);
+        fixture.add("undated", "1.0-1", "", &[]);
+        fixture.add("new", "1.0-1", "%INSTALLDATE%\n1620000000\n\n", &[]);
+        fixture.add("middle", "1.0-1", "%INSTALLDATE%\n1610000000\n\n", &[]);
+
+        let mut db = LocalDatabase::with_path(fixture.path());
+        let names = |entries: Vec<&LocalDatabaseEntry>| -> Vec<String> {
+            entries.iter().map(|x| x.desc.name.clone()).collect()
+        };
+        assert_eq!(
+            names(db.recently_installed(10)?),
+            ["new", "middle", "old", "undated"]
+        );
+        assert_eq!(names(db.recently_installed(2)?), ["new", "middle"]);
+        assert!(db.recently_installed(0)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn check_entries() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add_raw(
+            "bash-5.1.004-1",
+            "%NAME%\nbash\n\n%VERSION%\n5.1.004-1\n\n",
+            "#mtree\n\
+             ./usr time=1615000000.0 mode=755 type=dir\n\
+             ./usr/bin/bash time=1615000000.0 mode=755 size=1024 \
+             sha256digest=abcdef0123456789 type=file\n",
+        );
+        let mut db = LocalDatabase::with_path(fixture.path());
+        let entry = db.get("bash")?;
+        let entries: Vec<&MTreeEntry> = entry.entries()?.collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].path(), Path::new("/usr"));
+        assert!(entries[0].is_dir());
+        assert_eq!(entries[1].path(), Path::new("/usr/bin/bash"));
+        assert_eq!(entries[1].filesize, 1024);
+        assert_eq!(entries[1].mode, 0o755);
+        assert_eq!(entries[1].hashes.sha256(), Some("abcdef0123456789"));
+        // files() is the same entries, with only their paths
+        let files: Vec<&Path> = entry.files()?.collect();
+        assert_eq!(files, [Path::new("/usr"), Path::new("/usr/bin/bash")]);
+        Ok(())
+    }
+
+    #[test]
+    fn check_installed_between() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("old", "1.0-1", "%INSTALLDATE%\n1609459199\n\n", &[]);
+        fixture.add("start", "1.0-1", "%INSTALLDATE%\n1609459200\n\n", &[]);
+        fixture.add("undated", "1.0-1", "", &[]);
+        fixture.add("middle", "1.0-1", "%INSTALLDATE%\n1610000000\n\n", &[]);
+        fixture.add("end", "1.0-1", "%INSTALLDATE%\n1612137600\n\n", &[]);
+
+        let mut db = LocalDatabase::with_path(fixture.path());
+        let mut names = |start, end| -> Result<Vec<String>> {
+            let entries = db.installed_between(start, end)?;
+            Ok(entries.iter().map(|x| x.desc.name.clone()).collect())
+        };
+        // 2021-01-01 and 2021-02-01: the start is included and the end isn't
+        let (jan, feb) = (Some(1609459200), Some(1612137600));
+        assert_eq!(names(jan, feb)?, ["start", "middle"]);
+        assert_eq!(names(jan, None)?, ["start", "middle", "end"]);
+        assert_eq!(names(None, jan)?, ["old"]);
+        assert_eq!(names(None, None)?, ["old", "start", "middle", "end"]);
+        assert!(names(feb, jan)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn check_verify() -> Result<()> {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let fixture = Fixture::new();
+        let sysroot = tempfile::tempdir()?;
+        let metadata = sysroot.path().metadata()?;
+        // The sha256 of "hello\n"
+        fixture.add_raw(
+            "foo-1.0-1",
+            "%NAME%\nfoo\n\n%VERSION%\n1.0-1\n\n",
+            &format!(
+                "#mtree\n/set type=file uid={} gid={} mode=644\n\
+                 ./.PKGINFO time=1615000000.0 size=100\n\
+                 ./usr time=1615000000.0 mode=755 type=dir\n\
+                 ./usr/same time=1615000000.0 size=6 sha256digest=5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03\n\
+                 ./usr/edited time=1615000000.0 size=6 sha256digest=5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03\n\
+                 ./usr/grown time=1615000000.0 size=6 sha256digest=5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03\n\
+                 ./usr/missing time=1615000000.0 size=0\n",
+                metadata.uid(),
+                metadata.gid()
+            ),
+        );
+        std::fs::create_dir(sysroot.path().join("usr"))?;
+        std::fs::set_permissions(
+            sysroot.path().join("usr"),
+            std::fs::Permissions::from_mode(0o755),
+        )?;
+        for (file, contents) in [
+            ("same", "hello\n"),
+            ("edited", "HELLO\n"),
+            ("grown", "hello!\n"),
+        ]
+        .iter()
+        {
+            let path = sysroot.path().join("usr").join(file);
+            std::fs::write(&path, contents)?;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644))?;
+        }
+
+        let mut db =
+            LocalDatabase::with_resolver(fixture.path(), PathResolver::new(sysroot.path()));
+        let entry = db.get("foo")?;
+        let grown = FileIssue::SizeMismatch {
+            path: PathBuf::from("/usr/grown"),
+            expected: 6,
+            actual: 7,
+        };
+        let missing = FileIssue::Missing(PathBuf::from("/usr/missing"));
+        assert_eq!(entry.verify_quick()?, [grown, missing]);
+
+        let issues = entry.verify()?;
+        assert_eq!(issues.len(), 3);
+        assert_eq!(
+            issues[0],
+            FileIssue::ChecksumMismatch(PathBuf::from("/usr/edited"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn check_entry_from_strings() -> Result<()> {
+        let entry = LocalDatabaseEntry::from_strings(
+            "%NAME%\nfoo\n\n%VERSION%\n1.0-1\n\n",
+            Some("#mtree\n/set type=file uid=0 gid=0 mode=644\n./.PKGINFO size=10\n./usr type=dir mode=755\n./usr/foo size=3\n"),
+        )?;
+        assert_eq!(entry.desc.name, "foo");
+        assert!(entry.owns("/usr/foo")?);
+        assert_eq!(entry.mtree()?.len(), 3);
+        assert!(entry.backup_files()?.is_empty());
+
+        let entry = LocalDatabaseEntry::from_strings("%NAME%\nbar\n\n%VERSION%\n2.0-1\n", None)?;
+        assert_eq!(entry.desc.version, "2.0-1");
+        assert_eq!(entry.files()?.count(), 0);
+        assert!(LocalDatabaseEntry::from_strings("%VERSION%\n2.0-1\n", None).is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn check_verify_parallel() -> Result<()> {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        const FILES: usize = 200;
+        let fixture = Fixture::new();
+        let sysroot = tempfile::tempdir()?;
+        let metadata = sysroot.path().metadata()?;
+        let mut mtree = format!(
+            "#mtree\n/set type=file uid={} gid={} mode=644\n",
+            metadata.uid(),
+            metadata.gid()
+        );
+        std::fs::create_dir(sysroot.path().join("data"))?;
+        for i in 0..FILES {
+            let contents = format!("{:08}", i);
+            let digest = crate::sha256::hex_digest(contents.as_bytes())?;
+            mtree.push_str(&format!(
+                "./data/{:04} size={} sha256digest={}\n",
+                i,
+                contents.len(),
+                digest
+            ));
+            // Every 100th file is corrupted
+            let contents = if i % 100 == 0 {
+                contents.replace('0', "x")
+            } else {
+                contents
+            };
+            let path = sysroot.path().join(format!("data/{:04}", i));
+            std::fs::write(&path, contents)?;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644))?;
+        }
+        fixture.add_raw("big-1.0-1", "%NAME%\nbig\n\n%VERSION%\n1.0-1\n\n", &mtree);
+        // A file under something that isn't a directory can't be checked at all
+        mtree.push_str("./data/0001/nested size=0\n");
+        fixture.add_raw(
+            "broken-1.0-1",
+            "%NAME%\nbroken\n\n%VERSION%\n1.0-1\n\n",
+            &mtree,
+        );
+
+        let mut db =
+            LocalDatabase::with_resolver(fixture.path(), PathResolver::new(sysroot.path()));
+        let entry = db.get("big")?;
+        let parallel = entry.verify_parallel()?;
+        assert_eq!(parallel, entry.verify()?);
+        assert_eq!(
+            parallel,
+            [
+                FileIssue::ChecksumMismatch(PathBuf::from("/data/0000")),
+                FileIssue::ChecksumMismatch(PathBuf::from("/data/0100")),
+            ]
+        );
+
+        let entry = db.get("broken")?;
+        assert!(entry.verify().is_err());
+        assert!(entry.verify_parallel().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn check_verify_permissions() -> Result<()> {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let fixture = Fixture::new();
+        let sysroot = tempfile::tempdir()?;
+        let metadata = sysroot.path().metadata()?;
+        let (uid, gid) = (metadata.uid(), metadata.gid());
+        fixture.add_raw(
+            "foo-1.0-1",
+            "%NAME%\nfoo\n\n%VERSION%\n1.0-1\n\n",
+            &format!(
+                "#mtree\n/set type=file uid={} gid={} mode=755\n\
+                 ./script time=1615000000.0 size=0\n\
+                 ./chowned time=1615000000.0 size=0 gid={}\n",
+                uid,
+                gid,
+                gid + 1
+            ),
+        );
+        for file in ["script", "chowned"].iter() {
+            let path = sysroot.path().join(file);
+            std::fs::write(&path, "")?;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+        }
+        // Permission drift: the script is no longer executable
+        std::fs::set_permissions(
+            sysroot.path().join("script"),
+            std::fs::Permissions::from_mode(0o644),
+        )?;
+
+        let mut db =
+            LocalDatabase::with_resolver(fixture.path(), PathResolver::new(sysroot.path()));
+        let issues = db.get("foo")?.verify_quick()?;
+        assert_eq!(
+            issues,
+            [
+                FileIssue::ModeMismatch {
+                    path: PathBuf::from("/script"),
+                    expected: 0o755,
+                    actual: 0o644,
+                },
+                FileIssue::OwnerMismatch {
+                    path: PathBuf::from("/chowned"),
+                    expected: (uid, gid + 1),
+                    actual: (uid, gid),
+                },
+            ]
+        );
+        assert_eq!(
+            issues[0].to_string(),
+            "/script (Permissions mismatch: expected 755, found 644)"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn check_database_get() -> Result<()> {
+        let mut lazy_db = LocalDatabase::new();
+        let v = lazy_db.get("linux")?;
+        println!("{:?}", v.desc);
+        let x = lazy_db.get("vim")?;
+        println!("{:?}", x.desc);
+        Ok(())
+    }
+
+    #[test]
+    fn check_database_query() -> Result<()> {
+        let mut lazy_db = LocalDatabase::new();
+        lazy_db.populate(&PopulateFilter::Substring("linux".into()), true)?;
+        //assert_ne!(lazy_db.db, HashMap::new());
         for (name, _) in lazy_db.db.iter() {
             println!("{}", name);
         }
         Ok(())
     }
+
+    #[test]
+    fn check_owners_of() -> Result<()> {
+        let fixture = Fixture::new();
+        let mut paths = Vec::new();
+        for pkg in 0..50 {
+            let files: Vec<String> = (0..200)
+                .map(|file| format!("usr/share/pkg{}/file{}", pkg, file))
+                .collect();
+            let files: Vec<&str> = files.iter().map(|x| x.as_str()).collect();
+            fixture.add(&format!("pkg{}", pkg), "1.0-1", "", &files);
+            paths.push(PathBuf::from(format!("/usr/share/pkg{}/file{}", pkg, pkg)));
+        }
+        for file in 0..450 {
+            paths.push(PathBuf::from(format!("/usr/share/unowned/file{}", file)));
+        }
+
+        let mut db = LocalDatabase::with_path(fixture.path());
+        let owners = db.owners_of(&paths)?;
+
+        // The same as asking each package in turn
+        let naive: HashMap<PathBuf, Option<String>> = paths
+            .iter()
+            .map(|file| {
+                let owner = db
+                    .db
+                    .iter()
+                    .find(|(_, entry)| entry.owns(file).unwrap())
+                    .map(|(name, _)| name.clone());
+                (file.clone(), owner)
+            })
+            .collect();
+        assert_eq!(owners, naive);
+        assert_eq!(owners.len(), 500);
+        assert_eq!(
+            owners[Path::new("/usr/share/pkg7/file7")].as_deref(),
+            Some("pkg7")
+        );
+        assert_eq!(owners[Path::new("/usr/share/unowned/file0")], None);
+        Ok(())
+    }
+
+    #[test]
+    fn check_file_index() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("foo", "1.0-1", "", &["usr/", "usr/bin/", "usr/bin/foo"]);
+        fixture.add("bar", "2.0-1", "", &["usr/", "usr/lib/libbar.so"]);
+
+        let mut db = LocalDatabase::with_path(fixture.path());
+        let index = db.file_index()?;
+        assert_eq!(index[Path::new("/usr/bin/foo")], ["foo"]);
+        assert_eq!(index[Path::new("/usr/lib/libbar.so")], ["bar"]);
+        assert_eq!(index[Path::new("/usr")], ["bar", "foo"]);
+        assert_eq!(index.len(), 4);
+
+        // The index is cached, so it must not touch the filesystem again
+        std::fs::remove_dir_all(fixture.path().join("bar-2.0-1"))?;
+        assert_eq!(db.file_index()?[Path::new("/usr/lib/libbar.so")], ["bar"]);
+
+        // Re-populating invalidates it
+        db.db.clear();
+        db.populate(&PopulateFilter::Substring(String::new()), true)?;
+        assert!(!db
+            .file_index()?
+            .contains_key(Path::new("/usr/lib/libbar.so")));
+        Ok(())
+    }
+
+    #[test]
+    fn check_full_population_is_kept() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("foo", "1.0-1", "", &["usr/", "usr/bin/foo", "etc/shared"]);
+        fixture.add(
+            "bar",
+            "2.0-1",
+            "",
+            &["usr/", "usr/lib/libbar.so", "etc/shared"],
+        );
+        let files = [PathBuf::from("/usr/lib/libbar.so")];
+
+        let mut db = LocalDatabase::with_path(fixture.path());
+        assert_eq!(db.owners_of(&files)?[&files[0]].as_deref(), Some("bar"));
+        // Neither the packages nor the file index are read again by later queries
+        std::fs::remove_dir_all(fixture.path().join("bar-2.0-1"))?;
+        assert_eq!(db.audit_overlaps()?.len(), 1);
+        assert!(db.populate_full_database()?.is_empty());
+        assert_eq!(db.owners_of(&files)?[&files[0]].as_deref(), Some("bar"));
+
+        // Removing a package does invalidate the index
+        db.remove_db_entry("foo")?;
+        assert!(db.audit_overlaps()?.is_empty());
+        let foo = [PathBuf::from("/usr/bin/foo")];
+        assert_eq!(db.owners_of(&foo)?[&foo[0]], None);
+        assert_eq!(db.owners_of(&files)?[&files[0]].as_deref(), Some("bar"));
+        Ok(())
+    }
+
+    #[test]
+    fn check_siblings() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("systemd", "247.4-2", "%BASE%\nsystemd\n\n", &[]);
+        fixture.add("systemd-libs", "247.4-2", "%BASE%\nsystemd\n\n", &[]);
+        fixture.add("vim", "8.2.2576-1", "%BASE%\nvim\n\n", &[]);
+        fixture.add("bash", "5.1.004-1", "", &[]);
+
+        let mut db = LocalDatabase::with_path(fixture.path());
+        assert_eq!(db.siblings("systemd-libs")?, ["systemd", "systemd-libs"]);
+        assert_eq!(db.siblings("vim")?, ["vim"]);
+        assert_eq!(db.siblings("bash")?, ["bash"]);
+        Ok(())
+    }
+
+    #[test]
+    fn check_populate_filter() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("linux", "5.11.6.arch1-1", "", &[]);
+        fixture.add("linux-firmware", "20210315.3568f96-1", "", &[]);
+        fixture.add("util-linux", "2.36.2-1", "", &[]);
+        fixture.add("Linux-Custom", "1.0-1", "", &[]);
+
+        let populated = |filter, case_sensitive| -> Result<Vec<String>> {
+            let mut db = LocalDatabase::with_path(fixture.path());
+            db.populate(&filter, case_sensitive)?;
+            let mut names: Vec<String> = db.package_names().map(|x| x.to_owned()).collect();
+            names.sort_unstable();
+            Ok(names)
+        };
+
+        use PopulateFilter::*;
+        assert_eq!(populated(Exact("linux".into()), true)?, ["linux"]);
+        assert_eq!(populated(Exact("LINUX".into()), false)?, ["linux"]);
+        assert_eq!(
+            populated(Prefix("linux".into()), true)?,
+            ["linux", "linux-firmware"]
+        );
+        assert_eq!(
+            populated(Prefix("linux".into()), false)?,
+            ["Linux-Custom", "linux", "linux-firmware"]
+        );
+        assert_eq!(
+            populated(Substring("linux".into()), true)?,
+            ["linux", "linux-firmware", "util-linux"]
+        );
+        assert_eq!(
+            populated(Regex("^linux$|^util-".into()), true)?,
+            ["linux", "util-linux"]
+        );
+        // Versions are not part of the name
+        assert!(populated(Substring("5.11".into()), true)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn check_populate_matches_package_name() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add_raw(
+            "renamed-directory",
+            "%NAME%\nfoo\n\n%VERSION%\n1.0-1\n\n",
+            "#mtree\n./usr/bin/foo time=1615000000.0 size=0 type=file\n",
+        );
+        fixture.add("bar", "1.0-1", "", &[]);
+
+        let mut db = LocalDatabase::with_path(fixture.path());
+        db.populate(&PopulateFilter::Exact("foo".into()), true)?;
+        assert_eq!(db.package_names().collect::<Vec<_>>(), ["foo"]);
+        assert!(db.db["foo"].owns("/usr/bin/foo")?);
+
+        let mut db = LocalDatabase::with_path(fixture.path());
+        db.populate(&PopulateFilter::Substring("renamed".into()), true)?;
+        assert!(db.db.is_empty());
+
+        let mut db = LocalDatabase::with_path(fixture.path());
+        db.populate(&PopulateFilter::Substring("1.0".into()), true)?;
+        assert!(db.db.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn check_lazy_mtree() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("bash", "5.1.004-1", "", &["usr/bin/bash"]);
+        let dir = fixture.path().join("bash-5.1.004-1");
+
+        let entry = LocalDatabaseEntry::new_desc_only(&dir)?;
+        assert!(entry.mtree.get().is_none());
+        assert!(entry.owns("/usr/bin/bash")?);
+        assert_eq!(entry.mtree.get().map(|x| x.len()), Some(1));
+
+        let entry = LocalDatabaseEntry::new_from_directory(&dir)?;
+        assert!(entry.mtree.get().is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn check_populate_lazily() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add(
+            "bash",
+            "5.1.004-1",
+            "%DESC%\nThe GNU Bourne Again shell\n\n",
+            &["usr/bin/bash"],
+        );
+        fixture.add(
+            "readline",
+            "8.1-1",
+            "%REASON%\n1\n\n",
+            &["usr/lib/libreadline.so"],
+        );
+        fixture.add("broken", "1.0-1", "", &[]);
+        std::fs::write(
+            fixture.path().join("broken-1.0-1/mtree"),
+            b"\x1f\x8bnot gzipped",
+        )?;
+
+        // Populating doesn't read the mtrees, so the broken one isn't noticed
+        let mut db = LocalDatabase::with_path(fixture.path());
+        assert!(db.populate_full_database()?.is_empty());
+        assert_eq!(db.explicit_packages()?, ["bash", "broken"]);
+        for entry in db.db.values() {
+            assert!(entry.mtree.get().is_none());
+        }
+        let bash = &db.db["bash"];
+        assert_eq!(bash.desc.version, "5.1.004-1");
+        assert_eq!(
+            bash.desc.description.as_deref(),
+            Some("The GNU Bourne Again shell")
+        );
+        assert_eq!(db.db["readline"].desc.reason, Some(1));
+        // The files are still there when they're asked for
+        assert!(bash.owns("/usr/bin/bash")?);
+
+        let mut db = LocalDatabase::with_path(fixture.path());
+        db.set_load_files(true);
+        assert_eq!(db.populate_full_database()?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn check_summary() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("foo", "1.0-1", "%SIZE%\n1048576\n\n", &[]);
+        fixture.add("bar", "1.0-1", "%SIZE%\n524288\n\n", &[]);
+        fixture.add("baz", "1.0-1", "", &[]);
+
+        let mut db = LocalDatabase::with_path(fixture.path());
+        let summary = db.summary()?;
+        assert_eq!(
+            summary,
+            DbSummary {
+                packages: 3,
+                total_size: 1572864
+            }
+        );
+        assert_eq!(summary.packages, db.db.len());
+        assert_eq!(
+            summary.to_string(),
+            "3 packages installed, total size 1536.00 KiB"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn check_populate_warns_on_malformed_entry() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("foo", "1.0-1", "", &[]);
+        fixture.add_raw("broken-1.0-1", "%VERSION%\n1.0-1\n\n", "#mtree\n");
+
+        let mut db = LocalDatabase::with_path(fixture.path());
+        let (errors, logs) = capture_logs(|| db.populate_full_database());
+        let errors = errors?;
+        assert_eq!(db.package_names().collect::<Vec<_>>(), ["foo"]);
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].0, log::Level::Warn);
+        assert!(logs[0].1.contains("broken-1.0-1"));
+        assert!(logs[0].1.contains("Every package must have a name."));
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].path, fixture.path().join("broken-1.0-1"));
+        assert_eq!(
+            errors[0].error.to_string(),
+            "Every package must have a name."
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn check_populate_reports_bad_mtree() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("foo", "1.0-1", "", &[]);
+        fixture.add("bar", "1.0-1", "", &[]);
+        std::fs::write(
+            fixture.path().join("bar-1.0-1/mtree"),
+            b"\x1f\x8bnot gzipped",
+        )?;
+
+        let mut db = LocalDatabase::with_path(fixture.path());
+        db.set_load_files(true);
+        let errors = db.populate_full_database()?;
+        assert_eq!(db.package_names().collect::<Vec<_>>(), ["foo"]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].path, fixture.path().join("bar-1.0-1"));
+        assert_eq!(errors[0].kind, EntryErrorKind::Other);
+        Ok(())
+    }
+
+    #[test]
+    fn check_file_queries_skip_bad_mtree() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("foo", "1.0-1", "", &["usr/", "usr/bin/", "usr/bin/foo"]);
+        fixture.add("bar", "1.0-1", "", &["usr/", "usr/bin/", "usr/bin/foo"]);
+        fixture.add("meta", "1.0-1", "", &["usr/"]);
+        std::fs::write(
+            fixture.path().join("bar-1.0-1/mtree"),
+            b"\x1f\x8bnot gzipped",
+        )?;
+
+        let mut db = LocalDatabase::with_path(fixture.path());
+        assert_eq!(db.package_names().count(), 0);
+        let (_, logs) = capture_logs(|| db.file_index().map(|_| ()));
+        assert_eq!(logs.len(), 1);
+        let owners = db.owners_of(&[PathBuf::from("/usr/bin/foo")])?;
+        assert_eq!(owners[Path::new("/usr/bin/foo")].as_deref(), Some("foo"));
+        assert_eq!(db.metapackages()?, ["meta"]);
+        assert!(db.audit_overlaps()?.is_empty());
+        // Only the files that could be read are checked
+        let broken = db.find_broken()?;
+        assert_eq!(broken.len(), 1);
+        assert_eq!(broken[0].0, "foo");
+        assert!(db.modified_configs()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn check_entry_names_cache() -> Result<()> {
+        let source = TestSource::new(memory_fixture(&["bash", "glibc", "zlib"]));
+        let scans = source.scans.clone();
+        let modified = source.modified.clone();
+        *modified.lock().unwrap() = Some(SystemTime::UNIX_EPOCH);
+        let mut db = LocalDatabase::with_source(source, PathResolver::default());
+
+        db.get("bash")?;
+        assert_eq!(scans.load(Ordering::Relaxed), 1);
+        // The source hasn't changed, so the listing is reused
+        db.get("glibc")?;
+        assert!(db.contains("zlib")?);
+        assert!(!db.contains("vim")?);
+        assert_eq!(scans.load(Ordering::Relaxed), 1);
+
+        *modified.lock().unwrap() = Some(SystemTime::UNIX_EPOCH + Duration::from_secs(60));
+        db.get("zlib")?;
+        assert_eq!(scans.load(Ordering::Relaxed), 2);
+        assert!(!db.contains("vim")?);
+        assert_eq!(scans.load(Ordering::Relaxed), 2);
+
+        // A listing taken just after the source changed could miss a change made in the same
+        // tick, so it isn't kept
+        *modified.lock().unwrap() = Some(SystemTime::now());
+        assert!(!db.contains("vim")?);
+        assert!(!db.contains("vim")?);
+        assert_eq!(scans.load(Ordering::Relaxed), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn check_get_many() -> Result<()> {
+        let names = ["glibc", "bash", "readline", "ncurses", "zlib", "vim"];
+        let source = TestSource::new(memory_fixture(&names));
+        let scans = source.scans.clone();
+        let mut db = LocalDatabase::with_source(source, PathResolver::default());
+
+        let entries = db.get_many(&names[..5])?;
+        let loaded: Vec<_> = entries.iter().map(|x| x.desc.name.as_str()).collect();
+        assert_eq!(loaded, &names[..5]);
+        assert_eq!(scans.load(Ordering::Relaxed), 1);
+        // Packages that are already loaded don't need another scan
+        db.get_many(&["zlib", "bash"])?;
+        assert_eq!(scans.load(Ordering::Relaxed), 1);
+        db.get_many(&["bash", "vim"])?;
+        assert_eq!(scans.load(Ordering::Relaxed), 2);
+
+        let err = db.get_many(&["bash", "emacs"]).unwrap_err();
+        assert!(err.to_string().contains("'emacs'"), "{}", err);
+        Ok(())
+    }
+
+    #[test]
+    fn check_populate_skips_unreadable_entries() -> Result<()> {
+        let source = TestSource {
+            denied: Some("bar-1.0-1"),
+            ..TestSource::new(memory_fixture(&["foo", "bar", "baz"]))
+        };
+
+        let mut db = LocalDatabase::with_source(source, PathResolver::default());
+        let mut names = db.names()?;
+        names.sort_unstable();
+        assert_eq!(names, ["baz", "foo"]);
+
+        let errors = db.populate_full_database()?;
+        let mut loaded: Vec<_> = db.package_names().collect();
+        loaded.sort_unstable();
+        assert_eq!(loaded, ["baz", "foo"]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].path, Path::new("bar-1.0-1"));
+        assert_eq!(errors[0].kind, EntryErrorKind::PermissionDenied);
+
+        let missing = LocalDatabaseEntry::from_source(Arc::new(MemorySource::new()), "qux-1.0-1")
+            .unwrap_err();
+        assert_eq!(
+            EntryErrorKind::of(missing.as_ref()),
+            EntryErrorKind::NotFound
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn check_populate_ordered() -> Result<()> {
+        let fixture = Fixture::new();
+        for i in 0..40 {
+            let name = format!("pkg{:02}", i);
+            let file = format!("/usr/bin/{}", name);
+            fixture.add(&name, "1.0-1", "%SIZE%\n1024\n\n", &[&file]);
+        }
+        fixture.add_raw(
+            "broken-1.0-1",
+            "%NAME%\nbroken\n\n%VERSION%\n1.0-1\n\n",
+            "#mtree\n./usr/bin/broken type=file mode=notoctal\n",
+        );
+
+        let mut serial = LocalDatabase::with_path(fixture.path());
+        serial.set_load_files(true);
+        let serial_errors = serial.populate_full_database()?;
+        let mut ordered = LocalDatabase::with_path(fixture.path());
+        ordered.set_load_files(true);
+        let errors = ordered.populate_ordered()?;
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].path, serial_errors[0].path);
+        assert_eq!(errors[0].kind, serial_errors[0].kind);
+        let listing = |db: &LocalDatabase| -> Result<Vec<(String, String, Vec<PathBuf>)>> {
+            let mut ret = Vec::new();
+            for (name, entry) in db.db.iter() {
+                let files = entry.files()?.map(PathBuf::from).collect();
+                ret.push((name.clone(), entry.desc.version.clone(), files));
+            }
+            Ok(ret)
+        };
+        // Both iterate over the packages in the same order, without sorting them first
+        assert_eq!(listing(&ordered)?, listing(&serial)?);
+        let names: Vec<&str> = ordered.package_names().collect();
+        assert_eq!(names.len(), 40);
+        assert_eq!(names[..3], ["pkg00", "pkg01", "pkg02"]);
+        assert_eq!(ordered.summary()?, serial.summary()?);
+
+        // Of two entries for the same package, the one that sorts last is kept
+        fixture.add_raw(
+            "pkg00-0.9-1",
+            "%NAME%\npkg00\n\n%VERSION%\n0.9-1\n\n",
+            "#mtree\n",
+        );
+        fixture.add_raw(
+            "pkg00-2.0-1",
+            "%NAME%\npkg00\n\n%VERSION%\n2.0-1\n\n",
+            "#mtree\n",
+        );
+        for _ in 0..5 {
+            let mut db = LocalDatabase::with_path(fixture.path());
+            db.populate_ordered()?;
+            assert_eq!(db.db["pkg00"].desc.version, "2.0-1");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn check_populate_skips_unreadable_directory() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let fixture = Fixture::new();
+        fixture.add("foo", "1.0-1", "", &[]);
+        fixture.add("bar", "1.0-1", "", &[]);
+        let bar = fixture.path().join("bar-1.0-1");
+        std::fs::set_permissions(&bar, std::fs::Permissions::from_mode(0o000))?;
+        if std::fs::read(bar.join("desc")).is_ok() {
+            // Permissions aren't enforced for root, so there is nothing to test
+            std::fs::set_permissions(&bar, std::fs::Permissions::from_mode(0o755))?;
+            return Ok(());
+        }
+
+        let mut db = LocalDatabase::with_path(fixture.path());
+        assert_eq!(db.names()?, ["foo"]);
+        let errors = db.populate_full_database()?;
+        assert_eq!(db.package_names().collect::<Vec<_>>(), ["foo"]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].path, bar);
+        assert_eq!(errors[0].kind, EntryErrorKind::PermissionDenied);
+        std::fs::set_permissions(&bar, std::fs::Permissions::from_mode(0o755))?;
+        Ok(())
+    }
+
+    #[test]
+    fn check_db_version() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("foo", "1.0-1", "", &[]);
+        let db = LocalDatabase::with_path(fixture.path());
+        assert_eq!(db.db_version()?, SUPPORTED_DB_VERSION);
+        let ((), logs) = capture_logs(|| db.check_db_version().unwrap());
+        assert!(logs.is_empty());
+
+        std::fs::write(fixture.path().join("ALPM_DB_VERSION"), "9\n")?;
+        assert_eq!(db.db_version()?, 9);
+
+        std::fs::write(fixture.path().join("ALPM_DB_VERSION"), "10\n")?;
+        assert_eq!(db.db_version()?, 10);
+        let ((), logs) = capture_logs(|| db.check_db_version().unwrap());
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].0, log::Level::Warn);
+        assert!(logs[0].1.contains("newer"), "{}", logs[0].1);
+        // The database can still be read
+        assert!(LocalDatabase::with_path(fixture.path()).contains("foo")?);
+
+        std::fs::write(fixture.path().join("ALPM_DB_VERSION"), "nine\n")?;
+        assert!(db.db_version().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn check_modified_configs() -> Result<()> {
+        let fixture = Fixture::new();
+        let sysroot = tempfile::tempdir()?;
+        // The sha256 of "hello\n"
+        let hello = "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03";
+        for name in ["foo", "bar"] {
+            fixture.add_raw(
+                &format!("{}-1.0-1", name),
+                &format!("%NAME%\n{}\n\n%VERSION%\n1.0-1\n\n", name),
+                &format!(
+                    "#mtree\n/set type=file uid=0 gid=0 mode=644\n\
+                     ./etc/{0}.conf size=6 sha256digest={1}\n\
+                     ./etc/{0}.d/extra.conf size=6 sha256digest={1}\n\
+                     ./etc/{0}.d/gone.conf size=6 sha256digest={1}\n\
+                     ./usr/bin/{0} size=6 sha256digest={1}\n",
+                    name, hello
+                ),
+            );
+            std::fs::write(
+                fixture.path().join(format!("{}-1.0-1/files", name)),
+                format!(
+                    "%FILES%\netc/{0}.conf\n\n%BACKUP%\netc/{0}.conf\tmd5\n\
+                     etc/{0}.d/extra.conf\tmd5\netc/{0}.d/gone.conf\tmd5\n",
+                    name
+                ),
+            )?;
+            std::fs::create_dir_all(sysroot.path().join(format!("etc/{}.d", name)))?;
+            std::fs::create_dir_all(sysroot.path().join("usr/bin"))?;
+            std::fs::write(sysroot.path().join(format!("etc/{}.conf", name)), "hello\n")?;
+            std::fs::write(
+                sysroot.path().join(format!("etc/{}.d/extra.conf", name)),
+                "hello\n",
+            )?;
+            // Not a backup file, so edits to it aren't reported
+            std::fs::write(sysroot.path().join(format!("usr/bin/{}", name)), "edited\n")?;
+        }
+        std::fs::write(sysroot.path().join("etc/foo.conf"), "edited\n")?;
+        std::fs::write(sysroot.path().join("etc/bar.d/extra.conf"), "edited\n")?;
+
+        let mut db =
+            LocalDatabase::with_resolver(fixture.path(), PathResolver::new(sysroot.path()));
+        assert_eq!(
+            db.modified_configs()?,
+            [
+                ("bar".to_owned(), PathBuf::from("/etc/bar.d/extra.conf")),
+                ("foo".to_owned(), PathBuf::from("/etc/foo.conf")),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn check_find_broken() -> Result<()> {
+        let fixture = Fixture::new();
+        let sysroot = tempfile::tempdir()?;
+        fixture.add(
+            "foo",
+            "1.0-1",
+            "",
+            &[
+                ".PKGINFO",
+                "usr/",
+                "usr/bin/",
+                "usr/bin/foo",
+                "usr/bin/foo-helper",
+            ],
+        );
+        fixture.add("bar", "1.0-1", "", &["usr/", "usr/bin/", "usr/bin/bar"]);
+        std::fs::create_dir_all(sysroot.path().join("usr/bin"))?;
+        std::fs::write(sysroot.path().join("usr/bin/foo"), "")?;
+        std::fs::write(sysroot.path().join("usr/bin/bar"), "")?;
+        // A dangling symlink still exists
+        std::os::unix::fs::symlink("missing", sysroot.path().join("usr/bin/foo-helper"))?;
+
+        let mut db =
+            LocalDatabase::with_resolver(fixture.path(), PathResolver::new(sysroot.path()));
+        assert!(db.find_broken()?.is_empty());
+
+        std::fs::remove_file(sysroot.path().join("usr/bin/foo"))?;
+        std::fs::remove_file(sysroot.path().join("usr/bin/foo-helper"))?;
+        assert_eq!(
+            db.find_broken()?,
+            [(
+                "foo".to_owned(),
+                vec![
+                    PathBuf::from("/usr/bin/foo"),
+                    PathBuf::from("/usr/bin/foo-helper")
+                ]
+            )]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn check_diff() -> Result<()> {
+        let old = Fixture::new();
+        old.add("bash", "5.1.004-1", "", &[]);
+        old.add("glibc", "2.33-4", "", &[]);
+        old.add("python2", "2.7.18-3", "", &[]);
+        old.add("zlib", "1:1.2.11-4", "", &[]);
+        let new = Fixture::new();
+        new.add("bash", "5.1.004-1", "", &[]);
+        new.add("glibc", "2.33-5", "", &[]);
+        new.add("python", "3.9.2-1", "", &[]);
+        new.add("zlib", "1:1.2.11-4", "", &[]);
+
+        let mut old = LocalDatabase::with_path(old.path());
+        let mut new = LocalDatabase::with_path(new.path());
+        old.populate_full_database()?;
+        new.populate_full_database()?;
+        let diff = new.diff(&old);
+        assert_eq!(
+            diff,
+            DbDiff {
+                only_in_self: vec!["python".to_owned()],
+                only_in_other: vec!["python2".to_owned()],
+                changed: vec![VersionChange {
+                    name: "glibc".to_owned(),
+                    self_version: "2.33-5".to_owned(),
+                    other_version: "2.33-4".to_owned(),
+                }],
+            }
+        );
+        assert!(!diff.is_empty());
+        assert!(old.diff(&old).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn check_repo_of() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("bash", "5.1.004-1", "", &[]);
+        fixture.add("firefox", "87.0-1", "", &[]);
+        fixture.add("yay", "10.2.0-4", "", &[]);
+        let db = |name: &str, packages: &[&str]| {
+            let mut archive = TarBuilder::new();
+            for package in packages {
+                let desc = format!("%NAME%\n{}\n\n%VERSION%\n1.0-1\n\n", package);
+                archive = archive.file(&format!("{}-1.0-1/desc", package), 0o644, desc.as_bytes());
+            }
+            SyncDatabase::from_archive(name, &archive.finish())
+        };
+        let sync = SyncDatabases::new(vec![
+            db("core", &["bash"])?,
+            db("extra", &["firefox", "bash", "vim"])?,
+        ]);
+
+        let mut local = LocalDatabase::with_path(fixture.path());
+        assert_eq!(local.repo_of("bash", &sync)?, Some("core"));
+        assert_eq!(local.repo_of("firefox", &sync)?, Some("extra"));
+        // Foreign packages, and packages that aren't installed, have no repository
+        assert_eq!(local.repo_of("yay", &sync)?, None);
+        assert_eq!(local.repo_of("vim", &sync)?, None);
+
+        assert_eq!(local.attributed_repo("bash"), None);
+        local.attribute_repos(&sync);
+        assert_eq!(local.attributed_repo("bash"), Some("core"));
+        assert_eq!(local.attributed_repo("firefox"), Some("extra"));
+        assert_eq!(local.attributed_repo("yay"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn check_metapackages() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add(
+            "base",
+            "2-2",
+            "%DEPENDS%\nbash\n\n",
+            &[".PKGINFO", ".MTREE"],
+        );
+        fixture.add("base-devel", "1-1", "", &[]);
+        fixture.add("filesystem", "2021.01.19-1", "", &["usr/", "usr/bin/"]);
+        fixture.add(
+            "bash",
+            "5.1.004-1",
+            "",
+            &["usr/", "usr/bin/", "usr/bin/bash"],
+        );
+
+        let mut db = LocalDatabase::with_path(fixture.path());
+        assert_eq!(db.metapackages()?, ["base", "base-devel", "filesystem"]);
+        Ok(())
+    }
+
+    #[test]
+    fn check_owners_matching() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add(
+            "systemd",
+            "247-1",
+            "",
+            &["etc/", "etc/systemd/", "etc/systemd/system.conf"],
+        );
+        fixture.add(
+            "bash",
+            "5.1.004-1",
+            "",
+            &["etc/", "etc/bash.bashrc", "usr/bin/bash"],
+        );
+        fixture.add("coreutils", "8.32-1", "", &["usr/bin/ls"]);
+        let mut db = LocalDatabase::with_path(fixture.path());
+
+        let owners = db.owners_matching("^/etc/")?;
+        assert_eq!(
+            owners,
+            [
+                (PathBuf::from("/etc/bash.bashrc"), "bash".to_owned()),
+                (PathBuf::from("/etc/systemd"), "systemd".to_owned()),
+                (
+                    PathBuf::from("/etc/systemd/system.conf"),
+                    "systemd".to_owned()
+                ),
+            ]
+        );
+        let mut packages: Vec<_> = owners.iter().map(|(_, x)| x.as_str()).collect();
+        packages.dedup();
+        assert_eq!(packages, ["bash", "systemd"]);
+
+        // The shared directory has both owners
+        assert_eq!(
+            db.owners_matching("^/etc$")?,
+            [
+                (PathBuf::from("/etc"), "bash".to_owned()),
+                (PathBuf::from("/etc"), "systemd".to_owned()),
+            ]
+        );
+        assert!(db.owners_matching("^/opt/")?.is_empty());
+        assert!(db.owners_matching("(").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn check_installed_size_on_disk() -> Result<()> {
+        let fixture = Fixture::new();
+        let sysroot = tempfile::tempdir()?;
+        fixture.add(
+            "foo",
+            "1.0-1",
+            "%SIZE%\n3072\n\n",
+            &[
+                "usr/",
+                "usr/bin/",
+                "usr/bin/foo",
+                "usr/lib/libfoo.so",
+                "usr/lib/missing",
+            ],
+        );
+        std::fs::create_dir_all(sysroot.path().join("usr/bin"))?;
+        std::fs::create_dir_all(sysroot.path().join("usr/lib"))?;
+        std::fs::write(sysroot.path().join("usr/bin/foo"), vec![0; 1024])?;
+        std::fs::write(sysroot.path().join("usr/lib/libfoo.so"), vec![0; 2000])?;
+
+        let mut db =
+            LocalDatabase::with_resolver(fixture.path(), PathResolver::new(sysroot.path()));
+        let entry = db.get("foo")?;
+        let (size, logs) = capture_logs(|| entry.installed_size_on_disk());
+        let size = size?;
+        assert_eq!(size, 3024);
+        let recorded = entry.desc.size.unwrap();
+        assert!(recorded.abs_diff(size) * 100 / recorded < 5);
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].1.contains("/usr/lib/missing"), "{}", logs[0].1);
+        Ok(())
+    }
+
+    #[test]
+    fn check_remove_db_entry() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        let owned = root.path().join("owned-file");
+        std::fs::write(&owned, "contents")?;
+        let owned_relative = owned.strip_prefix("/")?.to_str().unwrap();
+
+        let fixture = Fixture::new();
+        fixture.add("foo", "1.0-1", "", &[owned_relative]);
+        fixture.add("bar", "1.0-1", "", &[]);
+
+        let mut db = LocalDatabase::with_path(fixture.path());
+        db.remove_db_entry("foo")?;
+        assert!(!fixture.path().join("foo-1.0-1").exists());
+        assert!(fixture.path().join("bar-1.0-1").exists());
+        assert!(!db.db.contains_key("foo"));
+        assert_eq!(std::fs::read_to_string(&owned)?, "contents");
+        assert!(db.remove_db_entry("foo").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn check_remove_dependency_checks() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("foo", "2.0-1", "", &[]);
+        fixture.add("bar", "1.0-1", "%PROVIDES%\nfoo=1.0\n\n", &[]);
+        fixture.add("baz", "1.0-1", "%DEPENDS%\nfoo>=2\n\n", &[]);
+        fixture.add("qux", "1.0-1", "%DEPENDS%\nbar\n\n", &[]);
+        let mut db = LocalDatabase::with_path(fixture.path());
+
+        let err = db.remove(&["foo"], DependencyChecks::Full).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains(":: removing foo breaks dependency 'foo>=2' required by baz"));
+        // bar still provides something called foo
+        let transaction = db.remove(&["foo"], DependencyChecks::IgnoreVersions)?;
+        assert_eq!(transaction.remove, ["foo"]);
+
+        assert!(db
+            .remove(&["bar"], DependencyChecks::IgnoreVersions)
+            .is_err());
+        let transaction = db.remove(&["bar"], DependencyChecks::Skip)?;
+        assert_eq!(transaction.remove, ["bar"]);
+        assert_eq!(transaction.dependency_checks, DependencyChecks::Skip);
+
+        // Removing the dependent package as well is fine
+        db.remove(&["bar", "qux"], DependencyChecks::Full)?;
+        assert!(db.remove(&["missing"], DependencyChecks::Skip).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn check_reverse_dependencies() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("glibc", "2.33-4", "", &[]);
+        fixture.add("bash", "5.1.004-1", "%DEPENDS%\nglibc\nreadline\n\n", &[]);
+        fixture.add(
+            "readline",
+            "8.1.0-2",
+            "%DEPENDS%\nglibc\n\n%PROVIDES%\nlibreadline.so=8-64\n\n",
+            &[],
+        );
+        fixture.add(
+            "python",
+            "3.9.2-1",
+            "%DEPENDS%\nlibreadline.so=8-64\nmissing\n\n",
+            &[],
+        );
+        let mut db = LocalDatabase::with_path(fixture.path());
+
+        let map = db.reverse_dependencies()?;
+        assert_eq!(map["glibc"], ["bash", "readline"]);
+        assert_eq!(map["readline"], ["bash", "python"]);
+        assert!(map["python"].is_empty());
+        assert_eq!(map.len(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn check_explicit_packages() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("vim", "8.2-1", "%REASON%\n0\n\n", &[]);
+        fixture.add("glibc", "2.33-4", "%REASON%\n1\n\n", &[]);
+        fixture.add("bash", "5.1.004-1", "", &[]);
+        fixture.add("ncurses", "6.2-1", "%REASON%\n1\n\n", &[]);
+
+        let mut db = LocalDatabase::with_path(fixture.path());
+        assert_eq!(db.explicit_packages()?, ["bash", "vim"]);
+        Ok(())
+    }
+
+    #[test]
+    fn check_dump_manifest() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("vim", "8.2-1", "", &[]);
+        fixture.add("glibc", "2.33-4", "%REASON%\n1\n\n", &[]);
+        fixture.add("bash", "2:5.1.004-1", "", &[]);
+
+        let expected = "bash 2:5.1.004-1 explicit\nglibc 2.33-4 dependency\nvim 8.2-1 explicit\n";
+        let mut db = LocalDatabase::with_path(fixture.path());
+        assert_eq!(db.dump_manifest()?, expected);
+        assert_eq!(db.dump_manifest()?, expected);
+        assert_eq!(
+            LocalDatabase::with_path(fixture.path()).dump_manifest()?,
+            expected
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn check_audit_overlaps() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add(
+            "foo",
+            "1.0-1",
+            "",
+            &["usr/", "usr/lib/", "usr/lib/libshared.so", "usr/bin/foo"],
+        );
+        fixture.add(
+            "bar",
+            "1.0-1",
+            "",
+            &["usr/", "usr/lib/", "usr/lib/libshared.so", "usr/bin/bar"],
+        );
+        fixture.add("baz", "1.0-1", "", &["usr/", "usr/bin/foo"]);
+        let mut db = LocalDatabase::with_path(fixture.path());
+
+        assert_eq!(
+            db.audit_overlaps()?,
+            [
+                (
+                    PathBuf::from("/usr/bin/foo"),
+                    vec!["baz".to_owned(), "foo".to_owned()]
+                ),
+                (
+                    PathBuf::from("/usr/lib/libshared.so"),
+                    vec!["bar".to_owned(), "foo".to_owned()]
+                ),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn check_leaves() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("glibc", "2.33-4", "%REASON%\n1\n\n", &[]);
+        fixture.add("bash", "5.1.004-1", "%DEPENDS%\nglibc\n\n", &[]);
+        fixture.add("vim", "8.2-1", "%DEPENDS%\nglibc\n\n", &[]);
+        fixture.add("libfoo", "1.0-1", "%REASON%\n1\n\n", &[]);
+        let mut db = LocalDatabase::with_path(fixture.path());
+
+        assert_eq!(db.leaves()?, ["bash", "libfoo", "vim"]);
+        assert_eq!(db.orphans()?, ["libfoo"]);
+        Ok(())
+    }
 }
@@ -1,25 +1,31 @@
+use std::collections::HashMap;
+use std::fs::File;
 use std::io::prelude::*;
 use std::path::Path;
 
+use crate::database::compression;
 use crate::Result;
+use crate::{md5, sha256};
 
 /// Represents a single entry in an `mtree` file. This contains information about a single file
 /// owned by a single package.
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub struct MTreeEntry {
     /// The path of the file. Pacman seems to use relative paths from root, but it is much easier
     /// to work with absolute paths instead, so the leading `.` is stripped
     pub filepath: String,
     /// The checksum of the file.
     pub hashes: Hashes,
-    /// The unix permissions of the file.
+    /// The unix permissions of the file, which are written in octal in the `mtree`.
     pub mode: u16,
     /// The group that owns the file.
     pub gid: u32,
     /// The user that owns the file.
     pub uid: u32,
-    /// The time that the file was created.
+    /// The time that the file was last modified, in whole seconds since the Unix epoch.
     pub time: u64,
+    /// The fraction of a second after `time` that the file was last modified, in nanoseconds.
+    pub time_nanos: u32,
     /// The size of the file, in bytes.
     pub filesize: usize,
     /// The type of the file.
@@ -27,13 +33,47 @@ pub struct MTreeEntry {
     /// If the type is SymbolicLink, the target of the link, relative from the position of the file
     /// itself.
     pub link: Option<String>,
+    /// Any keywords not understood by the parser, and their values, in the order they appear.
+    pub extra: Vec<(String, String)>,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+impl MTreeEntry {
+    /// The absolute path of the file.
+    pub fn path(&self) -> &Path {
+        Path::new(self.filepath.as_str())
+    }
+
+    /// Whether the file is a symlink, whose target is in `link`.
+    pub fn is_symlink(&self) -> bool {
+        self.filetype == FileType::SymbolicLink
+    }
+
+    /// Whether the file is a directory.
+    pub fn is_dir(&self) -> bool {
+        self.filetype == FileType::Directory
+    }
+
+    /// The unix permission bits of the file, such as `0o644`.
+    pub fn permissions(&self) -> u16 {
+        self.mode
+    }
+
+    /// Whether this is one of the metadata files at the root of a package archive, such as
+    /// `.PKGINFO` or `.BUILDINFO`. These are recorded in the `mtree`, but never installed.
+    pub fn is_package_metadata(&self) -> bool {
+        let name = self.filepath.strip_prefix('/').unwrap_or(&self.filepath);
+        name.starts_with('.') && !name.contains('/')
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum FileType {
     Directory,
     File,
     SymbolicLink,
+    Block,
+    Char,
+    Fifo,
 
     None,
 }
@@ -41,114 +81,300 @@ pub enum FileType {
 #[derive(Debug, PartialEq, Eq)]
 pub struct Hashes {
     md5: Option<String>,
+    sha1: Option<String>,
     sha256: Option<String>,
+    rmd160: Option<String>,
+}
+
+impl Hashes {
+    /// The sha256 checksum of the file, as a lowercase hex string.
+    pub fn sha256(&self) -> Option<&str> {
+        self.sha256.as_deref()
+    }
+
+    /// Checks the contents of the file at `path` against the recorded sha256 checksum, or the md5
+    /// one if there is no sha256. Returns true if neither is recorded, as there is nothing to
+    /// compare against.
+    pub fn verify_file(&self, path: &Path) -> Result<bool> {
+        let (expected, actual) = match (&self.sha256, &self.md5) {
+            (Some(expected), _) => (expected, sha256::hex_digest(File::open(path)?)?),
+            (None, Some(expected)) => (expected, md5::hex_digest(File::open(path)?)?),
+            (None, None) => return Ok(true),
+        };
+        Ok(actual.eq_ignore_ascii_case(expected))
+    }
 }
 
 /// Reads an `mtree` file from disk, and returns a Vec of the parsed data.
 pub fn read_mtree_from_file<P: AsRef<Path>>(filepath: P) -> Result<Vec<MTreeEntry>> {
-    let mtree = {
-        let gzipped_bytes = std::fs::read(filepath)?;
-        let mut decoder = flate2::read::GzDecoder::new(&*gzipped_bytes);
-        let mut s = String::new();
-        decoder.read_to_string(&mut s)?;
-        s
-    };
-    read_mtree(mtree.as_str())
+    read_mtree(&compression::read_to_string(filepath)?)
+}
+
+/// The keywords that can be given defaults with a `/set` line. Pacman's mtree files use `/set` for
+/// the type, owner and mode, which are then overridden on individual lines where they differ.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct SetDefaults {
+    filetype: FileType,
+    uid: u32,
+    gid: u32,
+    mode: u16,
+}
+
+impl Default for SetDefaults {
+    fn default() -> Self {
+        Self {
+            filetype: FileType::None,
+            uid: 0,
+            gid: 0,
+            mode: 0o0000,
+        }
+    }
+}
+
+fn parse_filetype(s: &str) -> Option<FileType> {
+    Some(match s {
+        "file" => FileType::File,
+        "dir" => FileType::Directory,
+        "link" => FileType::SymbolicLink,
+        "block" => FileType::Block,
+        "char" => FileType::Char,
+        "fifo" => FileType::Fifo,
+        _ => return None,
+    })
+}
+
+fn filetype_keyword(filetype: FileType) -> Option<&'static str> {
+    Some(match filetype {
+        FileType::File => "file",
+        FileType::Directory => "dir",
+        FileType::SymbolicLink => "link",
+        FileType::Block => "block",
+        FileType::Char => "char",
+        FileType::Fifo => "fifo",
+        FileType::None => return None,
+    })
 }
 
-fn read_mtree(mtree: &str) -> Result<Vec<MTreeEntry>> {
+/// Parses the (decompressed) text of an `mtree` file.
+pub fn read_mtree(mtree: &str) -> Result<Vec<MTreeEntry>> {
     let mut ret = Vec::new();
-    let mut mode = 0o0000;
-    let mut gid = 0;
-    let mut uid = 0;
-    let mut filesize = 0;
+    let mut defaults = SetDefaults::default();
     for line in mtree.trim().split('\n') {
-        let mut filepath = None;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut sections = line.split(' ').map(|x| x.trim()).filter(|x| !x.is_empty());
+        let first = sections.next().unwrap();
+        if first == "/unset" {
+            defaults = SetDefaults::default();
+            continue;
+        }
+        let is_set = first == "/set";
+        let filepath = if is_set {
+            None
+        } else if let Some(stripped) = first.strip_prefix('.') {
+            Some(stripped.to_owned())
+        } else {
+            Some(first.to_owned())
+        };
+
         let mut hashes = Hashes {
             md5: None,
+            sha1: None,
             sha256: None,
+            rmd160: None,
         };
+        let mut current = defaults;
         let mut link = None;
-        let mut time = 0;
-        let mut filetype = FileType::None;
-
-        for section in line.trim().split(' ').map(|x| x.trim()) {
-            if !section.contains('=') {
-                if section.starts_with("/set") || section == "#mtree" {
-                    continue;
-                } else {
-                    filepath = if section.starts_with('.') {
-                        section.strip_prefix('.').map(|x| x.to_owned())
-                    } else {
-                        Some(section.to_owned())
-                    };
-                    continue;
-                }
-            }
-            let (first, second) = {
-                let mut it = section.split('=');
-                (it.next().unwrap().trim(), it.next().unwrap().trim())
+        let mut extra = Vec::new();
+        let mut time = (0, 0);
+        let mut filesize = 0;
+
+        for section in sections {
+            let (first, second) = match section.split_once('=') {
+                Some((first, second)) => (first.trim(), second.trim()),
+                None => continue,
             };
             match first {
-                "mode" => mode = second.parse()?,
-                "gid" => gid = second.parse()?,
-                "uid" => uid = second.parse()?,
+                "mode" => current.mode = parse_mode(second)?,
+                "gid" => current.gid = second.parse()?,
+                "uid" => current.uid = second.parse()?,
                 "size" => filesize = second.parse()?,
-                "time" => time = second.parse::<f64>()? as u64,
+                "time" => time = parse_time(second)?,
                 "link" => link = Some(second.to_owned()),
+                // Hardlink counts, file flags and `cksum` checksums aren't used by pacman
+                "nlink" | "flags" | "cksum" => {}
                 "type" => {
-                    filetype = match second {
-                        "file" => FileType::File,
-                        "dir" => FileType::Directory,
-                        "link" => FileType::SymbolicLink,
-                        _ => {
-                            return Err(format!(
-                                "Unknown filetype '{}' found in path '{}'",
-                                second,
-                                filepath.unwrap()
-                            )
-                            .into())
-                        }
-                    }
+                    current.filetype = parse_filetype(second).ok_or_else(|| {
+                        format!(
+                            "Unknown filetype '{}' found in path '{}'",
+                            second,
+                            filepath.as_deref().unwrap_or("/set")
+                        )
+                    })?
                 }
 
                 x if first.contains("digest") => {
                     let x = x.strip_suffix("digest").unwrap();
                     match x {
                         "md5" => hashes.md5 = Some(second.to_owned()),
+                        "sha1" => hashes.sha1 = Some(second.to_owned()),
                         "sha256" => hashes.sha256 = Some(second.to_owned()),
-                        _ => return Err(format!("Unknown hash type '{}' specified", second).into()),
+                        "rmd160" => hashes.rmd160 = Some(second.to_owned()),
+                        _ => extra.push((first.to_owned(), second.to_owned())),
                     }
                 }
 
-                x => {
-                    return Err(
-                        format!("Unknown mtree section '{}' found in '{}'.", x, line).into(),
-                    )
-                }
+                x => extra.push((x.to_owned(), second.to_owned())),
             }
         }
-        if let Some(filepath) = filepath {
-            ret.push(MTreeEntry {
+        match filepath {
+            Some(filepath) => ret.push(MTreeEntry {
                 filepath,
                 hashes,
-                mode,
-                gid,
-                uid,
-                time,
+                mode: current.mode,
+                gid: current.gid,
+                uid: current.uid,
+                time: time.0,
+                time_nanos: time.1,
                 filesize,
-                filetype,
+                filetype: current.filetype,
                 link,
-            });
+                extra,
+            }),
+            None => defaults = current,
         }
     }
 
     Ok(ret)
 }
 
+/// Parses the value of a `mode` keyword, which is written in octal, like `0644` or `755`. Only
+/// the permission bits (including setuid, setgid and sticky) can be given; the type of the file is
+/// in its `type` keyword instead.
+fn parse_mode(s: &str) -> Result<u16> {
+    match u16::from_str_radix(s, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(format!("Invalid mode '{}'", s).into()),
+    }
+}
+
+/// Parses the value of a `time` keyword, the seconds since the Unix epoch with an optional
+/// fraction, like `1615000000.123456789`, into whole seconds and nanoseconds. Digits after the
+/// ninth decimal place are ignored. This is done without going through a float, which can't hold
+/// nanoseconds at this magnitude.
+fn parse_time(s: &str) -> Result<(u64, u32)> {
+    let invalid = || format!("Invalid time '{}'", s);
+    let (seconds, fraction) = s.split_once('.').unwrap_or((s, ""));
+    if seconds.is_empty() || !fraction.bytes().all(|x| x.is_ascii_digit()) {
+        return Err(invalid().into());
+    }
+    let seconds = seconds.parse().map_err(|_| invalid())?;
+    let digits = &fraction[..fraction.len().min(9)];
+    let nanos = format!("{:0<9}", digits).parse().map_err(|_| invalid())?;
+    Ok((seconds, nanos))
+}
+
+/// Serializes `entries` into a gzip compressed `mtree` file, in the format that pacman stores in
+/// the local database. The type, owner and mode shared by the most entries are written once in a
+/// `/set` line, and only repeated on the lines that differ from it.
+pub fn write_mtree(entries: &[MTreeEntry]) -> Result<Vec<u8>> {
+    let mtree = format_mtree(entries);
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(mtree.as_bytes())?;
+    Ok(encoder.finish()?)
+}
+
+fn format_mtree(entries: &[MTreeEntry]) -> String {
+    use std::fmt::Write;
+
+    let key = |entry: &MTreeEntry| SetDefaults {
+        filetype: entry.filetype,
+        uid: entry.uid,
+        gid: entry.gid,
+        mode: entry.mode,
+    };
+    let mut counts = HashMap::new();
+    for entry in entries {
+        *counts.entry(key(entry)).or_insert(0usize) += 1;
+    }
+    // Ties go to whichever combination appears first, so the output doesn't depend on hash order
+    let mut defaults = entries
+        .iter()
+        .map(key)
+        .min_by_key(|x| std::cmp::Reverse(counts[x]))
+        .unwrap_or_default();
+    // A `/set type=` can't be overridden back to no type, so only use one if every entry has a type
+    if entries.iter().any(|x| x.filetype == FileType::None) {
+        defaults.filetype = FileType::None;
+    }
+
+    let mut ret = String::from("#mtree\n");
+    ret.push_str("/set");
+    if let Some(filetype) = filetype_keyword(defaults.filetype) {
+        write!(ret, " type={}", filetype).unwrap();
+    }
+    writeln!(
+        ret,
+        " uid={} gid={} mode={:o}",
+        defaults.uid, defaults.gid, defaults.mode
+    )
+    .unwrap();
+
+    for entry in entries {
+        write!(ret, ".{} time={}", entry.filepath, entry.time).unwrap();
+        if entry.time_nanos == 0 {
+            ret.push_str(".0");
+        } else {
+            write!(ret, ".{:09}", entry.time_nanos).unwrap();
+        }
+        if entry.uid != defaults.uid {
+            write!(ret, " uid={}", entry.uid).unwrap();
+        }
+        if entry.gid != defaults.gid {
+            write!(ret, " gid={}", entry.gid).unwrap();
+        }
+        if entry.mode != defaults.mode {
+            write!(ret, " mode={:o}", entry.mode).unwrap();
+        }
+        if entry.filesize != 0 {
+            write!(ret, " size={}", entry.filesize).unwrap();
+        }
+        if entry.filetype != defaults.filetype {
+            if let Some(filetype) = filetype_keyword(entry.filetype) {
+                write!(ret, " type={}", filetype).unwrap();
+            }
+        }
+        if let Some(link) = &entry.link {
+            write!(ret, " link={}", link).unwrap();
+        }
+        let hashes = [
+            ("md5", &entry.hashes.md5),
+            ("sha1", &entry.hashes.sha1),
+            ("sha256", &entry.hashes.sha256),
+            ("rmd160", &entry.hashes.rmd160),
+        ];
+        for (name, hash) in &hashes {
+            if let Some(hash) = hash {
+                write!(ret, " {}digest={}", name, hash).unwrap();
+            }
+        }
+        for (keyword, value) in &entry.extra {
+            write!(ret, " {}={}", keyword, value).unwrap();
+        }
+        ret.push('\n');
+    }
+    ret
+}
+
 #[cfg(test)]
 mod test {
+    use super::{FileType, Hashes, MTreeEntry};
+    use crate::database::compression;
+    use crate::test_util::Fixture;
     use crate::Result;
+    use std::path::Path;
 
     #[test]
     fn test_mtree() -> Result<()> {
@@ -156,4 +382,263 @@ mod test {
         println!("{:#?}", v);
         Ok(())
     }
+
+    #[test]
+    fn test_read_mtree_bom_and_crlf() -> Result<()> {
+        let mtree = "#mtree\n/set type=file uid=0 gid=0 mode=644\n\
+                     ./usr time=1615000000.0 mode=755 type=dir\n\
+                     ./usr/bin/foo time=1615000000.0 size=3 sha256digest=abcd\n";
+        let dir = tempfile::tempdir()?;
+        let crlf = dir.path().join("crlf");
+        let bom = dir.path().join("bom");
+        let crlf_mtree = mtree.replace('\n', "\r\n");
+        std::fs::write(&crlf, crate::test_util::gzip(crlf_mtree.as_bytes()))?;
+        std::fs::write(&bom, format!("\u{feff}{}", crlf_mtree))?;
+
+        let expected = super::read_mtree(mtree)?;
+        assert_eq!(expected.len(), 2);
+        assert_eq!(super::read_mtree_from_file(&crlf)?, expected);
+        assert_eq!(super::read_mtree_from_file(&bom)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_mtree_compressions() -> Result<()> {
+        let mtree = "#mtree\n/set type=file uid=0 gid=0 mode=644\n\
+                     ./usr time=1615000000.0 mode=755 type=dir\n\
+                     ./usr/bin/foo time=1615000000.0 size=3 sha256digest=abcd\n";
+        let dir = tempfile::tempdir()?;
+        let gzip = dir.path().join("gzip");
+        let zstd = dir.path().join("zstd");
+        let plain = dir.path().join("plain");
+        std::fs::write(&gzip, crate::test_util::gzip(mtree.as_bytes()))?;
+        std::fs::write(&zstd, zstd::stream::encode_all(mtree.as_bytes(), 0)?)?;
+        std::fs::write(&plain, mtree)?;
+
+        let expected = super::read_mtree(mtree)?;
+        assert_eq!(expected.len(), 2);
+        for path in [gzip, zstd, plain] {
+            assert_eq!(super::read_mtree_from_file(&path)?, expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_mtree_entry_accessors() -> Result<()> {
+        let v = super::read_mtree(
+            "#mtree\n/set type=file uid=0 gid=0 mode=644\n\
+             ./usr/bin time=1615000000.0 mode=755 type=dir\n\
+             ./usr/bin/sh time=1615000000.0 mode=777 type=link link=bash\n\
+             ./usr/bin/bash time=1615000000.0 mode=755 size=8\n\
+             ./etc/bash.bashrc time=1615000000.0 size=8\n",
+        )?;
+        let paths: Vec<_> = v.iter().map(|x| x.path()).collect();
+        assert_eq!(
+            paths,
+            [
+                Path::new("/usr/bin"),
+                Path::new("/usr/bin/sh"),
+                Path::new("/usr/bin/bash"),
+                Path::new("/etc/bash.bashrc")
+            ]
+        );
+        let symlinks: Vec<_> = v.iter().map(|x| x.is_symlink()).collect();
+        assert_eq!(symlinks, [false, true, false, false]);
+        let dirs: Vec<_> = v.iter().map(|x| x.is_dir()).collect();
+        assert_eq!(dirs, [true, false, false, false]);
+        let permissions: Vec<_> = v.iter().map(|x| x.permissions()).collect();
+        assert_eq!(permissions, [0o755, 0o777, 0o755, 0o644]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mtree_special_files() -> Result<()> {
+        let v = super::read_mtree(
+            "#mtree\n\
+             ./dev/null time=1615000000.0 mode=666 type=char\n\
+             ./dev/sda time=1615000000.0 mode=660 type=block\n\
+             ./run/initctl time=1615000000.0 mode=600 type=fifo\n\
+             ./usr/bin/perl5.32.1 time=1615000000.0 mode=755 size=8 nlink=2 flags=none cksum=1234 type=file\n",
+        )?;
+        let types: Vec<_> = v.iter().map(|x| &x.filetype).collect();
+        assert_eq!(
+            types,
+            [
+                &FileType::Char,
+                &FileType::Block,
+                &FileType::Fifo,
+                &FileType::File
+            ]
+        );
+        assert_eq!(v[3].filepath, "/usr/bin/perl5.32.1");
+        assert_eq!(v[3].filesize, 8);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mtree_unknown_keywords() -> Result<()> {
+        let v = super::read_mtree(
+            "#mtree\n\
+             ./etc/passwd time=1615000000.0 mode=644 size=10 flags=none uname=root gname=root sha512digest=abc type=file\n",
+        )?;
+        assert_eq!(v.len(), 1);
+        assert_eq!(v[0].filetype, FileType::File);
+        assert_eq!(
+            v[0].extra,
+            [
+                ("uname".to_owned(), "root".to_owned()),
+                ("gname".to_owned(), "root".to_owned()),
+                ("sha512digest".to_owned(), "abc".to_owned()),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_mtree_legacy_digests() -> Result<()> {
+        let v = super::read_mtree(
+            "#mtree\n\
+             ./usr/bin/foo time=1615000000.0 mode=755 size=0 md5digest=d41d8cd98f00b204e9800998ecf8427e sha1digest=da39a3ee5e6b4b0d3255bfef95601890afd80709 rmd160digest=9c1185a5c5e9fc54612808977ee8f548b2258d31 type=file\n",
+        )?;
+        let hashes = &v[0].hashes;
+        assert_eq!(
+            hashes.sha1.as_deref(),
+            Some("da39a3ee5e6b4b0d3255bfef95601890afd80709")
+        );
+        assert_eq!(
+            hashes.rmd160.as_deref(),
+            Some("9c1185a5c5e9fc54612808977ee8f548b2258d31")
+        );
+        assert_eq!(
+            hashes.md5.as_deref(),
+            Some("d41d8cd98f00b204e9800998ecf8427e")
+        );
+        assert_eq!(hashes.sha256, None);
+        assert!(v[0].extra.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_mtree_set_overrides_only_apply_to_their_line() -> Result<()> {
+        let v = super::read_mtree(
+            "#mtree\n\
+             /set type=file uid=0 gid=0 mode=644\n\
+             ./usr time=1615000000.0 mode=755 type=dir\n\
+             ./usr/foo time=1615000000.0 size=3\n",
+        )?;
+        assert_eq!(v[0].mode, 0o755);
+        assert_eq!(v[0].filetype, FileType::Directory);
+        assert_eq!(v[1].mode, 0o644);
+        assert_eq!(v[1].filetype, FileType::File);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mtree_round_trip() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("foo", "1.0-1", "", &["usr/", "usr/bin/", "usr/bin/foo"]);
+        let mut entries = super::read_mtree_from_file(fixture.path().join("foo-1.0-1/mtree"))?;
+        entries[2].filesize = 1234;
+        entries[2].uid = 1000;
+        entries[2].hashes.sha256 = Some("abcd".to_owned());
+        entries[2]
+            .extra
+            .push(("uname".to_owned(), "jamie".to_owned()));
+        entries.push(MTreeEntry {
+            filepath: "/usr/bin/bar".to_owned(),
+            hashes: Hashes {
+                md5: Some("d41d8cd98f00b204e9800998ecf8427e".to_owned()),
+                sha1: None,
+                sha256: None,
+                rmd160: None,
+            },
+            mode: 0o777,
+            gid: 0,
+            uid: 0,
+            time: 1615000001,
+            time_nanos: 500,
+            filesize: 0,
+            filetype: FileType::SymbolicLink,
+            link: Some("foo".to_owned()),
+            extra: Vec::new(),
+        });
+
+        let written = super::write_mtree(&entries)?;
+        let text = compression::decompress_to_string(&written)?;
+        assert!(text.starts_with("#mtree\n/set type="));
+        assert_eq!(super::read_mtree(&text)?, entries);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_mode() -> Result<()> {
+        let entries = super::read_mtree(
+            "#mtree\n/set type=file uid=0 gid=0 mode=0644\n\
+             ./a time=1615000000.0 size=0\n\
+             ./b time=1615000000.0 mode=4755 size=0\n",
+        )?;
+        assert_eq!(entries[0].mode, 0o644);
+        assert_eq!(entries[0].mode, 420);
+        assert_eq!(entries[1].mode, 0o4755);
+        for mode in ["", "0648", "-644", "10644", "177777", "rw-r--r--"].iter() {
+            assert!(super::parse_mode(mode).is_err(), "{:?}", mode);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_time() -> Result<()> {
+        let entries = super::read_mtree(
+            "#mtree\n/set type=file uid=0 gid=0 mode=644\n\
+             ./a time=1615000000.123456789 size=0\n\
+             ./b time=1615000000 size=0\n\
+             ./c time=1615000000.0 size=0\n\
+             ./d time=1615000000.5 size=0\n\
+             ./e time=18446744073709551615.0000000019 size=0\n",
+        )?;
+        let times: Vec<_> = entries.iter().map(|x| (x.time, x.time_nanos)).collect();
+        assert_eq!(
+            times,
+            [
+                (1615000000, 123456789),
+                (1615000000, 0),
+                (1615000000, 0),
+                (1615000000, 500000000),
+                (u64::MAX, 1),
+            ]
+        );
+        for time in ["", ".5", "1615000000.-5", "1615000000.5e3", "16150x"].iter() {
+            assert!(super::parse_time(time).is_err(), "{:?}", time);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("hello");
+        std::fs::write(&path, "hello\n")?;
+        let hashes = |md5: Option<&str>, sha256: Option<&str>| Hashes {
+            md5: md5.map(str::to_owned),
+            sha1: None,
+            sha256: sha256.map(str::to_owned),
+            rmd160: None,
+        };
+        let sha256 = "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03";
+        let md5 = "b1946ac92492d2347c6235b4d2611184";
+        let empty_md5 = "d41d8cd98f00b204e9800998ecf8427e";
+
+        assert!(hashes(None, Some(sha256)).verify_file(&path)?);
+        assert!(hashes(Some(md5), None).verify_file(&path)?);
+        // sha256 is preferred when both are recorded
+        assert!(hashes(Some(empty_md5), Some(sha256)).verify_file(&path)?);
+        assert!(!hashes(Some(md5), Some(&"0".repeat(64))).verify_file(&path)?);
+        assert!(!hashes(Some(empty_md5), None).verify_file(&path)?);
+        // With nothing to compare against, the file isn't even read
+        assert!(hashes(None, None).verify_file(&dir.path().join("missing"))?);
+        assert!(hashes(Some(md5), None)
+            .verify_file(&dir.path().join("missing"))
+            .is_err());
+        Ok(())
+    }
 }
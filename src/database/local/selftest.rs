@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::database::local::{LocalDatabase, LocalDatabaseEntry, SUPPORTED_DB_VERSION};
+use crate::Result;
+
+/// One of the invariants checked by `LocalDatabase::self_test`.
+#[derive(Debug)]
+pub struct SelfTestCheck {
+    pub name: &'static str,
+    /// A line for each problem found, naming the entry it was found in. The check passed if there
+    /// are none.
+    pub problems: Vec<String>,
+}
+
+/// The results of `LocalDatabase::self_test`, printed by `--selftest` for bug reports.
+#[derive(Debug)]
+pub struct SelfTestReport {
+    /// The number of entries in the database.
+    pub entries: usize,
+    pub checks: Vec<SelfTestCheck>,
+}
+
+impl SelfTestReport {
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|x| x.problems.is_empty())
+    }
+}
+
+impl fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in self.checks.iter() {
+            let status = if check.problems.is_empty() {
+                "PASS"
+            } else {
+                "FAIL"
+            };
+            writeln!(f, "[{}] {}", status, check.name)?;
+            for problem in check.problems.iter() {
+                writeln!(f, "       {}", problem)?;
+            }
+        }
+        let failed = self
+            .checks
+            .iter()
+            .filter(|x| !x.problems.is_empty())
+            .count();
+        if failed == 0 {
+            writeln!(f, "Checked {} entries: all checks passed", self.entries)
+        } else {
+            writeln!(
+                f,
+                "Checked {} entries: {} of {} checks failed",
+                self.entries,
+                failed,
+                self.checks.len()
+            )
+        }
+    }
+}
+
+impl LocalDatabase {
+    /// Reads every entry in the database and checks the invariants that the rest of the program
+    /// relies on: the database is in the supported format, every entry can be read, no version is
+    /// empty, no two entries have the same package name, and every file has an absolute path and a
+    /// mode that fits in the permission bits. Nothing is written, and the entries aren't kept in
+    /// the database, so broken ones are reported rather than skipped as `populate` does.
+    pub fn self_test(&self) -> Result<SelfTestReport> {
+        let mut names = self.source.entry_names()?;
+        names.sort_unstable();
+
+        let mut version = Vec::new();
+        match self.db_version() {
+            Ok(x) if x == SUPPORTED_DB_VERSION => {}
+            Ok(x) => version.push(format!(
+                "version {}, but only {} is supported",
+                x, SUPPORTED_DB_VERSION
+            )),
+            Err(e) => version.push(e.to_string()),
+        }
+        let mut unreadable = Vec::new();
+        let mut empty_versions = Vec::new();
+        let mut relative_paths = Vec::new();
+        let mut bad_modes = Vec::new();
+        let mut entries_by_name: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+        for name in names.iter() {
+            let entry = match LocalDatabaseEntry::from_source(self.source.clone(), name) {
+                Ok(entry) => entry,
+                Err(e) => {
+                    unreadable.push(format!("{}: {}", name, e));
+                    continue;
+                }
+            };
+            if entry.desc.version.is_empty() {
+                empty_versions.push(format!("{}: {} has no version", name, entry.desc.name));
+            }
+            entries_by_name
+                .entry(entry.desc.name.clone())
+                .or_default()
+                .push(name);
+            let files = match entry.mtree() {
+                Ok(files) => files,
+                Err(e) => {
+                    unreadable.push(format!("{}: {}", name, e));
+                    continue;
+                }
+            };
+            for file in files {
+                if !file.filepath.starts_with('/') {
+                    relative_paths.push(format!("{}: {}", name, file.filepath));
+                }
+                if file.mode > 0o7777 {
+                    bad_modes.push(format!(
+                        "{}: {} has mode {:o}",
+                        name, file.filepath, file.mode
+                    ));
+                }
+            }
+        }
+        let duplicates = entries_by_name
+            .into_iter()
+            .filter(|(_, entries)| entries.len() > 1)
+            .map(|(package, entries)| format!("{}: {}", package, entries.join(", ")))
+            .collect();
+
+        let check = |name, problems| SelfTestCheck { name, problems };
+        Ok(SelfTestReport {
+            entries: names.len(),
+            checks: vec![
+                check("database version is supported", version),
+                check("entries can be read", unreadable),
+                check("versions are not empty", empty_versions),
+                check("package names are unique", duplicates),
+                check("file paths are absolute", relative_paths),
+                check("file modes are in range", bad_modes),
+            ],
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::Fixture;
+
+    #[test]
+    fn test_self_test() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add(
+            "bash",
+            "5.1.004-1",
+            "",
+            &["usr/", "usr/bin/", "usr/bin/bash"],
+        );
+        fixture.add("glibc", "2.33-4", "", &["usr/", "usr/lib/"]);
+
+        let db = LocalDatabase::with_path(fixture.path());
+        let report = db.self_test()?;
+        assert!(report.passed());
+        assert_eq!(
+            report.to_string(),
+            "[PASS] database version is supported\n\
+             [PASS] entries can be read\n\
+             [PASS] versions are not empty\n\
+             [PASS] package names are unique\n\
+             [PASS] file paths are absolute\n\
+             [PASS] file modes are in range\n\
+             Checked 2 entries: all checks passed\n"
+        );
+
+        fixture.add_raw("broken-1.0-1", "%VERSION%\n1.0-1\n\n", "#mtree\n");
+        fixture.add_raw(
+            "empty-1.0-1",
+            "%NAME%\nempty\n\n%VERSION%\n \n\n",
+            "#mtree\n",
+        );
+        fixture.add_raw(
+            "bash-5.0-1",
+            "%NAME%\nbash\n\n%VERSION%\n5.0-1\n\n",
+            "#mtree\nusr/bin/bash time=1615000000.0 mode=755 size=0 type=file\n",
+        );
+        let report = db.self_test()?;
+        assert!(!report.passed());
+        let report = report.to_string();
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines[0], "[PASS] database version is supported");
+        assert_eq!(lines[1], "[FAIL] entries can be read");
+        assert!(lines[2].starts_with("       broken-1.0-1: "));
+        assert_eq!(
+            lines[3..],
+            [
+                "[FAIL] versions are not empty",
+                "       empty-1.0-1: empty has no version",
+                "[FAIL] package names are unique",
+                "       bash: bash-5.0-1, bash-5.1.004-1",
+                "[FAIL] file paths are absolute",
+                "       bash-5.0-1: usr/bin/bash",
+                "[PASS] file modes are in range",
+                "Checked 5 entries: 4 of 6 checks failed",
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,141 @@
+use std::cmp::Ordering;
+
+use crate::database::local::LocalDatabaseEntry;
+
+/// What to order a listing of installed packages by, as given to `--sort`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    Name,
+    /// The installed size, smallest first.
+    Size,
+    /// When the package was installed, oldest first.
+    InstallDate,
+    /// When the package was built, oldest first.
+    BuildDate,
+}
+
+impl std::str::FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "name" => Ok(SortKey::Name),
+            "size" => Ok(SortKey::Size),
+            "installdate" => Ok(SortKey::InstallDate),
+            "builddate" => Ok(SortKey::BuildDate),
+            _ => Err(format!(
+                "Invalid sort key '{}', expected one of name, size, installdate or builddate",
+                s
+            )),
+        }
+    }
+}
+
+/// Sorts `entries` by `key`, breaking ties by name, or in exactly the opposite order with
+/// `reverse`. Packages whose desc doesn't record the field being sorted by always come last.
+pub fn sort_entries(entries: &mut [&LocalDatabaseEntry], key: SortKey, reverse: bool) {
+    let field = |entry: &LocalDatabaseEntry| match key {
+        SortKey::Name => None,
+        SortKey::Size => entry.desc.size,
+        SortKey::InstallDate => entry.desc.install_date,
+        SortKey::BuildDate => entry.desc.build_date,
+    };
+    entries.sort_unstable_by(|a, b| {
+        let order = match (key, field(a), field(b)) {
+            (SortKey::Name, _, _) | (_, None, None) => Ordering::Equal,
+            (_, Some(x), Some(y)) => x.cmp(&y),
+            (_, Some(_), None) => return Ordering::Less,
+            (_, None, Some(_)) => return Ordering::Greater,
+        };
+        let order = order.then_with(|| a.desc.name.cmp(&b.desc.name));
+        if reverse {
+            order.reverse()
+        } else {
+            order
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::local::LocalDatabase;
+    use crate::test_util::Fixture;
+    use crate::Result;
+
+    fn sorted_names(db: &LocalDatabase, key: SortKey, reverse: bool) -> Vec<&str> {
+        let mut entries: Vec<_> = db.db.values().collect();
+        sort_entries(&mut entries, key, reverse);
+        entries.iter().map(|x| x.desc.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_parse_sort_key() {
+        assert_eq!("name".parse(), Ok(SortKey::Name));
+        assert_eq!("size".parse(), Ok(SortKey::Size));
+        assert_eq!("installdate".parse(), Ok(SortKey::InstallDate));
+        assert_eq!("builddate".parse(), Ok(SortKey::BuildDate));
+        assert!("date".parse::<SortKey>().is_err());
+    }
+
+    #[test]
+    fn test_sort_entries() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add(
+            "bash",
+            "5.1.004-1",
+            "%SIZE%\n8192\n\n%INSTALLDATE%\n1615000300\n\n%BUILDDATE%\n1614000100\n\n",
+            &[],
+        );
+        fixture.add(
+            "glibc",
+            "2.33-4",
+            "%SIZE%\n4096\n\n%INSTALLDATE%\n1615000100\n\n%BUILDDATE%\n1614000300\n\n",
+            &[],
+        );
+        fixture.add(
+            "zlib",
+            "1.2.11-4",
+            "%SIZE%\n4096\n\n%INSTALLDATE%\n1615000200\n\n",
+            &[],
+        );
+        fixture.add("acl", "2.2.53-3", "", &[]);
+        let mut db = LocalDatabase::with_path(fixture.path());
+        db.populate_full_database()?;
+
+        assert_eq!(
+            sorted_names(&db, SortKey::Name, false),
+            ["acl", "bash", "glibc", "zlib"]
+        );
+        assert_eq!(
+            sorted_names(&db, SortKey::Name, true),
+            ["zlib", "glibc", "bash", "acl"]
+        );
+        // Equal sizes are ordered by name, and acl records no size
+        assert_eq!(
+            sorted_names(&db, SortKey::Size, false),
+            ["glibc", "zlib", "bash", "acl"]
+        );
+        assert_eq!(
+            sorted_names(&db, SortKey::Size, true),
+            ["bash", "zlib", "glibc", "acl"]
+        );
+        assert_eq!(
+            sorted_names(&db, SortKey::InstallDate, false),
+            ["glibc", "zlib", "bash", "acl"]
+        );
+        assert_eq!(
+            sorted_names(&db, SortKey::InstallDate, true),
+            ["bash", "zlib", "glibc", "acl"]
+        );
+        assert_eq!(
+            sorted_names(&db, SortKey::BuildDate, false),
+            ["bash", "glibc", "acl", "zlib"]
+        );
+        assert_eq!(
+            sorted_names(&db, SortKey::BuildDate, true),
+            ["glibc", "bash", "zlib", "acl"]
+        );
+        Ok(())
+    }
+}
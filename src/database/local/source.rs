@@ -0,0 +1,255 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::database::compression;
+use crate::database::local::DatabaseError;
+use crate::Result;
+
+use log::warn;
+
+/// Where the entries of a local database are read from. Each entry is named like its directory in
+/// `/var/lib/pacman/local`, `<name>-<pkgver>-<pkgrel>`, and is made up of members such as `desc`,
+/// `mtree` and `files`. Sources are shared between threads while a database is populated.
+pub trait DatabaseSource: std::fmt::Debug + Send + Sync {
+    /// Lists the names of the entries that have both a `desc` and an `mtree`.
+    fn entry_names(&self) -> Result<Vec<String>>;
+
+    /// Reads a member of an entry, decompressing it if needed. Returns `None` if the entry has no
+    /// such member.
+    fn read_member(&self, entry: &str, member: &str) -> Result<Option<String>>;
+
+    /// Deletes an entry, along with all of its members.
+    fn remove_entry(&self, entry: &str) -> Result<()>;
+
+    /// Where an entry is stored, for reporting errors.
+    fn entry_path(&self, entry: &str) -> PathBuf;
+
+    /// Reads the contents of the `ALPM_DB_VERSION` file kept alongside the entries, if there is
+    /// one.
+    fn read_db_version(&self) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// When the list of entries last changed, so that a listing can be reused until it changes
+    /// again. Sources that can't tell return `None`, and are listed every time.
+    fn modified(&self) -> Option<SystemTime> {
+        None
+    }
+}
+
+/// Reads entries from a directory laid out like `/var/lib/pacman/local`, with one subdirectory per
+/// entry holding its members as (possibly compressed) files.
+#[derive(Debug)]
+pub struct FilesystemSource {
+    path: PathBuf,
+}
+
+impl FilesystemSource {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+/// Whether `path` is an entry directory, containing `desc` and `mtree` files. Directories that can't
+/// be looked inside are counted as entries, so that reading them reports the error rather than the
+/// package silently disappearing.
+fn is_valid_local_entry_dir(path: &Path) -> bool {
+    if !path.is_dir() {
+        return false;
+    }
+    match path.join("desc").metadata() {
+        Ok(metadata) => metadata.is_file() && path.join("mtree").is_file(),
+        Err(e) => e.kind() == std::io::ErrorKind::PermissionDenied,
+    }
+}
+
+impl DatabaseSource for FilesystemSource {
+    fn entry_names(&self) -> Result<Vec<String>> {
+        let mut ret = Vec::new();
+        let dir = match self.path.read_dir() {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(DatabaseError::DatabaseNotFound(self.path.clone()).into())
+            }
+            Err(e) => return Err(e.into()),
+        };
+        for subdir in dir {
+            let subdir = match subdir {
+                Ok(subdir) => subdir,
+                Err(e) => {
+                    warn!("Could not read '{}': {}", self.path.display(), e);
+                    continue;
+                }
+            };
+            if let Some(name) = subdir.file_name().to_str() {
+                if is_valid_local_entry_dir(&subdir.path()) {
+                    ret.push(name.to_owned());
+                }
+            }
+        }
+        Ok(ret)
+    }
+
+    fn read_member(&self, entry: &str, member: &str) -> Result<Option<String>> {
+        let path = self.entry_path(entry).join(member);
+        match path.metadata() {
+            Ok(metadata) if metadata.is_file() => compression::read_to_string(path).map(Some),
+            Ok(_) => Ok(None),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn remove_entry(&self, entry: &str) -> Result<()> {
+        Ok(std::fs::remove_dir_all(self.entry_path(entry))?)
+    }
+
+    fn entry_path(&self, entry: &str) -> PathBuf {
+        self.path.join(entry)
+    }
+
+    fn read_db_version(&self) -> Result<Option<String>> {
+        let path = self.path.join("ALPM_DB_VERSION");
+        if !path.is_file() {
+            return Ok(None);
+        }
+        Ok(Some(std::fs::read_to_string(path)?))
+    }
+
+    /// Entries are added and removed as subdirectories, which updates the mtime of the directory.
+    fn modified(&self) -> Option<SystemTime> {
+        self.path.metadata().and_then(|x| x.modified()).ok()
+    }
+}
+
+/// Holds entries in memory, so that a database can be built up without touching the disk.
+#[derive(Debug, Default)]
+pub struct MemorySource {
+    entries: Mutex<BTreeMap<String, BTreeMap<String, String>>>,
+}
+
+impl MemorySource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the (uncompressed) contents of a member of an entry, creating the entry if needed.
+    pub fn insert(&self, entry: &str, member: &str, contents: &str) {
+        self.entries
+            .lock()
+            .unwrap()
+            .entry(entry.to_owned())
+            .or_default()
+            .insert(member.to_owned(), contents.to_owned());
+    }
+}
+
+impl DatabaseSource for MemorySource {
+    fn entry_names(&self) -> Result<Vec<String>> {
+        Ok(self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, members)| members.contains_key("desc") && members.contains_key("mtree"))
+            .map(|(name, _)| name.clone())
+            .collect())
+    }
+
+    fn read_member(&self, entry: &str, member: &str) -> Result<Option<String>> {
+        Ok(self
+            .entries
+            .lock()
+            .unwrap()
+            .get(entry)
+            .and_then(|members| members.get(member))
+            .cloned())
+    }
+
+    fn remove_entry(&self, entry: &str) -> Result<()> {
+        match self.entries.lock().unwrap().remove(entry) {
+            Some(_) => Ok(()),
+            None => Err(format!("No database entry '{}'", entry).into()),
+        }
+    }
+
+    fn entry_path(&self, entry: &str) -> PathBuf {
+        PathBuf::from(entry)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::local::{LocalDatabase, PopulateFilter};
+    use crate::sysroot::PathResolver;
+
+    const MTREE: &str = "#mtree\n/set type=file uid=0 gid=0 mode=644\n";
+
+    #[test]
+    fn test_missing_database() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("local");
+        let mut db = LocalDatabase::with_path(&path);
+        let error = db.populate_full_database().unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<DatabaseError>(),
+            Some(&DatabaseError::DatabaseNotFound(path.clone()))
+        );
+        assert!(error.to_string().contains(&path.display().to_string()));
+        assert!(db.get("bash").is_err());
+
+        std::fs::create_dir(&path)?;
+        db.populate_full_database()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_memory_source() -> Result<()> {
+        let source = MemorySource::new();
+        source.insert("foo-1.0-1", "desc", "%NAME%\nfoo\n\n%VERSION%\n1.0-1\n\n");
+        source.insert(
+            "foo-1.0-1",
+            "mtree",
+            &format!("{}./etc/foo.conf size=0\n./usr/bin/foo size=0\n", MTREE),
+        );
+        source.insert(
+            "foo-1.0-1",
+            "files",
+            "%BACKUP%\netc/foo.conf\td41d8cd98f00b204e9800998ecf8427e\n",
+        );
+        source.insert(
+            "bar-2.0-1",
+            "desc",
+            "%NAME%\nbar\n\n%VERSION%\n2.0-1\n\n%DEPENDS%\nfoo\n\n",
+        );
+        source.insert(
+            "bar-2.0-1",
+            "mtree",
+            &format!("{}./usr/bin/bar size=0\n", MTREE),
+        );
+        // Without an mtree, this isn't a complete entry
+        source.insert("baz-1.0-1", "desc", "%NAME%\nbaz\n\n%VERSION%\n1.0-1\n\n");
+
+        let mut db = LocalDatabase::with_source(source, PathResolver::default());
+        assert!(db.contains("foo")?);
+        assert!(!db.contains("baz")?);
+        let foo = db.get("foo")?;
+        assert!(foo.owns("/usr/bin/foo")?);
+        assert_eq!(foo.backup_files()?, [PathBuf::from("/etc/foo.conf")]);
+
+        let errors = db.populate(&PopulateFilter::Substring(String::new()), true)?;
+        assert!(errors.is_empty());
+        let mut names: Vec<_> = db.package_names().collect();
+        names.sort_unstable();
+        assert_eq!(names, ["bar", "foo"]);
+        assert_eq!(db.leaves()?, ["bar"]);
+
+        db.remove_db_entry("bar")?;
+        assert!(!db.contains("bar")?);
+        assert_eq!(db.names()?, ["foo"]);
+        Ok(())
+    }
+}
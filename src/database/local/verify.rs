@@ -0,0 +1,167 @@
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use crate::database::local::mtree::{FileType, MTreeEntry};
+use crate::sysroot::PathResolver;
+use crate::Result;
+
+/// A difference between a file recorded in a package's `mtree` and the file on disk.
+#[derive(Debug, PartialEq, Eq)]
+pub enum FileIssue {
+    Missing(PathBuf),
+    TypeMismatch(PathBuf),
+    SizeMismatch {
+        path: PathBuf,
+        expected: usize,
+        actual: u64,
+    },
+    ModeMismatch {
+        path: PathBuf,
+        expected: u16,
+        actual: u16,
+    },
+    /// The owning user or group differs, as `(uid, gid)` pairs.
+    OwnerMismatch {
+        path: PathBuf,
+        expected: (u32, u32),
+        actual: (u32, u32),
+    },
+    ChecksumMismatch(PathBuf),
+}
+
+impl FileIssue {
+    /// The path of the file, as recorded in the `mtree`.
+    pub fn path(&self) -> &Path {
+        match self {
+            FileIssue::Missing(path)
+            | FileIssue::TypeMismatch(path)
+            | FileIssue::ChecksumMismatch(path)
+            | FileIssue::SizeMismatch { path, .. }
+            | FileIssue::ModeMismatch { path, .. }
+            | FileIssue::OwnerMismatch { path, .. } => path,
+        }
+    }
+}
+
+impl std::fmt::Display for FileIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileIssue::Missing(path) => write!(f, "{} (No such file or directory)", path.display()),
+            FileIssue::TypeMismatch(path) => write!(f, "{} (File type mismatch)", path.display()),
+            FileIssue::SizeMismatch {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} (Size mismatch: expected {}, found {})",
+                path.display(),
+                expected,
+                actual
+            ),
+            FileIssue::ModeMismatch {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} (Permissions mismatch: expected {:o}, found {:o})",
+                path.display(),
+                expected,
+                actual
+            ),
+            FileIssue::OwnerMismatch {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} (Ownership mismatch: expected {}:{}, found {}:{})",
+                path.display(),
+                expected.0,
+                expected.1,
+                actual.0,
+                actual.1
+            ),
+            FileIssue::ChecksumMismatch(path) => {
+                write!(f, "{} (SHA256 checksum mismatch)", path.display())
+            }
+        }
+    }
+}
+
+/// How thoroughly files are compared against their `mtree` entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyLevel {
+    /// Only check the existence, type, size, permissions and owner of each file (`-k`).
+    Quick,
+    /// Also compare the checksum of each file's contents (`-kk`).
+    Full,
+}
+
+/// Checks one file from an `mtree` against the disk, returning the first difference found.
+/// The metadata files at the root of a package archive (`.PKGINFO`, ...) are never installed, so
+/// they are skipped.
+pub fn verify_entry(
+    entry: &MTreeEntry,
+    resolver: &PathResolver,
+    level: VerifyLevel,
+) -> Result<Option<FileIssue>> {
+    let path = entry.path().to_owned();
+    if entry.is_package_metadata() {
+        return Ok(None);
+    }
+    let disk_path = resolver.resolve(&path);
+    let metadata = match disk_path.symlink_metadata() {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(Some(FileIssue::Missing(path)))
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let file_type = metadata.file_type();
+    let type_matches = match entry.filetype {
+        FileType::File => file_type.is_file(),
+        FileType::Directory => file_type.is_dir(),
+        FileType::SymbolicLink => file_type.is_symlink(),
+        _ => true,
+    };
+    if !type_matches {
+        return Ok(Some(FileIssue::TypeMismatch(path)));
+    }
+    if entry.is_symlink() {
+        return Ok(None);
+    }
+
+    let mode = (metadata.mode() & 0o7777) as u16;
+    if mode != entry.permissions() {
+        return Ok(Some(FileIssue::ModeMismatch {
+            path,
+            expected: entry.permissions(),
+            actual: mode,
+        }));
+    }
+    let owner = (metadata.uid(), metadata.gid());
+    if owner != (entry.uid, entry.gid) {
+        return Ok(Some(FileIssue::OwnerMismatch {
+            path,
+            expected: (entry.uid, entry.gid),
+            actual: owner,
+        }));
+    }
+    if entry.filetype != FileType::File {
+        return Ok(None);
+    }
+    if metadata.len() != entry.filesize as u64 {
+        return Ok(Some(FileIssue::SizeMismatch {
+            path,
+            expected: entry.filesize,
+            actual: metadata.len(),
+        }));
+    }
+    if level == VerifyLevel::Full && !entry.hashes.verify_file(&disk_path)? {
+        return Ok(Some(FileIssue::ChecksumMismatch(path)));
+    }
+    Ok(None)
+}
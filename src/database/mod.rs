@@ -1 +1,3 @@
+pub mod compression;
 pub mod local;
+pub mod sync;
@@ -0,0 +1,824 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::Path;
+
+use crate::config::{Repo, SigCheck};
+use crate::database::compression;
+use crate::database::local::desc::{self, PackageDescription};
+use crate::database::local::LocalDatabase;
+use crate::dependency::Dependency;
+use crate::download::{self, CacheValidators, RetryPolicy};
+use crate::signature;
+use crate::transaction::{DependencyChecks, SyncPackage, Transaction};
+use crate::version::vercmp;
+use crate::Result;
+
+use log::{debug, warn};
+use regex::Regex;
+
+/// A sync database (e.g. `/var/lib/pacman/sync/core.db`), listing the packages available from a
+/// repository. It is a compressed tar archive with a `desc` file for each package, in the same
+/// format as the local database.
+#[derive(Debug)]
+pub struct SyncDatabase {
+    /// The name of the repository.
+    pub name: String,
+    pub packages: HashMap<String, PackageDescription>,
+}
+
+impl SyncDatabase {
+    /// Reads the sync database for the repository `name` from disk.
+    pub fn read_from_file<P: AsRef<Path>>(name: &str, filepath: P) -> Result<Self> {
+        Self::from_reader(name, BufReader::new(File::open(filepath)?))
+    }
+
+    /// Reads a sync database from the contents of its (possibly compressed) archive.
+    pub fn from_archive(name: &str, archive: &[u8]) -> Result<Self> {
+        Self::from_reader(name, archive)
+    }
+
+    /// Reads a sync database from a (possibly compressed) archive, decompressing and parsing it
+    /// one entry at a time.
+    pub fn from_reader<R: BufRead>(name: &str, reader: R) -> Result<Self> {
+        let mut packages = HashMap::new();
+        let mut archive = tar::Archive::new(compression::decoder(reader)?);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let path = String::from_utf8_lossy(&entry.path_bytes()).into_owned();
+            if !path.ends_with("/desc") {
+                continue;
+            }
+            let mut text = String::new();
+            entry.read_to_string(&mut text)?;
+            let desc = desc::parse_desc(&text)
+                .map_err(|e| format!("Could not read '{}' in {}.db: {}", path, name, e))?;
+            packages.insert(desc.name.clone(), desc);
+        }
+        Ok(Self {
+            name: name.to_owned(),
+            packages,
+        })
+    }
+
+    pub fn get(&self, package_name: &str) -> Option<&PackageDescription> {
+        self.packages.get(package_name)
+    }
+}
+
+/// The sync databases of several repositories, in order of precedence. When more than one
+/// repository has a package, the one listed first in `pacman.conf` is used.
+#[derive(Debug, Default)]
+pub struct SyncDatabases {
+    databases: Vec<SyncDatabase>,
+}
+
+impl SyncDatabases {
+    pub fn new(databases: Vec<SyncDatabase>) -> Self {
+        Self { databases }
+    }
+
+    /// Finds a package in the first repository that has it, returning the repository's name
+    /// along with the package.
+    pub fn get(&self, package_name: &str) -> Option<(&str, &PackageDescription)> {
+        self.databases
+            .iter()
+            .find_map(|db| Some((db.name.as_str(), db.get(package_name)?)))
+    }
+
+    /// Like `get`, but only looks in the repository `repo` if it is given.
+    pub fn get_from(
+        &self,
+        repo: Option<&str>,
+        package_name: &str,
+    ) -> Option<(&str, &PackageDescription)> {
+        match repo {
+            Some(repo) => {
+                let db = self.databases.iter().find(|db| db.name == repo)?;
+                Some((db.name.as_str(), db.get(package_name)?))
+            }
+            None => self.get(package_name),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &SyncDatabase> {
+        self.databases.iter()
+    }
+
+    /// Finds the packages that satisfy `dep`, either by name or through their `provides`. A
+    /// package named after the dependency comes first, then the other providers in order of
+    /// precedence of their repositories, and by name within each one. A package that is in more
+    /// than one repository is only taken from the first.
+    pub fn providers(&self, dep: &Dependency) -> Vec<(&str, &PackageDescription)> {
+        let mut ret: Vec<(&str, &PackageDescription)> = Vec::new();
+        for db in self.databases.iter() {
+            let mut providers: Vec<&PackageDescription> = db
+                .packages
+                .values()
+                .filter(|desc| dep.is_satisfied_by_package(desc))
+                .filter(|desc| !ret.iter().any(|(_, x)| x.name == desc.name))
+                .collect();
+            providers.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+            ret.extend(providers.into_iter().map(|desc| (db.name.as_str(), desc)));
+        }
+        // Stable, so that the repository precedence is kept among the rest
+        ret.sort_by_key(|(_, desc)| desc.name != dep.name);
+        ret
+    }
+
+    /// Finds the packages whose name or description matches every one of `patterns`, like
+    /// `pacman -Ss`. Only the repository `repo` is searched if it is given. The matches are
+    /// returned with the name of their repository, in order of precedence and then by name.
+    pub fn search(
+        &self,
+        patterns: &[Regex],
+        repo: Option<&str>,
+    ) -> Result<Vec<(&str, &PackageDescription)>> {
+        if let Some(repo) = repo {
+            if !self.databases.iter().any(|db| db.name == repo) {
+                return Err(format!("repository '{}' not found", repo).into());
+            }
+        }
+        let mut ret = Vec::new();
+        for db in self
+            .databases
+            .iter()
+            .filter(|db| repo.is_none_or(|x| db.name == x))
+        {
+            let mut matches: Vec<&PackageDescription> = db
+                .packages
+                .values()
+                .filter(|desc| desc.matches_all(patterns))
+                .collect();
+            matches.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+            ret.extend(matches.into_iter().map(|desc| (db.name.as_str(), desc)));
+        }
+        Ok(ret)
+    }
+}
+
+/// Reads the sync database of each of `repos` from `sync_dir`, in the order they are configured.
+/// Repositories whose database hasn't been downloaded yet are skipped.
+pub fn read_sync_databases(sync_dir: &Path, repos: &[Repo]) -> Result<SyncDatabases> {
+    let mut ret = Vec::new();
+    for repo in repos {
+        let path = sync_dir.join(format!("{}.db", repo.name));
+        if path.is_file() {
+            ret.push(SyncDatabase::read_from_file(&repo.name, path)?);
+        }
+    }
+    Ok(SyncDatabases::new(ret))
+}
+
+/// Downloads the sync database of each of `repos` from its mirrors into `sync_dir`, like
+/// `pacman -Sy`. The `Last-Modified` and `ETag` headers of each database are saved next to it, so
+/// that a database which hasn't changed since it was last downloaded isn't downloaded again,
+/// unless `force` is set (`-Syy`). The signature of each new database is downloaded and checked
+/// against the keyring in `gpg_dir` according to the repository's `SigLevel`, and the database
+/// isn't replaced if its signature is invalid, or missing when one is required. Returns each
+/// repository's name and whether it was downloaded.
+pub fn refresh_databases<'a>(
+    repos: &'a [Repo],
+    arch: &str,
+    sync_dir: &Path,
+    gpg_dir: &Path,
+    force: bool,
+    policy: &RetryPolicy,
+) -> Result<Vec<(&'a str, bool)>> {
+    std::fs::create_dir_all(sync_dir)?;
+    let mut ret = Vec::new();
+    for repo in repos {
+        let file = format!("{}.db", repo.name);
+        let path = sync_dir.join(&file);
+        let validators_path = sync_dir.join(format!("{}.validators", file));
+        let validators = if force || !path.is_file() {
+            CacheValidators::default()
+        } else {
+            CacheValidators::read_from_file(&validators_path)?
+        };
+        let downloaded =
+            download::download_if_modified(&repo.mirrors(arch), &file, policy, &validators)
+                .map_err(|e| format!("Failed to update {}: {}", repo.name, e))?;
+        if let Some((data, validators)) = &downloaded {
+            // Check the new database can be read before replacing the old one
+            SyncDatabase::from_archive(&repo.name, data)?;
+            let part = sync_dir.join(format!("{}.part", file));
+            std::fs::write(&part, data)?;
+            if let Err(e) = check_database_signature(repo, arch, sync_dir, gpg_dir, policy) {
+                std::fs::remove_file(&part)?;
+                return Err(e);
+            }
+            std::fs::rename(&part, &path)?;
+            validators.write_to_file(&validators_path)?;
+        }
+        ret.push((repo.name.as_str(), downloaded.is_some()));
+    }
+    Ok(ret)
+}
+
+/// Downloads the signature of the new database of `repo`, which is in `<repo>.db.part` in
+/// `sync_dir`, and checks it. A valid signature replaces the old database's signature. A missing
+/// signature is only an error if the repository's `SigLevel` requires one.
+fn check_database_signature(
+    repo: &Repo,
+    arch: &str,
+    sync_dir: &Path,
+    gpg_dir: &Path,
+    policy: &RetryPolicy,
+) -> Result<()> {
+    let check = repo.sig_level.database;
+    let sig = format!("{}.db.sig", repo.name);
+    let sig_path = sync_dir.join(&sig);
+    let signature = match check {
+        SigCheck::Never => None,
+        _ => match download::download(&repo.mirrors(arch), &sig, policy) {
+            Ok(signature) => Some(signature),
+            Err(e) if check == SigCheck::Required => {
+                return Err(format!(
+                    "Database '{}' has no signature, which is required by its SigLevel: {}",
+                    repo.name, e
+                )
+                .into());
+            }
+            Err(e) => {
+                debug!("No signature was downloaded for {}: {}", repo.name, e);
+                None
+            }
+        },
+    };
+    let signature = match signature {
+        Some(signature) => signature,
+        None => {
+            // Any signature that's already there is for the old database
+            if sig_path.exists() {
+                std::fs::remove_file(&sig_path)?;
+            }
+            return Ok(());
+        }
+    };
+    let sig_part = sync_dir.join(format!("{}.part", sig));
+    std::fs::write(&sig_part, signature)?;
+    let part = sync_dir.join(format!("{}.db.part", repo.name));
+    if let Err(e) = signature::verify(&part, &sig_part, gpg_dir) {
+        std::fs::remove_file(&sig_part)?;
+        return Err(e);
+    }
+    std::fs::rename(&sig_part, &sig_path)?;
+    Ok(())
+}
+
+/// Splits a target such as `extra/firefox` into the repository it is restricted to, if any, and
+/// the package name.
+pub fn parse_target(s: &str) -> (Option<String>, String) {
+    match s.split_once('/') {
+        Some((repo, name)) => (Some(repo.to_owned()), name.to_owned()),
+        None => (None, s.to_owned()),
+    }
+}
+
+/// The targets of `pacman -S`, grouped by what installing each of them would do.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct TargetClasses {
+    /// The packages that aren't installed.
+    pub installs: Vec<String>,
+    /// The packages that are installed at a different version from the one that would be
+    /// installed.
+    pub upgrades: Vec<String>,
+    /// The packages that are installed at the same version, so they would only be reinstalled.
+    pub reinstalls: Vec<String>,
+    /// The targets that aren't the name of any package in the sync databases.
+    pub missing: Vec<String>,
+}
+
+/// Works out what installing each of `targets` from `databases` would do to the packages installed
+/// in `local`. Targets are found by name in the same way as `plan_install`, but not through the
+/// packages that provide them. Each package is listed by its name, and each missing target as it
+/// was given, in the order of `targets`.
+pub fn classify_targets(
+    targets: &[&str],
+    local: &mut LocalDatabase,
+    databases: &SyncDatabases,
+) -> Result<TargetClasses> {
+    let mut ret = TargetClasses::default();
+    for target in targets {
+        let (repo, name) = parse_target(target);
+        let desc = match databases.get_from(repo.as_deref(), &name) {
+            Some((_, desc)) => desc,
+            None => {
+                ret.missing.push(target.to_string());
+                continue;
+            }
+        };
+        let class = if !local.contains(&desc.name)? {
+            &mut ret.installs
+        } else if vercmp(&local.get(&desc.name)?.desc.version, &desc.version) == Ordering::Equal {
+            &mut ret.reinstalls
+        } else {
+            &mut ret.upgrades
+        };
+        class.push(desc.name.clone());
+    }
+    Ok(ret)
+}
+
+/// Plans the installation of `targets`, each taken from the first repository that has it, or from
+/// the repository it names (`<repo>/<name>`). A target that isn't the name of a package is treated
+/// as a dependency, such as `cron`, and if more than one package provides it, `chooser` is called
+/// with the target and the names of the providers and returns the index of the one to install, as
+/// in `Resolver::resolve_with_chooser`. A warning is logged for each package that isn't built for
+/// the `machine` architecture.
+/// Dependencies of the targets are not resolved yet, so `checks` is only recorded.
+pub fn plan_install(
+    databases: &SyncDatabases,
+    targets: &[&str],
+    machine: &str,
+    checks: DependencyChecks,
+    mut chooser: impl FnMut(&str, &[&str]) -> usize,
+) -> Result<Transaction> {
+    let mut install = Vec::new();
+    for target in targets {
+        let (repo, name) = parse_target(target);
+        let (repo, desc) = match databases.get_from(repo.as_deref(), &name) {
+            Some(found) => found,
+            None => {
+                let dep: Dependency = name.parse()?;
+                let providers: Vec<_> = databases
+                    .providers(&dep)
+                    .into_iter()
+                    .filter(|(x, _)| repo.as_deref().is_none_or(|repo| *x == repo))
+                    .collect();
+                let i = match providers.len() {
+                    0 => return Err(format!("target not found: {}", target).into()),
+                    1 => 0,
+                    _ => {
+                        let names: Vec<&str> =
+                            providers.iter().map(|(_, x)| x.name.as_str()).collect();
+                        chooser(target, &names)
+                    }
+                };
+                *providers
+                    .get(i)
+                    .ok_or_else(|| format!("invalid choice {} of provider for '{}'", i, target))?
+            }
+        };
+        if !desc.is_compatible_with(machine) {
+            warn!(
+                "{} is built for {}, not {}",
+                desc.name,
+                desc.arch.as_ref().map_or("", |x| x.as_str()),
+                machine
+            );
+        }
+        install.push(SyncPackage {
+            repo: repo.to_owned(),
+            desc: desc.clone(),
+        });
+    }
+    Ok(Transaction {
+        install,
+        dependency_checks: checks,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::SigLevel;
+    use crate::test_util::{
+        capture_logs, gpg_dir, gzip, have_gpgv, header, mock_server_with, Fixture, TarBuilder,
+    };
+    use log::Level;
+    use std::time::Duration;
+
+    #[test]
+    fn test_read_sync_database() -> Result<()> {
+        let archive = TarBuilder::new()
+            .directory("bash-5.1.004-1/", 0o755)
+            .file(
+                "bash-5.1.004-1/desc",
+                0o644,
+                b"%FILENAME%\nbash-5.1.004-1-x86_64.pkg.tar.zst\n\n%NAME%\nbash\n\n\
+                  %VERSION%\n5.1.004-1\n\n%CSIZE%\n1234\n\n%ISIZE%\n5678\n\n\
+                  %MD5SUM%\nabcd\n\n%SHA256SUM%\nef01\n\n%DEPENDS%\nreadline\n\n",
+            )
+            .directory("glibc-2.33-4/", 0o755)
+            .file(
+                "glibc-2.33-4/desc",
+                0o644,
+                b"%NAME%\nglibc\n\n%VERSION%\n2.33-4\n\n",
+            )
+            .finish();
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("core.db"), gzip(&archive))?;
+        let repos = [
+            Repo {
+                name: "core".into(),
+                servers: Vec::new(),
+                sig_level: SigLevel::default(),
+            },
+            Repo {
+                name: "missing".into(),
+                servers: Vec::new(),
+                sig_level: SigLevel::default(),
+            },
+        ];
+
+        let dbs = read_sync_databases(dir.path(), &repos)?;
+        let core: Vec<_> = dbs.iter().collect();
+        assert_eq!(core.len(), 1);
+        assert_eq!(core[0].name, "core");
+        assert_eq!(core[0].packages.len(), 2);
+        let (repo, bash) = dbs.get("bash").unwrap();
+        assert_eq!(repo, "core");
+        assert_eq!(
+            bash.filename.as_deref(),
+            Some("bash-5.1.004-1-x86_64.pkg.tar.zst")
+        );
+        assert_eq!(bash.download_size, Some(1234));
+        assert_eq!(bash.size, Some(5678));
+        assert_eq!(bash.sha256sum.as_deref(), Some("ef01"));
+        assert_eq!(bash.dependencies, ["readline"]);
+
+        let transaction =
+            plan_install(&dbs, &["glibc"], "x86_64", DependencyChecks::Full, |_, _| 0)?;
+        assert_eq!(transaction.install[0].repo, "core");
+        assert_eq!(transaction.install[0].desc.version, "2.33-4");
+        assert!(plan_install(
+            &dbs,
+            &["missing"],
+            "x86_64",
+            DependencyChecks::Full,
+            |_, _| 0
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_repo_precedence() -> Result<()> {
+        let db = |name: &str, version: &str| {
+            let desc = format!("%NAME%\nfoo\n\n%VERSION%\n{}\n\n", version);
+            let archive = TarBuilder::new()
+                .file(&format!("foo-{}/desc", version), 0o644, desc.as_bytes())
+                .finish();
+            SyncDatabase::from_archive(name, &archive)
+        };
+        let dbs = SyncDatabases::new(vec![db("testing", "2.0-1")?, db("core", "1.0-1")?]);
+        let (repo, foo) = dbs.get("foo").unwrap();
+        assert_eq!(repo, "testing");
+        assert_eq!(foo.version, "2.0-1");
+        assert!(dbs.get("bar").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_search() -> Result<()> {
+        let db = |name: &str, packages: &[(&str, &str)]| {
+            let mut archive = TarBuilder::new();
+            for (package, description) in packages {
+                let desc = format!(
+                    "%NAME%\n{}\n\n%VERSION%\n1.0-1\n\n%DESC%\n{}\n\n",
+                    package, description
+                );
+                archive = archive.file(&format!("{}-1.0-1/desc", package), 0o644, desc.as_bytes());
+            }
+            SyncDatabase::from_archive(name, &archive.finish())
+        };
+        let dbs = SyncDatabases::new(vec![
+            db(
+                "core",
+                &[
+                    ("bash", "The GNU Bourne Again shell"),
+                    ("zsh", "A very advanced shell"),
+                ],
+            )?,
+            db(
+                "extra",
+                &[
+                    ("fish", "Smart and user friendly shell"),
+                    ("python", "Next generation scripting language"),
+                ],
+            )?,
+        ]);
+        let names = |patterns: &[&str], repo: Option<&str>| -> Result<Vec<String>> {
+            let patterns = patterns
+                .iter()
+                .map(|x| Regex::new(x))
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+            Ok(dbs
+                .search(&patterns, repo)?
+                .iter()
+                .map(|(repo, desc)| format!("{}/{}", repo, desc.name))
+                .collect())
+        };
+
+        assert_eq!(
+            names(&["shell"], None)?,
+            ["core/bash", "core/zsh", "extra/fish"]
+        );
+        assert_eq!(names(&["shell"], Some("core"))?, ["core/bash", "core/zsh"]);
+        assert!(names(&["fish"], Some("core"))?.is_empty());
+        assert_eq!(names(&["fish"], Some("extra"))?, ["extra/fish"]);
+        // Every pattern has to match, either the name or the description
+        assert_eq!(
+            names(&["shell", "^[bf]"], None)?,
+            ["core/bash", "extra/fish"]
+        );
+        assert!(names(&["shell"], Some("community")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_providers() -> Result<()> {
+        let db = |name: &str, packages: &[(&str, &str)]| {
+            let mut archive = TarBuilder::new();
+            for (package, provides) in packages {
+                let desc = format!(
+                    "%NAME%\n{}\n\n%VERSION%\n1.0-1\n\n%PROVIDES%\n{}\n\n",
+                    package, provides
+                );
+                archive = archive.file(&format!("{}-1.0-1/desc", package), 0o644, desc.as_bytes());
+            }
+            SyncDatabase::from_archive(name, &archive.finish())
+        };
+        let dbs = SyncDatabases::new(vec![
+            db("core", &[("cronie", "cron"), ("dcron", "cron")])?,
+            db(
+                "extra",
+                &[("fcron", "cron"), ("dcron", "cron"), ("cron", "cron")],
+            )?,
+        ]);
+        let providers: Vec<String> = dbs
+            .providers(&"cron".parse()?)
+            .iter()
+            .map(|(repo, desc)| format!("{}/{}", repo, desc.name))
+            .collect();
+        assert_eq!(
+            providers,
+            ["extra/cron", "core/cronie", "core/dcron", "extra/fcron"]
+        );
+        assert!(dbs.providers(&"anacron".parse()?).is_empty());
+
+        let checks = DependencyChecks::Full;
+        let mut asked = Vec::new();
+        let transaction =
+            plan_install(&dbs, &["core/cron"], "x86_64", checks, |dep, providers| {
+                asked.push(format!("{}: {}", dep, providers.join(" ")));
+                1
+            })?;
+        assert_eq!(asked, ["core/cron: cronie dcron"]);
+        assert_eq!(transaction.install[0].desc.name, "dcron");
+        assert_eq!(transaction.install[0].repo, "core");
+        // A package named after the target is installed without asking
+        let transaction = plan_install(&dbs, &["cron"], "x86_64", checks, |_, _| {
+            panic!("chooser called")
+        })?;
+        assert_eq!(transaction.install[0].repo, "extra");
+        assert!(plan_install(&dbs, &["anacron"], "x86_64", checks, |_, _| 0).is_err());
+        assert!(plan_install(&dbs, &["core/cron"], "x86_64", checks, |_, _| 2).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_classify_targets() -> Result<()> {
+        let archive = TarBuilder::new()
+            .file(
+                "bash-5.1.004-1/desc",
+                0o644,
+                b"%NAME%\nbash\n\n%VERSION%\n5.1.004-1\n\n",
+            )
+            .file(
+                "linux-5.12.1-1/desc",
+                0o644,
+                b"%NAME%\nlinux\n\n%VERSION%\n5.12.1-1\n\n",
+            )
+            .file(
+                "vim-8.2-1/desc",
+                0o644,
+                b"%NAME%\nvim\n\n%VERSION%\n8.2-1\n\n",
+            )
+            .finish();
+        let dbs = SyncDatabases::new(vec![SyncDatabase::from_archive("core", &archive)?]);
+        let fixture = Fixture::new();
+        fixture.add("bash", "5.1.004-1", "", &[]);
+        fixture.add("linux", "5.11.6-1", "", &[]);
+        let mut local = LocalDatabase::with_path(fixture.path());
+
+        let classes = classify_targets(
+            &["vim", "core/linux", "bash", "emacs", "extra/vim"],
+            &mut local,
+            &dbs,
+        )?;
+        assert_eq!(
+            classes,
+            TargetClasses {
+                installs: vec!["vim".into()],
+                upgrades: vec!["linux".into()],
+                reinstalls: vec!["bash".into()],
+                missing: vec!["emacs".into(), "extra/vim".into()],
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_target() -> Result<()> {
+        assert_eq!(
+            parse_target("core/bash"),
+            (Some("core".to_owned()), "bash".to_owned())
+        );
+        assert_eq!(parse_target("bash"), (None, "bash".to_owned()));
+
+        let db = |name: &str| {
+            let archive = TarBuilder::new()
+                .file(
+                    "foo-1.0-1/desc",
+                    0o644,
+                    b"%NAME%\nfoo\n\n%VERSION%\n1.0-1\n\n",
+                )
+                .finish();
+            SyncDatabase::from_archive(name, &archive)
+        };
+        let dbs = SyncDatabases::new(vec![db("testing")?, db("core")?]);
+        let checks = DependencyChecks::Full;
+        assert_eq!(
+            plan_install(&dbs, &["foo"], "x86_64", checks, |_, _| 0)?.install[0].repo,
+            "testing"
+        );
+        assert_eq!(
+            plan_install(&dbs, &["core/foo"], "x86_64", checks, |_, _| 0)?.install[0].repo,
+            "core"
+        );
+        assert!(plan_install(&dbs, &["extra/foo"], "x86_64", checks, |_, _| 0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_install_warns_incompatible() -> Result<()> {
+        let archive = TarBuilder::new()
+            .file(
+                "foo-1.0-1/desc",
+                0o644,
+                b"%NAME%\nfoo\n\n%VERSION%\n1.0-1\n\n%ARCH%\nx86_64\n\n",
+            )
+            .finish();
+        let dbs = SyncDatabases::new(vec![SyncDatabase::from_archive("core", &archive)?]);
+        let (result, logs) = capture_logs(|| {
+            plan_install(&dbs, &["foo"], "aarch64", DependencyChecks::Full, |_, _| 0)
+        });
+        result?;
+        assert_eq!(
+            logs,
+            [(
+                Level::Warn,
+                "foo is built for x86_64, not aarch64".to_owned()
+            )]
+        );
+        let (_, logs) = capture_logs(|| {
+            plan_install(&dbs, &["foo"], "x86_64", DependencyChecks::Full, |_, _| 0)
+        });
+        assert!(logs.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_refresh_databases() -> Result<()> {
+        let archive = TarBuilder::new()
+            .file(
+                "foo-1.0-1/desc",
+                0o644,
+                b"%NAME%\nfoo\n\n%VERSION%\n1.0-1\n\n",
+            )
+            .finish();
+        let db = gzip(&archive);
+        let response = |db: &[u8]| {
+            let mut response = format!(
+                "HTTP/1.0 200 OK\r\nETag: \"v1\"\r\nContent-Length: {}\r\n\r\n",
+                db.len()
+            )
+            .into_bytes();
+            response.extend_from_slice(db);
+            response
+        };
+        let (server, requests) = mock_server_with(3, move |i, _| match i {
+            1 => b"HTTP/1.0 304 Not Modified\r\n\r\n".to_vec(),
+            _ => response(&db),
+        });
+        let mut sig_level = SigLevel::default();
+        sig_level.apply("DatabaseNever")?;
+        let repos = [Repo {
+            name: "core".into(),
+            servers: vec![format!("{}/$repo/os/$arch", server)],
+            sig_level,
+        }];
+        let dir = tempfile::tempdir()?;
+        let sync_dir = dir.path().join("sync");
+        let gpg_dir = gpg_dir();
+        let policy = RetryPolicy {
+            retries: 0,
+            base_delay: Duration::from_millis(1),
+        };
+
+        assert_eq!(
+            refresh_databases(&repos, "x86_64", &sync_dir, gpg_dir.path(), false, &policy)?,
+            [("core", true)]
+        );
+        let request = requests.recv()?;
+        assert!(request.starts_with("GET /core/os/x86_64/core.db "));
+        assert_eq!(header(&request, "If-None-Match"), None);
+        assert!(read_sync_databases(&sync_dir, &repos)?.get("foo").is_some());
+        let modified = sync_dir.join("core.db").metadata()?.modified()?;
+
+        // The server says the database is unchanged, so it isn't downloaded again
+        assert_eq!(
+            refresh_databases(&repos, "x86_64", &sync_dir, gpg_dir.path(), false, &policy)?,
+            [("core", false)]
+        );
+        assert_eq!(sync_dir.join("core.db").metadata()?.modified()?, modified);
+        assert_eq!(header(&requests.recv()?, "If-None-Match"), Some("\"v1\""));
+
+        // Forcing the refresh doesn't send the validators
+        assert_eq!(
+            refresh_databases(&repos, "x86_64", &sync_dir, gpg_dir.path(), true, &policy)?,
+            [("core", true)]
+        );
+        assert_eq!(header(&requests.recv()?, "If-None-Match"), None);
+
+        // A database without a signature is rejected if its SigLevel requires one, and one with
+        // an invalid signature is always rejected
+        let signed = |signature: Option<&'static [u8]>, level: &str| -> Result<Vec<Repo>> {
+            let archive = archive.clone();
+            let (server, _) = mock_server_with(2, move |_, request| {
+                match (
+                    request.starts_with("GET /core/os/x86_64/core.db "),
+                    signature,
+                ) {
+                    (true, _) => response(&gzip(&archive)),
+                    (false, Some(signature)) => {
+                        let mut response = format!(
+                            "HTTP/1.0 200 OK\r\nContent-Length: {}\r\n\r\n",
+                            signature.len()
+                        )
+                        .into_bytes();
+                        response.extend_from_slice(signature);
+                        response
+                    }
+                    (false, None) => b"HTTP/1.0 404 Not Found\r\n\r\n".to_vec(),
+                }
+            });
+            let mut sig_level = SigLevel::default();
+            sig_level.apply(level)?;
+            Ok(vec![Repo {
+                name: "core".into(),
+                servers: vec![format!("{}/$repo/os/$arch", server)],
+                sig_level,
+            }])
+        };
+        let sync_dir = dir.path().join("signed");
+        let error = refresh_databases(
+            &signed(None, "DatabaseRequired")?,
+            "x86_64",
+            &sync_dir,
+            gpg_dir.path(),
+            false,
+            &policy,
+        )
+        .unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Database 'core' has no signature"));
+        assert!(!sync_dir.join("core.db").exists());
+
+        if have_gpgv() {
+            let error = refresh_databases(
+                &signed(Some(b"signature"), "DatabaseOptional")?,
+                "x86_64",
+                &sync_dir,
+                gpg_dir.path(),
+                false,
+                &policy,
+            )
+            .unwrap_err();
+            assert!(error.to_string().starts_with("Invalid signature for"));
+            assert!(!sync_dir.join("core.db").exists());
+            assert!(!sync_dir.join("core.db.sig").exists());
+        }
+
+        // A missing signature is fine if it's optional
+        assert_eq!(
+            refresh_databases(
+                &signed(None, "DatabaseOptional")?,
+                "x86_64",
+                &sync_dir,
+                gpg_dir.path(),
+                false,
+                &policy,
+            )?,
+            [("core", true)]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,196 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::database::local::desc::PackageDescription;
+use crate::version::vercmp;
+
+/// A dependency on a package, optionally restricted to certain versions, as found in `%DEPENDS%`
+/// or given on the command line (e.g. `glibc>=2.30`). The name can also be a soname, like
+/// `libsystemd.so=0-64`, which is provided by the package containing that library.
+#[derive(Debug, Clone, Eq)]
+pub struct Dependency {
+    pub name: String,
+    pub constraint: Option<(Comparison, String)>,
+    /// The text the dependency was parsed from.
+    text: String,
+}
+
+/// Dependencies are equal if they accept the same packages, however they were written.
+impl PartialEq for Dependency {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.constraint == other.constraint
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Less,
+    LessEqual,
+    Equal,
+    GreaterEqual,
+    Greater,
+}
+
+impl Comparison {
+    /// The operator as it is written in a dependency, like `>=`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Comparison::Less => "<",
+            Comparison::LessEqual => "<=",
+            Comparison::Equal => "=",
+            Comparison::GreaterEqual => ">=",
+            Comparison::Greater => ">",
+        }
+    }
+
+    fn matches(&self, ordering: Ordering) -> bool {
+        match self {
+            Comparison::Less => ordering == Ordering::Less,
+            Comparison::LessEqual => ordering != Ordering::Greater,
+            Comparison::Equal => ordering == Ordering::Equal,
+            Comparison::GreaterEqual => ordering != Ordering::Less,
+            Comparison::Greater => ordering == Ordering::Greater,
+        }
+    }
+}
+
+impl Dependency {
+    /// The dependency exactly as it was written, for showing to the user. Unlike `to_string`, this
+    /// isn't affected by changes to `name` or `constraint` after parsing.
+    pub fn display(&self) -> &str {
+        &self.text
+    }
+
+    /// Checks if a package or provision called `name` at `version` satisfies this dependency. A
+    /// provision with no version only satisfies dependencies that don't require one.
+    pub fn is_satisfied_by(&self, name: &str, version: Option<&str>) -> bool {
+        if name != self.name {
+            return false;
+        }
+        match (&self.constraint, version) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some((comparison, required)), Some(version)) => {
+                comparison.matches(vercmp(version, required))
+            }
+        }
+    }
+
+    /// Checks if a package satisfies this dependency, either directly or through one of the
+    /// entries in its `provides`.
+    pub fn is_satisfied_by_package(&self, desc: &PackageDescription) -> bool {
+        self.is_satisfied_by(&desc.name, Some(&desc.version))
+            || desc.provides.iter().any(|provision| {
+                provision.parse::<Dependency>().is_ok_and(|provision| {
+                    let version = provision.constraint.as_ref().map(|(_, x)| x.as_str());
+                    self.is_satisfied_by(&provision.name, version)
+                })
+            })
+    }
+}
+
+impl FromStr for Dependency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let i = match s.find(['<', '>', '=']) {
+            Some(i) => i,
+            None if s.is_empty() => return Err("Empty dependency".into()),
+            None => {
+                return Ok(Self {
+                    name: s.to_owned(),
+                    constraint: None,
+                    text: s.to_owned(),
+                })
+            }
+        };
+        let (name, rest) = s.split_at(i);
+        let (comparison, version) = if let Some(x) = rest.strip_prefix(">=") {
+            (Comparison::GreaterEqual, x)
+        } else if let Some(x) = rest.strip_prefix("<=") {
+            (Comparison::LessEqual, x)
+        } else if let Some(x) = rest.strip_prefix('=') {
+            (Comparison::Equal, x)
+        } else if let Some(x) = rest.strip_prefix('>') {
+            (Comparison::Greater, x)
+        } else {
+            (Comparison::Less, &rest[1..])
+        };
+        if name.is_empty() || version.is_empty() {
+            return Err(format!("Invalid dependency '{}'", s));
+        }
+        Ok(Self {
+            name: name.to_owned(),
+            constraint: Some((comparison, version.to_owned())),
+            text: s.to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for Dependency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some((comparison, version)) = &self.constraint {
+            write!(f, "{}{}", comparison.as_str(), version)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_dependency() {
+        let dep: Dependency = "glibc>=2.30".parse().unwrap();
+        assert_eq!(dep.name, "glibc");
+        assert_eq!(
+            dep.constraint,
+            Some((Comparison::GreaterEqual, "2.30".to_owned()))
+        );
+        assert_eq!(dep.to_string(), "glibc>=2.30");
+
+        let dep: Dependency = "bash".parse().unwrap();
+        assert_eq!(dep.constraint, None);
+        for s in ["linux<5.12", "linux<=5.12", "linux=5.12", "linux>5.12"].iter() {
+            assert_eq!(s.parse::<Dependency>().unwrap().to_string(), *s);
+        }
+        assert!("".parse::<Dependency>().is_err());
+        assert!(">=1.0".parse::<Dependency>().is_err());
+        assert!("foo>=".parse::<Dependency>().is_err());
+    }
+
+    #[test]
+    fn test_soname_dependency() {
+        let dep: Dependency = " libsystemd.so=0-64\n".parse().unwrap();
+        assert_eq!(dep.name, "libsystemd.so");
+        assert_eq!(dep.constraint, Some((Comparison::Equal, "0-64".to_owned())));
+        assert_eq!(dep.display(), "libsystemd.so=0-64");
+        assert_eq!(dep.to_string(), "libsystemd.so=0-64");
+        assert!(dep.is_satisfied_by("libsystemd.so", Some("0-64")));
+        assert!(!dep.is_satisfied_by("libsystemd.so", Some("1-64")));
+
+        let mut unversioned = dep.clone();
+        unversioned.constraint = None;
+        assert_eq!(unversioned.display(), "libsystemd.so=0-64");
+        assert_eq!(unversioned.to_string(), "libsystemd.so");
+        assert_ne!(unversioned, dep);
+        assert_eq!(unversioned, "libsystemd.so".parse().unwrap());
+    }
+
+    #[test]
+    fn test_dependency_satisfaction() {
+        let dep: Dependency = "glibc>=2.30".parse().unwrap();
+        assert!(dep.is_satisfied_by("glibc", Some("2.33-4")));
+        assert!(dep.is_satisfied_by("glibc", Some("2.30")));
+        assert!(!dep.is_satisfied_by("glibc", Some("2.29-1")));
+        assert!(!dep.is_satisfied_by("glibc", None));
+        assert!(!dep.is_satisfied_by("musl", Some("2.33")));
+
+        let dep: Dependency = "sh".parse().unwrap();
+        assert!(dep.is_satisfied_by("sh", None));
+    }
+}
@@ -0,0 +1,540 @@
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::sha256;
+use crate::Result;
+
+use log::{info, warn};
+
+/// How many times a download from a single mirror is retried before moving on to the next one,
+/// and how long to wait before the first retry. The delay doubles after every failed attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub base_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 3,
+            base_delay: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Why fetching a URL failed, which decides whether it is worth trying the same mirror again.
+#[derive(Debug)]
+pub enum FetchError {
+    /// The mirror doesn't have the file. Retrying won't help, so the next mirror is tried.
+    NotFound,
+    /// The URL can't be fetched from this mirror at all, e.g. because of an unsupported scheme.
+    Fatal(String),
+    /// A failure that may go away by itself, such as a dropped connection or a server error.
+    Transient(String),
+}
+
+impl std::fmt::Display for FetchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FetchError::NotFound => write!(f, "file not found"),
+            FetchError::Fatal(x) | FetchError::Transient(x) => write!(f, "{}", x),
+        }
+    }
+}
+
+impl std::error::Error for FetchError {}
+
+impl From<std::io::Error> for FetchError {
+    fn from(e: std::io::Error) -> Self {
+        FetchError::Transient(e.to_string())
+    }
+}
+
+impl From<ureq::Error> for FetchError {
+    fn from(e: ureq::Error) -> Self {
+        match e {
+            ureq::Error::Io(_)
+            | ureq::Error::Timeout(_)
+            | ureq::Error::HostNotFound
+            | ureq::Error::ConnectionFailed
+            | ureq::Error::Protocol(_) => FetchError::Transient(e.to_string()),
+            _ => FetchError::Fatal(e.to_string()),
+        }
+    }
+}
+
+/// The `Last-Modified` and `ETag` headers a server sent with a file. Sending them back with the
+/// next request for the file lets the server reply that it hasn't changed, instead of sending it
+/// again.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheValidators {
+    pub last_modified: Option<String>,
+    pub etag: Option<String>,
+}
+
+impl CacheValidators {
+    /// Reads validators saved by `write_to_file`. A missing file means there are none.
+    pub fn read_from_file(filepath: &Path) -> Result<Self> {
+        let contents = match std::fs::read_to_string(filepath) {
+            Ok(x) => x,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut ret = Self::default();
+        for line in contents.lines() {
+            ret.parse_header(line);
+        }
+        Ok(ret)
+    }
+
+    /// Saves the validators as HTTP header lines.
+    pub fn write_to_file(&self, filepath: &Path) -> Result<()> {
+        std::fs::write(filepath, self.headers().join("\n"))?;
+        Ok(())
+    }
+
+    fn parse_header(&mut self, header: &str) {
+        if let Some((name, value)) = header.split_once(':') {
+            let value = Some(value.trim().to_owned());
+            match name.trim().to_ascii_lowercase().as_str() {
+                "last-modified" => self.last_modified = value,
+                "etag" => self.etag = value,
+                _ => {}
+            }
+        }
+    }
+
+    fn headers(&self) -> Vec<String> {
+        let mut ret = Vec::new();
+        if let Some(x) = &self.last_modified {
+            ret.push(format!("Last-Modified: {}", x));
+        }
+        if let Some(x) = &self.etag {
+            ret.push(format!("ETag: {}", x));
+        }
+        ret
+    }
+
+    /// The request headers asking the server to only send the file if it has changed.
+    fn conditional_headers(&self) -> Vec<String> {
+        let mut ret = Vec::new();
+        if let Some(x) = &self.last_modified {
+            ret.push(format!("If-Modified-Since: {}", x));
+        }
+        if let Some(x) = &self.etag {
+            ret.push(format!("If-None-Match: {}", x));
+        }
+        ret
+    }
+}
+
+/// Downloads `file` from the first of `mirrors` that has it, retrying each mirror according to
+/// `policy`.
+pub fn download(mirrors: &[String], file: &str, policy: &RetryPolicy) -> Result<Vec<u8>> {
+    download_with(mirrors, file, policy, http_get)
+}
+
+/// Checks that `file` is a plain file name, so that joining it to a directory can't point
+/// anywhere outside that directory. File names come from sync databases, which may not be signed.
+pub fn check_file_name(file: &str) -> Result<()> {
+    if file.is_empty() || file.contains('/') || file == "." || file == ".." {
+        return Err(format!("Invalid file name '{}'", file).into());
+    }
+    Ok(())
+}
+
+/// Downloads `file` into `dir`, like `download`. The data is written to `<file>.part` first, and
+/// if that already exists, e.g. from an interrupted download, only the rest of the file is
+/// requested. Once complete, the size and sha256 checksum are checked if they are given, and the
+/// file is renamed to its final name. A download that fails the checks is deleted.
+pub fn download_to_file(
+    mirrors: &[String],
+    file: &str,
+    dir: &Path,
+    policy: &RetryPolicy,
+    expected_size: Option<u64>,
+    expected_sha256: Option<&str>,
+) -> Result<PathBuf> {
+    check_file_name(file)?;
+    let part = dir.join(format!("{}.part", file));
+    download_with(mirrors, file, policy, |url| fetch_resuming(url, &part))?;
+
+    let size = part.metadata()?.len();
+    if let Some(expected) = expected_size {
+        if size != expected {
+            std::fs::remove_file(&part)?;
+            return Err(format!(
+                "Downloaded '{}' is {} bytes, expected {}",
+                file, size, expected
+            )
+            .into());
+        }
+    }
+    if let Some(expected) = expected_sha256 {
+        if sha256::hex_digest(File::open(&part)?)? != expected {
+            std::fs::remove_file(&part)?;
+            return Err(format!("Downloaded '{}' failed its sha256 check", file).into());
+        }
+    }
+    let destination = dir.join(file);
+    std::fs::rename(&part, &destination)?;
+    Ok(destination)
+}
+
+/// Downloads `file` like `download`, unless the server says it hasn't changed since it was
+/// downloaded with `validators`, in which case `None` is returned. Otherwise the file is returned
+/// along with its new validators.
+pub fn download_if_modified(
+    mirrors: &[String],
+    file: &str,
+    policy: &RetryPolicy,
+    validators: &CacheValidators,
+) -> Result<Option<(Vec<u8>, CacheValidators)>> {
+    download_with(mirrors, file, policy, |url| {
+        let response = http_request(url, &validators.conditional_headers())?;
+        match response.status {
+            304 => Ok(None),
+            200 => {
+                let validators = response.validators.clone();
+                let mut body = Vec::new();
+                response.copy_body_to(&mut body)?;
+                Ok(Some((body, validators)))
+            }
+            _ => Err(response.error()),
+        }
+    })
+}
+
+/// Like `download`, but fetches each URL with `fetch`.
+fn download_with<T>(
+    mirrors: &[String],
+    file: &str,
+    policy: &RetryPolicy,
+    mut fetch: impl FnMut(&str) -> std::result::Result<T, FetchError>,
+) -> Result<T> {
+    for mirror in mirrors {
+        let url = format!("{}/{}", mirror.trim_end_matches('/'), file);
+        let mut delay = policy.base_delay;
+        for attempt in 0..=policy.retries {
+            if attempt > 0 {
+                std::thread::sleep(delay);
+                delay *= 2;
+            }
+            match fetch(&url) {
+                Ok(data) => {
+                    info!("Downloaded {} (attempt {})", url, attempt + 1);
+                    return Ok(data);
+                }
+                Err(FetchError::Transient(e)) => {
+                    warn!("Attempt {} to download {} failed: {}", attempt + 1, url, e);
+                }
+                Err(e) => {
+                    warn!("Could not download {}: {}", url, e);
+                    break;
+                }
+            }
+        }
+    }
+    Err(format!("Failed to download '{}' from any mirror", file).into())
+}
+
+/// Fetches `url`, appending to `part` if it already has some of the file. Whatever is received
+/// before the connection drops is kept, so that the next attempt can carry on from there.
+fn fetch_resuming(url: &str, part: &Path) -> std::result::Result<(), FetchError> {
+    let offset = part.metadata().map(|x| x.len()).unwrap_or(0);
+    let mut headers = Vec::new();
+    if offset > 0 {
+        headers.push(format!("Range: bytes={}-", offset));
+    }
+    let response = http_request(url, &headers)?;
+    let mut out = match response.status {
+        206 if offset > 0 => OpenOptions::new().append(true).open(part)?,
+        // The server ignored the range and sent the whole file
+        200 => File::create(part)?,
+        // The part file is corrupt or longer than the file, so start again
+        416 => {
+            std::fs::remove_file(part)?;
+            return Err(FetchError::Transient(response.status_line));
+        }
+        _ => return Err(response.error()),
+    };
+    response.copy_body_to(&mut out)?;
+    Ok(())
+}
+
+/// Fetches a `http://` or `https://` URL, following any redirects.
+pub fn http_get(url: &str) -> std::result::Result<Vec<u8>, FetchError> {
+    let response = http_request(url, &[])?;
+    if response.status != 200 {
+        return Err(response.error());
+    }
+    let mut body = Vec::new();
+    response.copy_body_to(&mut body)?;
+    Ok(body)
+}
+
+/// A HTTP response whose headers have been read, but whose body hasn't.
+struct Response {
+    status: u16,
+    status_line: String,
+    content_length: Option<u64>,
+    validators: CacheValidators,
+    body: ureq::BodyReader<'static>,
+}
+
+impl Response {
+    /// The error for a response that didn't contain the requested file.
+    fn error(self) -> FetchError {
+        match self.status {
+            404 | 410 => FetchError::NotFound,
+            500..=599 | 408 | 429 => FetchError::Transient(self.status_line),
+            _ => FetchError::Fatal(self.status_line),
+        }
+    }
+
+    /// Writes the body to `out`. If the connection closes before the whole body is received, the
+    /// part that was received is still written.
+    fn copy_body_to(mut self, out: &mut impl Write) -> std::result::Result<(), FetchError> {
+        let copied = std::io::copy(&mut self.body, out)?;
+        match self.content_length {
+            Some(length) if copied < length => Err(FetchError::Transient(format!(
+                "Connection closed after {} of {} bytes",
+                copied, length
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The client that every request is sent with. It keeps connections to mirrors open between
+/// requests, and hands back error statuses as responses so that `Response::error` can sort them.
+fn agent() -> &'static ureq::Agent {
+    static AGENT: OnceLock<ureq::Agent> = OnceLock::new();
+    AGENT.get_or_init(|| {
+        ureq::Agent::config_builder()
+            .http_status_as_error(false)
+            .user_agent("pacman-rs")
+            .timeout_connect(Some(Duration::from_secs(30)))
+            .timeout_recv_response(Some(Duration::from_secs(30)))
+            .build()
+            .into()
+    })
+}
+
+/// Sends a GET request for `url` with any `extra_headers`, and reads the headers of the response.
+fn http_request(url: &str, extra_headers: &[String]) -> std::result::Result<Response, FetchError> {
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        return Err(FetchError::Fatal(format!("Unsupported URL '{}'", url)));
+    }
+    let mut request = agent().get(url);
+    for header in extra_headers {
+        if let Some((name, value)) = header.split_once(':') {
+            request = request.header(name.trim(), value.trim());
+        }
+    }
+    let response = request.call()?;
+
+    let status = response.status();
+    let status_line = format!("{:?} {}", response.version(), status);
+    let content_length = response
+        .headers()
+        .get(ureq::http::header::CONTENT_LENGTH)
+        .and_then(|x| x.to_str().ok()?.parse().ok());
+    let mut validators = CacheValidators::default();
+    for (name, value) in response.headers() {
+        if let Ok(value) = value.to_str() {
+            validators.parse_header(&format!("{}: {}", name, value));
+        }
+    }
+    Ok(Response {
+        status: status.as_u16(),
+        status_line,
+        content_length,
+        validators,
+        body: response.into_body().into_reader(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{capture_logs, header, mock_server_with};
+    use log::Level;
+
+    /// Serves one response for each of `statuses` in turn, on a random local port, and returns
+    /// the mirror URL for it.
+    fn mock_server(statuses: &'static [u16]) -> String {
+        mock_server_with(statuses.len(), move |i, _| {
+            let body = if statuses[i] == 200 {
+                "package data"
+            } else {
+                ""
+            };
+            format!("HTTP/1.0 {} Status\r\n\r\n{}", statuses[i], body)
+        })
+        .0
+    }
+
+    #[test]
+    fn test_download_if_modified() -> Result<()> {
+        let (mirror, requests) = mock_server_with(2, |i, _| match i {
+            0 => {
+                "HTTP/1.0 200 OK\r\nLast-Modified: Sat, 13 Mar 2021 00:00:00 GMT\r\n\
+                  ETag: \"abc\"\r\n\r\ndata"
+            }
+            _ => "HTTP/1.0 304 Not Modified\r\n\r\n",
+        });
+        let mirrors = [mirror];
+        let (data, validators) =
+            download_if_modified(&mirrors, "core.db", &fast_retries(0), &Default::default())?
+                .unwrap();
+        assert_eq!(data, b"data");
+        assert_eq!(validators.etag.as_deref(), Some("\"abc\""));
+        let request = requests.recv()?;
+        assert_eq!(header(&request, "If-None-Match"), None);
+        assert_eq!(header(&request, "If-Modified-Since"), None);
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("core.db.validators");
+        validators.write_to_file(&path)?;
+        let validators = CacheValidators::read_from_file(&path)?;
+        assert!(
+            download_if_modified(&mirrors, "core.db", &fast_retries(0), &validators)?.is_none()
+        );
+        let request = requests.recv()?;
+        assert_eq!(header(&request, "If-None-Match"), Some("\"abc\""));
+        assert_eq!(
+            header(&request, "If-Modified-Since"),
+            Some("Sat, 13 Mar 2021 00:00:00 GMT")
+        );
+        Ok(())
+    }
+
+    fn fast_retries(retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            retries,
+            base_delay: Duration::from_millis(1),
+        }
+    }
+
+    #[test]
+    fn test_retry_then_succeed() -> Result<()> {
+        let mirror = mock_server(&[503, 500, 200]);
+        let (data, logs) =
+            capture_logs(|| download(&[mirror], "foo-1.0-1-x86_64.pkg.tar.zst", &fast_retries(2)));
+        assert_eq!(data?, b"package data");
+        let attempts: Vec<_> = logs.iter().map(|(level, _)| *level).collect();
+        assert_eq!(attempts, [Level::Warn, Level::Warn, Level::Info]);
+        assert!(logs[1].1.starts_with("Attempt 2 to download"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_retry_budget_exhausted() {
+        let mirror = mock_server(&[503, 503]);
+        assert!(download(&[mirror], "foo", &fast_retries(1)).is_err());
+    }
+
+    #[test]
+    fn test_not_found_skips_to_next_mirror() -> Result<()> {
+        let mirrors = [mock_server(&[404]), mock_server(&[200])];
+        let mut urls = Vec::new();
+        let data = download_with(&mirrors, "foo", &fast_retries(3), |url| {
+            urls.push(url.to_owned());
+            http_get(url)
+        })?;
+        assert_eq!(data, b"package data");
+        assert_eq!(urls.len(), 2);
+        assert!(urls[0].starts_with(&mirrors[0]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_follow_redirect() -> Result<()> {
+        let (mirror, requests) = mock_server_with(2, |i, _| match i {
+            0 => "HTTP/1.0 302 Found\r\nLocation: /elsewhere/foo\r\n\r\n",
+            _ => "HTTP/1.0 200 OK\r\n\r\npackage data",
+        });
+        assert_eq!(
+            download(&[mirror], "foo", &fast_retries(0))?,
+            b"package data"
+        );
+        assert!(requests.recv()?.starts_with("GET /foo "));
+        assert!(requests.recv()?.starts_with("GET /elsewhere/foo "));
+        Ok(())
+    }
+
+    #[test]
+    fn test_resume_interrupted_download() -> Result<()> {
+        const DATA: &str = "0123456789abcdef";
+        let (mirror, requests) = mock_server_with(2, |i, _| match i {
+            // Drops the connection part way through the body
+            0 => format!(
+                "HTTP/1.0 200 OK\r\nContent-Length: 16\r\n\r\n{}",
+                &DATA[..6]
+            ),
+            _ => format!(
+                "HTTP/1.0 206 Partial Content\r\nContent-Length: 10\r\n\r\n{}",
+                &DATA[6..]
+            ),
+        });
+        let dir = tempfile::tempdir()?;
+        let path = download_to_file(
+            &[mirror],
+            "foo.pkg.tar.zst",
+            dir.path(),
+            &fast_retries(1),
+            Some(16),
+            Some(&sha256::hex_digest(DATA.as_bytes())?),
+        )?;
+        assert_eq!(path, dir.path().join("foo.pkg.tar.zst"));
+        assert_eq!(std::fs::read_to_string(&path)?, DATA);
+        assert!(!dir.path().join("foo.pkg.tar.zst.part").exists());
+        assert_eq!(header(&requests.recv()?, "Range"), None);
+        assert_eq!(header(&requests.recv()?, "Range"), Some("bytes=6-"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_resume_from_existing_part() -> Result<()> {
+        let (mirror, requests) = mock_server_with(1, |_, _| {
+            "HTTP/1.0 206 Partial Content\r\nContent-Length: 5\r\n\r\n data"
+        });
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("foo.part"), "some")?;
+        let path = download_to_file(&[mirror], "foo", dir.path(), &fast_retries(0), None, None)?;
+        assert_eq!(std::fs::read_to_string(path)?, "some data");
+        assert_eq!(header(&requests.recv()?, "Range"), Some("bytes=4-"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_file_name() {
+        assert!(check_file_name("foo-1.0-1-x86_64.pkg.tar.zst").is_ok());
+        assert!(check_file_name("..foo").is_ok());
+        for name in ["", ".", "..", "/etc/cron.d/x", "../../usr/bin/foo", "foo/"].iter() {
+            assert!(check_file_name(name).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_download_checksum_mismatch() -> Result<()> {
+        let mirror = mock_server(&[200]);
+        let dir = tempfile::tempdir()?;
+        let result = download_to_file(
+            &[mirror],
+            "foo",
+            dir.path(),
+            &fast_retries(0),
+            None,
+            Some("0000"),
+        );
+        assert!(result.is_err());
+        assert_eq!(dir.path().read_dir()?.count(), 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,490 @@
+use std::ffi::OsStr;
+
+use crate::database::local::desc::PackageDescription;
+
+use ansi_term::Style;
+
+/// The environment variable that turns off styled output when set to anything other than an empty
+/// string, following https://no-color.org.
+pub const NO_COLOR_ENV: &str = "NO_COLOR";
+
+/// When output is styled with colours and bold text, given with `--color`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorMode {
+    /// Style output going to a terminal, unless `NO_COLOR` is set.
+    Auto,
+    Always,
+    Never,
+}
+
+impl std::str::FromStr for ColorMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorMode::Auto),
+            "always" => Ok(ColorMode::Always),
+            "never" => Ok(ColorMode::Never),
+            _ => Err(format!(
+                "Invalid color mode '{}', expected auto, always or never",
+                s
+            )),
+        }
+    }
+}
+
+impl ColorMode {
+    /// Whether to style output that is written to a terminal if `is_terminal`, with `no_color`
+    /// the value of the `NO_COLOR` environment variable. `--color always` and `--color never` win
+    /// over `NO_COLOR`, which wins over whether it is a terminal.
+    pub fn enabled(self, is_terminal: bool, no_color: Option<&OsStr>) -> bool {
+        match self {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            ColorMode::Auto => is_terminal && no_color.is_none_or(|x| x.is_empty()),
+        }
+    }
+}
+
+/// Returns `style` if output is styled, or a plain style that writes no escape codes if not.
+pub fn styled(color: bool, style: Style) -> Style {
+    if color {
+        style
+    } else {
+        Style::new()
+    }
+}
+
+/// The width used for wrapping output when it isn't going to a terminal.
+pub const DEFAULT_WIDTH: usize = 80;
+
+/// Returns the width of the terminal that stdout is connected to, or `DEFAULT_WIDTH` if it isn't
+/// connected to one.
+pub fn terminal_width() -> usize {
+    terminal_size::terminal_size()
+        .map(|(terminal_size::Width(width), _)| width as usize)
+        .unwrap_or(DEFAULT_WIDTH)
+}
+
+/// Returns the width of the longest of `names`, for aligning a column of text that follows them.
+pub fn column_width<'a>(names: impl IntoIterator<Item = &'a str>) -> usize {
+    names
+        .into_iter()
+        .map(|x| x.chars().count())
+        .max()
+        .unwrap_or(0)
+}
+
+/// Joins `items` with two spaces, wrapping onto new lines so that no line is longer than `width`.
+/// Every line after the first is indented by `indent` spaces, so that the list lines up when it
+/// is printed after a label of that width. An item longer than a line is put on its own line.
+pub fn wrap_list(items: &[&str], indent: usize, width: usize) -> String {
+    let mut ret = String::new();
+    let mut line_len = indent;
+    for (i, item) in items.iter().enumerate() {
+        let item_len = item.chars().count();
+        if i != 0 {
+            if line_len + 2 + item_len > width {
+                ret.push('\n');
+                ret.push_str(&" ".repeat(indent));
+                line_len = indent;
+            } else {
+                ret.push_str("  ");
+                line_len += 2;
+            }
+        }
+        ret.push_str(item);
+        line_len += item_len;
+    }
+    ret
+}
+
+/// Formats a unix timestamp as a UTC date and time, like `2021-03-15 12:34:56`.
+pub fn format_timestamp(timestamp: u64) -> String {
+    let days = timestamp / 86400;
+    let seconds = timestamp % 86400;
+    // Converts days since the epoch to a civil date, from Howard Hinnant's `civil_from_days`
+    let z = days + 719468;
+    let era = z / 146097;
+    let day_of_era = z % 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Parses a UTC date like `2021-03-15` into the unix timestamp of its start, for comparing with
+/// install dates.
+pub fn parse_date(s: &str) -> Result<u64, String> {
+    let invalid = || format!("Invalid date '{}', expected YYYY-MM-DD", s);
+    let parts: Vec<&str> = s.split('-').collect();
+    let (year, month, day) = match parts[..] {
+        [year, month, day] if year.len() == 4 && month.len() == 2 && day.len() == 2 => (
+            year.parse::<u64>().map_err(|_| invalid())?,
+            month.parse::<u64>().map_err(|_| invalid())?,
+            day.parse::<u64>().map_err(|_| invalid())?,
+        ),
+        _ => return Err(invalid()),
+    };
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days_in_month = match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        1..=12 => 31,
+        _ => return Err(invalid()),
+    };
+    if year < 1970 || day == 0 || day > days_in_month {
+        return Err(invalid());
+    }
+    // The inverse of `format_timestamp`, from Howard Hinnant's `days_from_civil`
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let day_of_year = (153 * if month > 2 { month - 3 } else { month + 9 } + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    Ok(days * 86400)
+}
+
+/// Formats a size in bytes like pacman does, such as `12.34 MiB`, using the largest binary unit
+/// that keeps the number below 2048. Every size that is printed goes through this or
+/// `human_size_signed`, so that they all look the same.
+pub fn human_size(bytes: u64) -> String {
+    scale_size(bytes as f64)
+}
+
+/// Like `human_size`, but for a change in size, such as the net size of an upgrade. Negative sizes,
+/// such as for a downgrade to a smaller package, keep their sign.
+pub fn human_size_signed(bytes: i64) -> String {
+    scale_size(bytes as f64)
+}
+
+fn scale_size(bytes: f64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes;
+    let mut unit = 0;
+    while size.abs() >= 2048.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.2} {}", size, UNITS[unit])
+}
+
+/// A template for printing a package on one line, given with `--print-format`, such as `%n %v`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrintFormat {
+    segments: Vec<Segment>,
+    /// What the items of list placeholders, such as `%D`, are joined with.
+    separator: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Name,
+    Version,
+    Description,
+    Size,
+    Repo,
+    Link,
+    Depends,
+    OptDepends,
+    Provides,
+    Conflicts,
+    Replaces,
+    Groups,
+    Licences,
+}
+
+impl std::str::FromStr for PrintFormat {
+    type Err = String;
+
+    /// Parses a template. The placeholders are `%n` (name), `%v` (version), `%d` (description),
+    /// `%s` (installed size in bytes), `%r` (repository) and `%l` (download URL), and `%%` is a
+    /// literal `%`. The list placeholders are `%D` (depends), `%O` (the names of the optdepends),
+    /// `%P` (provides), `%C` (conflicts), `%R` (replaces), `%G` (groups) and `%L` (licenses), whose
+    /// items are separated by spaces unless `with_separator` is used.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                literal.push(c);
+                continue;
+            }
+            let segment = match chars.next() {
+                Some('%') => {
+                    literal.push('%');
+                    continue;
+                }
+                Some('n') => Segment::Name,
+                Some('v') => Segment::Version,
+                Some('d') => Segment::Description,
+                Some('s') => Segment::Size,
+                Some('r') => Segment::Repo,
+                Some('l') => Segment::Link,
+                Some('D') => Segment::Depends,
+                Some('O') => Segment::OptDepends,
+                Some('P') => Segment::Provides,
+                Some('C') => Segment::Conflicts,
+                Some('R') => Segment::Replaces,
+                Some('G') => Segment::Groups,
+                Some('L') => Segment::Licences,
+                Some(x) => return Err(format!("Unknown placeholder '%{}' in '{}'", x, s)),
+                None => return Err(format!("Unterminated placeholder at the end of '{}'", s)),
+            };
+            if !literal.is_empty() {
+                segments.push(Segment::Literal(std::mem::take(&mut literal)));
+            }
+            segments.push(segment);
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Ok(Self {
+            segments,
+            separator: " ".to_owned(),
+        })
+    }
+}
+
+impl PrintFormat {
+    /// Joins the items of list placeholders with `separator` instead of a space.
+    pub fn with_separator(mut self, separator: &str) -> Self {
+        self.separator = separator.to_owned();
+        self
+    }
+
+    /// Whether the template contains `%l`, which can only be filled in for packages from a sync
+    /// database.
+    pub fn uses_link(&self) -> bool {
+        self.segments.contains(&Segment::Link)
+    }
+
+    /// Fills in the template for a package from the repository `repo`. Fields that the package
+    /// doesn't have are left empty, except for the size, which is `0`. `%l` is left empty too, so
+    /// templates that use it should be rejected where there is no download URL.
+    pub fn render(&self, desc: &PackageDescription, repo: &str) -> String {
+        self.render_with_link(desc, repo, "")
+    }
+
+    /// Fills in the template like `render`, for a sync package that would be downloaded from
+    /// `link`.
+    pub fn render_with_link(&self, desc: &PackageDescription, repo: &str, link: &str) -> String {
+        let mut ret = String::new();
+        for segment in self.segments.iter() {
+            match segment {
+                Segment::Literal(x) => ret.push_str(x),
+                Segment::Name => ret.push_str(&desc.name),
+                Segment::Version => ret.push_str(&desc.version),
+                Segment::Description => ret.push_str(desc.description.as_deref().unwrap_or("")),
+                Segment::Size => ret.push_str(&desc.size.unwrap_or(0).to_string()),
+                Segment::Repo => ret.push_str(repo),
+                Segment::Link => ret.push_str(link),
+                Segment::Depends => ret.push_str(&desc.dependencies.join(&self.separator)),
+                Segment::OptDepends => {
+                    let names: Vec<&str> = desc
+                        .optional_dependencies
+                        .iter()
+                        .map(|x| x.package.as_str())
+                        .collect();
+                    ret.push_str(&names.join(&self.separator))
+                }
+                Segment::Provides => ret.push_str(&desc.provides.join(&self.separator)),
+                Segment::Conflicts => ret.push_str(&desc.conflicts.join(&self.separator)),
+                Segment::Replaces => ret.push_str(&desc.replaces.join(&self.separator)),
+                Segment::Groups => ret.push_str(&desc.groups.join(&self.separator)),
+                Segment::Licences => ret.push_str(&desc.licences.join(&self.separator)),
+            }
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_color_mode() {
+        assert_eq!("auto".parse(), Ok(ColorMode::Auto));
+        assert_eq!("always".parse(), Ok(ColorMode::Always));
+        assert_eq!("never".parse(), Ok(ColorMode::Never));
+        assert!("yes".parse::<ColorMode>().is_err());
+
+        let no_color = Some(OsStr::new("1"));
+        assert!(!ColorMode::Auto.enabled(true, no_color));
+        assert!(ColorMode::Always.enabled(true, no_color));
+        let bold = styled(ColorMode::Auto.enabled(true, no_color), Style::new().bold());
+        assert_eq!(bold.paint("bash").to_string(), "bash");
+        // An empty value doesn't count
+        assert!(ColorMode::Auto.enabled(true, Some(OsStr::new(""))));
+        assert!(ColorMode::Auto.enabled(true, None));
+        assert!(!ColorMode::Auto.enabled(false, None));
+        assert!(!ColorMode::Never.enabled(true, None));
+        let bold = styled(ColorMode::Always.enabled(false, None), Style::new().bold());
+        assert_eq!(bold.paint("bash").to_string(), "\x1b[1mbash\x1b[0m");
+    }
+
+    #[test]
+    fn test_column_width() {
+        assert_eq!(column_width(vec!["vim", "linux-firmware", "bash"]), 14);
+        assert_eq!(column_width(Vec::new()), 0);
+        let names = ["vim", "linux-firmware"];
+        let width = column_width(names.iter().copied());
+        let lines: Vec<String> = names
+            .iter()
+            .map(|name| format!("{:width$} {}", name, "1.0-1", width = width))
+            .collect();
+        assert_eq!(lines, ["vim            1.0-1", "linux-firmware 1.0-1"]);
+    }
+
+    #[test]
+    fn test_wrap_list() {
+        let items = ["alsa-lib", "gtk3", "libx11", "nss", "ffmpeg", "libpulse"];
+        assert_eq!(
+            wrap_list(&items, 10, 40),
+            "alsa-lib  gtk3  libx11  nss\n          ffmpeg  libpulse"
+        );
+        assert_eq!(
+            wrap_list(&items, 0, 80),
+            "alsa-lib  gtk3  libx11  nss  ffmpeg  libpulse"
+        );
+        assert_eq!(wrap_list(&[], 4, 80), "");
+        assert_eq!(
+            wrap_list(&["a-very-long-name", "b"], 4, 10),
+            "a-very-long-name\n    b"
+        );
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00");
+        assert_eq!(format_timestamp(1615000000), "2021-03-06 03:06:40");
+        assert_eq!(format_timestamp(951782400), "2000-02-29 00:00:00");
+        assert_eq!(format_timestamp(4102444799), "2099-12-31 23:59:59");
+    }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse_date("1970-01-01"), Ok(0));
+        assert_eq!(parse_date("2021-03-06"), Ok(1614988800));
+        assert_eq!(parse_date("2000-02-29"), Ok(951782400));
+        assert_eq!(parse_date("2099-12-31"), Ok(4102358400));
+        for date in &[
+            "2021-02-29",
+            "2021-13-01",
+            "2021-00-10",
+            "2021-3-6",
+            "1969-12-31",
+            "2021-03-06 12:00",
+            "yesterday",
+        ] {
+            assert!(parse_date(date).is_err(), "{}", date);
+        }
+    }
+
+    #[test]
+    fn test_human_size() {
+        assert_eq!(human_size(0), "0.00 B");
+        assert_eq!(human_size(1023), "1023.00 B");
+        // Like pacman, a unit is only used once the number would be at least 2
+        assert_eq!(human_size(1024), "1024.00 B");
+        assert_eq!(human_size(2047), "2047.00 B");
+        assert_eq!(human_size(2048), "2.00 KiB");
+        assert_eq!(human_size(1024 * 1024), "1024.00 KiB");
+        assert_eq!(human_size(2 * 1024 * 1024), "2.00 MiB");
+        assert_eq!(human_size(1294534902), "1234.56 MiB");
+        assert_eq!(human_size(5 * 1024 * 1024 * 1024), "5.00 GiB");
+        assert_eq!(human_size(3 << 40), "3.00 TiB");
+        // There's no unit above TiB
+        assert_eq!(human_size(4096 << 40), "4096.00 TiB");
+    }
+
+    #[test]
+    fn test_human_size_signed() {
+        assert_eq!(human_size_signed(0), "0.00 B");
+        assert_eq!(human_size_signed(5 * 1024 * 1024), "5.00 MiB");
+        assert_eq!(human_size_signed(-2047), "-2047.00 B");
+        assert_eq!(human_size_signed(-3 * 1024 * 1024 / 2), "-1536.00 KiB");
+        assert_eq!(human_size_signed(-3 * 1024 * 1024 * 1024), "-3.00 GiB");
+    }
+
+    #[test]
+    fn test_print_format() -> crate::Result<()> {
+        let fixture = crate::test_util::Fixture::new();
+        fixture.add(
+            "foo",
+            "1:2.0-1",
+            "%DESC%\nA foo utility\n\n%SIZE%\n2048\n\n",
+            &[],
+        );
+        fixture.add("bar", "1.0-1", "", &[]);
+        let foo = crate::database::local::desc::read_desc_from_file(
+            fixture.path().join("foo-1:2.0-1/desc"),
+        )?;
+        let bar = crate::database::local::desc::read_desc_from_file(
+            fixture.path().join("bar-1.0-1/desc"),
+        )?;
+
+        let render = |template: &str, desc| {
+            template
+                .parse::<PrintFormat>()
+                .unwrap()
+                .render(desc, "local")
+        };
+        assert_eq!(render("%n %v", &foo), "foo 1:2.0-1");
+        assert_eq!(
+            render("%r/%n: %d (%s bytes)", &foo),
+            "local/foo: A foo utility (2048 bytes)"
+        );
+        assert_eq!(render("100%% %n", &foo), "100% foo");
+        assert_eq!(render("[%d] %s", &bar), "[] 0");
+        assert_eq!(render("no placeholders", &bar), "no placeholders");
+
+        let template: PrintFormat = "%n %l".parse().unwrap();
+        assert!(template.uses_link());
+        assert!(!"%n %v".parse::<PrintFormat>().unwrap().uses_link());
+        assert_eq!(
+            template.render_with_link(&bar, "core", "https://example.com/bar-1.0-1.pkg.tar.zst"),
+            "bar https://example.com/bar-1.0-1.pkg.tar.zst"
+        );
+        assert_eq!(template.render(&bar, "local"), "bar ");
+
+        let bash = crate::database::local::desc::parse_desc(
+            "%NAME%\nbash\n\n%VERSION%\n5.1.004-1\n\n%LICENSE%\nGPL\n\n\
+             %DEPENDS%\nreadline>=7.0\nglibc\nncurses\n\n\
+             %OPTDEPENDS%\nbash-completion: for tab completion\n\n\
+             %PROVIDES%\nsh\n\n%GROUPS%\nbase\nbase-devel\n\n",
+        )?;
+        assert_eq!(render("%n: %D", &bash), "bash: readline>=7.0 glibc ncurses");
+        assert_eq!(
+            render("%O|%P|%C|%R|%G|%L", &bash),
+            "bash-completion|sh|||base base-devel|GPL"
+        );
+        assert_eq!(render("%n: %D", &bar), "bar: ");
+        let template: PrintFormat = "%D".parse().unwrap();
+        assert_eq!(
+            template.with_separator(",").render(&bash, "local"),
+            "readline>=7.0,glibc,ncurses"
+        );
+
+        assert!("%n %x".parse::<PrintFormat>().is_err());
+        assert!("%n %".parse::<PrintFormat>().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,55 @@
+/// A shell-style wildcard pattern, such as `lib*`, matched against a whole string. `*` matches any
+/// run of characters, `?` matches a single character, and `[...]` matches one of a set of
+/// characters, with `[!...]` for its complement. Matching is done by `globset`; this wraps it so
+/// that patterns can be parsed from the command line.
+#[derive(Debug, Clone)]
+pub struct Glob {
+    matcher: globset::GlobMatcher,
+}
+
+impl Glob {
+    pub fn is_match(&self, s: &str) -> bool {
+        self.matcher.is_match(s)
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.matcher.glob().glob()
+    }
+}
+
+impl std::str::FromStr for Glob {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let glob =
+            globset::Glob::new(s).map_err(|e| format!("Invalid pattern '{}': {}", s, e.kind()))?;
+        Ok(Self {
+            matcher: glob.compile_matcher(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Glob;
+
+    #[test]
+    fn test_glob() {
+        let glob = |s: &str| s.parse::<Glob>().unwrap();
+        assert!(glob("linux*").is_match("linux"));
+        assert!(glob("linux*").is_match("linux-firmware"));
+        assert!(!glob("linux*").is_match("util-linux"));
+        assert!(glob("lib?").is_match("libc"));
+        assert!(!glob("lib?").is_match("lib"));
+        assert!(glob("python[23]-*").is_match("python3-foo"));
+        assert!(!glob("python[!23]*").is_match("python3"));
+        assert!(glob("[a-c]sh").is_match("bsh"));
+        // Regex syntax has no special meaning
+        assert!(glob("g++").is_match("g++"));
+        assert!(!glob("a.c").is_match("abc"));
+        assert!("foo[".parse::<Glob>().is_err());
+        // Like fnmatch without FNM_PATHNAME, which pacman uses for hook targets
+        assert!(glob("usr/lib/modules/*").is_match("usr/lib/modules/6.1/vmlinuz"));
+        assert_eq!(glob("lib*").as_str(), "lib*");
+    }
+}
@@ -0,0 +1,487 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::glob::Glob;
+use crate::transaction::Transaction;
+use crate::Result;
+
+/// The directory that packages install their hooks into. Hooks here can be overridden or disabled
+/// by a hook with the same file name in one of the configured `HookDir`s.
+pub const SYSTEM_HOOK_DIR: &str = "/usr/share/libalpm/hooks/";
+
+/// A pacman hook, read from a `.hook` file.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Hook {
+    /// The file name of the hook, which decides the order hooks run in.
+    pub name: String,
+    pub path: PathBuf,
+    pub triggers: Vec<Trigger>,
+    pub action: Action,
+}
+
+/// A `[Trigger]` section. The hook runs if any of its triggers match the transaction.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Trigger {
+    pub operations: Vec<Operation>,
+    pub kind: TriggerType,
+    /// Package names or paths relative to root, depending on the type. These may contain glob
+    /// patterns, or start with `!` to exclude what an earlier target matched.
+    pub targets: Vec<String>,
+}
+
+impl Trigger {
+    /// Whether `target` is matched by the trigger's targets. Targets are checked in order, and the
+    /// last one to match decides, so a later `!` target can exclude what an earlier one matched.
+    pub fn matches(&self, target: &str) -> bool {
+        let mut matched = false;
+        for pattern in self.targets.iter() {
+            let (negated, pattern) = match pattern.strip_prefix('!') {
+                Some(pattern) => (true, pattern),
+                None => (false, pattern.as_str()),
+            };
+            // Every target is checked to be a valid pattern when the hook is parsed
+            if pattern.parse::<Glob>().is_ok_and(|x| x.is_match(target)) {
+                matched = !negated;
+            }
+        }
+        matched
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Install,
+    Upgrade,
+    Remove,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerType {
+    /// The targets are paths of files in the packages of the transaction.
+    Path,
+    /// The targets are the names of the packages in the transaction.
+    Package,
+}
+
+/// The `[Action]` section of a hook.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Action {
+    pub description: Option<String>,
+    pub when: When,
+    /// The command to run, which is not passed through a shell.
+    pub exec: String,
+    /// Packages that must be installed for the hook to run.
+    pub depends: Vec<String>,
+    /// Cancel the transaction if the hook fails. Only valid for `PreTransaction` hooks.
+    pub abort_on_fail: bool,
+    /// Pass the matched targets to the command on its standard input.
+    pub needs_targets: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum When {
+    PreTransaction,
+    PostTransaction,
+}
+
+/// Reads a `.hook` file from disk and parses it.
+pub fn read_hook_from_file<P: AsRef<Path>>(filepath: P) -> Result<Hook> {
+    let filepath = filepath.as_ref();
+    let name = filepath
+        .file_name()
+        .and_then(|x| x.to_str())
+        .ok_or_else(|| format!("Invalid hook path '{}'", filepath.display()))?;
+    let hook = std::fs::read_to_string(filepath)?;
+    let mut hook = parse_hook(name, hook.as_str())
+        .map_err(|e| format!("Could not parse hook '{}': {}", filepath.display(), e))?;
+    hook.path = filepath.to_owned();
+    Ok(hook)
+}
+
+/// Parses the contents of a `.hook` file. The file is INI-like, with one or more `[Trigger]`
+/// sections followed by an `[Action]` section. Keys may be repeated where they take more than one
+/// value, and lines starting with `#` are comments.
+pub fn parse_hook(name: &str, hook: &str) -> Result<Hook> {
+    let mut triggers = Vec::new();
+    let mut section = None;
+    let mut operations = Vec::new();
+    let mut kind = None;
+    let mut targets = Vec::new();
+    let mut description = None;
+    let mut when = None;
+    let mut exec = None;
+    let mut depends = Vec::new();
+    let mut abort_on_fail = false;
+    let mut needs_targets = false;
+
+    // Each `[Trigger]` ends at the start of the next section
+    let mut finish_trigger = |operations: &mut Vec<Operation>,
+                              kind: &mut Option<TriggerType>,
+                              targets: &mut Vec<String>|
+     -> Result<()> {
+        if operations.is_empty() {
+            return Err("Every trigger must have an Operation.".into());
+        }
+        for target in targets.iter() {
+            target.trim_start_matches('!').parse::<Glob>()?;
+        }
+        triggers.push(Trigger {
+            operations: std::mem::take(operations),
+            kind: kind.take().ok_or("Every trigger must have a Type.")?,
+            targets: Some(std::mem::take(targets))
+                .filter(|x| !x.is_empty())
+                .ok_or("Every trigger must have a Target.")?,
+        });
+        Ok(())
+    };
+
+    for line in hook.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|x| x.strip_suffix(']')) {
+            if section == Some("Trigger") {
+                finish_trigger(&mut operations, &mut kind, &mut targets)?;
+            }
+            section = match name {
+                "Trigger" | "Action" if section != Some("Action") => Some(name),
+                "Trigger" | "Action" => return Err("The Action must be the last section.".into()),
+                _ => return Err(format!("Unknown section '{}'", name).into()),
+            };
+            continue;
+        }
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => (line, ""),
+        };
+        match (section, key) {
+            (None, _) => {
+                return Err(format!("Option '{}' found outside of a section", key).into());
+            }
+            (Some("Trigger"), "Operation") => operations.push(match value {
+                "Install" => Operation::Install,
+                "Upgrade" => Operation::Upgrade,
+                "Remove" => Operation::Remove,
+                _ => return Err(format!("Invalid Operation '{}'", value).into()),
+            }),
+            (Some("Trigger"), "Type") => {
+                kind = Some(match value {
+                    // `File` is the old name for `Path`
+                    "Path" | "File" => TriggerType::Path,
+                    "Package" => TriggerType::Package,
+                    _ => return Err(format!("Invalid Type '{}'", value).into()),
+                })
+            }
+            (Some("Trigger"), "Target") => targets.push(value.to_owned()),
+            (Some("Action"), "Description") => description = Some(value.to_owned()),
+            (Some("Action"), "When") => {
+                when = Some(match value {
+                    "PreTransaction" => When::PreTransaction,
+                    "PostTransaction" => When::PostTransaction,
+                    _ => return Err(format!("Invalid When '{}'", value).into()),
+                })
+            }
+            (Some("Action"), "Exec") => exec = Some(value.to_owned()),
+            (Some("Action"), "Depends") => depends.push(value.to_owned()),
+            (Some("Action"), "AbortOnFail") => abort_on_fail = true,
+            (Some("Action"), "NeedsTargets") => needs_targets = true,
+            (Some(section), _) => {
+                return Err(format!("Unknown option '{}' in [{}]", key, section).into());
+            }
+        }
+    }
+    if section == Some("Trigger") {
+        finish_trigger(&mut operations, &mut kind, &mut targets)?;
+    }
+    if section != Some("Action") {
+        return Err("Every hook must have an Action.".into());
+    }
+    if triggers.is_empty() {
+        return Err("Every hook must have a Trigger.".into());
+    }
+    let when = when.ok_or("Every hook must have a When.")?;
+    if abort_on_fail && when != When::PreTransaction {
+        return Err("AbortOnFail is only allowed for PreTransaction hooks.".into());
+    }
+
+    Ok(Hook {
+        name: name.to_owned(),
+        path: PathBuf::new(),
+        triggers,
+        action: Action {
+            description,
+            when,
+            exec: exec.ok_or("Every hook must have an Exec.")?,
+            depends,
+            abort_on_fail,
+            needs_targets,
+        },
+    })
+}
+
+/// Returns the hooks from `hooks` that `tx` would run, in the order they would run: the
+/// `PreTransaction` hooks, then the `PostTransaction` hooks, each in the order given, which should
+/// be by name as returned by `discover_hooks`.
+///
+/// `Package` triggers match the names of the packages being installed, upgraded or removed. `Path`
+/// triggers match the files of those packages, relative to root, where `tx` knows them. Files
+/// aren't compared between the old and new versions of an upgraded package, so all of the files
+/// of an upgrade count as being upgraded.
+pub fn matching_hooks<'a>(hooks: &'a [Hook], tx: &Transaction) -> Vec<&'a Hook> {
+    let mut candidates: Vec<(Operation, &str)> = Vec::new();
+    for package in tx.install.iter() {
+        let name = package.desc.name.as_str();
+        if tx.upgrades.iter().any(|x| x == name) {
+            candidates.push((Operation::Upgrade, name));
+        } else {
+            candidates.push((Operation::Install, name));
+        }
+    }
+    candidates.extend(tx.remove.iter().map(|x| (Operation::Remove, x.as_str())));
+
+    let triggered = |trigger: &Trigger| {
+        candidates.iter().any(|(operation, name)| {
+            if !trigger.operations.contains(operation) {
+                return false;
+            }
+            match trigger.kind {
+                TriggerType::Package => trigger.matches(name),
+                TriggerType::Path => tx.files.get(*name).is_some_and(|files| {
+                    files
+                        .iter()
+                        .filter_map(|x| x.to_str())
+                        .any(|x| trigger.matches(x.strip_prefix('/').unwrap_or(x)))
+                }),
+            }
+        })
+    };
+    let mut ret: Vec<&Hook> = hooks
+        .iter()
+        .filter(|hook| hook.triggers.iter().any(triggered))
+        .collect();
+    // The sort is stable, so each group stays in the order it was given in
+    ret.sort_by_key(|hook| hook.action.when == When::PostTransaction);
+    ret
+}
+
+/// Finds and parses every hook in `dirs`, sorted by file name, which is the order that pacman runs
+/// them in. A hook in a later directory replaces one with the same file name in an earlier
+/// directory, and a hook that is a symlink to `/dev/null` disables any hooks it replaces.
+/// Directories that don't exist are skipped.
+pub fn discover_hooks(dirs: &[PathBuf]) -> Result<Vec<Hook>> {
+    let mut found = BTreeMap::new();
+    for dir in dirs {
+        if !dir.is_dir() {
+            continue;
+        }
+        for file in dir.read_dir()? {
+            let file = file?;
+            let name = file.file_name();
+            match name.to_str() {
+                Some(name) if name.ends_with(".hook") => {
+                    found.insert(name.to_owned(), file.path());
+                }
+                _ => {}
+            }
+        }
+    }
+    found
+        .values()
+        .filter(|path| std::fs::read_link(path).map_or(true, |x| x != Path::new("/dev/null")))
+        .map(read_hook_from_file)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::sync_package;
+
+    const LINUX_HOOK: &str = "\
+        # Remove the initramfs after the kernel is removed\n\
+        [Trigger]\n\
+        Type = Path\n\
+        Operation = Install\n\
+        Operation = Upgrade\n\
+        Target = usr/lib/modules/*/vmlinuz\n\
+        \n\
+        [Trigger]\n\
+        Type = Package\n\
+        Operation = Remove\n\
+        Target = linux\n\
+        Target = !linux-lts\n\
+        \n\
+        [Action]\n\
+        Description = Updating linux initcpios...\n\
+        When = PostTransaction\n\
+        Exec = /usr/share/libalpm/scripts/mkinitcpio-install\n\
+        Depends = mkinitcpio\n\
+        NeedsTargets\n";
+
+    #[test]
+    fn test_parse_hook() -> Result<()> {
+        let hook = parse_hook("90-mkinitcpio-install.hook", LINUX_HOOK)?;
+        assert_eq!(
+            hook,
+            Hook {
+                name: "90-mkinitcpio-install.hook".into(),
+                path: PathBuf::new(),
+                triggers: vec![
+                    Trigger {
+                        operations: vec![Operation::Install, Operation::Upgrade],
+                        kind: TriggerType::Path,
+                        targets: vec!["usr/lib/modules/*/vmlinuz".into()],
+                    },
+                    Trigger {
+                        operations: vec![Operation::Remove],
+                        kind: TriggerType::Package,
+                        targets: vec!["linux".into(), "!linux-lts".into()],
+                    },
+                ],
+                action: Action {
+                    description: Some("Updating linux initcpios...".into()),
+                    when: When::PostTransaction,
+                    exec: "/usr/share/libalpm/scripts/mkinitcpio-install".into(),
+                    depends: vec!["mkinitcpio".into()],
+                    abort_on_fail: false,
+                    needs_targets: true,
+                },
+            }
+        );
+
+        let action = "[Action]\nWhen = PreTransaction\nExec = /bin/true\n";
+        let trigger = "[Trigger]\nType = Package\nOperation = Install\nTarget = foo\n";
+        assert!(parse_hook("a.hook", &format!("{}{}", trigger, action)).is_ok());
+        // No trigger, no action, or a trigger missing a key
+        assert!(parse_hook("a.hook", action).is_err());
+        assert!(parse_hook("a.hook", trigger).is_err());
+        assert!(parse_hook(
+            "a.hook",
+            &format!("[Trigger]\nType = Package\nTarget = foo\n{}", action)
+        )
+        .is_err());
+        assert!(parse_hook("a.hook", &format!("{}{}Foo = bar\n", trigger, action)).is_err());
+        assert!(parse_hook(
+            "a.hook",
+            &format!("{}Target = [unclosed\n{}", trigger, action)
+        )
+        .is_err());
+        assert!(parse_hook(
+            "a.hook",
+            &format!(
+                "{}{}",
+                trigger,
+                action.replace("Pre", "Post") + "AbortOnFail\n"
+            )
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_matching_hooks() -> Result<()> {
+        let hook = |name: &str, trigger: &str, when: &str| {
+            parse_hook(
+                name,
+                &format!(
+                    "[Trigger]\n{}\n[Action]\nWhen = {}\nExec = /bin/true\n",
+                    trigger, when
+                ),
+            )
+            .unwrap()
+        };
+        let hooks = [
+            hook(
+                "10-depmod.hook",
+                "Type = Path\nOperation = Install\nOperation = Upgrade\nTarget = usr/lib/modules/*",
+                "PostTransaction",
+            ),
+            hook(
+                "20-snapshot.hook",
+                "Type = Package\nOperation = Upgrade\nOperation = Remove\nTarget = *\nTarget = !*-docs",
+                "PreTransaction",
+            ),
+            hook(
+                "30-removed.hook",
+                "Type = Path\nOperation = Remove\nTarget = usr/lib/modules/*",
+                "PostTransaction",
+            ),
+        ];
+        let names = |hooks: Vec<&Hook>| -> Vec<String> {
+            hooks.into_iter().map(|x| x.name.clone()).collect()
+        };
+
+        let mut tx = Transaction {
+            install: vec![sync_package("core", "linux", "1.0-1", "")],
+            ..Default::default()
+        };
+        // Without knowing the files, only package triggers can match
+        assert!(matching_hooks(&hooks, &tx).is_empty());
+        tx.files.insert(
+            "linux".into(),
+            vec![
+                PathBuf::from("/usr/lib/modules/5.11.6-arch1-1/kernel/fs/ext4.ko.zst"),
+                PathBuf::from("/usr/share/licenses/linux/COPYING"),
+            ],
+        );
+        assert_eq!(names(matching_hooks(&hooks, &tx)), ["10-depmod.hook"]);
+
+        // Pre-transaction hooks come first
+        tx.upgrades.push("linux".into());
+        assert_eq!(
+            names(matching_hooks(&hooks, &tx)),
+            ["20-snapshot.hook", "10-depmod.hook"]
+        );
+
+        tx.install.clear();
+        tx.upgrades.clear();
+        tx.remove.push("linux-docs".into());
+        assert!(matching_hooks(&hooks, &tx).is_empty());
+        tx.remove.push("linux".into());
+        assert_eq!(
+            names(matching_hooks(&hooks, &tx)),
+            ["20-snapshot.hook", "30-removed.hook"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_discover_hooks() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let system = dir.path().join("usr/share/libalpm/hooks");
+        let etc = dir.path().join("etc/pacman.d/hooks");
+        std::fs::create_dir_all(&system)?;
+        std::fs::create_dir_all(&etc)?;
+        let hook = |exec: &str| {
+            format!(
+                "[Trigger]\nType = Package\nOperation = Upgrade\nTarget = *\n[Action]\nWhen = PostTransaction\nExec = {}\n",
+                exec
+            )
+        };
+        std::fs::write(system.join("20-b.hook"), hook("/usr/bin/b"))?;
+        std::fs::write(system.join("10-a.hook"), hook("/usr/bin/a"))?;
+        std::fs::write(system.join("30-disabled.hook"), hook("/usr/bin/c"))?;
+        std::fs::write(system.join("README"), "not a hook")?;
+        std::fs::write(etc.join("20-b.hook"), hook("/usr/local/bin/b"))?;
+        std::os::unix::fs::symlink("/dev/null", etc.join("30-disabled.hook"))?;
+
+        let hooks = discover_hooks(&[system.clone(), etc.clone(), dir.path().join("missing")])?;
+        let found: Vec<_> = hooks
+            .iter()
+            .map(|x| (x.name.as_str(), x.action.exec.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("10-a.hook", "/usr/bin/a"),
+                ("20-b.hook", "/usr/local/bin/b")
+            ]
+        );
+        assert_eq!(hooks[1].path, etc.join("20-b.hook"));
+
+        std::fs::write(etc.join("40-broken.hook"), "[Action]\n")?;
+        assert!(discover_hooks(&[system, etc]).is_err());
+        Ok(())
+    }
+}
@@ -1,3 +1,13 @@
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::PathBuf;
+
+use crate::database::local::sort::SortKey;
+use crate::dependency::{Comparison, Dependency};
+use crate::format::{self, ColorMode, PrintFormat};
+use crate::glob::Glob;
+use crate::output::OutputFormat;
+use crate::resolver::ProviderPolicy;
+
 pub use structopt::StructOpt;
 
 #[derive(StructOpt, Debug)]
@@ -24,9 +34,354 @@ pub struct Args {
     /// Upgrade mode
     #[structopt(long, short = "U", group = "mode")]
     pub upgrade: bool,
+    /// Check that the local database is in a state this program can read, and print a report to
+    /// include in bug reports. Nothing is changed
+    #[structopt(long, group = "mode", hidden = true)]
+    pub selftest: bool,
+
+    /// Query the packages that own the given files
+    #[structopt(long, short = "o")]
+    pub owns: bool,
+
+    /// Query the packages that own any file whose path matches the given regular expressions
+    #[structopt(long)]
+    pub owns_regex: bool,
+
+    /// List the installed members of package groups
+    #[structopt(long, short = "g")]
+    pub groups: bool,
+
+    /// Check that the files owned by the given packages are unchanged. Pass twice to also compare
+    /// checksums
+    #[structopt(long, short = "k", parse(from_occurrences))]
+    pub check: u8,
+
+    /// List the configuration files of every package that have been changed since they were
+    /// installed
+    #[structopt(long)]
+    pub changed_config: bool,
+
+    /// Only list packages that were installed explicitly, not as dependencies
+    #[structopt(long, short = "e", alias = "explicit-only")]
+    pub explicit: bool,
+
+    /// Leave packages whose names match a wildcard pattern, such as 'lib*', out of query listings.
+    /// Can be repeated
+    #[structopt(long, number_of_values = 1)]
+    pub exclude: Vec<Glob>,
+
+    /// List the packages that no other installed package depends on
+    #[structopt(long, short = "t")]
+    pub unrequired: bool,
+
+    /// Print the dependency tree of the given packages
+    #[structopt(long)]
+    pub tree: bool,
+    /// Show the packages that depend on the given packages in the dependency tree instead. In
+    /// package listings, reverse the order given by --sort
+    #[structopt(long)]
+    pub reverse: bool,
+    /// Limit the depth of the dependency tree
+    #[structopt(long)]
+    pub depth: Option<usize>,
+
+    /// Print every installed package as a `<name> <version> <reason>` line, for comparing
+    /// systems with diff
+    #[structopt(long)]
+    pub dump_manifest: bool,
+
+    /// Order package listings by name, size, installdate or builddate
+    #[structopt(long, default_value = "name")]
+    pub sort: SortKey,
+
+    /// List the given number of most recently installed packages
+    #[structopt(long)]
+    pub recent: Option<usize>,
+
+    /// Don't read the file lists of packages, only their descriptions. File lists are only read
+    /// when a query needs them, so this is always the case, and the flag is kept for scripts that
+    /// pass it. Can't be used with queries about files
+    #[structopt(long, conflicts_with_all = &["owns", "owns-regex", "check", "changed-config"])]
+    pub no_files: bool,
+
+    /// Only list packages installed on or after the given date, as YYYY-MM-DD in UTC
+    #[structopt(long, parse(try_from_str = format::parse_date))]
+    pub newer_than: Option<u64>,
+
+    /// Only list packages installed before the given date, as YYYY-MM-DD in UTC
+    #[structopt(long, parse(try_from_str = format::parse_date))]
+    pub older_than: Option<u64>,
+
+    /// Show installed packages as `repo/name`, naming the sync repository each one comes from.
+    /// Foreign packages are shown without a repository
+    #[structopt(long)]
+    pub show_repo: bool,
+
+    /// Download fresh copies of the sync databases. Pass twice to download them even if they are
+    /// up to date
+    #[structopt(long, short = "y", parse(from_occurrences))]
+    pub refresh: u8,
+    /// Print the transaction that would make the installed packages match a manifest written by
+    /// --dump-manifest
+    #[structopt(long, parse(from_os_str))]
+    pub apply_manifest: Option<PathBuf>,
+
+    /// Search the sync databases, or the installed packages with -Q, for packages whose name or
+    /// description matches every one of the given regular expressions
+    #[structopt(long, short = "s")]
+    pub search: bool,
+    /// Only search the sync database of this repository
+    #[structopt(long)]
+    pub repo: Option<String>,
+
+    /// Remove old packages from the cache, keeping the installed versions. Pass twice to only keep
+    /// the versions in the sync databases
+    #[structopt(long, short = "c", parse(from_occurrences))]
+    pub clean: u8,
+    /// Print the download URLs of the packages instead of installing them
+    #[structopt(long, short = "p", alias = "print-uris")]
+    pub print: bool,
+    /// Download the packages into the cache without installing them
+    #[structopt(long, short = "w", alias = "downloadonly")]
+    pub download_only: bool,
+    /// Don't ask which package to use when several provide a dependency, and use the first one
+    /// from the repositories in the order they are configured
+    #[structopt(long)]
+    pub assume_yes_to_first_provider: bool,
+    /// Don't reinstall packages that are already installed at the version that would be installed
+    #[structopt(long)]
+    pub needed: bool,
+    /// Also install the optional dependencies of the packages being installed, as dependencies
+    #[structopt(long, alias = "asdeps-optional")]
+    pub install_optdeps: bool,
+    /// Only print what would be done, without changing anything
+    #[structopt(long)]
+    pub dry_run: bool,
+
+    /// Only modify database entries, not package files
+    #[structopt(long)]
+    pub dbonly: bool,
+
+    /// Skip dependency version checks. Pass twice to skip all dependency checks. In query mode,
+    /// only list packages installed as dependencies instead
+    #[structopt(long, short = "d", parse(from_occurrences))]
+    pub nodeps: u8,
+
+    /// Do not ask for any confirmation
+    #[structopt(long, overrides_with = "confirm")]
+    pub noconfirm: bool,
+    /// Always ask for confirmation
+    #[structopt(long, overrides_with = "noconfirm")]
+    pub confirm: bool,
+
+    /// Print each package using a template, where %n is the name, %v the version, %d the
+    /// description, %s the installed size, %r the repository and, with -Sp, %l the download URL.
+    /// %D, %O, %P, %C, %R, %G and %L are the depends, optdepends, provides, conflicts, replaces,
+    /// groups and licenses
+    #[structopt(long, group = "output")]
+    pub print_format: Option<PrintFormat>,
+    /// What to separate the items of list placeholders in --print-format with, such as %D
+    #[structopt(long, default_value = " ")]
+    pub list_separator: String,
+    /// Print package listings as a JSON array
+    #[structopt(long, group = "output")]
+    pub json: bool,
+    /// Print only the names in package listings, each followed by a NUL byte instead of a newline
+    #[structopt(long, group = "output")]
+    pub null: bool,
+
+    /// Style output with colours: auto (only when printing to a terminal, unless NO_COLOR is
+    /// set), always or never
+    #[structopt(long, default_value = "auto")]
+    pub color: ColorMode,
+
+    /// Show less information for query operations
+    #[structopt(long, short)]
+    pub quiet: bool,
+
+    /// Print more information about what is happening. Can be repeated for more detail
+    #[structopt(long, short, parse(from_occurrences))]
+    pub verbose: u8,
+
+    /// Use an alternate config file
+    #[structopt(long, parse(from_os_str))]
+    pub config: Option<PathBuf>,
+    /// Use an alternate database location
+    #[structopt(long, short = "b", parse(from_os_str))]
+    pub dbpath: Option<PathBuf>,
+    /// Use an alternate installation root
+    #[structopt(long, short = "r", parse(from_os_str))]
+    pub root: Option<PathBuf>,
+    /// Operate on a system mounted at <sysroot>. Every path used, including the config file and
+    /// database, is looked up under it
+    #[structopt(long, parse(from_os_str))]
+    pub sysroot: Option<PathBuf>,
+
+    /// Install packages for this architecture instead of the one in the config file, or the
+    /// machine's architecture
+    #[structopt(long)]
+    pub machine_arch: Option<String>,
+
+    /// Treat a dependency as satisfied, as if <package=version> were installed
+    #[structopt(long, number_of_values = 1, parse(try_from_str = parse_assume_installed))]
+    pub assume_installed: Vec<Dependency>,
+
+    /// The packages or dependencies to operate on
+    #[structopt(name = "TARGETS")]
+    pub targets: Vec<String>,
+}
+
+fn parse_assume_installed(s: &str) -> Result<Dependency, String> {
+    let dep: Dependency = s.parse()?;
+    match dep.constraint {
+        None | Some((Comparison::Equal, _)) => Ok(dep),
+        Some(_) => Err(format!("'{}' must be of the form <package>[=<version>]", s)),
+    }
+}
+
+/// Asks the user a yes/no question on the terminal, where the default answer is yes. If stdin is not
+/// a terminal, nobody can answer, so this returns false.
+pub fn confirm(prompt: &str) -> bool {
+    let stdin = std::io::stdin();
+    if !stdin.is_terminal() {
+        return false;
+    }
+    confirm_with(prompt, true, &mut stdin.lock(), &mut std::io::stdout()).unwrap_or(false)
+}
+
+/// Like `confirm`, but the default answer is no, for questions about doing something dangerous.
+pub fn confirm_default_no(prompt: &str) -> bool {
+    let stdin = std::io::stdin();
+    if !stdin.is_terminal() {
+        return false;
+    }
+    confirm_with(prompt, false, &mut stdin.lock(), &mut std::io::stdout()).unwrap_or(false)
+}
+
+/// Asks the user which of the packages that provide `dep` to use, like pacman does, returning the
+/// index of the chosen one. The default answer is the first. If stdin is not a terminal, nobody can
+/// answer, so this returns the default.
+pub fn choose_provider(dep: &str, providers: &[&str]) -> usize {
+    let stdin = std::io::stdin();
+    if !stdin.is_terminal() {
+        return 0;
+    }
+    choose_provider_with(dep, providers, &mut stdin.lock(), &mut std::io::stdout()).unwrap_or(0)
+}
+
+fn choose_provider_with(
+    dep: &str,
+    providers: &[&str],
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> Result<usize, std::io::Error> {
+    writeln!(
+        output,
+        ":: There are {} providers available for {}:",
+        providers.len(),
+        dep
+    )?;
+    let choices: Vec<String> = providers
+        .iter()
+        .enumerate()
+        .map(|(i, x)| format!("{}) {}", i + 1, x))
+        .collect();
+    writeln!(output, "   {}", choices.join("  "))?;
+    loop {
+        write!(output, "\nEnter a number (default=1): ")?;
+        output.flush()?;
+        let mut answer = String::new();
+        if input.read_line(&mut answer)? == 0 {
+            return Ok(0);
+        }
+        let answer = answer.trim();
+        if answer.is_empty() {
+            return Ok(0);
+        }
+        match answer.parse::<usize>() {
+            Ok(n) if (1..=providers.len()).contains(&n) => return Ok(n - 1),
+            _ => writeln!(output, "error: invalid number: {}", answer)?,
+        }
+    }
+}
+
+fn confirm_with(
+    prompt: &str,
+    default: bool,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> Result<bool, std::io::Error> {
+    let choices = if default { "[Y/n]" } else { "[y/N]" };
+    write!(output, "{} {} ", prompt, choices)?;
+    output.flush()?;
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    Ok(parse_answer(&answer, default))
+}
+
+fn parse_answer(answer: &str, default: bool) -> bool {
+    let answer = answer.trim();
+    if answer.is_empty() {
+        return default;
+    }
+    answer.eq_ignore_ascii_case("y") || answer.eq_ignore_ascii_case("yes")
 }
 
 impl Args {
+    /// Whether a query should only list packages installed as dependencies. Like pacman, `-d`
+    /// means this in query mode rather than `--nodeps`.
+    pub fn deps_only(&self) -> bool {
+        self.query && self.nodeps > 0
+    }
+
+    /// The `--print-format` template, with the `--list-separator` applied.
+    pub fn print_format(&self) -> Option<PrintFormat> {
+        self.print_format
+            .clone()
+            .map(|x| x.with_separator(&self.list_separator))
+    }
+
+    /// The format that package listings are printed in. `color` is whether the text format is
+    /// styled, which depends on where it is printed.
+    pub fn output_format(&self, color: bool) -> OutputFormat {
+        if let Some(template) = self.print_format() {
+            OutputFormat::Template(template)
+        } else if self.json {
+            OutputFormat::Json
+        } else if self.null {
+            OutputFormat::Null
+        } else {
+            OutputFormat::Text {
+                quiet: self.quiet,
+                color,
+            }
+        }
+    }
+
+    /// How to choose between the packages that provide a dependency. pacman asks, unless
+    /// `--noconfirm` is given, in which case it takes the default answer.
+    pub fn provider_policy(&self) -> ProviderPolicy {
+        if self.assume_yes_to_first_provider {
+            ProviderPolicy::FirstInRepoOrder
+        } else if self.noconfirm {
+            ProviderPolicy::PreferInstalled
+        } else {
+            ProviderPolicy::Prompt
+        }
+    }
+
+    /// Checks that `--print-format` is only given `%l` (the download URL) with `-Sp`, which is the
+    /// only operation that knows where packages are downloaded from.
+    pub fn check_print_format(&self) -> Result<(), String> {
+        match &self.print_format {
+            Some(template) if template.uses_link() && !(self.sync && self.print) => Err(
+                "%l in --print-format needs the sync databases, so it can only be used with -Sp"
+                    .into(),
+            ),
+            _ => Ok(()),
+        }
+    }
+
     pub fn parse_mode(&self) -> Mode {
         if self.database {
             Mode::Database
@@ -42,6 +397,8 @@ impl Args {
             Mode::Deptest
         } else if self.upgrade {
             Mode::Upgrade
+        } else if self.selftest {
+            Mode::SelfTest
         } else {
             panic!("Error: No operation mode provided");
         }
@@ -57,4 +414,167 @@ pub enum Mode {
     Sync,
     Deptest,
     Upgrade,
+    SelfTest,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_confirm() {
+        let answers = [
+            ("\n", true),
+            ("y\n", true),
+            ("YES\n", true),
+            ("  yes  \n", true),
+            ("n\n", false),
+            ("No\n", false),
+            ("maybe\n", false),
+        ];
+        for (input, expected) in answers.iter() {
+            let mut output = Vec::new();
+            let answer =
+                confirm_with("Proceed?", true, &mut input.as_bytes(), &mut output).unwrap();
+            assert_eq!(answer, *expected, "answer {:?}", input);
+            assert_eq!(output, b"Proceed? [Y/n] ");
+        }
+
+        let mut output = Vec::new();
+        assert!(!confirm_with("Remove?", false, &mut &b"\n"[..], &mut output).unwrap());
+        assert_eq!(output, b"Remove? [y/N] ");
+        assert!(confirm_with("Remove?", false, &mut &b"y\n"[..], &mut Vec::new()).unwrap());
+    }
+
+    #[test]
+    fn test_choose_provider() {
+        let providers = ["cronie", "dcron", "fcron"];
+        let answers = [("\n", 0), ("2\n", 1), (" 3 \n", 2), ("", 0), ("4\n1\n", 0)];
+        for (input, expected) in answers.iter() {
+            let mut output = Vec::new();
+            let choice =
+                choose_provider_with("cron", &providers, &mut input.as_bytes(), &mut output)
+                    .unwrap();
+            assert_eq!(choice, *expected, "answer {:?}", input);
+            assert!(String::from_utf8(output).unwrap().starts_with(
+                ":: There are 3 providers available for cron:\n   1) cronie  2) dcron  3) fcron\n\n\
+                 Enter a number (default=1): "
+            ));
+        }
+
+        let mut output = Vec::new();
+        choose_provider_with("cron", &providers, &mut &b"x\n"[..], &mut output).unwrap();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .ends_with("error: invalid number: x\n\nEnter a number (default=1): "));
+    }
+
+    #[test]
+    fn test_provider_policy() {
+        let policy = |args: &[&str]| Args::from_iter(args).provider_policy();
+        assert_eq!(policy(&["pacman-rs", "-S"]), ProviderPolicy::Prompt);
+        assert_eq!(
+            policy(&["pacman-rs", "-S", "--noconfirm"]),
+            ProviderPolicy::PreferInstalled
+        );
+        assert_eq!(
+            policy(&[
+                "pacman-rs",
+                "-S",
+                "--noconfirm",
+                "--assume-yes-to-first-provider"
+            ]),
+            ProviderPolicy::FirstInRepoOrder
+        );
+    }
+
+    #[test]
+    fn test_deps_only() {
+        assert!(Args::from_iter(&["pacman-rs", "-Qdt"]).deps_only());
+        assert!(!Args::from_iter(&["pacman-rs", "-Qt"]).deps_only());
+        assert!(!Args::from_iter(&["pacman-rs", "-Rd"]).deps_only());
+    }
+
+    #[test]
+    fn test_output_format() {
+        let format = |args: &[&str]| Args::from_iter(args).output_format(false);
+        assert_eq!(
+            format(&["pacman-rs", "-Q"]),
+            OutputFormat::Text {
+                quiet: false,
+                color: false
+            }
+        );
+        assert_eq!(
+            format(&["pacman-rs", "-Qq"]),
+            OutputFormat::Text {
+                quiet: true,
+                color: false
+            }
+        );
+        assert_eq!(format(&["pacman-rs", "-Q", "--json"]), OutputFormat::Json);
+        assert_eq!(format(&["pacman-rs", "-Q", "--null"]), OutputFormat::Null);
+        assert_eq!(
+            format(&["pacman-rs", "-Q", "--print-format", "%n"]),
+            OutputFormat::Template("%n".parse().unwrap())
+        );
+        assert!(Args::from_iter_safe(&["pacman-rs", "-Q", "--json", "--null"]).is_err());
+        assert_eq!(Args::from_iter(&["pacman-rs", "-Q"]).color, ColorMode::Auto);
+        let args = Args::from_iter(&["pacman-rs", "-Q", "--color", "never"]);
+        assert_eq!(args.color, ColorMode::Never);
+    }
+
+    #[test]
+    fn test_list_separator() {
+        let format = |args: &[&str]| Args::from_iter(args).output_format(false);
+        let expected = |separator| {
+            OutputFormat::Template(
+                "%D".parse::<PrintFormat>()
+                    .unwrap()
+                    .with_separator(separator),
+            )
+        };
+        assert_eq!(
+            format(&["pacman-rs", "-Q", "--print-format", "%D"]),
+            expected(" ")
+        );
+        assert_eq!(
+            format(&[
+                "pacman-rs",
+                "-Q",
+                "--print-format",
+                "%D",
+                "--list-separator",
+                ","
+            ]),
+            expected(",")
+        );
+    }
+
+    #[test]
+    fn test_check_print_format() {
+        let check = |args: &[&str]| Args::from_iter(args).check_print_format();
+        assert!(check(&["pacman-rs", "-Sp", "--print-format", "%n %l", "bash"]).is_ok());
+        assert!(check(&["pacman-rs", "-Q", "--print-format", "%n %v"]).is_ok());
+        assert!(check(&["pacman-rs", "-Q", "--print-format", "%n %l"]).is_err());
+        assert!(check(&["pacman-rs", "-S", "--print-format", "%l", "bash"]).is_err());
+    }
+
+    #[test]
+    fn test_no_files() {
+        assert!(Args::from_iter(&["pacman-rs", "-Qe", "--no-files"]).no_files);
+        assert!(!Args::from_iter(&["pacman-rs", "-Qe"]).no_files);
+        for flag in &["-o", "--owns-regex", "-k", "--changed-config"] {
+            let args = Args::from_iter_safe(&["pacman-rs", "-Q", flag, "--no-files", "bash"]);
+            assert!(args.is_err(), "{} --no-files", flag);
+        }
+    }
+
+    #[test]
+    fn test_noconfirm_flags() {
+        let args = Args::from_iter(&["pacman-rs", "-R", "--noconfirm"]);
+        assert!(args.noconfirm);
+        let args = Args::from_iter(&["pacman-rs", "-R", "--noconfirm", "--confirm"]);
+        assert!(!args.noconfirm);
+    }
 }
@@ -0,0 +1,28 @@
+//! A port of pacman. The `pacman-rs` binary parses its arguments and hands them to
+//! `commands::run`; the modules are exposed as a library so that they can be benchmarked.
+
+#![allow(dead_code)]
+pub mod cache;
+pub mod commands;
+pub mod config;
+pub mod database;
+pub mod dependency;
+pub mod download;
+pub mod format;
+pub mod glob;
+pub mod hooks;
+pub mod interface;
+pub mod manifest;
+pub mod md5;
+pub mod output;
+pub mod package;
+pub mod resolver;
+pub mod sha256;
+pub mod signature;
+pub mod sysroot;
+#[cfg(test)]
+mod test_util;
+pub mod transaction;
+pub mod version;
+
+pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
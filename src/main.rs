@@ -1,53 +1,19 @@
-#![allow(dead_code)]
-mod database;
-mod interface;
+use pacman_rs::interface::Args;
+use pacman_rs::{commands, config, Result};
 
-use crate::interface::Args;
-use crate::interface::Mode;
-
-use ansi_term::Style;
 use structopt::StructOpt;
 
-type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
-
 fn main() -> Result<()> {
-    println!("Hello, world!");
     let args = Args::from_args();
+    let log_level = match args.verbose {
+        0 => log::LevelFilter::Warn,
+        1 => log::LevelFilter::Info,
+        2 => log::LevelFilter::Debug,
+        _ => log::LevelFilter::Trace,
+    };
+    env_logger::Builder::new().filter_level(log_level).init();
     let mode = args.parse_mode();
-
-    match mode {
-        Mode::Database => {
-            unimplemented!()
-        }
-        Mode::Files => {
-            unimplemented!()
-        }
-        Mode::Query => {
-            let mut local_database = database::local::LocalDatabase::new();
-            local_database.populate_full_database()?;
-            for (name, pkg) in local_database.db {
-                let style = Style::new().bold();
-                println!(
-                    "{} {}",
-                    style.paint(name.as_str()),
-                    style
-                        .fg(ansi_term::Color::Green)
-                        .paint(pkg.desc.version.as_str())
-                );
-            }
-            Ok(())
-        }
-        Mode::Remove => {
-            unimplemented!()
-        }
-        Mode::Sync => {
-            unimplemented!()
-        }
-        Mode::Deptest => {
-            unimplemented!()
-        }
-        Mode::Upgrade => {
-            unimplemented!()
-        }
-    }
+    args.check_print_format()?;
+    let config = config::load(&args)?;
+    commands::run(mode, &args, &config)
 }
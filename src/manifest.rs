@@ -0,0 +1,214 @@
+use std::collections::HashSet;
+
+use crate::database::local::LocalDatabase;
+use crate::database::sync::SyncDatabases;
+use crate::dependency::Dependency;
+use crate::transaction::{DependencyChecks, SyncPackage, Transaction};
+use crate::Result;
+
+/// A package listed in a manifest written by `LocalDatabase::dump_manifest`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub name: String,
+    pub version: String,
+    pub explicit: bool,
+}
+
+/// Parses a manifest of `<name> <version> <reason>` lines. Blank lines and lines starting with `#`
+/// are ignored.
+pub fn parse_manifest(manifest: &str) -> Result<Vec<ManifestEntry>> {
+    let mut ret = Vec::new();
+    for (i, line) in manifest.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let explicit = match fields.as_slice() {
+            [_, _, "explicit"] => true,
+            [_, _, "dependency"] => false,
+            _ => {
+                return Err(format!(
+                    "Invalid manifest line {}: expected '<name> <version> <explicit|dependency>'",
+                    i + 1
+                )
+                .into())
+            }
+        };
+        ret.push(ManifestEntry {
+            name: fields[0].to_owned(),
+            version: fields[1].to_owned(),
+            explicit,
+        });
+    }
+    Ok(ret)
+}
+
+/// Plans the transaction that makes the installed packages match `manifest`. Packages in the
+/// manifest that aren't installed are installed from `sync`. Installed packages that aren't in the
+/// manifest are removed, except for ones that a package which stays installed, or is about to be,
+/// still depends on, whether they were installed explicitly or not. The removals are checked like
+/// `pacman -R`. Versions are not compared, so nothing is upgraded or downgraded.
+pub fn plan_manifest(
+    local: &mut LocalDatabase,
+    sync: &SyncDatabases,
+    manifest: &[ManifestEntry],
+) -> Result<Transaction> {
+    local.populate_full_database()?;
+    let wanted: HashSet<&str> = manifest.iter().map(|x| x.name.as_str()).collect();
+
+    let mut install = Vec::new();
+    for entry in manifest.iter() {
+        if local.db.contains_key(&entry.name) {
+            continue;
+        }
+        let (repo, desc) = sync
+            .get(&entry.name)
+            .ok_or_else(|| format!("target not found: {}", entry.name))?;
+        install.push(SyncPackage {
+            repo: repo.to_owned(),
+            desc: desc.clone(),
+        });
+    }
+    let mut install_deps = Vec::new();
+    for package in install.iter() {
+        for dep in package.desc.dependencies.iter() {
+            install_deps.push(dep.parse::<Dependency>()?);
+        }
+    }
+
+    let mut removed: HashSet<String> = local
+        .package_names()
+        .filter(|x| !wanted.contains(x))
+        .map(|x| x.to_owned())
+        .collect();
+    let reverse_dependencies = local.reverse_dependencies()?.clone();
+    // Keeping one dependency can mean keeping its own dependencies, so repeat until nothing changes
+    loop {
+        let still_required: Vec<String> = removed
+            .iter()
+            .filter(|name| {
+                let desc = &local.db[*name].desc;
+                reverse_dependencies[*name]
+                    .iter()
+                    .any(|x| !removed.contains(x))
+                    || install_deps.iter().any(|x| x.is_satisfied_by_package(desc))
+            })
+            .cloned()
+            .collect();
+        if still_required.is_empty() {
+            break;
+        }
+        for name in still_required {
+            removed.remove(&name);
+        }
+    }
+
+    let mut remove: Vec<&str> = removed.iter().map(|x| x.as_str()).collect();
+    remove.sort_unstable();
+    let mut transaction = local.remove(&remove, DependencyChecks::Full)?;
+    transaction.install = install;
+    Ok(transaction)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::sync::SyncDatabase;
+    use crate::test_util::{Fixture, TarBuilder};
+
+    #[test]
+    fn test_parse_manifest() -> Result<()> {
+        let manifest =
+            parse_manifest("# from host1\nbash 5.1.004-1 explicit\n\nglibc 2.33-4 dependency\n")?;
+        assert_eq!(
+            manifest,
+            [
+                ManifestEntry {
+                    name: "bash".into(),
+                    version: "5.1.004-1".into(),
+                    explicit: true,
+                },
+                ManifestEntry {
+                    name: "glibc".into(),
+                    version: "2.33-4".into(),
+                    explicit: false,
+                },
+            ]
+        );
+        assert!(parse_manifest("bash 5.1.004-1\n").is_err());
+        assert!(parse_manifest("bash 5.1.004-1 maybe\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_manifest() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("bash", "5.1.004-1", "%DEPENDS%\nreadline\n\n", &[]);
+        fixture.add("readline", "8.1-1", "%REASON%\n1\n\n", &[]);
+        fixture.add("emacs", "27.1-1", "%DEPENDS%\ngtk3\n\n", &[]);
+        fixture.add("gtk3", "3.24-1", "%REASON%\n1\n\n%DEPENDS%\npango\n\n", &[]);
+        fixture.add("pango", "1.48-1", "%REASON%\n1\n\n", &[]);
+        fixture.add("ncurses", "6.2-1", "%REASON%\n1\n\n", &[]);
+        let mut local = LocalDatabase::with_path(fixture.path());
+
+        let archive = TarBuilder::new()
+            .file(
+                "gvim-8.2-1/desc",
+                0o644,
+                b"%NAME%\ngvim\n\n%VERSION%\n8.2-1\n\n%DEPENDS%\ngtk3\nncurses\n\n",
+            )
+            .finish();
+        let sync = SyncDatabases::new(vec![SyncDatabase::from_archive("extra", &archive)?]);
+
+        // emacs goes, but gtk3 and pango stay for gvim, which also needs ncurses
+        let manifest = parse_manifest(
+            "bash 5.1.004-1 explicit\nreadline 8.1-1 dependency\ngvim 8.2-1 explicit\n",
+        )?;
+        let transaction = plan_manifest(&mut local, &sync, &manifest)?;
+        assert_eq!(transaction.remove, ["emacs"]);
+        let install: Vec<_> = transaction
+            .install
+            .iter()
+            .map(|x| x.desc.name.as_str())
+            .collect();
+        assert_eq!(install, ["gvim"]);
+
+        // Without gvim, the dependencies that only emacs needed are removed as well
+        let manifest = parse_manifest("bash 5.1.004-1 explicit\n")?;
+        let transaction = plan_manifest(&mut local, &sync, &manifest)?;
+        assert_eq!(transaction.remove, ["emacs", "gtk3", "ncurses", "pango"]);
+        assert!(transaction.install.is_empty());
+
+        let manifest = parse_manifest("firefox 86.0-1 explicit\n")?;
+        assert!(plan_manifest(&mut local, &sync, &manifest).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_manifest_explicit_dependency() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("bash", "5.1.004-1", "%DEPENDS%\nglibc\n\n", &[]);
+        fixture.add("glibc", "2.33-4", "", &[]);
+        fixture.add("zlib", "1.2.11-4", "", &[]);
+        fixture.add("emacs", "27.1-1", "", &[]);
+        let mut local = LocalDatabase::with_path(fixture.path());
+
+        let archive = TarBuilder::new()
+            .file(
+                "vim-8.2-1/desc",
+                0o644,
+                b"%NAME%\nvim\n\n%VERSION%\n8.2-1\n\n%DEPENDS%\nzlib\n\n",
+            )
+            .finish();
+        let sync = SyncDatabases::new(vec![SyncDatabase::from_archive("extra", &archive)?]);
+
+        // glibc and zlib were installed explicitly and aren't in the manifest, but bash and vim
+        // still need them
+        let manifest = parse_manifest("bash 5.1.004-1 explicit\nvim 8.2-1 explicit\n")?;
+        let transaction = plan_manifest(&mut local, &sync, &manifest)?;
+        assert_eq!(transaction.remove, ["emacs"]);
+        assert_eq!(transaction.install.len(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,46 @@
+use std::io::Read;
+
+use md5::{Digest, Md5};
+
+/// Computes the md5 checksum of everything read from `reader`, as a lowercase hex string, in the
+/// same format as the `md5digest` keyword of an `mtree`.
+pub fn hex_digest(mut reader: impl Read) -> std::io::Result<String> {
+    let mut hasher = Md5::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|x| format!("{:02x}", x))
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::hex_digest;
+
+    #[test]
+    fn test_hex_digest() -> std::io::Result<()> {
+        assert_eq!(hex_digest(&b""[..])?, "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(hex_digest(&b"abc"[..])?, "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(
+            hex_digest(&b"The quick brown fox jumps over the lazy dog"[..])?,
+            "9e107d9d372bb6826bd81d3542a419d6"
+        );
+        // Long enough that the padding spills into a second block
+        assert_eq!(
+            hex_digest(
+                &b"12345678901234567890123456789012345678901234567890123456789012345678901234567890"
+                    [..]
+            )?,
+            "57edf4a22be3c955ac49da2e2107b67a"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,339 @@
+use std::io::Write;
+
+use crate::database::local::LocalDatabaseEntry;
+use crate::format::{self, PrintFormat};
+use crate::Result;
+
+use ansi_term::Style;
+
+/// Writes a listing of installed packages in one output format. Each package is passed to
+/// `package` in the order it should be listed, along with the sync repository it comes from if
+/// that is known, and `finish` is called once after the last one.
+pub trait OutputFormatter {
+    fn package(&mut self, entry: &LocalDatabaseEntry, repo: Option<&str>) -> Result<()>;
+
+    /// Writes anything that has to come after the last package. Formats that write each package
+    /// as soon as it is given don't need to do anything.
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// How a listing of packages is written, chosen from the command line flags.
+#[derive(Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// The names and versions in aligned columns, or just the names with `quiet`. The names are
+    /// bold and the versions green if `color`.
+    Text { quiet: bool, color: bool },
+    /// A JSON array with an object for each package, from `--json`.
+    Json,
+    /// Only the names, each followed by a NUL byte for `xargs -0`, from `--null`.
+    Null,
+    /// A line for each package from a `--print-format` template.
+    Template(PrintFormat),
+}
+
+impl OutputFormat {
+    /// Creates a formatter that writes packages in this format to `out`.
+    pub fn formatter<'a>(&'a self, out: impl Write + 'a) -> Box<dyn OutputFormatter + 'a> {
+        match self {
+            OutputFormat::Text { quiet, color } => {
+                Box::new(TextFormatter::new(out, *quiet, *color))
+            }
+            OutputFormat::Json => Box::new(JsonFormatter::new(out)),
+            OutputFormat::Null => Box::new(NulFormatter::new(out)),
+            OutputFormat::Template(template) => Box::new(TemplateFormatter::new(out, template)),
+        }
+    }
+}
+
+/// The default format, with bold names and green versions like `pacman -Q` if `color`. Packages
+/// from a known repository are named `repo/name`. The versions line up after the longest name, so
+/// nothing is written until every package has been given.
+pub struct TextFormatter<W> {
+    out: W,
+    quiet: bool,
+    color: bool,
+    packages: Vec<(String, String)>,
+}
+
+impl<W: Write> TextFormatter<W> {
+    pub fn new(out: W, quiet: bool, color: bool) -> Self {
+        Self {
+            out,
+            quiet,
+            color,
+            packages: Vec::new(),
+        }
+    }
+}
+
+impl<W: Write> OutputFormatter for TextFormatter<W> {
+    fn package(&mut self, entry: &LocalDatabaseEntry, repo: Option<&str>) -> Result<()> {
+        let name = match repo {
+            Some(repo) => format!("{}/{}", repo, entry.desc.name),
+            None => entry.desc.name.clone(),
+        };
+        if self.quiet {
+            writeln!(self.out, "{}", name)?;
+        } else {
+            self.packages.push((name, entry.desc.version.clone()));
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let width = format::column_width(self.packages.iter().map(|(name, _)| name.as_str()));
+        let style = format::styled(self.color, Style::new().bold());
+        let version_style = format::styled(self.color, style.fg(ansi_term::Color::Green));
+        for (name, version) in self.packages.drain(..) {
+            writeln!(
+                self.out,
+                "{} {}",
+                style.paint(format!("{:width$}", name, width = width)),
+                version_style.paint(version)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A JSON array of objects with the name, repository, version, description, installed size,
+/// install date and install reason of each package. Fields that a package doesn't record, and the
+/// repository of packages that don't come from a known one, are `null`.
+pub struct JsonFormatter<W> {
+    out: W,
+    started: bool,
+}
+
+impl<W: Write> JsonFormatter<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            started: false,
+        }
+    }
+}
+
+impl<W: Write> OutputFormatter for JsonFormatter<W> {
+    fn package(&mut self, entry: &LocalDatabaseEntry, repo: Option<&str>) -> Result<()> {
+        let desc = &entry.desc;
+        write!(self.out, "{}", if self.started { ",\n" } else { "[\n" })?;
+        self.started = true;
+        let repo = repo.map(json_string);
+        let description = desc.description.as_deref().map(json_string);
+        write!(
+            self.out,
+            "  {{\"name\": {}, \"repo\": {}, \"version\": {}, \"description\": {}, \
+             \"size\": {}, \"install_date\": {}, \"explicit\": {}}}",
+            json_string(&desc.name),
+            repo.as_deref().unwrap_or("null"),
+            json_string(&desc.version),
+            description.as_deref().unwrap_or("null"),
+            json_number(desc.size),
+            json_number(desc.install_date),
+            desc.is_explicit()
+        )?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if self.started {
+            writeln!(self.out, "\n]")?;
+        } else {
+            writeln!(self.out, "[]")?;
+        }
+        Ok(())
+    }
+}
+
+/// Quotes `s` as a JSON string.
+fn json_string(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len() + 2);
+    ret.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => ret.push_str("\\\""),
+            '\\' => ret.push_str("\\\\"),
+            '\n' => ret.push_str("\\n"),
+            '\t' => ret.push_str("\\t"),
+            c if (c as u32) < 0x20 => ret.push_str(&format!("\\u{:04x}", c as u32)),
+            c => ret.push(c),
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+fn json_number(n: Option<u64>) -> String {
+    n.map_or_else(|| "null".to_owned(), |x| x.to_string())
+}
+
+/// Only the package names, each followed by a NUL byte rather than a newline, so that they can be
+/// passed to `xargs -0`.
+pub struct NulFormatter<W> {
+    out: W,
+}
+
+impl<W: Write> NulFormatter<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+}
+
+impl<W: Write> OutputFormatter for NulFormatter<W> {
+    fn package(&mut self, entry: &LocalDatabaseEntry, _repo: Option<&str>) -> Result<()> {
+        self.out.write_all(entry.desc.name.as_bytes())?;
+        self.out.write_all(b"\0")?;
+        Ok(())
+    }
+}
+
+/// A line for each package from a `--print-format` template. `%r` is the repository the package
+/// comes from, or `local` if that isn't known.
+pub struct TemplateFormatter<'a, W> {
+    out: W,
+    template: &'a PrintFormat,
+}
+
+impl<'a, W: Write> TemplateFormatter<'a, W> {
+    pub fn new(out: W, template: &'a PrintFormat) -> Self {
+        Self { out, template }
+    }
+}
+
+impl<W: Write> OutputFormatter for TemplateFormatter<'_, W> {
+    fn package(&mut self, entry: &LocalDatabaseEntry, repo: Option<&str>) -> Result<()> {
+        let repo = repo.unwrap_or("local");
+        writeln!(self.out, "{}", self.template.render(&entry.desc, repo))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn render(format: &OutputFormat, entries: &[LocalDatabaseEntry]) -> Result<String> {
+        let mut out = Vec::new();
+        let mut formatter = format.formatter(&mut out);
+        for entry in entries {
+            formatter.package(entry, None)?;
+        }
+        formatter.finish()?;
+        drop(formatter);
+        Ok(String::from_utf8(out)?)
+    }
+
+    fn fixture() -> Result<Vec<LocalDatabaseEntry>> {
+        Ok(vec![
+            LocalDatabaseEntry::from_strings(
+                "%NAME%\nbash\n\n%VERSION%\n5.1.004-1\n\n%DESC%\nThe \"Bourne-again\" shell\n\n\
+                 %SIZE%\n8192\n\n%INSTALLDATE%\n1615000000\n\n",
+                None,
+            )?,
+            LocalDatabaseEntry::from_strings(
+                "%NAME%\nreadline\n\n%VERSION%\n8.1-1\n\n%REASON%\n1\n\n",
+                None,
+            )?,
+        ])
+    }
+
+    #[test]
+    fn test_text_formatter() -> Result<()> {
+        let format = OutputFormat::Text {
+            quiet: false,
+            color: true,
+        };
+        let out = render(&format, &fixture()?)?;
+        let style = Style::new().bold();
+        let green = style.fg(ansi_term::Color::Green);
+        assert_eq!(
+            out,
+            format!(
+                "{} {}\n{} {}\n",
+                style.paint("bash    "),
+                green.paint("5.1.004-1"),
+                style.paint("readline"),
+                green.paint("8.1-1")
+            )
+        );
+        let format = OutputFormat::Text {
+            quiet: false,
+            color: false,
+        };
+        let out = render(&format, &fixture()?)?;
+        assert_eq!(out, "bash     5.1.004-1\nreadline 8.1-1\n");
+        let format = OutputFormat::Text {
+            quiet: true,
+            color: true,
+        };
+        let out = render(&format, &fixture()?)?;
+        assert_eq!(out, "bash\nreadline\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_formatter() -> Result<()> {
+        let out = render(&OutputFormat::Json, &fixture()?)?;
+        assert_eq!(
+            out,
+            "[\n  {\"name\": \"bash\", \"repo\": null, \"version\": \"5.1.004-1\", \
+             \"description\": \"The \\\"Bourne-again\\\" shell\", \"size\": 8192, \
+             \"install_date\": 1615000000, \"explicit\": true},\n  \
+             {\"name\": \"readline\", \"repo\": null, \"version\": \"8.1-1\", \
+             \"description\": null, \"size\": null, \"install_date\": null, \
+             \"explicit\": false}\n]\n"
+        );
+        assert_eq!(render(&OutputFormat::Json, &[])?, "[]\n");
+        assert_eq!(json_string("a\\b\n\u{1}"), "\"a\\\\b\\n\\u0001\"");
+        Ok(())
+    }
+
+    #[test]
+    fn test_repo_attribution() -> Result<()> {
+        let entries = fixture()?;
+        let render_with_repo = |format: &OutputFormat| -> Result<String> {
+            let mut out = Vec::new();
+            let mut formatter = format.formatter(&mut out);
+            formatter.package(&entries[0], Some("core"))?;
+            formatter.package(&entries[1], None)?;
+            formatter.finish()?;
+            drop(formatter);
+            Ok(String::from_utf8(out)?)
+        };
+        let format = OutputFormat::Text {
+            quiet: false,
+            color: false,
+        };
+        assert_eq!(
+            render_with_repo(&format)?,
+            "core/bash 5.1.004-1\nreadline  8.1-1\n"
+        );
+        let out = render_with_repo(&OutputFormat::Json)?;
+        assert!(out.contains("{\"name\": \"bash\", \"repo\": \"core\", "));
+        assert!(out.contains("{\"name\": \"readline\", \"repo\": null, "));
+        assert_eq!(render_with_repo(&OutputFormat::Null)?, "bash\0readline\0");
+        let format = OutputFormat::Template("%r/%n".parse()?);
+        assert_eq!(render_with_repo(&format)?, "core/bash\nlocal/readline\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_nul_formatter() -> Result<()> {
+        let out = render(&OutputFormat::Null, &fixture()?)?;
+        assert_eq!(out, "bash\0readline\0");
+        Ok(())
+    }
+
+    #[test]
+    fn test_template_formatter() -> Result<()> {
+        let format = OutputFormat::Template("%r/%n %v: %d".parse()?);
+        let out = render(&format, &fixture()?)?;
+        assert_eq!(
+            out,
+            "local/bash 5.1.004-1: The \"Bourne-again\" shell\nlocal/readline 8.1-1: \n"
+        );
+        Ok(())
+    }
+}
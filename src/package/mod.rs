@@ -0,0 +1,379 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+
+use crate::database::compression;
+use crate::database::local::desc::PackageDescription;
+use crate::sysroot::PathResolver;
+use crate::Result;
+
+use log::{debug, warn};
+use tar::EntryType;
+
+pub mod pkginfo;
+
+/// A package archive (`.pkg.tar.zst`). The archive is decompressed and read as it is needed, so a
+/// package is never held in memory all at once.
+#[derive(Debug)]
+pub struct Package {
+    source: Source,
+}
+
+#[derive(Debug)]
+enum Source {
+    File(PathBuf),
+    /// The (possibly compressed) contents of an archive.
+    Memory(Vec<u8>),
+}
+
+impl Package {
+    pub fn read_from_file<P: AsRef<Path>>(filepath: P) -> Result<Self> {
+        let filepath = filepath.as_ref();
+        // Fail early for a missing or unreadable package, rather than part way through using it
+        File::open(filepath)?;
+        Ok(Self {
+            source: Source::File(filepath.to_owned()),
+        })
+    }
+
+    /// Reads a package from the contents of its (possibly compressed) archive.
+    pub fn from_archive(archive: &[u8]) -> Result<Self> {
+        Ok(Self {
+            source: Source::Memory(archive.to_vec()),
+        })
+    }
+
+    /// Opens the archive from the start. Each call reads it again, decompressing it on the fly.
+    fn open(&self) -> Result<tar::Archive<Box<dyn Read + '_>>> {
+        let reader: Box<dyn BufRead> = match &self.source {
+            Source::File(path) => Box::new(BufReader::new(File::open(path)?)),
+            Source::Memory(data) => Box::new(&data[..]),
+        };
+        Ok(tar::Archive::new(compression::decoder(reader)?))
+    }
+
+    /// Parses the package's `.PKGINFO`.
+    pub fn pkginfo(&self) -> Result<PackageDescription> {
+        let mut archive = self.open()?;
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let path = sanitize_path(&entry_path(&entry)).ok().flatten();
+            if path.as_deref() == Some(Path::new(".PKGINFO")) {
+                let mut text = String::new();
+                entry.read_to_string(&mut text)?;
+                return pkginfo::parse_pkginfo(&text);
+            }
+        }
+        Err("Package has no .PKGINFO".into())
+    }
+
+    /// Extracts the files of the package into `root`. The metadata files at the root of the
+    /// archive (`.PKGINFO`, `.MTREE`, ...) are skipped.
+    ///
+    /// Every path is checked before anything is written, and the whole package is refused if any
+    /// is absolute or contains `..`. Symlinks already under `root`, including ones created by this
+    /// package, are followed as if `root` were `/`, so nothing can be written outside of it.
+    /// Modes and modification times are preserved, as is ownership when running as root.
+    pub fn extract_to(&self, root: &Path) -> Result<()> {
+        // The first pass only looks at the headers, so the file contents are skipped over
+        for entry in self.open()?.entries()? {
+            let entry = entry?;
+            check_entry(&entry)?;
+        }
+
+        let resolver = PathResolver::new(root);
+        for entry in self.open()?.entries()? {
+            let mut entry = entry?;
+            // The archive is read again, so check it again rather than trusting the first pass
+            let path = match check_entry(&entry)? {
+                Some(path) if !is_package_metadata(&path) => path,
+                _ => continue,
+            };
+            let disk_path = disk_path(&resolver, root, &path)?;
+            extract_entry(&mut entry, &resolver, root, &disk_path)
+                .map_err(|e| format!("Could not extract '{}': {}", path.display(), e))?;
+        }
+        Ok(())
+    }
+}
+
+/// The path of an entry, as stored in the archive. This is not sanitised in any way.
+fn entry_path<R: Read>(entry: &tar::Entry<R>) -> String {
+    String::from_utf8_lossy(&entry.path_bytes()).into_owned()
+}
+
+/// The target of a symlink or hardlink entry, as stored in the archive.
+fn entry_link<R: Read>(entry: &tar::Entry<R>) -> String {
+    entry
+        .link_name_bytes()
+        .map(|x| String::from_utf8_lossy(&x).into_owned())
+        .unwrap_or_default()
+}
+
+/// Checks the path of an entry, and the target of a hardlink, with `sanitize_path`.
+fn check_entry<R: Read>(entry: &tar::Entry<R>) -> Result<Option<PathBuf>> {
+    let path = sanitize_path(&entry_path(entry))?;
+    if let (EntryType::Link, Some(path)) = (entry.header().entry_type(), &path) {
+        let target = entry_link(entry);
+        if sanitize_path(&target)?.is_none() {
+            return Err(format!(
+                "Invalid hardlink target '{}' for '{}'",
+                target,
+                path.display()
+            )
+            .into());
+        }
+    }
+    Ok(path)
+}
+
+/// Checks a path from the archive, returning it relative to the root. Returns `None` for the root
+/// itself (`./`), and an error if the path is absolute or contains `..`.
+fn sanitize_path(path: &str) -> Result<Option<PathBuf>> {
+    let mut ret = PathBuf::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(name) => ret.push(name),
+            Component::CurDir => {}
+            Component::RootDir | Component::Prefix(_) | Component::ParentDir => {
+                return Err(format!("Refusing to extract unsafe path '{}'", path).into());
+            }
+        }
+    }
+    Ok(Some(ret).filter(|x| x.as_os_str() != ""))
+}
+
+fn is_package_metadata(path: &Path) -> bool {
+    let mut components = path.components();
+    let first = components.next().and_then(|x| x.as_os_str().to_str());
+    components.next().is_none() && first.is_some_and(|x| x.starts_with('.'))
+}
+
+/// Finds where `path` should be written under `root`, following any symlinks in its parent
+/// directories without leaving `root`.
+fn disk_path(resolver: &PathResolver, root: &Path, path: &Path) -> Result<PathBuf> {
+    let disk_path = resolver.resolve(resolver.real_path(Path::new("/").join(path)));
+    // real_path gives up after too many links, leaving one in place for the OS to follow
+    let mut ancestor = disk_path.parent();
+    while let Some(dir) = ancestor.filter(|x| x.starts_with(root) && *x != root) {
+        if dir
+            .symlink_metadata()
+            .is_ok_and(|x| x.file_type().is_symlink())
+        {
+            return Err(
+                format!("Too many levels of symbolic links in '{}'", path.display()).into(),
+            );
+        }
+        ancestor = dir.parent();
+    }
+    Ok(disk_path)
+}
+
+fn extract_entry<R: Read>(
+    entry: &mut tar::Entry<R>,
+    resolver: &PathResolver,
+    root: &Path,
+    disk_path: &Path,
+) -> Result<()> {
+    let header = entry.header();
+    let kind = header.entry_type();
+    let (mode, uid, gid, mtime) = (
+        header.mode()?,
+        header.uid()?,
+        header.gid()?,
+        header.mtime()?,
+    );
+    if !matches!(
+        kind,
+        EntryType::Regular
+            | EntryType::Continuous
+            | EntryType::Directory
+            | EntryType::Symlink
+            | EntryType::Link
+    ) {
+        warn!("Skipping special file '{}'", entry_path(entry));
+        return Ok(());
+    }
+    let existing = disk_path.symlink_metadata().ok();
+    if let Some(parent) = disk_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if kind == EntryType::Directory {
+        match existing {
+            Some(metadata) if metadata.is_dir() => {}
+            Some(_) => {
+                std::fs::remove_file(disk_path)?;
+                std::fs::create_dir(disk_path)?;
+            }
+            None => std::fs::create_dir(disk_path)?,
+        }
+    } else {
+        // Replace whatever is there rather than writing through it, in case it is a symlink
+        match existing {
+            Some(metadata) if metadata.is_dir() => {
+                return Err("A directory is in the way".into());
+            }
+            Some(_) => std::fs::remove_file(disk_path)?,
+            None => {}
+        }
+        match kind {
+            EntryType::Symlink => {
+                std::os::unix::fs::symlink(entry_link(entry), disk_path)?;
+            }
+            EntryType::Link => {
+                // The target was checked by check_entry before extracting anything
+                let target = sanitize_path(&entry_link(entry))?.unwrap();
+                let target = resolver.resolve(resolver.real_path(Path::new("/").join(&target)));
+                if !target.starts_with(root) {
+                    return Err("Hardlink target is outside of the root".into());
+                }
+                std::fs::hard_link(target, disk_path)?;
+            }
+            _ => {
+                let mut file = std::fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(disk_path)?;
+                std::io::copy(entry, &mut file)?;
+                file.set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(mtime))?;
+            }
+        }
+    }
+
+    if kind != EntryType::Symlink {
+        std::fs::set_permissions(disk_path, std::fs::Permissions::from_mode(mode & 0o7777))?;
+    }
+    // Only root can give files away, so failing to is expected otherwise
+    if let Err(e) = std::os::unix::fs::lchown(disk_path, Some(uid as u32), Some(gid as u32)) {
+        if e.kind() != std::io::ErrorKind::PermissionDenied {
+            return Err(e.into());
+        }
+        debug!(
+            "Could not set the owner of '{}': {}",
+            disk_path.display(),
+            e
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{gzip, TarBuilder};
+
+    #[test]
+    fn test_extract_to() -> Result<()> {
+        let archive = TarBuilder::new()
+            .file(".PKGINFO", 0o644, b"pkgname = foo\npkgver = 1.0-1\n")
+            .file(".MTREE", 0o644, b"")
+            .directory("./", 0o755)
+            .directory("usr/", 0o755)
+            .directory("usr/bin/", 0o755)
+            .file("usr/bin/foo", 0o755, b"#!/bin/sh\n")
+            .symlink("usr/bin/bar", "foo")
+            .file("./usr/share/foo/data", 0o600, b"data")
+            .finish();
+        let dir = tempfile::tempdir()?;
+        let filepath = dir.path().join("foo-1.0-1-any.pkg.tar.gz");
+        std::fs::write(&filepath, gzip(&archive))?;
+        let package = Package::read_from_file(&filepath)?;
+        assert_eq!(package.pkginfo()?.name, "foo");
+
+        let root = tempfile::tempdir()?;
+        package.extract_to(root.path())?;
+        assert!(!root.path().join(".PKGINFO").exists());
+        assert!(!root.path().join(".MTREE").exists());
+        let foo = root.path().join("usr/bin/foo");
+        assert_eq!(std::fs::read(&foo)?, b"#!/bin/sh\n");
+        assert_eq!(foo.metadata()?.permissions().mode() & 0o7777, 0o755);
+        assert_eq!(
+            std::fs::read_link(root.path().join("usr/bin/bar"))?,
+            Path::new("foo")
+        );
+        let data = root.path().join("usr/share/foo/data");
+        assert_eq!(data.metadata()?.permissions().mode() & 0o7777, 0o600);
+        assert_eq!(
+            data.metadata()?.modified()?,
+            std::time::UNIX_EPOCH + std::time::Duration::from_secs(1615000000)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_long_paths_and_corrupt_archives() -> Result<()> {
+        let long_name = format!("usr/share/{}/file", "a".repeat(120));
+        let archive = TarBuilder::new()
+            .file(".PKGINFO", 0o644, b"pkgname = foo\npkgver = 1.0-1\n")
+            .file(&long_name, 0o644, b"long")
+            .finish();
+        let root = tempfile::tempdir()?;
+        Package::from_archive(&archive)?.extract_to(root.path())?;
+        assert_eq!(std::fs::read(root.path().join(&long_name))?, b"long");
+
+        let mut corrupt = archive.clone();
+        corrupt[0] = b'x';
+        assert!(Package::from_archive(&corrupt)?.pkginfo().is_err());
+        let truncated = Package::from_archive(&archive[..700])?;
+        assert!(truncated.extract_to(tempfile::tempdir()?.path()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_rejects_traversal() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path().join("root");
+        std::fs::create_dir(&root)?;
+        for path in ["../evil", "usr/../../evil", "/evil"] {
+            let archive = TarBuilder::new()
+                .file("usr/bin/foo", 0o755, b"")
+                .file(path, 0o644, b"evil")
+                .finish();
+            let err = Package::from_archive(&archive)?
+                .extract_to(&root)
+                .unwrap_err();
+            assert!(err.to_string().contains("unsafe path"), "{}", err);
+            // Nothing is extracted from a bad package
+            assert!(!root.join("usr").exists());
+            assert!(!dir.path().join("evil").exists());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_through_symlinks_stays_in_root() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path().join("root");
+        let outside = dir.path().join("outside");
+        std::fs::create_dir_all(&outside)?;
+        std::fs::create_dir_all(root.join("etc"))?;
+        std::fs::write(outside.join("passwd"), "original")?;
+        let archive = TarBuilder::new()
+            .symlink("escape", outside.to_str().unwrap())
+            .symlink("up", "../..")
+            .file("escape/passwd", 0o644, b"evil")
+            .file("up/etc/hosts", 0o644, b"hosts")
+            .symlink("etc/passwd", outside.join("passwd").to_str().unwrap())
+            .file("etc/passwd", 0o644, b"evil")
+            .finish();
+        Package::from_archive(&archive)?.extract_to(&root)?;
+
+        assert_eq!(std::fs::read_to_string(outside.join("passwd"))?, "original");
+        // Absolute link targets are followed relative to the root
+        assert_eq!(
+            std::fs::read_to_string(root.join(outside.strip_prefix("/")?).join("passwd"))?,
+            "evil"
+        );
+        assert_eq!(std::fs::read_to_string(root.join("etc/hosts"))?, "hosts");
+        assert!(!dir.path().join("etc").exists());
+        // The symlink is replaced rather than written through
+        assert!(!root
+            .join("etc/passwd")
+            .symlink_metadata()?
+            .file_type()
+            .is_symlink());
+        Ok(())
+    }
+}
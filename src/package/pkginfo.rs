@@ -0,0 +1,142 @@
+use crate::database::local::desc::{OptionalDependency, PackageDescription, Packager};
+use crate::Result;
+
+use log::warn;
+
+/// Parses the `.PKGINFO` file of a package archive. Unlike a `desc` file, this has one
+/// `key = value` pair per line, and keys such as `depend` are repeated once for each value.
+/// Fields that only exist in the local database, like the install date, are left unset.
+pub fn parse_pkginfo(pkginfo: &str) -> Result<PackageDescription> {
+    let mut name = None;
+    let mut version = None;
+    let mut pkgbase = None;
+    let mut description = None;
+    let mut url = None;
+    let mut arch = None;
+    let mut build_date = None;
+    let mut packager = None;
+    let mut size = None;
+    let mut licences = Vec::new();
+    let mut replaces = Vec::new();
+    let mut dependencies = Vec::new();
+    let mut optional_dependencies = Vec::new();
+    let mut provides = Vec::new();
+    let mut groups = Vec::new();
+    let mut conflicts = Vec::new();
+    for line in pkginfo.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => {
+                return Err(format!("Expected 'key = value' in .PKGINFO, found '{}'", line).into())
+            }
+        };
+        match key {
+            "pkgname" => name = Some(value.to_owned()),
+            "pkgbase" => pkgbase = Some(value.to_owned()),
+            "pkgver" => version = Some(value.to_owned()),
+            "pkgdesc" => description = Some(value.to_owned()),
+            "url" => url = Some(value.to_owned()),
+            "builddate" => build_date = Some(value.parse()?),
+            "packager" => packager = Packager::parse(value),
+            "size" => size = Some(value.parse()?),
+            "arch" => arch = Some(value.parse()?),
+            "license" => licences.push(value.to_owned()),
+            "replaces" => replaces.push(value.to_owned()),
+            "group" => groups.push(value.to_owned()),
+            "conflict" => conflicts.push(value.to_owned()),
+            "provides" => provides.push(value.to_owned()),
+            "depend" => dependencies.push(value.to_owned()),
+            "optdepend" => optional_dependencies.push(OptionalDependency::parse(value)),
+            // Only used when building the package, or handled separately when installing it
+            "makedepend" | "checkdepend" | "backup" | "makepkgopt" | "xdata" => {}
+            x => warn!("Unknown key '{}' in .PKGINFO", x),
+        }
+    }
+    Ok(PackageDescription {
+        name: name.ok_or("Every package must have a name.")?,
+        version: version.ok_or("Every package must have a version.")?,
+        pkgbase,
+        description,
+        url,
+        arch,
+        build_date,
+        install_date: None,
+        packager,
+        size,
+        reason: None,
+        licences,
+        validation: None,
+        replaces,
+        dependencies,
+        optional_dependencies,
+        provides,
+        groups,
+        conflicts,
+        filename: None,
+        download_size: None,
+        sha256sum: None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::Result;
+
+    #[test]
+    fn test_parse_pkginfo() -> Result<()> {
+        let desc = super::parse_pkginfo(
+            "# Generated by makepkg 5.2.2\n\
+             # using fakeroot version 1.25.3\n\
+             pkgname = python-foo\n\
+             pkgbase = foo\n\
+             pkgver = 1:2.0.1-3\n\
+             pkgdesc = A foo library = with an equals sign\n\
+             url = https://example.com/foo\n\
+             builddate = 1615000000\n\
+             packager = Jamie Quigley <jamie@quigley.xyz>\n\
+             size = 123456\n\
+             arch = x86_64\n\
+             license = MIT\n\
+             license = Apache\n\
+             group = foo-group\n\
+             provides = libfoo.so=2-64\n\
+             conflict = python-foo-git\n\
+             depend = python>=3.9\n\
+             depend = glibc\n\
+             optdepend = python-bar: for bar support\n\
+             optdepend = python-baz\n\
+             makedepend = python-setuptools\n\
+             backup = etc/foo.conf\n",
+        )?;
+        assert_eq!(desc.name, "python-foo");
+        assert_eq!(desc.pkgbase.as_deref(), Some("foo"));
+        assert_eq!(desc.version, "1:2.0.1-3");
+        assert_eq!(
+            desc.description.as_deref(),
+            Some("A foo library = with an equals sign")
+        );
+        assert_eq!(desc.build_date, Some(1615000000));
+        assert_eq!(desc.packager.as_ref().unwrap().name, "Jamie Quigley");
+        assert_eq!(desc.size, Some(123456));
+        assert_eq!(desc.licences, ["MIT", "Apache"]);
+        assert_eq!(desc.groups, ["foo-group"]);
+        assert_eq!(desc.provides, ["libfoo.so=2-64"]);
+        assert_eq!(desc.conflicts, ["python-foo-git"]);
+        assert_eq!(desc.dependencies, ["python>=3.9", "glibc"]);
+        assert_eq!(desc.optional_dependencies.len(), 2);
+        assert_eq!(
+            desc.optional_dependencies[0].reason.as_deref(),
+            Some("for bar support")
+        );
+        assert_eq!(desc.optional_dependencies[1].reason, None);
+        assert_eq!(desc.install_date, None);
+
+        assert!(super::parse_pkginfo("pkgver = 1.0-1\n").is_err());
+        assert!(super::parse_pkginfo("pkgname = foo\npkgver\n").is_err());
+        Ok(())
+    }
+}
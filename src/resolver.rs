@@ -0,0 +1,386 @@
+use crate::database::local::LocalDatabase;
+use crate::database::sync::SyncDatabases;
+use crate::dependency::Dependency;
+use crate::interface;
+use crate::transaction::SyncPackage;
+use crate::Result;
+
+use log::{info, warn};
+
+/// How to choose between the packages that provide a dependency, when none of them is named after
+/// it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProviderPolicy {
+    /// Ask which one to use, like pacman. Nobody can answer if stdin isn't a terminal, so the
+    /// first one is used then.
+    Prompt,
+    /// Use the first one, in order of precedence of the repositories, for scripts
+    /// (`--assume-yes-to-first-provider`).
+    FirstInRepoOrder,
+    /// Use one that is already installed under its own name, or else the first one. This is the
+    /// default answer to the prompt, so it is used with `--noconfirm`.
+    PreferInstalled,
+}
+
+impl ProviderPolicy {
+    /// Makes a chooser for `Resolver::resolve_with_chooser` or `sync::plan_install` that follows
+    /// this policy. `installed` is the names of the installed packages. Choices that aren't made
+    /// by the user are logged.
+    pub fn chooser<'a>(self, installed: &'a [String]) -> impl FnMut(&str, &[&str]) -> usize + 'a {
+        move |dep, providers| {
+            let i = match self {
+                ProviderPolicy::Prompt => return interface::choose_provider(dep, providers),
+                ProviderPolicy::FirstInRepoOrder => 0,
+                ProviderPolicy::PreferInstalled => providers
+                    .iter()
+                    .position(|x| installed.iter().any(|name| name == x))
+                    .unwrap_or(0),
+            };
+            info!("Using {} to satisfy {}", providers[i], dep);
+            i
+        }
+    }
+}
+
+/// Decides whether dependencies are satisfied by the installed packages.
+pub struct Resolver {
+    /// Dependencies to treat as satisfied, as if a package with the given name and version were
+    /// installed (`--assume-installed`).
+    assume_installed: Vec<Dependency>,
+}
+
+impl Resolver {
+    pub fn new(assume_installed: Vec<Dependency>) -> Self {
+        Self { assume_installed }
+    }
+
+    /// Checks if a dependency is satisfied by one of the packages in `db`, or by an
+    /// assumed-installed package. Only the packages already read into `db` are considered.
+    pub fn is_satisfied(&self, db: &LocalDatabase, dep: &Dependency) -> bool {
+        self.assume_installed.iter().any(|assumed| {
+            let version = assumed.constraint.as_ref().map(|(_, x)| x.as_str());
+            dep.is_satisfied_by(&assumed.name, version)
+        }) || db
+            .db
+            .values()
+            .any(|entry| dep.is_satisfied_by_package(&entry.desc))
+    }
+
+    /// Returns the dependencies from `deps` that are not satisfied by the installed packages, like
+    /// `pacman -T`. Unversioned dependencies on installed packages are checked with
+    /// `LocalDatabase::contains`, so the database is only read if something else needs checking.
+    pub fn deptest<'a>(
+        &self,
+        db: &mut LocalDatabase,
+        deps: &'a [Dependency],
+    ) -> Result<Vec<&'a Dependency>> {
+        let mut remaining = Vec::new();
+        for dep in deps {
+            if dep.constraint.is_some() || !db.contains(&dep.name)? {
+                remaining.push(dep);
+            }
+        }
+        if remaining.is_empty() {
+            return Ok(remaining);
+        }
+        db.populate_full_database()?;
+        Ok(remaining
+            .into_iter()
+            .filter(|dep| !self.is_satisfied(db, dep))
+            .collect())
+    }
+
+    /// Finds a package in `databases` for each of `deps` that isn't already satisfied, like
+    /// `resolve_with_chooser`, choosing between the providers of a dependency by `policy`.
+    pub fn resolve(
+        &self,
+        db: &mut LocalDatabase,
+        databases: &SyncDatabases,
+        deps: &[Dependency],
+        policy: ProviderPolicy,
+    ) -> Result<Vec<SyncPackage>> {
+        db.populate_full_database()?;
+        let installed: Vec<String> = db.package_names().map(|x| x.to_owned()).collect();
+        self.resolve_with_chooser(db, databases, deps, policy.chooser(&installed))
+    }
+
+    /// Finds the packages to install for the optional dependencies of `packages`, like `resolve`.
+    /// Optional dependencies that are already installed, or satisfied by one of `packages`, are
+    /// left out. Those that no package in `databases` provides are skipped with a warning, since
+    /// the packages work without them.
+    pub fn resolve_optional(
+        &self,
+        db: &mut LocalDatabase,
+        databases: &SyncDatabases,
+        packages: &[SyncPackage],
+        policy: ProviderPolicy,
+    ) -> Result<Vec<SyncPackage>> {
+        let mut deps: Vec<Dependency> = Vec::new();
+        for package in packages {
+            for optdep in package.desc.optional_dependencies.iter() {
+                let dep: Dependency = optdep.package.parse()?;
+                if deps.contains(&dep)
+                    || packages
+                        .iter()
+                        .any(|x| dep.is_satisfied_by_package(&x.desc))
+                {
+                    continue;
+                }
+                if databases.providers(&dep).is_empty() {
+                    warn!(
+                        "optional dependency '{}' of {} was not found",
+                        dep, package.desc.name
+                    );
+                    continue;
+                }
+                deps.push(dep);
+            }
+        }
+        self.resolve(db, databases, &deps, policy)
+    }
+
+    /// Finds a package in `databases` for each of `deps` that isn't satisfied by the installed
+    /// packages, or by a package already chosen for an earlier dependency. A package named after
+    /// the dependency is always used if there is one. Otherwise, if more than one package
+    /// provides it, `chooser` is called with the dependency and the names of its providers (as
+    /// ordered by `SyncDatabases::providers`) and returns the index of the one to use, like the
+    /// prompt pacman shows. The dependencies of the chosen packages are not resolved yet.
+    pub fn resolve_with_chooser(
+        &self,
+        db: &mut LocalDatabase,
+        databases: &SyncDatabases,
+        deps: &[Dependency],
+        mut chooser: impl FnMut(&str, &[&str]) -> usize,
+    ) -> Result<Vec<SyncPackage>> {
+        db.populate_full_database()?;
+        let mut ret: Vec<SyncPackage> = Vec::new();
+        for dep in deps {
+            if self.is_satisfied(db, dep)
+                || ret.iter().any(|x| dep.is_satisfied_by_package(&x.desc))
+            {
+                continue;
+            }
+            let providers = databases.providers(dep);
+            let (repo, desc) = match providers.as_slice() {
+                [] => return Err(format!("unable to satisfy dependency '{}'", dep).into()),
+                [first, ..] if providers.len() == 1 || first.1.name == dep.name => *first,
+                _ => {
+                    let names: Vec<&str> = providers.iter().map(|(_, x)| x.name.as_str()).collect();
+                    let i = chooser(&dep.to_string(), &names);
+                    *providers
+                        .get(i)
+                        .ok_or_else(|| format!("invalid choice {} of provider for '{}'", i, dep))?
+                }
+            };
+            ret.push(SyncPackage {
+                repo: repo.to_owned(),
+                desc: desc.clone(),
+            });
+        }
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::sync::SyncDatabase;
+    use crate::test_util::{Fixture, TarBuilder};
+    use crate::Result;
+
+    #[test]
+    fn test_assume_installed() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("bash", "5.1.004-1", "%PROVIDES%\nsh\n\n", &[]);
+        let mut db = LocalDatabase::with_path(fixture.path());
+        let deps: Vec<Dependency> = vec!["glibc>=2.30".parse()?, "sh".parse()?];
+
+        let resolver = Resolver::new(Vec::new());
+        assert_eq!(resolver.deptest(&mut db, &deps)?, [&deps[0]]);
+
+        let resolver = Resolver::new(vec!["glibc=2.33".parse()?]);
+        assert!(resolver.deptest(&mut db, &deps)?.is_empty());
+
+        let resolver = Resolver::new(vec!["glibc=2.29".parse()?]);
+        assert_eq!(resolver.deptest(&mut db, &deps)?, [&deps[0]]);
+        Ok(())
+    }
+
+    fn cron_providers() -> Result<SyncDatabases> {
+        let mut archive = TarBuilder::new();
+        for package in ["cronie", "fcron"].iter() {
+            let desc = format!(
+                "%NAME%\n{}\n\n%VERSION%\n1.0-1\n\n%PROVIDES%\ncron\n\n",
+                package
+            );
+            archive = archive.file(&format!("{}-1.0-1/desc", package), 0o644, desc.as_bytes());
+        }
+        let archive = archive
+            .file(
+                "bash-5.1.004-1/desc",
+                0o644,
+                b"%NAME%\nbash\n\n%VERSION%\n5.1.004-1\n\n",
+            )
+            .finish();
+        Ok(SyncDatabases::new(vec![SyncDatabase::from_archive(
+            "extra", &archive,
+        )?]))
+    }
+
+    #[test]
+    fn test_resolve_with_chooser() -> Result<()> {
+        let fixture = Fixture::new();
+        let mut db = LocalDatabase::with_path(fixture.path());
+        let databases = cron_providers()?;
+        let deps: Vec<Dependency> = vec!["cron".parse()?, "bash".parse()?];
+        let resolver = Resolver::new(Vec::new());
+
+        let mut asked = Vec::new();
+        let chosen =
+            resolver.resolve_with_chooser(&mut db, &databases, &deps, |dep, providers| {
+                asked.push((dep.to_owned(), providers.join(" ")));
+                1
+            })?;
+        assert_eq!(asked, [("cron".to_owned(), "cronie fcron".to_owned())]);
+        let names: Vec<&str> = chosen.iter().map(|x| x.desc.name.as_str()).collect();
+        assert_eq!(names, ["fcron", "bash"]);
+        assert_eq!(chosen[0].repo, "extra");
+
+        let chosen = resolver.resolve(
+            &mut db,
+            &databases,
+            &deps[..1],
+            ProviderPolicy::PreferInstalled,
+        )?;
+        assert_eq!(chosen[0].desc.name, "cronie");
+        assert!(resolver
+            .resolve_with_chooser(&mut db, &databases, &deps, |_, _| 2)
+            .is_err());
+        assert!(resolver
+            .resolve(
+                &mut db,
+                &databases,
+                &["anacron".parse()?],
+                ProviderPolicy::PreferInstalled
+            )
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_provider_policies() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("fcron", "0.9-1", "", &[]);
+        let mut db = LocalDatabase::with_path(fixture.path());
+        let resolver = Resolver::new(Vec::new());
+        let deps: Vec<Dependency> = vec!["cron".parse()?];
+
+        let databases = cron_providers()?;
+        let mut chosen = |policy| -> Result<Vec<String>> {
+            let chosen = resolver.resolve(&mut db, &databases, &deps, policy)?;
+            Ok(chosen.into_iter().map(|x| x.desc.name).collect())
+        };
+        assert_eq!(chosen(ProviderPolicy::PreferInstalled)?, ["fcron"]);
+        assert_eq!(chosen(ProviderPolicy::FirstInRepoOrder)?, ["cronie"]);
+
+        // Nothing is asked for a dependency that is already satisfied
+        let resolver = Resolver::new(vec!["cron".parse()?]);
+        let chosen =
+            resolver.resolve_with_chooser(&mut db, &cron_providers()?, &deps, |_, _| {
+                panic!("chooser called")
+            })?;
+        assert!(chosen.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_optional() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("python", "3.9.2-1", "", &[]);
+        let mut db = LocalDatabase::with_path(fixture.path());
+        let archive = TarBuilder::new()
+            .file(
+                "vim-8.2-1/desc",
+                0o644,
+                b"%NAME%\nvim\n\n%VERSION%\n8.2-1\n\n%OPTDEPENDS%\n\
+                  python: Python 3 language support\nruby: Ruby language support\n\
+                  perl\nmissing: not packaged\n\n",
+            )
+            .file(
+                "perl-5.32.1-1/desc",
+                0o644,
+                b"%NAME%\nperl\n\n%VERSION%\n5.32.1-1\n\n",
+            )
+            .file(
+                "python-3.9.2-1/desc",
+                0o644,
+                b"%NAME%\npython\n\n%VERSION%\n3.9.2-1\n\n",
+            )
+            .file(
+                "ruby-3.0.0-1/desc",
+                0o644,
+                b"%NAME%\nruby\n\n%VERSION%\n3.0.0-1\n\n%OPTDEPENDS%\nlua\n\n",
+            )
+            .file(
+                "lua-5.4.2-1/desc",
+                0o644,
+                b"%NAME%\nlua\n\n%VERSION%\n5.4.2-1\n\n",
+            )
+            .finish();
+        let databases = SyncDatabases::new(vec![SyncDatabase::from_archive("extra", &archive)?]);
+        let planned = |names: &[&str]| -> Vec<SyncPackage> {
+            names
+                .iter()
+                .map(|x| {
+                    let (repo, desc) = databases.get(x).unwrap();
+                    SyncPackage {
+                        repo: repo.to_owned(),
+                        desc: desc.clone(),
+                    }
+                })
+                .collect()
+        };
+        let resolver = Resolver::new(Vec::new());
+        let names = |packages: Vec<SyncPackage>| -> Vec<String> {
+            packages.into_iter().map(|x| x.desc.name).collect()
+        };
+
+        // python is installed, perl is already being installed and nothing provides missing
+        let chosen = resolver.resolve_optional(
+            &mut db,
+            &databases,
+            &planned(&["vim", "perl"]),
+            ProviderPolicy::FirstInRepoOrder,
+        )?;
+        // The optional dependencies of ruby aren't looked up in turn
+        assert_eq!(names(chosen), ["ruby"]);
+        let chosen = resolver.resolve_optional(
+            &mut db,
+            &databases,
+            &planned(&["ruby"]),
+            ProviderPolicy::FirstInRepoOrder,
+        )?;
+        assert_eq!(names(chosen), ["lua"]);
+        let chosen = resolver.resolve_optional(
+            &mut db,
+            &databases,
+            &planned(&["perl"]),
+            ProviderPolicy::FirstInRepoOrder,
+        )?;
+        assert!(chosen.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_deptest_installed_by_name() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("bash", "5.1.004-1", "", &[]);
+        let mut db = LocalDatabase::with_path(fixture.path());
+        let deps: Vec<Dependency> = vec!["bash".parse()?];
+
+        let resolver = Resolver::new(Vec::new());
+        assert!(resolver.deptest(&mut db, &deps)?.is_empty());
+        assert!(db.db.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,49 @@
+use std::io::Read;
+
+use sha2::{Digest, Sha256};
+
+/// Computes the sha256 checksum of everything read from `reader`, as a lowercase hex string, in
+/// the same format as the `sha256digest` keyword of an `mtree`.
+pub fn hex_digest(mut reader: impl Read) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|x| format!("{:02x}", x))
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::hex_digest;
+
+    #[test]
+    fn test_hex_digest() -> std::io::Result<()> {
+        assert_eq!(
+            hex_digest(&b""[..])?,
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex_digest(&b"abc"[..])?,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        // Exactly fills the first block before padding is added
+        assert_eq!(
+            hex_digest(&b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"[..])?,
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+        assert_eq!(
+            hex_digest(&[b'a'; 1000][..])?,
+            "41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3"
+        );
+        Ok(())
+    }
+}
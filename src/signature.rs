@@ -0,0 +1,86 @@
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::Result;
+
+/// The keyring in the `GPGDir` that signatures are checked against.
+pub const KEYRING: &str = "pubring.gpg";
+
+/// The program that checks signatures. It comes with GnuPG, and has to be installed for any
+/// `SigLevel` other than `Never`.
+const GPGV: &str = "gpgv";
+
+/// Checks that `signature` is a valid detached signature of `file` made by one of the keys in the
+/// keyring in `gpg_dir`, using `gpgv`.
+pub fn verify(file: &Path, signature: &Path, gpg_dir: &Path) -> Result<()> {
+    verify_with(GPGV, file, signature, gpg_dir)
+}
+
+fn verify_with(gpgv: &str, file: &Path, signature: &Path, gpg_dir: &Path) -> Result<()> {
+    let output = Command::new(gpgv)
+        .arg("--homedir")
+        .arg(gpg_dir)
+        .arg("--keyring")
+        .arg(gpg_dir.join(KEYRING))
+        .arg(signature)
+        .arg(file)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => format!(
+                "Could not find {} to check signatures, install GnuPG or set SigLevel = Never",
+                gpgv
+            ),
+            _ => format!("Could not run {} to check signatures: {}", gpgv, e),
+        })?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!(
+            "Invalid signature for '{}': {}",
+            file.display(),
+            stderr.lines().last().unwrap_or("gpgv failed").trim()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{gpg_dir, have_gpgv, FOO_SIGNATURE};
+
+    #[test]
+    fn test_verify() -> Result<()> {
+        if !have_gpgv() {
+            return Ok(());
+        }
+        let gpg_dir = gpg_dir();
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("foo");
+        let signature = dir.path().join("foo.sig");
+        std::fs::write(&file, b"foo package")?;
+        std::fs::write(&signature, FOO_SIGNATURE)?;
+        verify(&file, &signature, gpg_dir.path())?;
+
+        // The signature doesn't match other data
+        std::fs::write(&file, b"foo packagf")?;
+        assert!(verify(&file, &signature, gpg_dir.path()).is_err());
+        // ...and garbage isn't a signature
+        std::fs::write(&file, b"foo package")?;
+        std::fs::write(&signature, b"signature")?;
+        assert!(verify(&file, &signature, gpg_dir.path()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_without_gpgv() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("foo");
+        let error = verify_with("gpgv-not-installed", &file, &file, dir.path()).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Could not find gpgv-not-installed to check signatures"));
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+use std::path::{Component, Path, PathBuf};
+
+/// The maximum number of symlinks followed while resolving a single path, the same limit as
+/// Linux's `MAXSYMLINKS`.
+const MAX_SYMLINKS: usize = 40;
+
+/// Maps absolute paths, such as the ones recorded in package data, onto the filesystem under an
+/// alternate system root given with `--sysroot`. Without a sysroot, paths are used unchanged.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PathResolver {
+    sysroot: Option<PathBuf>,
+}
+
+impl PathResolver {
+    pub fn new<P: Into<PathBuf>>(sysroot: P) -> Self {
+        Self {
+            sysroot: Some(sysroot.into()),
+        }
+    }
+
+    /// Returns the path on disk of `path`, by joining it onto the sysroot.
+    pub fn resolve<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        let path = path.as_ref();
+        match &self.sysroot {
+            Some(sysroot) => sysroot.join(path.strip_prefix("/").unwrap_or(path)),
+            None => path.to_owned(),
+        }
+    }
+
+    /// Resolves any symlinks in the directories leading up to `path`, as seen from inside the
+    /// sysroot, so that e.g. `/bin/bash` becomes `/usr/bin/bash` when `/bin` links to `usr/bin`.
+    /// Absolute link targets are followed relative to the sysroot rather than the host root. The
+    /// last component is left alone, as a package can own a symlink itself. Directories that don't
+    /// exist are kept as they are.
+    pub fn real_path<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        let path = path.as_ref();
+        let (parent, file_name) = match (path.parent(), path.file_name()) {
+            (Some(parent), Some(file_name)) => (parent, file_name),
+            _ => return path.to_owned(),
+        };
+
+        let mut pending: Vec<PathBuf> = components(parent);
+        let mut resolved = PathBuf::from("/");
+        let mut links_followed = 0;
+        while let Some(component) = pending.pop() {
+            if component == Path::new("..") {
+                resolved.pop();
+                continue;
+            }
+            resolved.push(component);
+            let target = match std::fs::read_link(self.resolve(&resolved)) {
+                Ok(target) if links_followed < MAX_SYMLINKS => target,
+                _ => continue,
+            };
+            links_followed += 1;
+            resolved.pop();
+            if target.is_absolute() {
+                resolved = PathBuf::from("/");
+            }
+            pending.extend(components(&target));
+        }
+        resolved.join(file_name)
+    }
+}
+
+/// Returns the normal and `..` components of `path` as separate paths, in reverse order so that
+/// they can be popped off the end.
+fn components(path: &Path) -> Vec<PathBuf> {
+    path.components()
+        .rev()
+        .filter_map(|x| match x {
+            Component::Normal(name) => Some(PathBuf::from(name)),
+            Component::ParentDir => Some(PathBuf::from("..")),
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let resolver = PathResolver::new("/srv/container");
+        assert_eq!(
+            resolver.resolve("/usr/bin/x"),
+            Path::new("/srv/container/usr/bin/x")
+        );
+        assert_eq!(
+            PathResolver::default().resolve("/usr/bin/x"),
+            Path::new("/usr/bin/x")
+        );
+    }
+
+    #[test]
+    fn test_real_path() -> crate::Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir_all(dir.path().join("usr/bin"))?;
+        std::fs::create_dir_all(dir.path().join("usr/lib"))?;
+        std::os::unix::fs::symlink("usr/bin", dir.path().join("bin"))?;
+        // An absolute link target must stay inside the sysroot
+        std::os::unix::fs::symlink("/usr/lib", dir.path().join("lib"))?;
+        std::os::unix::fs::symlink("../lib", dir.path().join("usr/lib64"))?;
+
+        let resolver = PathResolver::new(dir.path());
+        assert_eq!(resolver.real_path("/bin/x"), Path::new("/usr/bin/x"));
+        assert_eq!(
+            resolver.real_path("/lib/libc.so"),
+            Path::new("/usr/lib/libc.so")
+        );
+        assert_eq!(resolver.real_path("/usr/lib64/a"), Path::new("/usr/lib/a"));
+        // The file itself is not followed
+        assert_eq!(resolver.real_path("/bin"), Path::new("/bin"));
+        assert_eq!(resolver.real_path("/opt/x/y"), Path::new("/opt/x/y"));
+        Ok(())
+    }
+}
@@ -0,0 +1,320 @@
+use std::cell::RefCell;
+use std::io::prelude::*;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex, Once};
+use std::time::SystemTime;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use tar::EntryType;
+use tempfile::TempDir;
+
+use crate::database::local::desc::parse_desc;
+use crate::database::local::source::{DatabaseSource, MemorySource};
+use crate::transaction::SyncPackage;
+use crate::Result;
+
+/// A temporary local database directory, laid out the same way as `/var/lib/pacman/local`. Used
+/// so that tests don't depend on the packages installed on the machine running them.
+pub struct Fixture {
+    dir: TempDir,
+}
+
+impl Fixture {
+    pub fn new() -> Self {
+        Self {
+            dir: tempfile::tempdir().unwrap(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Adds a package entry named `<name>-<version>`. `extra_desc` is appended verbatim to the
+    /// generated `desc` file, and `files` are the paths owned by the package, relative to root.
+    /// Paths ending in `/` are recorded as directories.
+    pub fn add(&self, name: &str, version: &str, extra_desc: &str, files: &[&str]) {
+        let desc = format!(
+            "%NAME%\n{}\n\n%VERSION%\n{}\n\n{}",
+            name, version, extra_desc
+        );
+        let mut mtree = String::from("#mtree\n/set type=file uid=0 gid=0 mode=644\n");
+        for file in files {
+            if let Some(dir) = file.strip_suffix('/') {
+                mtree.push_str(&format!("./{} time=1615000000.0 mode=755 type=dir\n", dir));
+            } else {
+                mtree.push_str(&format!("./{} time=1615000000.0 size=0 type=file\n", file));
+            }
+        }
+        self.add_raw(&format!("{}-{}", name, version), &desc, &mtree);
+    }
+
+    /// Adds a package entry directory containing exactly the given `desc` and (uncompressed)
+    /// `mtree` contents.
+    pub fn add_raw(&self, dirname: &str, desc: &str, mtree: &str) {
+        let dir = self.path().join(dirname);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("desc"), desc).unwrap();
+        std::fs::write(dir.join("mtree"), gzip(mtree.as_bytes())).unwrap();
+    }
+}
+
+/// Builds a package from the repository `repo`, with a desc made up the same way as the ones
+/// written by `Fixture::add`.
+pub fn sync_package(repo: &str, name: &str, version: &str, extra_desc: &str) -> SyncPackage {
+    let desc = format!(
+        "%NAME%\n{}\n\n%VERSION%\n{}\n\n{}",
+        name, version, extra_desc
+    );
+    SyncPackage {
+        repo: repo.to_owned(),
+        desc: parse_desc(&desc).unwrap(),
+    }
+}
+
+/// Builds a `MemorySource` with an entry `<name>-1.0-1` for each of `names`, owning no files.
+pub fn memory_fixture(names: &[&str]) -> MemorySource {
+    let source = MemorySource::new();
+    for name in names {
+        let entry = format!("{}-1.0-1", name);
+        let desc = format!("%NAME%\n{}\n\n%VERSION%\n1.0-1\n\n", name);
+        source.insert(&entry, "desc", &desc);
+        source.insert(&entry, "mtree", "#mtree\n");
+    }
+    source
+}
+
+/// A source that wraps a `MemorySource`, counting how many times its entries are listed. It can
+/// also report a modification time, and refuse to read one of its entries. The counter and the
+/// modification time are shared, so that a test can keep hold of them once the source has been
+/// moved into a database.
+#[derive(Debug, Default)]
+pub struct TestSource {
+    pub inner: MemorySource,
+    pub scans: Arc<AtomicUsize>,
+    /// The time to report from `modified`, if any.
+    pub modified: Arc<Mutex<Option<SystemTime>>>,
+    /// An entry whose members can't be read, like a directory that isn't readable by the current
+    /// user.
+    pub denied: Option<&'static str>,
+}
+
+impl TestSource {
+    pub fn new(inner: MemorySource) -> Self {
+        Self {
+            inner,
+            ..Self::default()
+        }
+    }
+}
+
+impl DatabaseSource for TestSource {
+    fn entry_names(&self) -> Result<Vec<String>> {
+        self.scans.fetch_add(1, Ordering::Relaxed);
+        self.inner.entry_names()
+    }
+
+    fn read_member(&self, entry: &str, member: &str) -> Result<Option<String>> {
+        if self.denied == Some(entry) {
+            return Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied).into());
+        }
+        self.inner.read_member(entry, member)
+    }
+
+    fn remove_entry(&self, entry: &str) -> Result<()> {
+        self.inner.remove_entry(entry)
+    }
+
+    fn entry_path(&self, entry: &str) -> PathBuf {
+        self.inner.entry_path(entry)
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        *self.modified.lock().unwrap()
+    }
+}
+
+/// Builds an uncompressed tar archive in memory with `tar::Builder`. Paths longer than a ustar
+/// header allows are stored with a GNU long name entry. Absolute paths and paths containing `..`
+/// are written as given, so this can also make archives with unsafe paths.
+pub struct TarBuilder {
+    builder: tar::Builder<Vec<u8>>,
+}
+
+impl TarBuilder {
+    pub fn new() -> Self {
+        Self {
+            builder: tar::Builder::new(Vec::new()),
+        }
+    }
+
+    pub fn file(self, path: &str, mode: u32, contents: &[u8]) -> Self {
+        self.entry(path, EntryType::Regular, mode, None, contents)
+    }
+
+    pub fn directory(self, path: &str, mode: u32) -> Self {
+        self.entry(path, EntryType::Directory, mode, None, &[])
+    }
+
+    pub fn symlink(self, path: &str, target: &str) -> Self {
+        self.entry(path, EntryType::Symlink, 0o777, Some(target), &[])
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.builder.into_inner().unwrap()
+    }
+
+    fn entry(
+        mut self,
+        path: &str,
+        kind: EntryType,
+        mode: u32,
+        link: Option<&str>,
+        contents: &[u8],
+    ) -> Self {
+        let mut header = tar::Header::new_ustar();
+        header.set_entry_type(kind);
+        header.set_mode(mode);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_mtime(1615000000);
+        header.set_size(contents.len() as u64);
+        if let Some(link) = link {
+            header.set_link_name(link).unwrap();
+        }
+        if path.starts_with('/') || path.split('/').any(|x| x == "..") {
+            // `tar::Builder` refuses to write unsafe paths, so archives that try to escape the
+            // extraction root are made by filling in the name field directly
+            header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+            header.set_cksum();
+            self.builder.append(&header, contents).unwrap();
+        } else {
+            self.builder
+                .append_data(&mut header, path, contents)
+                .unwrap();
+        }
+        self
+    }
+}
+
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+thread_local! {
+    static CAPTURED_LOGS: RefCell<Option<Vec<(Level, String)>>> = const { RefCell::new(None) };
+}
+
+/// A logger that records messages logged by this crate on the current thread while inside
+/// `capture_logs`. Messages from dependencies, like the HTTP client, are ignored.
+struct CaptureLogger;
+
+impl Log for CaptureLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata
+            .target()
+            .starts_with(module_path!().split("::").next().unwrap())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        CAPTURED_LOGS.with(|logs| {
+            if let Some(logs) = logs.borrow_mut().as_mut() {
+                logs.push((record.level(), record.args().to_string()));
+            }
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+/// Runs `f`, and returns its result along with every message this crate logged while it ran.
+pub fn capture_logs<T>(f: impl FnOnce() -> T) -> (T, Vec<(Level, String)>) {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        log::set_logger(&CaptureLogger).unwrap();
+        log::set_max_level(LevelFilter::Trace);
+    });
+    CAPTURED_LOGS.with(|logs| *logs.borrow_mut() = Some(Vec::new()));
+    let ret = f();
+    let logs = CAPTURED_LOGS.with(|logs| logs.borrow_mut().take().unwrap());
+    (ret, logs)
+}
+
+/// Serves `count` HTTP requests on a random local port, responding to the `i`th request with
+/// `respond(i, request)`. Returns the base URL of the server, and a receiver that gets each request
+/// as it is served, so that the test can check them.
+pub fn mock_server_with<R: AsRef<[u8]>>(
+    count: usize,
+    respond: impl Fn(usize, &str) -> R + Send + 'static,
+) -> (String, Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        for i in 0..count {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                let mut byte = [0];
+                stream.read_exact(&mut byte).unwrap();
+                request.push(byte[0]);
+            }
+            let request = String::from_utf8(request).unwrap();
+            let response = respond(i, &request);
+            // The test may not care about the requests
+            let _ = sender.send(request);
+            stream.write_all(response.as_ref()).unwrap();
+        }
+    });
+    (format!("http://127.0.0.1:{}", port), receiver)
+}
+
+/// The value of the header `name` in `request`, ignoring the case of its name.
+pub fn header<'a>(request: &'a str, name: &str) -> Option<&'a str> {
+    request.lines().skip(1).find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        if key.trim().eq_ignore_ascii_case(name) {
+            Some(value.trim())
+        } else {
+            None
+        }
+    })
+}
+
+/// A detached signature of `b"foo package"` by the key in `gpg_dir`.
+pub const FOO_SIGNATURE: &[u8] = include_bytes!("../testdata/foo.sig");
+
+/// Whether `gpgv` can be run. Tests that check signatures return early without it, so that the
+/// rest of the suite can run on machines without GnuPG.
+pub fn have_gpgv() -> bool {
+    let found = Command::new("gpgv")
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok();
+    if !found {
+        eprintln!("gpgv was not found, skipping signature checks");
+    }
+    found
+}
+
+/// A temporary `GPGDir` whose keyring holds the key that made the signatures in `testdata/`.
+pub fn gpg_dir() -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join(crate::signature::KEYRING),
+        include_bytes!("../testdata/pubring.gpg"),
+    )
+    .unwrap();
+    dir
+}
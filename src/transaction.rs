@@ -0,0 +1,800 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use log::{debug, warn};
+
+use crate::config::{Repo, SigCheck, SigLevel};
+use crate::database::local::desc::PackageDescription;
+use crate::database::local::LocalDatabase;
+use crate::download::{self, RetryPolicy};
+use crate::format;
+use crate::sha256;
+use crate::signature;
+use crate::version::vercmp;
+use crate::Result;
+
+/// How strictly dependencies are checked when planning a transaction, set by passing `--nodeps`
+/// zero, one or two times.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DependencyChecks {
+    /// Dependencies must be satisfied, including their version constraints.
+    #[default]
+    Full,
+    /// Dependencies must be satisfied by name, but their versions are not checked (`-d`).
+    IgnoreVersions,
+    /// Dependencies are not checked at all (`-dd`).
+    Skip,
+}
+
+impl DependencyChecks {
+    pub fn from_nodeps(count: u8) -> Self {
+        match count {
+            0 => DependencyChecks::Full,
+            1 => DependencyChecks::IgnoreVersions,
+            _ => DependencyChecks::Skip,
+        }
+    }
+}
+
+/// A package to be installed from a sync repository.
+#[derive(Debug)]
+pub struct SyncPackage {
+    /// The name of the repository the package comes from.
+    pub repo: String,
+    pub desc: PackageDescription,
+}
+
+/// A planned set of changes to the installed packages.
+#[derive(Debug, Default)]
+pub struct Transaction {
+    /// The names of the packages to be removed.
+    pub remove: Vec<String>,
+    pub install: Vec<SyncPackage>,
+    /// The packages to be removed that are listed in `HoldPkg`, which need an extra confirmation.
+    pub held: Vec<String>,
+    /// The packages to be installed that are already installed, so installing them is an upgrade.
+    pub upgrades: Vec<String>,
+    /// The packages in `upgrades` that are already installed at the same version, so installing
+    /// them only reinstalls them.
+    pub reinstalls: Vec<String>,
+    /// The packages in `install` that are only installed for the others, and are recorded as
+    /// installed as dependencies rather than explicitly.
+    pub dependencies: Vec<String>,
+    /// The files owned by the packages being installed or removed, by package name, where they
+    /// are known.
+    pub files: HashMap<String, Vec<PathBuf>>,
+    pub dependency_checks: DependencyChecks,
+}
+
+impl Transaction {
+    /// Records which of the packages to be removed are in `hold_packages`.
+    pub fn flag_held(&mut self, hold_packages: &[String]) {
+        self.held = self
+            .remove
+            .iter()
+            .filter(|x| hold_packages.contains(x))
+            .cloned()
+            .collect();
+    }
+
+    /// Records which of the packages to be installed are already installed in `local`, and which
+    /// of those are installed at the same version.
+    pub fn flag_upgrades(&mut self, local: &mut LocalDatabase) -> Result<()> {
+        let mut upgrades = Vec::new();
+        let mut reinstalls = Vec::new();
+        for package in self.install.iter() {
+            let desc = &package.desc;
+            if local.contains(&desc.name)? {
+                upgrades.push(desc.name.clone());
+                if vercmp(&local.get(&desc.name)?.desc.version, &desc.version) == Ordering::Equal {
+                    reinstalls.push(desc.name.clone());
+                }
+            }
+        }
+        self.upgrades = upgrades;
+        self.reinstalls = reinstalls;
+        Ok(())
+    }
+
+    /// Adds `packages` to the packages to be installed, to be installed as dependencies. Packages
+    /// that are already being installed are left as they are.
+    pub fn add_dependencies(&mut self, packages: Vec<SyncPackage>) {
+        for package in packages {
+            if self
+                .install
+                .iter()
+                .any(|x| x.desc.name == package.desc.name)
+            {
+                continue;
+            }
+            self.dependencies.push(package.desc.name.clone());
+            self.install.push(package);
+        }
+    }
+
+    /// Drops the packages that would only be reinstalled, like `pacman -S --needed`, and returns
+    /// them.
+    pub fn skip_reinstalls(&mut self) -> Vec<SyncPackage> {
+        let reinstalls = std::mem::take(&mut self.reinstalls);
+        self.upgrades.retain(|x| !reinstalls.contains(x));
+        let (skipped, install) = std::mem::take(&mut self.install)
+            .into_iter()
+            .partition(|x| reinstalls.contains(&x.desc.name));
+        self.install = install;
+        skipped
+    }
+
+    /// Downloads the packages to be installed into `cache_dir` without installing them, like
+    /// `pacman -Sw`, and returns their paths. Each package is tried from every mirror of its
+    /// repository in turn, and its size and sha256 are checked against the sync database. Packages
+    /// that are already in the cache and pass the same checks aren't downloaded again. Signatures
+    /// are downloaded alongside the packages and checked against the keyring in `gpg_dir`,
+    /// according to the `SigLevel` of their repository: not at all with `Never`, where the mirror
+    /// has them with `Optional`, and with `Required`, a package without a signature is an error. A
+    /// package whose signature is invalid is removed from the cache along with the signature.
+    pub fn download_only(
+        &self,
+        repos: &[Repo],
+        arch: &str,
+        cache_dir: &Path,
+        gpg_dir: &Path,
+        policy: &RetryPolicy,
+    ) -> Result<Vec<PathBuf>> {
+        std::fs::create_dir_all(cache_dir)?;
+        let mut ret = Vec::new();
+        for package in self.install.iter() {
+            let desc = &package.desc;
+            let filename = desc
+                .filename
+                .as_deref()
+                .ok_or_else(|| format!("No file name is known for package '{}'", desc.name))?;
+            download::check_file_name(filename)
+                .map_err(|e| format!("Package '{}' can't be downloaded: {}", desc.name, e))?;
+            let mirrors = || -> Result<Vec<String>> {
+                let mirrors = mirrors_for(repos, &package.repo, arch);
+                if mirrors.is_empty() {
+                    return Err(format!(
+                        "No mirrors are configured for repository '{}'",
+                        package.repo
+                    )
+                    .into());
+                }
+                Ok(mirrors)
+            };
+            let path = cache_dir.join(filename);
+            let sig = format!("{}.sig", filename);
+            let sig_path = cache_dir.join(&sig);
+            let cached = is_cached(&path, desc)?;
+            if cached {
+                debug!("{} is already in the cache", filename);
+            } else {
+                download::download_to_file(
+                    &mirrors()?,
+                    filename,
+                    cache_dir,
+                    policy,
+                    desc.download_size,
+                    desc.sha256sum.as_deref(),
+                )?;
+                // Any signature in the cache is for the old file
+                remove_if_exists(&sig_path)?;
+            }
+            let check = repos
+                .iter()
+                .find(|x| x.name == package.repo)
+                .map_or_else(SigLevel::default, |x| x.sig_level)
+                .package;
+            if check == SigCheck::Never {
+                ret.push(path);
+                continue;
+            }
+            // A package from the cache without a signature only needs one if it's required
+            if !sig_path.is_file() && (!cached || check == SigCheck::Required) {
+                let downloaded =
+                    download::download_to_file(&mirrors()?, &sig, cache_dir, policy, None, None);
+                match downloaded {
+                    Ok(_) => {}
+                    Err(e) if check == SigCheck::Required => {
+                        return Err(format!(
+                            "Package '{}' has no signature, which is required by the SigLevel of '{}': {}",
+                            desc.name, package.repo, e
+                        )
+                        .into());
+                    }
+                    Err(e) => debug!("No signature was downloaded for {}: {}", filename, e),
+                }
+            }
+            if sig_path.is_file() {
+                if let Err(e) = signature::verify(&path, &sig_path, gpg_dir) {
+                    remove_if_exists(&path)?;
+                    remove_if_exists(&sig_path)?;
+                    return Err(e);
+                }
+            }
+            ret.push(path);
+        }
+        Ok(ret)
+    }
+
+    /// The total installed size of the packages to be installed, in bytes.
+    pub fn total_installed_size(&self) -> u64 {
+        self.install.iter().filter_map(|x| x.desc.size).sum()
+    }
+
+    /// The change in the total installed size of the system, in bytes, once the transaction is
+    /// done: the sizes of the packages to be installed, less the sizes of the installed versions
+    /// they replace and of the packages to be removed. This is negative if the system shrinks,
+    /// such as when a package is downgraded to a smaller version. Installed packages are looked up
+    /// in the entries that have already been read into `local`, and any that haven't are treated
+    /// as not installed.
+    pub fn net_size_delta(&self, local: &LocalDatabase) -> i64 {
+        let installed_size =
+            |name: &str| local.db.get(name).and_then(|x| x.desc.size).unwrap_or(0) as i64;
+        let installed: i64 = self
+            .install
+            .iter()
+            .map(|x| x.desc.size.unwrap_or(0) as i64 - installed_size(&x.desc.name))
+            .sum();
+        let removed: i64 = self.remove.iter().map(|x| installed_size(x)).sum();
+        installed - removed
+    }
+
+    /// The size totals that pacman prints before asking to confirm a transaction, one per line.
+    pub fn size_summary(&self, local: &LocalDatabase) -> String {
+        let delta = self.net_size_delta(local);
+        if self.install.is_empty() {
+            format!(
+                "Total Removed Size:    {}\n",
+                format::human_size_signed(-delta)
+            )
+        } else {
+            format!(
+                "Total Installed Size:  {}\nNet Upgrade Size:      {}\n",
+                format::human_size(self.total_installed_size()),
+                format::human_size_signed(delta)
+            )
+        }
+    }
+
+    /// Whether the transaction removes a held package, so the user has to confirm it separately.
+    pub fn needs_hold_confirmation(&self) -> bool {
+        !self.held.is_empty()
+    }
+
+    /// The URL that each package to be installed would be downloaded from, as given by
+    /// `SyncPackage::download_uri`. Packages without one are skipped with a warning.
+    pub fn download_uris(&self, repos: &[Repo], arch: &str) -> Vec<String> {
+        let mut ret = Vec::new();
+        for package in self.install.iter() {
+            match package.download_uri(repos, arch) {
+                Ok(uri) => ret.push(uri),
+                Err(e) => warn!("{}", e),
+            }
+        }
+        ret
+    }
+}
+
+impl SyncPackage {
+    /// The URL that the package would be downloaded from: its file name under the first mirror of
+    /// its repository, with `arch` substituted for `$arch`. Packages without a known file name, or
+    /// from a repository that isn't in `repos` or that has no mirrors, have none.
+    pub fn download_uri(&self, repos: &[Repo], arch: &str) -> std::result::Result<String, String> {
+        let filename = self
+            .desc
+            .filename
+            .as_ref()
+            .ok_or_else(|| format!("No file name is known for package '{}'", self.desc.name))?;
+        match mirrors_for(repos, &self.repo, arch).into_iter().next() {
+            Some(mirror) => Ok(format!("{}/{}", mirror.trim_end_matches('/'), filename)),
+            None => Err(format!(
+                "No mirrors are configured for repository '{}'",
+                self.repo
+            )),
+        }
+    }
+}
+
+/// The mirrors of the repository called `repo`, with `arch` substituted for `$arch`.
+fn mirrors_for(repos: &[Repo], repo: &str, arch: &str) -> Vec<String> {
+    repos
+        .iter()
+        .find(|x| x.name == repo)
+        .map_or_else(Vec::new, |x| x.mirrors(arch))
+}
+
+/// Whether `path` is a complete download of the package described by `desc`, checked against its
+/// size and sha256 where they are known.
+fn is_cached(path: &Path, desc: &PackageDescription) -> Result<bool> {
+    let metadata = match path.metadata() {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+    if desc.download_size.is_some_and(|x| x != metadata.len()) {
+        return Ok(false);
+    }
+    Ok(match &desc.sha256sum {
+        Some(expected) => sha256::hex_digest(std::fs::File::open(path)?)? == *expected,
+        None => true,
+    })
+}
+
+/// Removes the file at `path`, if there is one.
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+impl fmt::Display for Transaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.dependency_checks {
+            DependencyChecks::Full => {}
+            DependencyChecks::IgnoreVersions => writeln!(
+                f,
+                "warning: dependency version checks are disabled (--nodeps); this may break installed packages"
+            )?,
+            DependencyChecks::Skip => writeln!(
+                f,
+                "warning: dependency checks are disabled (--nodeps --nodeps); this may break installed packages"
+            )?,
+        }
+        if !self.held.is_empty() {
+            writeln!(
+                f,
+                ":: The following packages should not be removed: {}",
+                self.held.join(", ")
+            )?;
+        }
+        for package in self.install.iter() {
+            if self.reinstalls.contains(&package.desc.name) {
+                writeln!(
+                    f,
+                    "warning: {}-{} is up to date -- reinstalling",
+                    package.desc.name, package.desc.version
+                )?;
+            }
+        }
+        let packages: Vec<String> = self
+            .remove
+            .iter()
+            .cloned()
+            .chain(
+                self.install
+                    .iter()
+                    .map(|x| format!("{}-{}", x.desc.name, x.desc.version)),
+            )
+            .collect();
+        writeln!(f, "Packages ({}) {}", packages.len(), packages.join("  "))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{
+        gpg_dir, have_gpgv, mock_server_with, sync_package, Fixture, FOO_SIGNATURE,
+    };
+
+    #[test]
+    fn test_transaction_summary() {
+        let mut transaction = Transaction {
+            remove: vec!["foo".into(), "bar".into()],
+            ..Default::default()
+        };
+        assert_eq!(transaction.to_string(), "Packages (2) foo  bar\n");
+        transaction.dependency_checks = DependencyChecks::from_nodeps(2);
+        assert!(transaction
+            .to_string()
+            .starts_with("warning: dependency checks are disabled"));
+    }
+
+    #[test]
+    fn test_download_uris() {
+        let package = |repo: &str, name: &str, filename: Option<&str>| {
+            let mut package = sync_package(repo, name, "1.0-1", "");
+            package.desc.filename = filename.map(|x| x.into());
+            package
+        };
+        let transaction = Transaction {
+            install: vec![
+                package("core", "bash", Some("bash-1.0-1-x86_64.pkg.tar.zst")),
+                package("extra", "vim", Some("vim-1.0-1-x86_64.pkg.tar.zst")),
+                package("core", "nofile", None),
+                package("unknown", "foo", Some("foo-1.0-1-any.pkg.tar.zst")),
+            ],
+            ..Default::default()
+        };
+        let repos = [
+            Repo {
+                name: "core".into(),
+                servers: vec![
+                    "https://a.example/$repo/os/$arch/".into(),
+                    "https://b.example/$repo/os/$arch".into(),
+                ],
+                sig_level: SigLevel::default(),
+            },
+            Repo {
+                name: "extra".into(),
+                servers: vec!["file:///srv/$arch/$repo".into()],
+                sig_level: SigLevel::default(),
+            },
+        ];
+        assert_eq!(
+            transaction.download_uris(&repos, "x86_64"),
+            [
+                "https://a.example/core/os/x86_64/bash-1.0-1-x86_64.pkg.tar.zst",
+                "file:///srv/x86_64/extra/vim-1.0-1-x86_64.pkg.tar.zst",
+            ]
+        );
+        assert!(transaction
+            .to_string()
+            .starts_with("Packages (4) bash-1.0-1  vim-1.0-1"));
+    }
+
+    #[test]
+    fn test_flag_upgrades() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("linux", "5.11.6-1", "", &["usr/", "usr/lib/"]);
+        let mut local = LocalDatabase::with_path(fixture.path());
+        let package = |name: &str| sync_package("core", name, "5.12.1-1", "");
+        let mut transaction = Transaction {
+            install: vec![package("linux"), package("linux-headers")],
+            ..Default::default()
+        };
+        transaction.flag_upgrades(&mut local)?;
+        assert_eq!(transaction.upgrades, ["linux"]);
+        assert!(transaction.reinstalls.is_empty());
+
+        // Removing a package records the files it owns
+        let transaction = local.remove(&["linux"], DependencyChecks::Full)?;
+        assert_eq!(
+            transaction.files["linux"],
+            [PathBuf::from("/usr"), PathBuf::from("/usr/lib")]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_dependencies() {
+        let package = |name: &str| sync_package("extra", name, "1.0-1", "");
+        let mut transaction = Transaction {
+            install: vec![package("vim")],
+            ..Default::default()
+        };
+        transaction.add_dependencies(vec![package("ruby"), package("vim"), package("lua")]);
+        let names: Vec<&str> = transaction
+            .install
+            .iter()
+            .map(|x| x.desc.name.as_str())
+            .collect();
+        assert_eq!(names, ["vim", "ruby", "lua"]);
+        assert_eq!(transaction.dependencies, ["ruby", "lua"]);
+    }
+
+    #[test]
+    fn test_reinstalls() -> Result<()> {
+        let fixture = Fixture::new();
+        fixture.add("bash", "5.1.004-1", "", &[]);
+        fixture.add("linux", "5.11.6-1", "", &[]);
+        let mut local = LocalDatabase::with_path(fixture.path());
+        let package = |name: &str, version: &str| sync_package("core", name, version, "");
+        let mut transaction = Transaction {
+            install: vec![
+                package("bash", "5.1.004-1"),
+                package("linux", "5.12.1-1"),
+                package("vim", "8.2-1"),
+            ],
+            ..Default::default()
+        };
+        transaction.flag_upgrades(&mut local)?;
+        assert_eq!(transaction.upgrades, ["bash", "linux"]);
+        assert_eq!(transaction.reinstalls, ["bash"]);
+        assert_eq!(
+            transaction.to_string(),
+            "warning: bash-5.1.004-1 is up to date -- reinstalling\n\
+             Packages (3) bash-5.1.004-1  linux-5.12.1-1  vim-8.2-1\n"
+        );
+
+        let skipped = transaction.skip_reinstalls();
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].desc.name, "bash");
+        assert_eq!(transaction.upgrades, ["linux"]);
+        assert!(transaction.reinstalls.is_empty());
+        assert_eq!(
+            transaction.to_string(),
+            "Packages (2) linux-5.12.1-1  vim-8.2-1\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_net_size_delta() -> Result<()> {
+        const MIB: u64 = 1024 * 1024;
+        let fixture = Fixture::new();
+        fixture.add(
+            "linux",
+            "5.11.6-1",
+            &format!("%SIZE%\n{}\n\n", 100 * MIB),
+            &[],
+        );
+        fixture.add("vim", "8.2-2", &format!("%SIZE%\n{}\n\n", 4 * MIB), &[]);
+        fixture.add("nano", "5.6-1", &format!("%SIZE%\n{}\n\n", MIB), &[]);
+        let mut local = LocalDatabase::with_path(fixture.path());
+        local.populate_full_database()?;
+        let package = |name: &str, size: u64| {
+            sync_package("core", name, "1.0-1", &format!("%ISIZE%\n{}\n", size))
+        };
+        let mut transaction = Transaction {
+            // An upgrade that grows, a new install, and a downgrade that shrinks
+            install: vec![
+                package("linux", 110 * MIB),
+                package("htop", 2 * MIB),
+                package("vim", MIB),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(transaction.total_installed_size(), 113 * MIB);
+        assert_eq!(
+            transaction.net_size_delta(&local),
+            (10 + 2 - 3) * MIB as i64
+        );
+        assert_eq!(
+            transaction.size_summary(&local),
+            "Total Installed Size:  113.00 MiB\nNet Upgrade Size:      9.00 MiB\n"
+        );
+
+        transaction.install.truncate(1);
+        transaction.install[0].desc.size = Some(90 * MIB);
+        transaction.remove.push("nano".into());
+        assert_eq!(transaction.net_size_delta(&local), -11 * MIB as i64);
+
+        transaction.install.clear();
+        assert_eq!(
+            transaction.size_summary(&local),
+            "Total Removed Size:    1024.00 KiB\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_download_malicious_filename() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cache = dir.path().join("cache");
+        let gpg_dir = gpg_dir();
+        let repos = [Repo {
+            name: "core".into(),
+            servers: vec!["http://127.0.0.1:1/$repo".into()],
+            sig_level: SigLevel::default(),
+        }];
+        for filename in ["../evil", "/etc/cron.d/evil", ".."].iter() {
+            let extra_desc = format!("%FILENAME%\n{}\n", filename);
+            let transaction = Transaction {
+                install: vec![sync_package("core", "evil", "1.0-1", &extra_desc)],
+                ..Default::default()
+            };
+            let error = transaction
+                .download_only(
+                    &repos,
+                    "x86_64",
+                    &cache,
+                    gpg_dir.path(),
+                    &Default::default(),
+                )
+                .unwrap_err();
+            assert!(error
+                .to_string()
+                .starts_with("Package 'evil' can't be downloaded"));
+        }
+        assert!(!dir.path().join("evil").exists());
+        assert!(!dir.path().join("evil.part").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_download_only() -> Result<()> {
+        if !have_gpgv() {
+            return Ok(());
+        }
+        let fixture = Fixture::new();
+        fixture.add("glibc", "2.33-4", "", &[]);
+        let local = LocalDatabase::with_path(fixture.path());
+        let installed = local.names()?;
+
+        let packages = [
+            ("foo", b"foo package".to_vec()),
+            ("bar", b"bar package".to_vec()),
+        ];
+        let mut install = Vec::new();
+        for (name, data) in packages.iter() {
+            let extra_desc = format!(
+                "%FILENAME%\n{}-1.0-1-any.pkg.tar.zst\n\n%CSIZE%\n{}\n\n%SHA256SUM%\n{}\n",
+                name,
+                data.len(),
+                sha256::hex_digest(&data[..])?
+            );
+            install.push(sync_package("core", name, "1.0-1", &extra_desc));
+        }
+        // Only foo has a signature
+        let (server, _) = mock_server_with(4, move |_, request| {
+            let path = request.split(' ').nth(1).unwrap();
+            let body: &[u8] = match path {
+                "/core/foo-1.0-1-any.pkg.tar.zst" => b"foo package",
+                "/core/bar-1.0-1-any.pkg.tar.zst" => b"bar package",
+                "/core/foo-1.0-1-any.pkg.tar.zst.sig" => FOO_SIGNATURE,
+                _ => return b"HTTP/1.0 404 Not Found\r\n\r\n".to_vec(),
+            };
+            let mut response =
+                format!("HTTP/1.0 200 OK\r\nContent-Length: {}\r\n\r\n", body.len()).into_bytes();
+            response.extend_from_slice(body);
+            response
+        });
+        let mut sig_level = SigLevel::default();
+        sig_level.apply("Optional")?;
+        let repos = [Repo {
+            name: "core".into(),
+            servers: vec![format!("{}/$repo", server)],
+            sig_level,
+        }];
+        let gpg_dir = gpg_dir();
+        let policy = RetryPolicy {
+            retries: 0,
+            base_delay: std::time::Duration::from_millis(1),
+        };
+        let transaction = Transaction {
+            install,
+            ..Default::default()
+        };
+
+        let cache = tempfile::tempdir()?;
+        let paths =
+            transaction.download_only(&repos, "x86_64", cache.path(), gpg_dir.path(), &policy)?;
+        assert_eq!(
+            paths,
+            [
+                cache.path().join("foo-1.0-1-any.pkg.tar.zst"),
+                cache.path().join("bar-1.0-1-any.pkg.tar.zst"),
+            ]
+        );
+        assert_eq!(std::fs::read(&paths[1])?, b"bar package");
+        assert_eq!(
+            std::fs::read(cache.path().join("foo-1.0-1-any.pkg.tar.zst.sig"))?,
+            FOO_SIGNATURE
+        );
+        assert!(!cache.path().join("bar-1.0-1-any.pkg.tar.zst.sig").exists());
+        assert_eq!(local.names()?, installed);
+
+        // The server has stopped, so the packages have to come from the cache
+        assert_eq!(
+            transaction.download_only(&repos, "x86_64", cache.path(), gpg_dir.path(), &policy)?,
+            paths
+        );
+        // A corrupt download is replaced, and fails if it can't be
+        std::fs::write(&paths[0], b"foo packagf")?;
+        assert!(transaction
+            .download_only(&repos, "x86_64", cache.path(), gpg_dir.path(), &policy)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_download_sig_level() -> Result<()> {
+        if !have_gpgv() {
+            return Ok(());
+        }
+        let install = ["foo", "bar"]
+            .iter()
+            .map(|name| {
+                let extra_desc = format!("%FILENAME%\n{}-1.0-1-any.pkg.tar.zst\n", name);
+                sync_package("core", name, "1.0-1", &extra_desc)
+            })
+            .collect();
+        let transaction = Transaction {
+            install,
+            ..Default::default()
+        };
+        // Only foo has a signature
+        let serve = |count| {
+            mock_server_with(count, move |_, request| {
+                let path = request.split(' ').nth(1).unwrap();
+                let body: &[u8] = match path {
+                    "/core/foo-1.0-1-any.pkg.tar.zst" => b"foo package",
+                    "/core/bar-1.0-1-any.pkg.tar.zst" => b"bar package",
+                    "/core/foo-1.0-1-any.pkg.tar.zst.sig" => FOO_SIGNATURE,
+                    _ => return b"HTTP/1.0 404 Not Found\r\n\r\n".to_vec(),
+                };
+                let mut response =
+                    format!("HTTP/1.0 200 OK\r\nContent-Length: {}\r\n\r\n", body.len())
+                        .into_bytes();
+                response.extend_from_slice(body);
+                response
+            })
+        };
+        let repo = |server: &str, level: &str| -> Result<Repo> {
+            let mut sig_level = SigLevel::default();
+            sig_level.apply(level)?;
+            Ok(Repo {
+                name: "core".into(),
+                servers: vec![format!("{}/$repo", server)],
+                sig_level,
+            })
+        };
+        let gpg_dir = gpg_dir();
+        let policy = RetryPolicy {
+            retries: 0,
+            base_delay: std::time::Duration::from_millis(1),
+        };
+
+        // Never doesn't ask for any signatures
+        let (server, requests) = serve(2);
+        let cache = tempfile::tempdir()?;
+        let repos = [repo(&server, "Never")?];
+        assert_eq!(
+            transaction
+                .download_only(&repos, "x86_64", cache.path(), gpg_dir.path(), &policy)?
+                .len(),
+            2
+        );
+        let paths: Vec<String> = requests
+            .try_iter()
+            .map(|x| x.split(' ').nth(1).unwrap().to_owned())
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                "/core/foo-1.0-1-any.pkg.tar.zst",
+                "/core/bar-1.0-1-any.pkg.tar.zst",
+            ]
+        );
+        assert!(!cache.path().join("foo-1.0-1-any.pkg.tar.zst.sig").exists());
+
+        // Required rejects the unsigned package
+        let (server, _) = serve(4);
+        let cache = tempfile::tempdir()?;
+        let repos = [repo(&server, "PackageRequired")?];
+        let error = transaction
+            .download_only(&repos, "x86_64", cache.path(), gpg_dir.path(), &policy)
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Package 'bar' has no signature"));
+        assert_eq!(
+            std::fs::read(cache.path().join("foo-1.0-1-any.pkg.tar.zst.sig"))?,
+            FOO_SIGNATURE
+        );
+
+        // An invalid signature in the cache is rejected, and removed along with the package
+        let foo = cache.path().join("foo-1.0-1-any.pkg.tar.zst");
+        std::fs::write(foo.with_extension("zst.sig"), b"signature")?;
+        let error = transaction
+            .download_only(&repos, "x86_64", cache.path(), gpg_dir.path(), &policy)
+            .unwrap_err();
+        assert!(error.to_string().starts_with("Invalid signature for"));
+        assert!(!foo.exists());
+        assert!(!foo.with_extension("zst.sig").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_held_packages() {
+        let hold_packages = ["pacman".to_owned(), "glibc".to_owned()];
+        let mut transaction = Transaction {
+            remove: vec!["foo".into()],
+            ..Default::default()
+        };
+        transaction.flag_held(&hold_packages);
+        assert!(!transaction.needs_hold_confirmation());
+
+        transaction.remove.push("glibc".into());
+        transaction.flag_held(&hold_packages);
+        assert!(transaction.needs_hold_confirmation());
+        assert_eq!(transaction.held, ["glibc"]);
+        assert!(transaction
+            .to_string()
+            .starts_with(":: The following packages should not be removed: glibc\n"));
+    }
+}
@@ -0,0 +1,236 @@
+use std::cmp::Ordering;
+
+/// Compares two package versions of the form `[epoch:]pkgver[-pkgrel]`, in the same way as
+/// pacman's `vercmp`. The release is only compared if both versions have one.
+pub fn vercmp(a: &str, b: &str) -> Ordering {
+    if a == b {
+        return Ordering::Equal;
+    }
+    let (epoch1, ver1, rel1) = parse_evr(a);
+    let (epoch2, ver2, rel2) = parse_evr(b);
+    rpmvercmp(epoch1, epoch2)
+        .then_with(|| rpmvercmp(ver1, ver2))
+        .then_with(|| match (rel1, rel2) {
+            (Some(rel1), Some(rel2)) => rpmvercmp(rel1, rel2),
+            _ => Ordering::Equal,
+        })
+}
+
+/// A package version of the form `[epoch:]pkgver[-pkgrel]`. Versions are ordered with `vercmp`, so
+/// versions that only differ in ways pacman ignores, such as `1.5` and `1.5-1`, are equal.
+#[derive(Debug, Clone)]
+pub struct Version(String);
+
+impl Version {
+    /// The epoch of the version, which is `0` if it doesn't have one.
+    pub fn epoch(&self) -> &str {
+        parse_evr(&self.0).0
+    }
+
+    /// The version of the upstream software, without the epoch or release.
+    pub fn pkgver(&self) -> &str {
+        parse_evr(&self.0).1
+    }
+
+    /// The release of the package, if the version has one.
+    pub fn pkgrel(&self) -> Option<&str> {
+        parse_evr(&self.0).2
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::str::FromStr for Version {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() || s.contains(char::is_whitespace) {
+            return Err(format!("Invalid version '{}'", s));
+        }
+        Ok(Self(s.to_owned()))
+    }
+}
+
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        vercmp(&self.0, &other.0)
+    }
+}
+
+/// Splits a version into its epoch, version and release. A missing epoch is treated as `0`.
+fn parse_evr(evr: &str) -> (&str, &str, Option<&str>) {
+    let digits = evr.find(|c: char| !c.is_ascii_digit()).unwrap_or(evr.len());
+    let (epoch, rest) = match evr[digits..].strip_prefix(':') {
+        Some(rest) if digits > 0 => (&evr[..digits], rest),
+        Some(rest) => ("0", rest),
+        None => ("0", evr),
+    };
+    match rest.rfind('-') {
+        Some(i) => (epoch, &rest[..i], Some(&rest[i + 1..])),
+        None => (epoch, rest, None),
+    }
+}
+
+/// The segment-by-segment version comparison algorithm used by rpm and pacman.
+fn rpmvercmp(a: &str, b: &str) -> Ordering {
+    if a == b {
+        return Ordering::Equal;
+    }
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let (mut one, mut two) = (0, 0);
+    while one < a.len() && two < b.len() {
+        let (start1, start2) = (one, two);
+        while one < a.len() && !a[one].is_ascii_alphanumeric() {
+            one += 1;
+        }
+        while two < b.len() && !b[two].is_ascii_alphanumeric() {
+            two += 1;
+        }
+        if one == a.len() || two == b.len() {
+            break;
+        }
+        // A segment with more separators in front of it is newer
+        if one - start1 != two - start2 {
+            return (one - start1).cmp(&(two - start2));
+        }
+
+        let (start1, start2) = (one, two);
+        let is_num = a[one].is_ascii_digit();
+        let same_kind = |c: &u8| {
+            if is_num {
+                c.is_ascii_digit()
+            } else {
+                c.is_ascii_alphabetic()
+            }
+        };
+        while one < a.len() && same_kind(&a[one]) {
+            one += 1;
+        }
+        while two < b.len() && same_kind(&b[two]) {
+            two += 1;
+        }
+        // The segments are of different types: numeric segments are always newer than alpha ones
+        if two == start2 {
+            return if is_num {
+                Ordering::Greater
+            } else {
+                Ordering::Less
+            };
+        }
+
+        let (mut seg1, mut seg2) = (&a[start1..one], &b[start2..two]);
+        if is_num {
+            while seg1.first() == Some(&b'0') {
+                seg1 = &seg1[1..];
+            }
+            while seg2.first() == Some(&b'0') {
+                seg2 = &seg2[1..];
+            }
+            if seg1.len() != seg2.len() {
+                return seg1.len().cmp(&seg2.len());
+            }
+        }
+        match seg1.cmp(seg2) {
+            Ordering::Equal => {}
+            x => return x,
+        }
+    }
+
+    let (rest1, rest2) = (&a[one..], &b[two..]);
+    if rest1.is_empty() && rest2.is_empty() {
+        return Ordering::Equal;
+    }
+    // A remaining alpha segment never beats an empty string, so `1.0alpha` is older than `1.0`
+    if (rest1.is_empty() && !rest2[0].is_ascii_alphabetic())
+        || rest1.first().is_some_and(|c| c.is_ascii_alphabetic())
+    {
+        Ordering::Less
+    } else {
+        Ordering::Greater
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{vercmp, Version};
+    use std::cmp::Ordering::*;
+
+    #[test]
+    fn test_vercmp() {
+        // Cases from pacman's vercmptest.sh
+        let cases = [
+            ("1.5.0", "1.5.0", Equal),
+            ("1.5.1", "1.5.0", Greater),
+            ("1.5.1", "1.5", Greater),
+            ("1.5.0-1", "1.5.0-1", Equal),
+            ("1.5.0-1", "1.5.0-2", Less),
+            ("1.5.0-1", "1.5.1-1", Less),
+            ("1.5.0-2", "1.5.1-1", Less),
+            ("1.5-1", "1.5", Equal),
+            ("1.5.0", "1.5", Greater),
+            ("1.1-1", "1.1", Equal),
+            ("1.0alpha", "1.0", Less),
+            ("1.0alpha", "1.0a", Greater),
+            ("1.0alpha", "1.0b", Less),
+            ("1.0beta", "1.0rc", Less),
+            ("1.0pre1", "1.0", Less),
+            ("1.0rc", "1.0", Less),
+            ("1.0", "1.0.a", Less),
+            ("1.0.a", "1.0.1", Less),
+            ("1.0a", "1.0.1", Less),
+            ("1.0", "1..0", Less),
+            ("1.0.1", "1..1", Less),
+            ("1.001", "1.1", Equal),
+            ("0:1.0", "1.0", Equal),
+            ("1:1.0", "1.0", Greater),
+            ("1:1.0", "2.0", Greater),
+            ("2:1.0", "1:2.0", Greater),
+            ("1:1.0-1", "1:1.0-2", Less),
+            ("1.5.0", "1.5.0+git20210101", Less),
+        ];
+        for (a, b, expected) in cases.iter() {
+            assert_eq!(vercmp(a, b), *expected, "vercmp({}, {})", a, b);
+            assert_eq!(vercmp(b, a), expected.reverse(), "vercmp({}, {})", b, a);
+        }
+    }
+
+    #[test]
+    fn test_version_parts() {
+        let epoch: Version = "1:1.0-1".parse().unwrap();
+        let plain: Version = "2.0-1".parse().unwrap();
+        assert_eq!(epoch.epoch(), "1");
+        assert_eq!(epoch.pkgver(), "1.0");
+        assert_eq!(epoch.pkgrel(), Some("1"));
+        assert_eq!(plain.epoch(), "0");
+        assert_eq!(plain.pkgver(), "2.0");
+        assert!(epoch > plain);
+        assert_eq!(epoch.to_string(), "1:1.0-1");
+
+        let no_rel: Version = "2.0".parse().unwrap();
+        assert_eq!(no_rel.pkgrel(), None);
+        assert_eq!(no_rel, plain);
+        assert!("".parse::<Version>().is_err());
+    }
+}